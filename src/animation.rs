@@ -1,24 +1,241 @@
+use std::cmp::min;
+use std::sync::OnceLock;
+use std::time::Duration;
+
 use num_rational::Ratio;
 
-use buffer::RgbaImage;
+use buffer::{ImageBuffer, Pixel, RgbaImage};
+use color::Rgba;
+use dynimage::DynamicImage;
+use image::{GenericImage, ImageResult};
+
+/// An iterator over the frames of an animated image
+///
+/// Frames are decoded lazily, one at a time, while the iterator is advanced.
+pub struct Frames<'a> {
+    iterator: Box<Iterator<Item = ImageResult<Frame>> + 'a>,
+}
+
+impl<'a> Frames<'a> {
+    /// Creates a new `Frames` from an implementation specific iterator.
+    pub fn new(iterator: Box<Iterator<Item = ImageResult<Frame>> + 'a>) -> Frames<'a> {
+        Frames { iterator }
+    }
+
+    /// Steps through the iterator from the current frame until the end and pushes each frame into
+    /// a `Vec`.
+    ///
+    /// If en error is encountered that error is returned instead.
+    ///
+    /// Note: This is equivalent to `Frames::collect::<ImageResult<Vec<Frame>>>()`
+    pub fn collect_frames(self) -> ImageResult<Vec<Frame>> {
+        self.collect()
+    }
+
+    /// Steps through the remaining frames and returns the time it takes to play them once.
+    pub fn total_duration(self) -> ImageResult<Duration> {
+        let mut total = Ratio::from_integer(0);
+        for frame in self {
+            total += delay_seconds(&frame?);
+        }
+        Ok(seconds_to_duration(total))
+    }
+
+    /// Steps through the frames until the frame that is displayed `time` after the start
+    /// of the animation is found.
+    ///
+    /// A frame is displayed from the moment its predecessors have been played, up to but
+    /// excluding the moment its own delay has passed as well. Returns `None` if `time` is
+    /// not shorter than the total duration, or too large to be represented in nanoseconds.
+    /// Looping is not taken into account, use `repeat` before calling this to do so.
+    pub fn frame_at(self, time: Duration) -> ImageResult<Option<Frame>> {
+        let nanos = time.as_secs().checked_mul(1_000_000_000)
+            .and_then(|nanos| nanos.checked_add(u64::from(time.subsec_nanos())));
+        let time = match nanos {
+            Some(nanos) => Ratio::new(nanos, 1_000_000_000),
+            None => return Ok(None),
+        };
+        let mut end = Ratio::from_integer(0);
+        for frame in self {
+            let frame = frame?;
+            end += delay_seconds(&frame);
+            if time < end {
+                return Ok(Some(frame))
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns an iterator that plays the frames as often as `count` demands.
+    ///
+    /// The frames are decoded once and kept in memory to be replayed. An error ends
+    /// the iteration after it has been returned. Frames whose delays are all 0 are
+    /// played only once, as repeating them would not advance the playback time.
+    pub fn repeat(self, count: LoopCount) -> Frames<'a> {
+        Frames::new(Box::new(Repeat {
+            frames: Some(self),
+            played: Vec::new(),
+            position: 0,
+            remaining: count,
+        }))
+    }
+}
+
+// The delay of a frame in seconds
+fn delay_seconds(frame: &Frame) -> Ratio<u64> {
+    let delay = frame.delay();
+    Ratio::new(u64::from(*delay.numer()), u64::from(*delay.denom()))
+}
+
+fn seconds_to_duration(seconds: Ratio<u64>) -> Duration {
+    let whole = seconds.to_integer();
+    let nanos = (seconds.fract() * Ratio::from_integer(1_000_000_000)).round().to_integer();
+    Duration::new(whole, nanos as u32)
+}
+
+struct Repeat<'a> {
+    // The frames of the first iteration that are still to be decoded
+    frames: Option<Frames<'a>>,
+    played: Vec<Frame>,
+    position: usize,
+    // Iterations left, including the current one
+    remaining: LoopCount,
+}
+
+impl<'a> Iterator for Repeat<'a> {
+    type Item = ImageResult<Frame>;
+
+    fn next(&mut self) -> Option<ImageResult<Frame>> {
+        if self.remaining == LoopCount::Finite(0) {
+            return None
+        }
+        if let Some(next) = self.frames.as_mut().map(|frames| frames.next()) {
+            match next {
+                Some(Ok(frame)) => {
+                    self.played.push(frame.clone());
+                    self.position += 1;
+                    return Some(Ok(frame))
+                },
+                Some(Err(err)) => {
+                    self.remaining = LoopCount::Finite(0);
+                    return Some(Err(err))
+                },
+                None => {
+                    self.frames = None;
+                    if self.played.iter().all(|frame| *frame.delay().numer() == 0) {
+                        self.remaining = LoopCount::Finite(1);
+                    }
+                },
+            }
+        }
+        if self.position == self.played.len() {
+            self.position = 0;
+            if let LoopCount::Finite(n) = self.remaining {
+                self.remaining = LoopCount::Finite(n - 1);
+            }
+            if self.remaining == LoopCount::Finite(0) || self.played.is_empty() {
+                return None
+            }
+        }
+        self.position += 1;
+        Some(Ok(self.played[self.position - 1].clone()))
+    }
+}
+
+impl<'a> Iterator for Frames<'a> {
+    type Item = ImageResult<Frame>;
+    fn next(&mut self) -> Option<ImageResult<Frame>> {
+        self.iterator.next()
+    }
 
-/// Holds the frames of the animated image
-pub struct Frames {
-    frames: Vec<Frame>,
-    current_frame: usize,
+    /// Skips `n` frames and returns the next one.
+    ///
+    /// Decoders skip frames without fully decoding them where the format allows it.
+    fn nth(&mut self, n: usize) -> Option<ImageResult<Frame>> {
+        self.iterator.nth(n)
+    }
 }
 
-impl Frames {
-    /// Contructs a new frame iterator
-    pub fn new(frames: Vec<Frame>) -> Frames {
-        Frames {
-            frames: frames,
-            current_frame: 0
+/// An encoder for animated images
+///
+/// Combined with `ImageDecoder::into_frames` this allows converting between animated
+/// formats without dealing with the frames individually:
+///
+/// ```no_run
+/// # use image::{AnimationEncoder, ImageDecoder, ImageResult};
+/// # #[cfg(feature = "gif_codec")]
+/// # fn convert() -> ImageResult<()> {
+/// use std::fs::File;
+/// use std::io::BufReader;
+///
+/// let decoder = image::gif::Decoder::new(BufReader::new(File::open("in.gif")?));
+/// let mut encoder = image::gif::Encoder::new(File::create("out.gif")?);
+/// encoder.try_encode_frames(decoder.into_frames()?)?;
+/// encoder.finish()
+/// # }
+/// ```
+///
+/// Encoders may hold back frames, e.g. to optimize them, and provide a method to write
+/// them after the last frame, like `gif::Encoder::finish`. The frames that are held back
+/// are lost if the encoder is dropped without calling it.
+pub trait AnimationEncoder {
+    /// Encodes a single frame and appends it to the animation.
+    fn encode_frame(&mut self, frame: Frame) -> ImageResult<()>;
+
+    /// Encodes all frames yielded by `frames`.
+    fn encode_frames<I>(&mut self, frames: I) -> ImageResult<()>
+    where I: IntoIterator<Item = Frame> {
+        for frame in frames {
+            self.encode_frame(frame)?;
+        }
+        Ok(())
+    }
+
+    /// Encodes all frames yielded by `frames`, stopping at the first error.
+    ///
+    /// This accepts the output of `ImageDecoder::into_frames` directly.
+    fn try_encode_frames<I>(&mut self, frames: I) -> ImageResult<()>
+    where I: IntoIterator<Item = ImageResult<Frame>> {
+        for frame in frames {
+            self.encode_frame(frame?)?;
         }
+        Ok(())
     }
 }
 
+/// The number of times an animation is played
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoopCount {
+    /// The animation is played in an endless loop
+    Infinite,
+    /// The animation is played the given number of times in total
+    Finite(u32),
+}
+
+/// Describes how the area covered by a frame is treated before the next frame is rendered
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisposalMethod {
+    /// The frame is left in place
+    Keep,
+    /// The area of the frame is cleared to the background (fully transparent)
+    Background,
+    /// The area of the frame is restored to what it was before the frame was rendered
+    Previous,
+}
+
+/// Describes how a frame is combined with the canvas it is rendered onto
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlendMethod {
+    /// The pixels of the frame replace the pixels of the canvas, including alpha
+    Source,
+    /// The frame is alpha-composited over the canvas
+    Over,
+}
+
 /// A single animation frame
+///
+/// The frame keeps the color type it was decoded or constructed with, conversion
+/// to RGBA only happens when it is requested with `into_buffer`.
 #[derive(Clone)]
 pub struct Frame {
     /// Delay between the frames in s
@@ -27,43 +244,110 @@ pub struct Frame {
     left: u32,
     /// y offset
     top: u32,
-    buffer: RgbaImage,
+    disposal: DisposalMethod,
+    blend: BlendMethod,
+    image: DynamicImage,
+    // An RGBA copy of `image` for the deprecated `buffer`, converted on demand
+    rgba: OnceLock<RgbaImage>,
 }
 
 impl Frame {
     /// Contructs a new frame
     pub fn new(buffer: RgbaImage) -> Frame {
-        Frame {
-            delay: Ratio::from_integer(0),
-            left: 0,
-            top: 0,
-            buffer: buffer
-        }
+        Frame::from_image(DynamicImage::ImageRgba8(buffer))
     }
 
     /// Contructs a new frame
     pub fn from_parts(buffer: RgbaImage, left: u32, top: u32, delay: Ratio<u16>) -> Frame {
+        let mut frame = Frame::new(buffer);
+        frame.set_offset(left, top);
+        frame.set_delay(delay);
+        frame
+    }
+
+    /// Contructs a new frame from an image of any color type
+    pub fn from_image(image: DynamicImage) -> Frame {
         Frame {
-            delay: delay,
-            left: left,
-            top: top,
-            buffer: buffer
+            delay: Ratio::from_integer(0),
+            left: 0,
+            top: 0,
+            disposal: DisposalMethod::Keep,
+            blend: BlendMethod::Over,
+            image,
+            rgba: OnceLock::new(),
         }
     }
 
     /// Delay of this frame
+    ///
+    /// The delay is exact, i.e. it is stored in the time base of the source format,
+    /// e.g. 1/100 s for GIF.
     pub fn delay(&self) -> Ratio<u16> {
         self.delay
     }
 
+    /// Sets the delay of this frame
+    pub fn set_delay(&mut self, delay: Ratio<u16>) {
+        self.delay = delay;
+    }
+
+    /// Returns how the area of this frame is disposed before the next frame is rendered
+    pub fn disposal(&self) -> DisposalMethod {
+        self.disposal
+    }
+
+    /// Sets the disposal method of this frame
+    pub fn set_disposal(&mut self, disposal: DisposalMethod) {
+        self.disposal = disposal;
+    }
+
+    /// Returns how this frame is combined with the previous content of the canvas
+    pub fn blend(&self) -> BlendMethod {
+        self.blend
+    }
+
+    /// Sets the blend method of this frame
+    pub fn set_blend(&mut self, blend: BlendMethod) {
+        self.blend = blend;
+    }
+
+    /// Sets the position of the top-left corner of this frame on the canvas
+    pub fn set_offset(&mut self, left: u32, top: u32) {
+        self.left = left;
+        self.top = top;
+    }
+
+    /// Returns the image of this frame in its original color type
+    pub fn image(&self) -> &DynamicImage {
+        &self.image
+    }
+
+    /// Returns a mutable reference to the image of this frame
+    pub fn image_mut(&mut self) -> &mut DynamicImage {
+        self.rgba = OnceLock::new();
+        &mut self.image
+    }
+
+    /// Returns the image of this frame in its original color type
+    pub fn into_image(self) -> DynamicImage {
+        self.image
+    }
+
     /// Returns the image buffer
+    ///
+    /// Frames of other color types than 8 bit RGBA are converted on the first call and
+    /// the copy is kept with the frame.
+    #[deprecated(since="0.18.0", note="Use `image` or `into_buffer`, frames may have other color types")]
     pub fn buffer(&self) -> &RgbaImage {
-        &self.buffer
+        match self.image.as_rgba8() {
+            Some(buffer) => buffer,
+            None => self.rgba.get_or_init(|| self.image.to_rgba()),
+        }
     }
 
-    /// Returns the image buffer
+    /// Returns the image buffer, converted to RGBA if necessary
     pub fn into_buffer(self) -> RgbaImage {
-        self.buffer
+        self.image.into_rgba()
     }
 
     /// Returns the x offset
@@ -77,11 +361,241 @@ impl Frame {
     }
 }
 
-impl<'a> Iterator for Frames {
-    type Item = Frame;
-    fn next(&mut self) -> Option<Frame> {
-        let frame = self.current_frame;
-        self.current_frame += 1;
-        self.frames.get(frame).cloned()
+// A canvas the partial frames of an animation are rendered onto, honoring the
+// disposal and blend method of each frame.
+#[cfg_attr(not(any(feature = "gif_codec", feature = "png_codec")), allow(dead_code))]
+pub struct Canvas {
+    canvas: RgbaImage,
+    // Content of the canvas before the last frame was drawn, only kept if it
+    // has to be restored
+    saved: Option<RgbaImage>,
+    // Disposal method and area (left, top, width, height) of the last frame
+    last: Option<(DisposalMethod, (u32, u32, u32, u32))>,
+}
+
+#[cfg_attr(not(any(feature = "gif_codec", feature = "png_codec")), allow(dead_code))]
+impl Canvas {
+    /// Creates a canvas of the given size which is initially fully transparent.
+    pub fn new(width: u32, height: u32) -> Canvas {
+        Canvas {
+            canvas: ImageBuffer::new(width, height),
+            saved: None,
+            last: None,
+        }
+    }
+
+    /// Disposes the previous frame, draws `frame` and returns the result.
+    pub fn render(&mut self, frame: &Frame) -> &RgbaImage {
+        self.dispose_last();
+        if frame.disposal() == DisposalMethod::Previous {
+            self.saved = Some(self.canvas.clone());
+        }
+        let area = self.draw(frame);
+        self.last = Some((frame.disposal(), area));
+        &self.canvas
+    }
+
+    fn dispose_last(&mut self) {
+        let (disposal, (left, top, width, height)) = match self.last.take() {
+            Some(last) => last,
+            None => return,
+        };
+        match disposal {
+            DisposalMethod::Keep => (),
+            DisposalMethod::Background => {
+                for y in top..top + height {
+                    for x in left..left + width {
+                        self.canvas.put_pixel(x, y, Rgba([0, 0, 0, 0]));
+                    }
+                }
+            },
+            DisposalMethod::Previous => if let Some(saved) = self.saved.take() {
+                for y in top..top + height {
+                    for x in left..left + width {
+                        self.canvas.put_pixel(x, y, *saved.get_pixel(x, y));
+                    }
+                }
+            },
+        }
+    }
+
+    fn draw(&mut self, frame: &Frame) -> (u32, u32, u32, u32) {
+        let (canvas_width, canvas_height) = self.canvas.dimensions();
+        let (width, height) = frame.image().dimensions();
+        // Clip the frame to the canvas
+        let left = min(frame.left(), canvas_width);
+        let top = min(frame.top(), canvas_height);
+        let width = min(width, canvas_width - left);
+        let height = min(height, canvas_height - top);
+
+        for y in 0..height {
+            for x in 0..width {
+                let pixel = frame.image().get_pixel(x, y);
+                let target = self.canvas.get_pixel_mut(left + x, top + y);
+                match (frame.blend(), pixel[3]) {
+                    (BlendMethod::Source, _) | (BlendMethod::Over, 255) => *target = pixel,
+                    (BlendMethod::Over, 0) => (),
+                    (BlendMethod::Over, _) => target.blend(&pixel),
+                }
+            }
+        }
+        (left, top, width, height)
+    }
+}
+
+// Yields the fully composited frames of an animation.
+#[cfg_attr(not(any(feature = "gif_codec", feature = "png_codec")), allow(dead_code))]
+pub struct Compositor<I> {
+    frames: I,
+    canvas: Canvas,
+}
+
+#[cfg_attr(not(any(feature = "gif_codec", feature = "png_codec")), allow(dead_code))]
+impl<I> Compositor<I> where I: Iterator<Item = ImageResult<Frame>> {
+    /// Creates a compositor for a canvas of the given size which is initially fully transparent.
+    pub fn new(frames: I, width: u32, height: u32) -> Compositor<I> {
+        Compositor {
+            frames,
+            canvas: Canvas::new(width, height),
+        }
+    }
+}
+
+impl<I> Iterator for Compositor<I> where I: Iterator<Item = ImageResult<Frame>> {
+    type Item = ImageResult<Frame>;
+
+    fn next(&mut self) -> Option<ImageResult<Frame>> {
+        let frame = match self.frames.next() {
+            Some(Ok(frame)) => frame,
+            other => return other,
+        };
+
+        let canvas = self.canvas.render(&frame).clone();
+        let mut composited = Frame::from_parts(canvas, 0, 0, frame.delay());
+        composited.set_blend(BlendMethod::Source);
+        Some(Ok(composited))
+    }
+    fn nth(&mut self, n: usize) -> Option<ImageResult<Frame>> {
+        // Skipped frames still have to be drawn, but the canvas is not copied
+        for _ in 0..n {
+            match self.frames.next() {
+                Some(Ok(frame)) => { self.canvas.render(&frame); },
+                Some(Err(err)) => return Some(Err(err)),
+                None => return None,
+            }
+        }
+        self.next()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use num_rational::Ratio;
+
+    use super::{BlendMethod, Compositor, DisposalMethod, Frame, Frames, LoopCount};
+    use buffer::ImageBuffer;
+    use color::{Luma, Rgba};
+    use dynimage::DynamicImage;
+
+    fn frame(color: [u8; 4], left: u32, top: u32, disposal: DisposalMethod) -> Frame {
+        let mut frame = Frame::new(ImageBuffer::from_pixel(1, 1, Rgba(color)));
+        frame.set_offset(left, top);
+        frame.set_disposal(disposal);
+        frame
+    }
+
+    #[test]
+    fn test_compositor_disposal() {
+        let white = [255, 255, 255, 255];
+        let black = [0, 0, 0, 255];
+        let clear = [0, 0, 0, 0];
+        let mut background = Frame::new(ImageBuffer::from_pixel(2, 1, Rgba(white)));
+        background.set_blend(BlendMethod::Source);
+        let frames = vec![
+            Ok(background),
+            Ok(frame(black, 0, 0, DisposalMethod::Previous)),
+            Ok(frame(black, 1, 0, DisposalMethod::Background)),
+            Ok(frame(clear, 0, 0, DisposalMethod::Keep)),
+        ];
+        let composited: Vec<_> = Compositor::new(frames.into_iter(), 2, 1)
+            .map(|frame| frame.unwrap().into_buffer().into_raw())
+            .collect();
+        assert_eq!(composited[0], [white, white].concat());
+        assert_eq!(composited[1], [black, white].concat());
+        // The previous content was restored before the third frame was drawn
+        assert_eq!(composited[2], [white, black].concat());
+        // The third frame was cleared and the fourth is fully transparent
+        assert_eq!(composited[3], [white, clear].concat());
+    }
+
+    #[test]
+    fn test_frame_keeps_color_type() {
+        let gray = ImageBuffer::from_pixel(2, 2, Luma([128u8]));
+        let frame = Frame::from_image(DynamicImage::ImageLuma8(gray));
+        assert!(frame.image().as_luma8().is_some());
+
+        let composited = Compositor::new(vec![Ok(frame.clone())].into_iter(), 2, 2)
+            .next().unwrap().unwrap();
+        assert_eq!(composited.into_buffer().into_raw(), [128, 128, 128, 255].repeat(4));
+        // The deprecated accessor converts gray frames instead of panicking
+        #[allow(deprecated)]
+        let buffer = frame.buffer().clone();
+        assert_eq!(buffer.into_raw(), [128, 128, 128, 255].repeat(4));
+        assert!(frame.image().as_luma8().is_some());
+        assert_eq!(frame.into_buffer().get_pixel(1, 1).data, [128, 128, 128, 255]);
+    }
+
+    // Frames with delays of 1/10 s, 1/3 s and 1/10 s, marked by their width
+    fn timed_frames<'a>() -> Frames<'a> {
+        let delays = [Ratio::new(10, 100), Ratio::new(1, 3), Ratio::new(1, 10)];
+        let frames: Vec<_> = delays.iter().enumerate().map(|(i, &delay)| {
+            let buffer = ImageBuffer::new(i as u32 + 1, 1);
+            Ok(Frame::from_parts(buffer, 0, 0, delay))
+        }).collect();
+        Frames::new(Box::new(frames.into_iter()))
+    }
+
+    fn width(frame: Option<Frame>) -> Option<u32> {
+        frame.map(|frame| frame.into_buffer().width())
+    }
+
+    #[test]
+    fn test_total_duration() {
+        assert_eq!(timed_frames().total_duration().unwrap(), Duration::new(0, 533_333_333));
+    }
+
+    #[test]
+    fn test_frame_at() {
+        let at = |millis| width(timed_frames().frame_at(Duration::from_millis(millis)).unwrap());
+        assert_eq!(at(0), Some(1));
+        assert_eq!(at(99), Some(1));
+        assert_eq!(at(100), Some(2));
+        assert_eq!(at(433), Some(2));
+        assert_eq!(at(434), Some(3));
+        assert_eq!(at(533), Some(3));
+        assert_eq!(at(534), None);
+        assert_eq!(width(timed_frames().repeat(LoopCount::Infinite)
+            .frame_at(Duration::from_millis(634)).unwrap()), Some(2));
+        assert_eq!(width(timed_frames().frame_at(Duration::new(u64::max_value(), 0)).unwrap()), None);
+
+        // An animation without duration ends even if it is repeated
+        let frames: Vec<_> = (0..2).map(|_| Ok(Frame::new(ImageBuffer::new(1, 1)))).collect();
+        let frames = Frames::new(Box::new(frames.into_iter())).repeat(LoopCount::Infinite);
+        assert!(frames.frame_at(Duration::from_millis(1)).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_repeat() {
+        let widths = |count| timed_frames().repeat(count)
+            .take(10)
+            .map(|frame| frame.unwrap().into_buffer().width())
+            .collect::<Vec<_>>();
+        assert_eq!(widths(LoopCount::Finite(1)), [1, 2, 3]);
+        assert_eq!(widths(LoopCount::Finite(2)), [1, 2, 3, 1, 2, 3]);
+        assert_eq!(widths(LoopCount::Infinite), [1, 2, 3, 1, 2, 3, 1, 2, 3, 1]);
+        let empty = Frames::new(Box::new(Vec::new().into_iter()));
+        assert_eq!(empty.repeat(LoopCount::Infinite).count(), 0);
     }
 }
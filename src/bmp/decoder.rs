@@ -10,7 +10,10 @@ use image::{
     DecodingResult,
     ImageResult,
     ImageDecoder,
-    ImageError
+    DecodingError,
+    DecodingErrorKind,
+    ImageError,
+    ImageFormat
 };
 use color::ColorType;
 
@@ -102,7 +105,10 @@ impl<'a> Iterator for RowIterator<'a> {
 /// Convenience function to check if the combination of width, length and number of
 /// channels would result in a buffer that would overflow.
 fn check_for_overflow(width: i32, length: i32, channels: usize) -> ImageResult<()> {
-    num_bytes(width, length, channels).map(|_| ()).ok_or_else(|| ImageError::FormatError("Image would require a buffer that is too large to be represented!".to_owned()))
+    num_bytes(width, length, channels).map(|_| ()).ok_or_else(|| ImageError::malformed(
+        ImageFormat::BMP,
+        "Image would require a buffer that is too large to be represented!"
+    ))
 }
 
 /// Calculate how many many bytes a buffer holding a decoded image with these properties would
@@ -315,10 +321,10 @@ impl Bitfield {
         let mut shift = mask.trailing_zeros();
         let mut len = (!(mask >> shift)).trailing_zeros();
         if len != mask.count_ones() {
-            return Err(ImageError::FormatError("Non-contiguous bitfield mask".to_string()))
+            return Err(ImageError::malformed(ImageFormat::BMP, "Non-contiguous bitfield mask"))
         }
         if len + shift > max_len {
-            return Err(ImageError::FormatError("Invalid bitfield mask".to_string()))
+            return Err(ImageError::malformed(ImageFormat::BMP, "Invalid bitfield mask"))
         }
         if len > 8 {
             shift += len - 8;
@@ -330,6 +336,8 @@ impl Bitfield {
     fn read(&self, data: u32) -> u8 {
         let data = data >> self.shift;
         match self.len {
+            // A missing alpha mask, the ICO decoder always asks for an alpha channel
+            0 => 0xff,
             1 => ((data & 0b1) * 0xff) as u8,
             2 => ((data & 0b11) * 0x55) as u8,
             3 => LOOKUP_TABLE_3_BIT_TO_8_BIT[(data & 0b00_0111) as usize],
@@ -360,7 +368,7 @@ impl Bitfields {
             a: try!(Bitfield::from_mask(a_mask, max_len)),
         };
         if bitfields.r.len == 0 || bitfields.g.len == 0 || bitfields.b.len == 0 {
-            return Err(ImageError::FormatError("Missing bitfield mask".to_string()))
+            return Err(ImageError::malformed(ImageFormat::BMP, "Missing bitfield mask"))
         }
         Ok(bitfields)
     }
@@ -494,7 +502,9 @@ impl<R: Read + Seek> BMPDecoder<R> {
         try!(self.r.read_exact(&mut signature));
 
         if signature != b"BM"[..] {
-            return Err(ImageError::FormatError("BMP signature not found".to_string()));
+            return Err(DecodingError::new(ImageFormat::BMP, DecodingErrorKind::InvalidSignature)
+                .with_offset(0)
+                .into());
         }
 
         // The next 8 bytes represent file size, followed the 4 reserved bytes
@@ -519,15 +529,16 @@ impl<R: Read + Seek> BMPDecoder<R> {
         try!(check_for_overflow(self.width, self.height, self.num_channels()));
 
         // Number of planes (format specifies that this should be 1).
-        if try!(self.r.read_u16::<LittleEndian>()) != 1 {
-            return Err(ImageError::FormatError("Invalid number of planes.".to_string()));
+        let planes = try!(self.r.read_u16::<LittleEndian>());
+        if planes != 1 {
+            return Err(DecodingError::invalid_value(ImageFormat::BMP, "number of planes", 1, planes).into());
         }
 
         self.bit_count = try!(self.r.read_u16::<LittleEndian>());
         self.image_type = match self.bit_count {
             1 | 4 | 8 => ImageType::Palette,
             24 => ImageType::RGB24,
-            _ => return Err(ImageError::FormatError("Invalid bit count".to_string())),
+            _ => return Err(ImageError::malformed(ImageFormat::BMP, "Invalid bit count")),
         };
 
         Ok(())
@@ -543,15 +554,15 @@ impl<R: Read + Seek> BMPDecoder<R> {
 
         // Width can not be negative
         if self.width < 0 {
-            return Err(ImageError::FormatError("Negative width".to_string()));
+            return Err(ImageError::malformed(ImageFormat::BMP, "Negative width"));
         } else if self.width > MAX_WIDTH_HEIGHT || self.height > MAX_WIDTH_HEIGHT {
             // Limit very large image sizes to avoid OOM issues. Images with these sizes are
             // unlikely to be valid anyhow.
-            return Err(ImageError::FormatError("Image too large".to_string()));
+            return Err(ImageError::malformed(ImageFormat::BMP, "Image too large"));
         }
 
         if self.height == i32::min_value() {
-            return Err(ImageError::FormatError("Invalid height".to_string()));
+            return Err(ImageError::malformed(ImageFormat::BMP, "Invalid height"));
         }
 
         // A negative height indicates a top-down DIB.
@@ -563,8 +574,9 @@ impl<R: Read + Seek> BMPDecoder<R> {
         try!(check_for_overflow(self.width, self.height, self.num_channels()));
 
         // Number of planes (format specifies that this should be 1).
-        if try!(self.r.read_u16::<LittleEndian>()) != 1 {
-            return Err(ImageError::FormatError("Invalid number of planes.".to_string()));
+        let planes = try!(self.r.read_u16::<LittleEndian>());
+        if planes != 1 {
+            return Err(DecodingError::invalid_value(ImageFormat::BMP, "number of planes", 1, planes).into());
         }
 
         self.bit_count = try!(self.r.read_u16::<LittleEndian>());
@@ -572,8 +584,7 @@ impl<R: Read + Seek> BMPDecoder<R> {
 
         // Top-down dibs can not be compressed.
         if self.top_down && image_type_u32 != 0 && image_type_u32 != 3 {
-            return Err(ImageError::FormatError("Invalid image type for top-down image."
-                                               .to_string()));
+            return Err(ImageError::malformed(ImageFormat::BMP, "Invalid image type for top-down image."));
         }
         self.image_type = match image_type_u32 {
             0 => match self.bit_count {
@@ -581,20 +592,20 @@ impl<R: Read + Seek> BMPDecoder<R> {
                 16 => ImageType::RGB16,
                 24 => ImageType::RGB24,
                 32 => if self.add_alpha_channel { ImageType::RGBA32 } else { ImageType::RGB32 },
-                _ => return Err(ImageError::FormatError("Invalid RGB bit count".to_string())),
+                _ => return Err(ImageError::malformed(ImageFormat::BMP, "Invalid RGB bit count")),
             },
             1 => match self.bit_count {
                 8 => ImageType::RLE8,
-                _ => return Err(ImageError::FormatError("Invalid RLE8 bit count".to_string())),
+                _ => return Err(ImageError::malformed(ImageFormat::BMP, "Invalid RLE8 bit count")),
             },
             2 => match self.bit_count {
                 4 => ImageType::RLE4,
-                _ => return Err(ImageError::FormatError("Invalid RLE4 bit count".to_string())),
+                _ => return Err(ImageError::malformed(ImageFormat::BMP, "Invalid RLE4 bit count")),
             },
             3 => match self.bit_count {
                 16 => ImageType::Bitfields16,
                 32 => ImageType::Bitfields32,
-                _ => return Err(ImageError::FormatError("Invalid bitfields bit count".to_string())),
+                _ => return Err(ImageError::malformed(ImageFormat::BMP, "Invalid bitfields bit count")),
             },
             // PNG and JPEG not implemented yet.
             _  => return Err(ImageError::UnsupportedError("Unsupported image type".to_string())),
@@ -709,7 +720,7 @@ impl<R: Read + Seek> BMPDecoder<R> {
             0 => Ok(1 << self.bit_count),
             _ => {
                 if self.colors_used > 1 << self.bit_count {
-                    return Err(ImageError::FormatError(format!(
+                    return Err(ImageError::malformed(ImageFormat::BMP, format!(
                         "Palette size {} exceeds maximum size for BMP with bit count of {}",
                         self.colors_used, self.bit_count
                     )))
@@ -924,7 +935,7 @@ impl<R: Read + Seek> BMPDecoder<R> {
 
         let full_image_size = try!(
             num_bytes(self.width, self.height, self.num_channels())
-                .ok_or_else(|| ImageError::FormatError("Image buffer would be too large!".to_owned()))
+                .ok_or_else(|| ImageError::malformed(ImageFormat::BMP, "Image buffer would be too large!"))
         );
         let mut pixel_data = self.create_pixel_data();
         let (skip_pixels, skip_rows, eof_hit) = try!(self.read_rle_data_step(&mut pixel_data, image_type, 0, 0));
@@ -1062,7 +1073,7 @@ impl<R: Read + Seek> BMPDecoder<R> {
                         }
                     } else {
                         // We ran out of data while we still had rows to fill in.
-                        return Err(ImageError::FormatError("Not enough RLE data".to_string()))
+                        return Err(ImageError::malformed(ImageFormat::BMP, "Not enough RLE data"))
                     }
                 }
             }
@@ -1083,7 +1094,7 @@ impl<R: Read + Seek> BMPDecoder<R> {
             ImageType::Bitfields16 => {
                 match self.bitfields {
                     Some(_) => self.read_16_bit_pixel_data(None),
-                    None => Err(ImageError::FormatError("Missing 16-bit bitfield masks".to_string()))
+                    None => Err(ImageError::malformed(ImageFormat::BMP, "Missing 16-bit bitfield masks"))
                 }
             },
             ImageType::Bitfields32 => {
@@ -1092,7 +1103,7 @@ impl<R: Read + Seek> BMPDecoder<R> {
                         self.read_full_byte_pixel_data(FormatFullBytes::Format888)
                     },
                     Some(_) => self.read_32_bit_pixel_data(),
-                    None => Err(ImageError::FormatError("Missing 32-bit bitfield masks".to_string()))
+                    None => Err(ImageError::malformed(ImageFormat::BMP, "Missing 32-bit bitfield masks"))
                 }
             },
         }
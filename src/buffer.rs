@@ -1,15 +1,18 @@
 use std::slice::{ Chunks, ChunksMut };
-use std::ops::{ Deref, DerefMut, Index, IndexMut };
+use std::ops::{ Deref, DerefMut, Index, IndexMut, Range };
 use std::marker::PhantomData;
 use std::path::Path;
 use std::io;
-use num_traits::Zero;
+use num_traits::{ Bounded, NumCast, Zero };
 
 use traits::Primitive;
 use color::{ Rgb, Rgba, Luma, LumaA, FromColor, ColorType };
 use image::GenericImage;
 use dynimage::save_buffer;
 use utils::expand_packed;
+use math::utils::clamp;
+#[cfg(feature = "rustc-serialize")]
+use rustc_serialize::{Decodable, Decoder, Encodable, Encoder};
 
 /// A generalized pixel.
 ///
@@ -289,11 +292,126 @@ where P: Pixel + 'static,
     ///
     /// Panics if `(x, y)` is out of the bounds `(width, height)`.
     pub fn get_pixel(&self, x: u32, y: u32) -> &P {
+        let indices = self.pixel_indices(x, y);
+        <P as Pixel>::from_slice(&self.data[indices])
+    }
+
+    // The number of subpixels of the image, the container may be longer
+    fn subpixel_count(&self) -> usize {
+        self.width as usize * self.height as usize * <P as Pixel>::channel_count() as usize
+    }
+
+    // The range of subpixels of the pixel at ```(x, y)```, panics if it is out of bounds
+    fn pixel_indices(&self, x: u32, y: u32) -> Range<usize> {
+        assert!(x < self.width && y < self.height,
+                "Image index {:?} out of bounds {:?}", (x, y), (self.width, self.height));
         let no_channels = <P as Pixel>::channel_count() as usize;
-        let index  = no_channels * (y * self.width + x) as usize;
-        <P as Pixel>::from_slice(
-            &self.data[index .. index + no_channels]
-        )
+        let index = no_channels * (y as usize * self.width as usize + x as usize);
+        index..index + no_channels
+    }
+
+    /// Converts this buffer into a new buffer of pixel type `Q`.
+    ///
+    /// Both the color model and the subpixel type may change. The conversion is
+    /// done in a single pass, subpixels are rescaled so that the maximum value of
+    /// the source type maps to the maximum value of the target type and rounded
+    /// to the nearest representable value. ```ConvertBuffer::convert``` only
+    /// changes the color model.
+    ///
+    /// ```
+    /// use image::{ImageBuffer, Rgb, Luma};
+    ///
+    /// let img: ImageBuffer<Rgb<u16>, _> = ImageBuffer::from_pixel(2, 2, Rgb([0xffff, 0, 0x8080]));
+    /// let gray: ImageBuffer<Luma<u8>, _> = img.convert_to();
+    /// let rgb: ImageBuffer<Rgb<u8>, _> = img.convert_to();
+    /// assert_eq!(rgb[(0, 0)], Rgb([0xff, 0, 0x80]));
+    /// assert_eq!(gray.dimensions(), (2, 2));
+    /// ```
+    pub fn convert_to<Q>(&self) -> ImageBuffer<Q, Vec<Q::Subpixel>>
+    where Q: Pixel + FromColor<Rgba<Q::Subpixel>> + FromColor<LumaA<Q::Subpixel>> + 'static,
+          Q::Subpixel: 'static {
+        self.convert_with_bias(|_, _| 0.5)
+    }
+
+    /// Converts this buffer into a new buffer of pixel type `Q`, applying an
+    /// ordered dither when the subpixel depth is reduced.
+    ///
+    /// This behaves like `convert_to` but trades the rounding error of the depth
+    /// reduction for a fine, regular pattern, which avoids banding in smooth
+    /// gradients (e.g. when going from 16 to 8 bit per channel).
+    pub fn convert_dithered<Q>(&self) -> ImageBuffer<Q, Vec<Q::Subpixel>>
+    where Q: Pixel + FromColor<Rgba<Q::Subpixel>> + FromColor<LumaA<Q::Subpixel>> + 'static,
+          Q::Subpixel: 'static {
+        self.convert_with_bias(|x, y| BAYER_4X4[(y % 4) as usize][(x % 4) as usize])
+    }
+
+    // `bias` returns the value in [0, 1) that is added before truncating a
+    // subpixel whose depth is reduced; 0.5 rounds to the nearest value.
+    fn convert_with_bias<Q, F>(&self, mut bias: F) -> ImageBuffer<Q, Vec<Q::Subpixel>>
+    where Q: Pixel + FromColor<Rgba<Q::Subpixel>> + FromColor<LumaA<Q::Subpixel>> + 'static,
+          Q::Subpixel: 'static,
+          F: FnMut(u32, u32) -> f64 {
+        let src_max: f64 = NumCast::from(P::Subpixel::max_value()).unwrap();
+        let dst_max: f64 = NumCast::from(Q::Subpixel::max_value()).unwrap();
+        let same_model = P::color_model() == Q::color_model();
+        // Luma to luma conversions are kept in gray space, going through
+        // RGB would introduce rounding errors.
+        let gray = P::color_model().starts_with('Y') && Q::color_model().starts_with('Y');
+
+        let mut out: ImageBuffer<Q, Vec<Q::Subpixel>> = ImageBuffer::new(self.width, self.height);
+        for ((x, y, to), from) in out.enumerate_pixels_mut().zip(self.pixels()) {
+            let bias = bias(x, y);
+            let scale = |c: P::Subpixel| -> Q::Subpixel {
+                rescale_subpixel(c, src_max, dst_max, bias)
+            };
+            if same_model {
+                for (t, &f) in to.channels_mut().iter_mut().zip(from.channels()) {
+                    *t = scale(f)
+                }
+            } else if gray {
+                let la = from.to_luma_alpha();
+                to.from_color(&LumaA([scale(la[0]), scale(la[1])]))
+            } else {
+                let rgba = from.to_rgba();
+                to.from_color(&Rgba([scale(rgba[0]), scale(rgba[1]), scale(rgba[2]), scale(rgba[3])]))
+            }
+        }
+        out
+    }
+
+    /// Returns a new buffer in which every pixel is replaced by the result of `f`.
+    /// `f` is called with the coordinates and the value of each pixel in turn.
+    ///
+    /// ```
+    /// use image::{ImageBuffer, Rgb};
+    ///
+    /// let img: ImageBuffer<Rgb<u8>, _> = ImageBuffer::from_pixel(2, 2, Rgb([1, 2, 3]));
+    /// let swapped = img.map_pixels(|_, _, p| Rgb([p[2], p[1], p[0]]));
+    /// assert_eq!(swapped[(1, 1)], Rgb([3, 2, 1]));
+    /// ```
+    pub fn map_pixels<F>(&self, mut f: F) -> ImageBuffer<P, Vec<P::Subpixel>>
+    where F: FnMut(u32, u32, P) -> P {
+        let mut out = ImageBuffer::new(self.width, self.height);
+        for ((x, y, to), from) in out.enumerate_pixels_mut().zip(self.pixels()) {
+            *to = f(x, y, *from)
+        }
+        out
+    }
+
+    /// Returns a new buffer in which every subpixel, including alpha, is replaced
+    /// by the result of `f`.
+    ///
+    /// ```
+    /// use image::{ImageBuffer, Luma};
+    ///
+    /// let img: ImageBuffer<Luma<u8>, _> = ImageBuffer::from_pixel(2, 2, Luma([64]));
+    /// let gamma = img.map_subpixels(|c| (255.0 * (c as f32 / 255.0).powf(0.5)) as u8);
+    /// assert_eq!(gamma[(0, 0)], Luma([127]));
+    /// ```
+    pub fn map_subpixels<F>(&self, f: F) -> ImageBuffer<P, Vec<P::Subpixel>>
+    where F: FnMut(P::Subpixel) -> P::Subpixel {
+        let data = self.data[..self.subpixel_count()].iter().cloned().map(f).collect();
+        ImageBuffer::from_raw(self.width, self.height, data).unwrap()
     }
 }
 
@@ -302,6 +420,24 @@ where P: Pixel + 'static,
       P::Subpixel: 'static,
       Container: Deref<Target=[P::Subpixel]> + DerefMut {
 
+    /// Replaces every pixel by the result of `f`, see `map_pixels`.
+    pub fn map_pixels_in_place<F>(&mut self, mut f: F)
+    where F: FnMut(u32, u32, P) -> P {
+        for (x, y, pixel) in self.enumerate_pixels_mut() {
+            *pixel = f(x, y, *pixel)
+        }
+    }
+
+    /// Replaces every subpixel, including alpha, by the result of `f`, see
+    /// `map_subpixels`.
+    pub fn map_subpixels_in_place<F>(&mut self, mut f: F)
+    where F: FnMut(P::Subpixel) -> P::Subpixel {
+        let len = self.subpixel_count();
+        for c in &mut self.data[..len] {
+            *c = f(*c)
+        }
+    }
+
     /// Returns an iterator over the mutable pixels of this image.
     pub fn pixels_mut(&mut self) -> PixelsMut<P> {
         PixelsMut {
@@ -330,11 +466,8 @@ where P: Pixel + 'static,
     ///
     /// Panics if `(x, y)` is out of the bounds `(width, height)`.
     pub fn get_pixel_mut(&mut self, x: u32, y: u32) -> &mut P {
-        let no_channels = <P as Pixel>::channel_count() as usize;
-        let index  = no_channels * (y * self.width + x) as usize;
-        <P as Pixel>::from_slice_mut(
-            &mut self.data[index .. index + no_channels]
-        )
+        let indices = self.pixel_indices(x, y);
+        <P as Pixel>::from_slice_mut(&mut self.data[indices])
     }
 
     /// Puts a pixel at location `(x, y)`
@@ -419,6 +552,45 @@ where P: Pixel,
     }
 }
 
+/// Encodes the width, the height and the subpixels of the image.
+#[cfg(feature = "rustc-serialize")]
+impl<P, Container> Encodable for ImageBuffer<P, Container>
+where P: Pixel,
+      P::Subpixel: Encodable,
+      Container: Deref<Target=[P::Subpixel]> {
+
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        s.emit_struct("ImageBuffer", 3, |s| {
+            s.emit_struct_field("width", 0, |s| self.width.encode(s))?;
+            s.emit_struct_field("height", 1, |s| self.height.encode(s))?;
+            s.emit_struct_field("data", 2, |s| self.data[..].encode(s))
+        })
+    }
+}
+
+/// Decodes an image encoded by the ```Encodable``` implementation, it is an error
+/// if the number of subpixels does not match the dimensions.
+#[cfg(feature = "rustc-serialize")]
+impl<P> Decodable for ImageBuffer<P, Vec<P::Subpixel>>
+where P: Pixel + 'static,
+      P::Subpixel: Decodable + 'static {
+
+    fn decode<D: Decoder>(d: &mut D) -> Result<Self, D::Error> {
+        d.read_struct("ImageBuffer", 3, |d| {
+            let width = d.read_struct_field("width", 0, u32::decode)?;
+            let height = d.read_struct_field("height", 1, u32::decode)?;
+            let data: Vec<P::Subpixel> = d.read_struct_field("data", 2, Decodable::decode)?;
+            let len = (width as usize)
+                .checked_mul(height as usize)
+                .and_then(|pixels| pixels.checked_mul(<P as Pixel>::channel_count() as usize));
+            if len != Some(data.len()) {
+                return Err(d.error("the length of the data does not match the dimensions"))
+            }
+            Ok(ImageBuffer::from_raw(width, height, data).unwrap())
+        })
+    }
+}
+
 impl<P, Container> GenericImage for ImageBuffer<P, Container>
 where P: Pixel + 'static,
       Container: Deref<Target=[P::Subpixel]> + DerefMut,
@@ -601,6 +773,26 @@ impl<'a, 'b, Container, FromType: Pixel + 'static, ToType: Pixel + 'static>
     }
 }
 
+// 4x4 Bayer threshold matrix, normalized to the open interval (0, 1).
+const BAYER_4X4: [[f64; 4]; 4] = [
+    [ 0.5 / 16.0,  8.5 / 16.0,  2.5 / 16.0, 10.5 / 16.0],
+    [12.5 / 16.0,  4.5 / 16.0, 14.5 / 16.0,  6.5 / 16.0],
+    [ 3.5 / 16.0, 11.5 / 16.0,  1.5 / 16.0,  9.5 / 16.0],
+    [15.5 / 16.0,  7.5 / 16.0, 13.5 / 16.0,  5.5 / 16.0],
+];
+
+// Maps `value` from the range `[0, src_max]` to `[0, dst_max]`.
+#[inline]
+fn rescale_subpixel<S: Primitive, T: Primitive>(value: S, src_max: f64, dst_max: f64, bias: f64) -> T {
+    if src_max == dst_max {
+        return NumCast::from(value).unwrap()
+    }
+    let v: f64 = NumCast::from(value).unwrap();
+    let v = v * dst_max / src_max;
+    let v = if dst_max < src_max { (v + bias).floor() } else { v.round() };
+    NumCast::from(clamp(v, 0.0, dst_max)).unwrap()
+}
+
 /// Sendable Rgb image buffer
 pub type RgbImage = ImageBuffer<Rgb<u8>, Vec<u8>>;
 /// Sendable Rgb + alpha channel image buffer
@@ -637,6 +829,22 @@ mod test {
 
     }
 
+    #[test]
+    fn test_index() {
+        let mut a: RgbImage = ImageBuffer::new(3, 2);
+        a[(2, 1)] = color::Rgb([1, 2, 3]);
+        assert_eq!(a[(2, 1)], color::Rgb([1, 2, 3]));
+        assert_eq!(a.into_raw()[15..], [1, 2, 3]);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn test_index_out_of_bounds() {
+        // Would be a valid pixel of the next row if the index was not checked
+        let a: RgbImage = ImageBuffer::new(3, 2);
+        let _ = a[(3, 0)];
+    }
+
     #[test]
     fn test_mut_iter() {
         let mut a: RgbImage = ImageBuffer::new(10, 10);
@@ -647,6 +855,51 @@ mod test {
         assert_eq!(a.data[0], 42)
     }
 
+    #[test]
+    fn test_convert_depth() {
+        let a: ImageBuffer<color::Rgb<u16>, _> = ImageBuffer::from_pixel(2, 2, color::Rgb([0xffff, 0x0101, 0]));
+        let b: ImageBuffer<color::Rgba<u8>, _> = a.convert_to();
+        assert_eq!(b[(1, 1)], color::Rgba([0xff, 0x01, 0, 0xff]));
+        let c: ImageBuffer<color::LumaA<u16>, _> = b.convert_to();
+        assert_eq!(c[(0, 0)][1], 0xffff);
+        let d: ImageBuffer<color::Luma<u8>, _> = ImageBuffer::from_pixel(2, 2, color::Luma([200]));
+        let e: ImageBuffer<color::LumaA<u16>, _> = d.convert_to();
+        assert_eq!(e[(0, 1)], color::LumaA([200 * 257, 0xffff]));
+    }
+
+    #[test]
+    fn test_convert_dithered() {
+        // 0x80 / 257 is roughly 0.5 so the dithered output should be half 0 and half 1
+        let a: ImageBuffer<color::Luma<u16>, _> = ImageBuffer::from_pixel(4, 4, color::Luma([0x80]));
+        let b: ImageBuffer<color::Luma<u8>, _> = a.convert_dithered();
+        assert_eq!(b.iter().filter(|&&v| v == 1).count(), 8);
+        assert_eq!(b.iter().filter(|&&v| v == 0).count(), 8);
+    }
+
+    #[test]
+    fn test_map() {
+        let a: RgbImage = ImageBuffer::from_fn(3, 2, |x, y| color::Rgb([x as u8, y as u8, 9]));
+        let b = a.map_pixels(|x, y, p| color::Rgb([p[2], (x + y) as u8, p[0]]));
+        assert_eq!(b.into_raw(), vec![9, 0, 0, 9, 1, 1, 9, 2, 2,
+                                      9, 1, 0, 9, 2, 1, 9, 3, 2]);
+        let mut c = a.map_subpixels(|v| v * 2);
+        assert_eq!(c[(2, 1)], color::Rgb([4, 2, 18]));
+
+        c.map_pixels_in_place(|x, _, p| if x == 0 { color::Rgb([0, 0, 0]) } else { p });
+        c.map_subpixels_in_place(|v| v + 1);
+        assert_eq!(c[(0, 1)], color::Rgb([1, 1, 1]));
+        assert_eq!(c[(1, 0)], color::Rgb([3, 1, 19]));
+
+        // Excess data in the container is left alone
+        let mut data = vec![1u8, 2, 3, 4, 5];
+        {
+            let mut d: ImageBuffer<color::Luma<u8>, _> = ImageBuffer::from_raw(2, 2, &mut data[..]).unwrap();
+            assert_eq!(d.map_subpixels(|v| v * 10).into_raw(), vec![10, 20, 30, 40]);
+            d.map_subpixels_in_place(|v| v * 10);
+        }
+        assert_eq!(data, [10, 20, 30, 40, 5]);
+    }
+
     #[bench]
     #[cfg(feature = "benchmarks")]
     fn bench_conversion(b: &mut test::Bencher) {
@@ -1,6 +1,7 @@
 use std::ops::{ Index, IndexMut };
 use num_traits::{ NumCast, Zero };
 use std::mem;
+use std::slice;
 
 use buffer::Pixel;
 use traits::Primitive;
@@ -58,6 +59,9 @@ macro_rules! define_colors {
 $( // START Structure definitions
 
 #[$doc]
+///
+/// The layout of a pixel is the same as that of its ```data``` array, so a slice of
+/// pixels is a slice of their channels and vice versa.
 #[derive(PartialEq, Eq, Clone, Debug, Copy, Hash)]
 #[repr(C)]
 #[allow(missing_docs)]
@@ -185,6 +189,62 @@ impl<T: Primitive + 'static> Pixel for $ident<T> {
     }
 }
 
+impl<T: Primitive + 'static> $ident<T> {
+    /// Views a slice of channels as a slice of pixels without copying.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the length of ```channels``` is not a multiple of the channel count.
+    pub fn slice_from_channels(channels: &[T]) -> &[$ident<T>] {
+        let count = <$ident<T> as Pixel>::channel_count() as usize;
+        assert!(channels.len() % count == 0, "the length is not a multiple of the channel count");
+        unsafe { slice::from_raw_parts(channels.as_ptr() as *const $ident<T>, channels.len() / $channels) }
+    }
+
+    /// Views a mutable slice of channels as a slice of pixels without copying.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the length of ```channels``` is not a multiple of the channel count.
+    pub fn slice_from_channels_mut(channels: &mut [T]) -> &mut [$ident<T>] {
+        let count = <$ident<T> as Pixel>::channel_count() as usize;
+        assert!(channels.len() % count == 0, "the length is not a multiple of the channel count");
+        unsafe { slice::from_raw_parts_mut(channels.as_mut_ptr() as *mut $ident<T>, channels.len() / $channels) }
+    }
+
+    /// Views a slice of pixels as a slice of their channels without copying.
+    pub fn slice_as_channels(pixels: &[$ident<T>]) -> &[T] {
+        unsafe { slice::from_raw_parts(pixels.as_ptr() as *const T, pixels.len() * $channels) }
+    }
+
+    /// Views a mutable slice of pixels as a slice of their channels without copying.
+    pub fn slice_as_channels_mut(pixels: &mut [$ident<T>]) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(pixels.as_mut_ptr() as *mut T, pixels.len() * $channels) }
+    }
+
+    /// Converts a vector of channels into a vector of pixels, reusing its allocation.
+    ///
+    /// The allocation can only be reused if both the length and the capacity of
+    /// ```channels``` are multiples of the channel count, otherwise the vector is
+    /// returned unchanged as the error.
+    pub fn vec_from_channels(mut channels: Vec<T>) -> Result<Vec<$ident<T>>, Vec<T>> {
+        let count = <$ident<T> as Pixel>::channel_count() as usize;
+        if channels.len() % count != 0 || channels.capacity() % count != 0 {
+            return Err(channels)
+        }
+        let (ptr, len, capacity) = (channels.as_mut_ptr(), channels.len(), channels.capacity());
+        mem::forget(channels);
+        Ok(unsafe { Vec::from_raw_parts(ptr as *mut $ident<T>, len / $channels, capacity / $channels) })
+    }
+
+    /// Converts a vector of pixels into a vector of their channels, reusing its allocation.
+    pub fn vec_into_channels(mut pixels: Vec<$ident<T>>) -> Vec<T> {
+        let (ptr, len, capacity) = (pixels.as_mut_ptr(), pixels.len(), pixels.capacity());
+        mem::forget(pixels);
+        unsafe { Vec::from_raw_parts(ptr as *mut T, len * $channels, capacity * $channels) }
+    }
+}
+
 impl<T: Primitive> Index<usize> for $ident<T> {
     type Output = T;
     #[inline(always)]
@@ -488,3 +548,43 @@ impl<T: Primitive> Invert for Rgb<T> {
         *self = Rgb([r1, g1, b1])
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Luma, Rgb, Rgba};
+
+    #[test]
+    fn test_reinterpret_slices() {
+        let mut data = vec![1u8, 2, 3, 4, 5, 6, 7, 8];
+        assert_eq!(Rgba::slice_from_channels(&data), &[Rgba([1, 2, 3, 4]), Rgba([5, 6, 7, 8])]);
+        Rgba::slice_from_channels_mut(&mut data)[1] = Rgba([0; 4]);
+        assert_eq!(data, [1, 2, 3, 4, 0, 0, 0, 0]);
+
+        let mut pixels = [Luma([1u16]), Luma([2])];
+        Luma::slice_as_channels_mut(&mut pixels)[0] = 3;
+        assert_eq!(Luma::slice_as_channels(&pixels), &[3, 2]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_reinterpret_partial_pixel() {
+        Rgb::slice_from_channels(&[1u8, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_reinterpret_vecs() {
+        let mut data = Vec::with_capacity(8);
+        data.extend_from_slice(&[1u8, 2, 3, 4]);
+        let ptr = data.as_ptr();
+        let pixels = Rgba::vec_from_channels(data).unwrap();
+        assert_eq!(pixels, [Rgba([1, 2, 3, 4])]);
+        assert_eq!(pixels.capacity(), 2);
+        let data = Rgba::vec_into_channels(pixels);
+        // The allocation was reused both ways
+        assert_eq!(data.as_ptr(), ptr);
+        assert_eq!(data.capacity(), 8);
+
+        assert!(Rgb::vec_from_channels(vec![1u8, 2]).is_err());
+        assert!(Rgb::vec_from_channels(Vec::<u8>::with_capacity(4)).is_err());
+    }
+}
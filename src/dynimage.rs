@@ -6,7 +6,11 @@ use std::iter;
 #[allow(unused)] // AsciiExt not needed for rust 1.23 and up.
 use std::ascii::AsciiExt;
 use num_iter;
+#[cfg(feature = "rustc-serialize")]
+use rustc_serialize::{Decodable, Decoder, Encodable, Encoder};
 
+#[cfg(feature = "mmap")]
+use memmap::Mmap;
 #[cfg(feature = "pnm")]
 use pnm;
 #[cfg(feature = "ppm")]
@@ -31,9 +35,11 @@ use ico;
 use hdr;
 
 use color;
-use buffer::{ImageBuffer, ConvertBuffer, Pixel, GrayImage, GrayAlphaImage, RgbImage, RgbaImage};
+use buffer::{ImageBuffer, Pixel, GrayImage, GrayAlphaImage, RgbImage, RgbaImage};
 use imageops;
 use image;
+use utils;
+use metadata::{Metadata, Orientation};
 use image:: {
     GenericImage,
     ImageDecoder,
@@ -44,7 +50,7 @@ use image:: {
 use image::DecodingResult::{U8};
 
 /// A Dynamic Image
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub enum DynamicImage {
     /// Each pixel in this image is 8-bit Luma
     ImageLuma8(GrayImage),
@@ -121,30 +127,91 @@ impl DynamicImage {
 
     /// Returns a copy of this image as an RGB image.
     pub fn to_rgb(&self) -> RgbImage {
-        dynamic_map!(*self, ref p -> {
-            p.convert()
-        })
+        let (width, height) = self.dimensions();
+        let data = dynamic_map!(*self, ref p -> utils::to_rgb8(p, color::num_components(self.color())));
+        ImageBuffer::from_raw(width, height, data).unwrap()
     }
 
     /// Returns a copy of this image as an RGBA image.
     pub fn to_rgba(&self) -> RgbaImage {
-        dynamic_map!(*self, ref p -> {
-            p.convert()
-        })
+        let (width, height) = self.dimensions();
+        let data = dynamic_map!(*self, ref p -> utils::to_rgba8(p, color::num_components(self.color())));
+        ImageBuffer::from_raw(width, height, data).unwrap()
+    }
+
+    /// Returns a copy of this image as an RGBA image which is flipped vertically,
+    /// the bottom row of this image is the top row of the copy.
+    ///
+    /// The conversion and the flip are done in a single pass.
+    pub fn to_rgba_flipped(&self) -> RgbaImage {
+        let (width, height) = self.dimensions();
+        let channels = color::num_components(self.color());
+        let data = dynamic_map!(*self, ref p -> utils::to_rgba8_flipped(p, channels, width as usize));
+        ImageBuffer::from_raw(width, height, data).unwrap()
+    }
+
+    /// Returns the pixels of this image as tightly packed 8-bit RGBA for uploading
+    /// to an OpenGL texture.
+    ///
+    /// OpenGL expects the first row of a texture at the bottom of the image, so the
+    /// rows are ordered from the bottom to the top. Each row is ```4 * width``` bytes
+    /// long without padding, which matches the default unpack alignment of 4.
+    pub fn to_gl_texture_data(&self) -> Vec<u8> {
+        self.to_rgba_flipped().into_raw()
     }
 
     /// Returns a copy of this image as a Luma image.
     pub fn to_luma(&self) -> GrayImage {
-        dynamic_map!(*self, ref p -> {
-            p.convert()
-        })
+        let (width, height) = self.dimensions();
+        let data = dynamic_map!(*self, ref p -> utils::to_luma8(p, color::num_components(self.color())));
+        ImageBuffer::from_raw(width, height, data).unwrap()
     }
 
     /// Returns a copy of this image as a LumaA image.
     pub fn to_luma_alpha(&self) -> GrayAlphaImage {
-        dynamic_map!(*self, ref p -> {
-            p.convert()
-        })
+        let (width, height) = self.dimensions();
+        let data = dynamic_map!(*self, ref p -> utils::to_luma_alpha8(p, color::num_components(self.color())));
+        ImageBuffer::from_raw(width, height, data).unwrap()
+    }
+
+    /// Consumes the image and returns an RGB image.
+    ///
+    /// If the image is already in this format the buffer is moved out without copying.
+    pub fn into_rgb(self) -> RgbImage {
+        match self {
+            DynamicImage::ImageRgb8(x) => x,
+            x => x.to_rgb(),
+        }
+    }
+
+    /// Consumes the image and returns an RGBA image.
+    ///
+    /// If the image is already in this format the buffer is moved out without copying.
+    pub fn into_rgba(self) -> RgbaImage {
+        match self {
+            DynamicImage::ImageRgba8(x) => x,
+            x => x.to_rgba(),
+        }
+    }
+
+    /// Consumes the image and returns a Luma image.
+    ///
+    /// If the image is already in this format the buffer is moved out without copying.
+    pub fn into_luma(self) -> GrayImage {
+        match self {
+            DynamicImage::ImageLuma8(x) => x,
+            x => x.to_luma(),
+        }
+    }
+
+    /// Consumes the image and returns a LumaA image.
+    ///
+    /// If the image is already in this format the buffer is moved out without copying.
+    pub fn into_luma_alpha(self) -> GrayAlphaImage {
+        match self {
+            DynamicImage::ImageLumaA8(x) => x,
+            x => x.to_luma_alpha(),
+        }
     }
 
     /// Return a cut out of this image delimited by the bounding rectangle.
@@ -395,7 +462,23 @@ impl DynamicImage {
         dynamic_map!(*self, ref p => imageops::rotate270(p))
     }
 
+    /// Transform this image according to an EXIF orientation, so that an
+    /// image which is stored with the orientation ```orientation``` is upright.
+    pub fn apply_orientation(&self, orientation: Orientation) -> DynamicImage {
+        match orientation {
+            Orientation::Normal => self.clone(),
+            Orientation::FlipHorizontal => self.fliph(),
+            Orientation::Rotate180 => self.rotate180(),
+            Orientation::FlipVertical => self.flipv(),
+            Orientation::Transpose => self.rotate90().fliph(),
+            Orientation::Rotate90 => self.rotate90(),
+            Orientation::Transverse => self.rotate270().fliph(),
+            Orientation::Rotate270 => self.rotate270(),
+        }
+    }
+
     /// Encode this image and write it to ```w```
+    #[allow(unused_variables)] // Unused if none of the encoders is enabled.
     pub fn save<W: Write>(&self, w: &mut W, format: ImageFormat) -> ImageResult<()> {
         let bytes = self.raw_pixels();
         let (width, height) = self.dimensions();
@@ -418,6 +501,14 @@ impl DynamicImage {
                 Ok(())
             }
 
+            #[cfg(feature = "pnm")]
+            ImageFormat::PNM => {
+                let mut p = pnm::PAMEncoder::new(w);
+
+                try!(p.encode(&bytes, width, height, color));
+                Ok(())
+            }
+
             #[cfg(feature = "jpeg")]
             image::ImageFormat::JPEG => {
                 let mut j = jpeg::JPEGEncoder::new(w);
@@ -460,6 +551,44 @@ impl DynamicImage {
     }
 }
 
+#[cfg(feature = "rustc-serialize")]
+const VARIANTS: [&str; 4] = ["ImageLuma8", "ImageLumaA8", "ImageRgb8", "ImageRgba8"];
+
+/// Encodes the variant and the image buffer of the image.
+#[cfg(feature = "rustc-serialize")]
+impl Encodable for DynamicImage {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        let index = match *self {
+            DynamicImage::ImageLuma8(_) => 0,
+            DynamicImage::ImageLumaA8(_) => 1,
+            DynamicImage::ImageRgb8(_) => 2,
+            DynamicImage::ImageRgba8(_) => 3,
+        };
+        s.emit_enum("DynamicImage", |s| {
+            s.emit_enum_variant(VARIANTS[index], index, 1, |s| {
+                s.emit_enum_variant_arg(0, |s| dynamic_map!(*self, ref p -> p.encode(s)))
+            })
+        })
+    }
+}
+
+#[cfg(feature = "rustc-serialize")]
+impl Decodable for DynamicImage {
+    fn decode<D: Decoder>(d: &mut D) -> Result<DynamicImage, D::Error> {
+        d.read_enum("DynamicImage", |d| {
+            d.read_enum_variant(&VARIANTS, |d, index| {
+                d.read_enum_variant_arg(0, |d| Ok(match index {
+                    0 => DynamicImage::ImageLuma8(Decodable::decode(d)?),
+                    1 => DynamicImage::ImageLumaA8(Decodable::decode(d)?),
+                    2 => DynamicImage::ImageRgb8(Decodable::decode(d)?),
+                    3 => DynamicImage::ImageRgba8(Decodable::decode(d)?),
+                    _ => return Err(d.error("unknown DynamicImage variant")),
+                }))
+            })
+        })
+    }
+}
+
 #[allow(deprecated)]
 impl GenericImage for DynamicImage {
     type Pixel = color::Rgba<u8>;
@@ -591,10 +720,110 @@ fn open_impl(path: &Path) -> ImageResult<DynamicImage> {
     };
     let fin = BufReader::new(fin);
 
+    load(fin, format_from_path(path)?)
+}
+
+/// Open the image located at the path specified and rotate or flip it upright
+/// according to the orientation stored in its EXIF metadata.
+/// The image's format is determined from the path's file extension.
+///
+/// Images without an orientation, or with EXIF data that can not be parsed,
+/// are returned as stored.
+pub fn open_with_orientation<P>(path: P) -> ImageResult<DynamicImage> where P: AsRef<Path> {
+    // thin wrapper function to strip generics before calling open_with_orientation_impl
+    open_with_orientation_impl(path.as_ref())
+}
+
+fn open_with_orientation_impl(path: &Path) -> ImageResult<DynamicImage> {
+    let fin = BufReader::new(File::open(path)?);
+    let (image, metadata) = load_with_metadata(fin, format_from_path(path)?)?;
+
+    Ok(match metadata.orientation {
+        Some(orientation) => image.apply_orientation(orientation),
+        None => image,
+    })
+}
+
+/// Open the image located at the path specified as a thumbnail which fits into
+/// ```width``` x ```height``` pixels, keeping the aspect ratio.
+/// The image's format is determined from the path's file extension.
+///
+/// Formats which support it are decoded at a reduced resolution, which is much
+/// faster than decoding the full image and resizing it. Images that are already
+/// small enough are returned as stored.
+pub fn open_thumbnail<P>(path: P, width: u32, height: u32) -> ImageResult<DynamicImage> where P: AsRef<Path> {
+    // thin wrapper function to strip generics before calling open_thumbnail_impl
+    open_thumbnail_impl(path.as_ref(), width, height)
+}
+
+fn open_thumbnail_impl(path: &Path, width: u32, height: u32) -> ImageResult<DynamicImage> {
+    let fin = BufReader::new(File::open(path)?);
+    let image = load_with_target_dimensions(fin, format_from_path(path)?, width, height)?;
+
+    let (w, h) = image.dimensions();
+    if w > width || h > height {
+        Ok(image.resize(width, height, imageops::FilterType::Triangle))
+    } else {
+        Ok(image)
+    }
+}
+
+/// Open the JPEG thumbnail which cameras embed in the EXIF metadata of the image located
+/// at the path specified.
+/// The image's format is determined from the path's file extension.
+///
+/// Only the metadata of the image is read, which makes this much faster than decoding
+/// the full image, e.g. for gallery views. Returns `None` if the image contains no
+/// thumbnail. The thumbnail is returned as stored, the orientation in the metadata of
+/// the image usually applies to it as well.
+pub fn open_exif_thumbnail<P>(path: P) -> ImageResult<Option<DynamicImage>> where P: AsRef<Path> {
+    // thin wrapper function to strip generics before calling open_exif_thumbnail_impl
+    open_exif_thumbnail_impl(path.as_ref())
+}
+
+fn open_exif_thumbnail_impl(path: &Path) -> ImageResult<Option<DynamicImage>> {
+    let fin = BufReader::new(File::open(path)?);
+    let metadata = read_metadata(fin, format_from_path(path)?)?;
+    match metadata.exif_thumbnail()? {
+        Some(thumbnail) => Ok(Some(load_from_memory_with_format(thumbnail, ImageFormat::JPEG)?)),
+        None => Ok(None),
+    }
+}
+
+/// Open the image located at the path specified by memory-mapping the file.
+/// The image's format is determined from the path's file extension.
+///
+/// The decoder reads directly from the mapped region instead of copying the
+/// file through a buffered reader, so the operating system can page the data
+/// in on demand. This is mostly useful for very large files.
+///
+/// The file must not be modified by another process while it is decoded,
+/// otherwise the result is undefined.
+#[cfg(feature = "mmap")]
+pub fn open_mmap<P>(path: P) -> ImageResult<DynamicImage> where P: AsRef<Path> {
+    // thin wrapper function to strip generics before calling open_mmap_impl
+    open_mmap_impl(path.as_ref())
+}
+
+#[cfg(feature = "mmap")]
+fn open_mmap_impl(path: &Path) -> ImageResult<DynamicImage> {
+    let format = format_from_path(path)?;
+    let fin = File::open(path)?;
+
+    // Empty files can not be mapped, let the decoder report them.
+    if fin.metadata()?.len() == 0 {
+        return load_from_memory_with_format(&[], format);
+    }
+
+    let map = unsafe { Mmap::map(&fin)? };
+    load_from_memory_with_format(&map, format)
+}
+
+fn format_from_path(path: &Path) -> ImageResult<ImageFormat> {
     let ext = path.extension().and_then(|s| s.to_str())
                   .map_or("".to_string(), |s| s.to_ascii_lowercase());
 
-    let format = match &ext[..] {
+    Ok(match &ext[..] {
         "jpg" |
         "jpeg" => image::ImageFormat::JPEG,
         "png"  => image::ImageFormat::PNG,
@@ -604,7 +833,8 @@ fn open_impl(path: &Path) -> ImageResult<DynamicImage> {
         "tiff" => image::ImageFormat::TIFF,
         "tga" => image::ImageFormat::TGA,
         "bmp" => image::ImageFormat::BMP,
-        "ico" => image::ImageFormat::ICO,
+        "ico" |
+        "cur" => image::ImageFormat::ICO,
         "hdr" => image::ImageFormat::HDR,
         "pbm" |
         "pam" |
@@ -617,9 +847,7 @@ fn open_impl(path: &Path) -> ImageResult<DynamicImage> {
             "Image format image/{:?} is not supported.",
             format
         )))
-    };
-
-    load(fin, format)
+    })
 }
 
 /// Saves the supplied buffer to a file at the path specified.
@@ -635,6 +863,7 @@ pub fn save_buffer<P>(path: P, buf: &[u8], width: u32, height: u32, color: color
     save_buffer_impl(path.as_ref(), buf, width, height, color)
 }
 
+#[allow(unused_variables)] // Unused if none of the encoders is enabled.
 fn save_buffer_impl(path: &Path, buf: &[u8], width: u32, height: u32, color: color::ColorType)
                       -> io::Result<()> {
     let fout = &mut BufWriter::new(try!(File::create(path)));
@@ -644,6 +873,8 @@ fn save_buffer_impl(path: &Path, buf: &[u8], width: u32, height: u32, color: col
     match &*ext {
         #[cfg(feature = "ico")]
         "ico" => ico::ICOEncoder::new(fout).encode(buf, width, height, color),
+        #[cfg(feature = "ico")]
+        "cur" => ico::ICOEncoder::new(fout).encode_cursor(buf, width, height, color, (0, 0)),
         #[cfg(feature = "jpeg")]
         "jpg" |
         "jpeg" => jpeg::JPEGEncoder::new(fout).encode(buf, width, height, color),
@@ -651,6 +882,8 @@ fn save_buffer_impl(path: &Path, buf: &[u8], width: u32, height: u32, color: col
         "png"  => png::PNGEncoder::new(fout).encode(buf, width, height, color),
         #[cfg(feature = "ppm")]
         "ppm"  => ppm::PPMEncoder::new(fout).encode(buf, width, height, color),
+        #[cfg(feature = "pnm")]
+        "pam"  => pnm::PAMEncoder::new(fout).encode(buf, width, height, color),
         #[cfg(feature = "bmp")]
         "bmp" => bmp::BMPEncoder::new(fout).encode(buf, width, height, color),
         format => Err(io::Error::new(
@@ -661,6 +894,7 @@ fn save_buffer_impl(path: &Path, buf: &[u8], width: u32, height: u32, color: col
 }
 
 /// Create a new image from a Reader
+#[allow(unused_variables)] // Unused if none of the decoders is enabled.
 pub fn load<R: BufRead+Seek>(r: R, format: ImageFormat) -> ImageResult<DynamicImage> {
     #[allow(deprecated, unreachable_patterns)] // Default is unreachable if all features are supported.
     match format {
@@ -690,7 +924,59 @@ pub fn load<R: BufRead+Seek>(r: R, format: ImageFormat) -> ImageResult<DynamicIm
     }
 }
 
-static MAGIC_BYTES: [(&'static [u8], ImageFormat); 17] = [
+// Like ```load```, but also returns the metadata of formats which support it
+fn load_with_metadata<R: BufRead+Seek>(r: R, format: ImageFormat) -> ImageResult<(DynamicImage, Metadata)> {
+    #[cfg(any(feature = "jpeg", feature = "tiff"))]
+    fn decode<I: ImageDecoder>(mut decoder: I) -> ImageResult<(DynamicImage, Metadata)> {
+        let metadata = decoder.metadata().unwrap_or_default();
+        Ok((decoder_to_image(decoder)?, metadata))
+    }
+
+    #[allow(unreachable_patterns)]
+    match format {
+        #[cfg(feature = "jpeg")]
+        ImageFormat::JPEG => decode(jpeg::JPEGDecoder::new(r)),
+        #[cfg(feature = "tiff")]
+        ImageFormat::TIFF => decode(tiff::TIFFDecoder::new(r)?),
+        _ => Ok((load(r, format)?, Metadata::default())),
+    }
+}
+
+// Reads the metadata of an image without decoding its pixels
+#[allow(unused_variables)] // Unused if no decoder with metadata support is enabled.
+fn read_metadata<R: BufRead+Seek>(r: R, format: ImageFormat) -> ImageResult<Metadata> {
+    #[allow(unreachable_patterns)]
+    match format {
+        #[cfg(feature = "jpeg")]
+        ImageFormat::JPEG => jpeg::JPEGDecoder::new(r).metadata(),
+        #[cfg(feature = "tiff")]
+        ImageFormat::TIFF => tiff::TIFFDecoder::new(r)?.metadata(),
+        _ => Ok(Metadata::default()),
+    }
+}
+
+// Like ```load```, but decodes formats which support it at a reduced resolution, see
+// ```ImageDecoder::set_target_dimensions```
+#[allow(unused_variables)] // Unused if neither the JPEG nor the TIFF decoder is enabled.
+fn load_with_target_dimensions<R: BufRead+Seek>(r: R, format: ImageFormat, width: u32, height: u32)
+                                                -> ImageResult<DynamicImage> {
+    #[cfg(any(feature = "jpeg", feature = "tiff"))]
+    fn decode<I: ImageDecoder>(mut decoder: I, width: u32, height: u32) -> ImageResult<DynamicImage> {
+        decoder.set_target_dimensions(width, height)?;
+        decoder_to_image(decoder)
+    }
+
+    #[allow(unreachable_patterns)]
+    match format {
+        #[cfg(feature = "jpeg")]
+        ImageFormat::JPEG => decode(jpeg::JPEGDecoder::new(r), width, height),
+        #[cfg(feature = "tiff")]
+        ImageFormat::TIFF => decode(tiff::TIFFDecoder::new(r)?, width, height),
+        _ => load(r, format),
+    }
+}
+
+static MAGIC_BYTES: [(&'static [u8], ImageFormat); 18] = [
     (b"\x89PNG\r\n\x1a\n", ImageFormat::PNG),
     (&[0xff, 0xd8, 0xff], ImageFormat::JPEG),
     (b"GIF89a", ImageFormat::GIF),
@@ -700,6 +986,7 @@ static MAGIC_BYTES: [(&'static [u8], ImageFormat); 17] = [
     (b"II*.", ImageFormat::TIFF),
     (b"BM", ImageFormat::BMP),
     (&[0, 0, 1, 0], ImageFormat::ICO),
+    (&[0, 0, 2, 0], ImageFormat::ICO),
     (b"#?RADIANCE", ImageFormat::HDR),
     (b"P1", ImageFormat::PNM),
     (b"P2", ImageFormat::PNM),
@@ -766,4 +1053,225 @@ mod test {
     fn test_empty_file() {
         assert!(super::load_from_memory(b"").is_err());
     }
+
+    #[test]
+    fn test_borrow_and_into() {
+        let mut image = super::DynamicImage::new_rgb8(2, 2);
+        assert!(image.as_rgba8().is_none());
+        image.as_mut_rgb8().unwrap().put_pixel(1, 1, ::Rgb([1, 2, 3]));
+        let ptr = image.as_rgb8().unwrap().as_ptr();
+        let rgb = image.into_rgb();
+        // No copy was made, the buffer was moved out of the enum
+        assert_eq!(rgb.as_ptr(), ptr);
+        assert_eq!(rgb[(1, 1)], ::Rgb([1, 2, 3]));
+        let rgba = super::DynamicImage::ImageRgb8(rgb).into_rgba();
+        assert_eq!(rgba[(1, 1)], ::Rgba([1, 2, 3, 255]));
+    }
+
+    #[test]
+    fn test_conversions_match_from_color() {
+        use buffer::{ConvertBuffer, GrayAlphaImage, GrayImage, RgbImage, RgbaImage};
+        use super::DynamicImage;
+
+        let rgba = ::ImageBuffer::from_fn(16, 16, |x, y| {
+            ::Rgba([(x * 16) as u8, (y * 16 + x) as u8, (255 - x * y) as u8, (x * 7 + y) as u8])
+        });
+        let images = [
+            DynamicImage::ImageRgba8(rgba.clone()),
+            DynamicImage::ImageRgb8(ConvertBuffer::convert(&rgba)),
+            DynamicImage::ImageLumaA8(ConvertBuffer::convert(&rgba)),
+            DynamicImage::ImageLuma8(ConvertBuffer::convert(&rgba)),
+        ];
+        for image in &images {
+            let (rgb, rgba, luma, luma_alpha): (RgbImage, RgbaImage, GrayImage, GrayAlphaImage) =
+                match *image {
+                    DynamicImage::ImageRgba8(ref p) => (ConvertBuffer::convert(p), ConvertBuffer::convert(p),
+                        ConvertBuffer::convert(p), ConvertBuffer::convert(p)),
+                    DynamicImage::ImageRgb8(ref p) => (ConvertBuffer::convert(p), ConvertBuffer::convert(p),
+                        ConvertBuffer::convert(p), ConvertBuffer::convert(p)),
+                    DynamicImage::ImageLumaA8(ref p) => (ConvertBuffer::convert(p), ConvertBuffer::convert(p),
+                        ConvertBuffer::convert(p), ConvertBuffer::convert(p)),
+                    DynamicImage::ImageLuma8(ref p) => (ConvertBuffer::convert(p), ConvertBuffer::convert(p),
+                        ConvertBuffer::convert(p), ConvertBuffer::convert(p)),
+                };
+            assert_eq!(image.to_rgb().into_raw(), rgb.into_raw());
+            assert_eq!(image.to_rgba().into_raw(), rgba.into_raw());
+            assert_eq!(image.to_luma().into_raw(), luma.into_raw());
+            assert_eq!(image.to_luma_alpha().into_raw(), luma_alpha.into_raw());
+        }
+    }
+
+    #[test]
+    fn test_to_rgba_flipped() {
+        use super::DynamicImage;
+
+        let rgb = ::ImageBuffer::from_fn(3, 2, |x, y| ::Rgb([x as u8, y as u8, 7]));
+        let images = [
+            DynamicImage::ImageRgb8(rgb),
+            DynamicImage::ImageLuma8(::ImageBuffer::from_fn(3, 2, |x, y| ::Luma([(y * 3 + x) as u8]))),
+        ];
+        for image in &images {
+            let flipped = image.to_rgba_flipped();
+            assert_eq!(flipped.into_raw(), image.flipv().to_rgba().into_raw());
+        }
+        let data = images[0].to_gl_texture_data();
+        assert_eq!(data.len(), 3 * 2 * 4);
+        // The bottom row comes first
+        assert_eq!(&data[..4], &[0, 1, 7, 255]);
+        assert_eq!(&data[12..16], &[0, 0, 7, 255]);
+
+        assert!(DynamicImage::new_rgb8(0, 5).to_gl_texture_data().is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "rustc-serialize")]
+    fn test_serialize() {
+        use rustc_serialize::json;
+        use super::DynamicImage;
+
+        let rgb = ::ImageBuffer::from_fn(3, 2, |x, y| ::Rgb([x as u8, y as u8, 7]));
+        let image = DynamicImage::ImageRgb8(rgb.clone());
+        let decoded: DynamicImage = json::decode(&json::encode(&image).unwrap()).unwrap();
+        assert_eq!(decoded.as_rgb8().unwrap().clone().into_raw(), rgb.into_raw());
+
+        let luma: ::GrayImage = json::decode(r#"{"width":2,"height":1,"data":[1,2]}"#).unwrap();
+        assert_eq!(luma[(1, 0)], ::Luma([2]));
+        // The data does not match the dimensions
+        assert!(json::decode::<::GrayImage>(r#"{"width":2,"height":2,"data":[1,2]}"#).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "pnm")]
+    fn test_save_pam() {
+        use image::ImageFormat;
+        use super::DynamicImage;
+
+        let rgba = ::ImageBuffer::from_fn(3, 2, |x, y| ::Rgba([x as u8, y as u8, 7, (x * y) as u8]));
+        let mut pam = Vec::new();
+        DynamicImage::ImageRgba8(rgba.clone()).save(&mut pam, ImageFormat::PNM).unwrap();
+        let decoded = super::load_from_memory(&pam).unwrap();
+        assert_eq!(decoded.as_rgba8().unwrap().clone().into_raw(), rgba.into_raw());
+    }
+
+    #[test]
+    #[cfg(feature = "jpeg")]
+    fn test_open_thumbnail() {
+        use image::GenericImage;
+
+        let path = "tests/images/jpg/progressive/cat.jpg";
+        let (width, height) = super::open(path).unwrap().dimensions();
+        let thumbnail = super::open_thumbnail(path, width / 5, height / 5).unwrap();
+        assert_eq!(thumbnail.dimensions(), (width / 5, height / 5));
+        let full = super::open_thumbnail(path, width * 2, height * 2).unwrap();
+        assert_eq!(full.dimensions(), (width, height));
+    }
+
+    #[test]
+    #[cfg(feature = "jpeg")]
+    fn test_open_exif_thumbnail() {
+        use image::GenericImage;
+
+        let path = "tests/images/jpg/progressive/cat.jpg";
+        let thumbnail = super::open_exif_thumbnail(path).unwrap().expect("no thumbnail");
+        let (width, height) = super::open(path).unwrap().dimensions();
+        let (t_width, t_height) = thumbnail.dimensions();
+        assert!(t_width < width && t_height < height);
+        // The thumbnail has about the same aspect ratio as the image
+        let ratio = |w: u32, h: u32| w as f64 / h as f64;
+        assert!((ratio(t_width, t_height) - ratio(width, height)).abs() < 0.1);
+
+        assert!(super::open_exif_thumbnail("tests/images/jpg/progressive/3.jpg").unwrap().is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "jpeg")]
+    fn test_exif_thumbnail_of_large_image() {
+        use std::cell::Cell;
+        use std::cmp;
+        use std::fs::File;
+        use std::io::{self, BufRead, Cursor, Read, Seek, SeekFrom};
+        use image::ImageFormat;
+
+        // Records how far the image has been read
+        struct Tracked<'a> {
+            inner: Cursor<&'a [u8]>,
+            end: &'a Cell<u64>,
+        }
+
+        impl<'a> Read for Tracked<'a> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let n = self.inner.read(buf)?;
+                self.end.set(cmp::max(self.end.get(), self.inner.position()));
+                Ok(n)
+            }
+        }
+
+        impl<'a> BufRead for Tracked<'a> {
+            fn fill_buf(&mut self) -> io::Result<&[u8]> {
+                self.inner.fill_buf()
+            }
+
+            fn consume(&mut self, amt: usize) {
+                self.inner.consume(amt);
+                self.end.set(cmp::max(self.end.get(), self.inner.position()));
+            }
+        }
+
+        impl<'a> Seek for Tracked<'a> {
+            fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+                self.inner.seek(pos)
+            }
+        }
+
+        // A progressive image of 4000x3000 pixels followed by 1 MB of data
+        let mut data = Vec::new();
+        File::open("tests/images/jpg/progressive/cat.jpg").unwrap().read_to_end(&mut data).unwrap();
+        let sof = data.windows(2).position(|w| w == [0xFF, 0xC2]).unwrap();
+        data[sof + 5..sof + 9].copy_from_slice(&[0x0B, 0xB8, 0x0F, 0xA0]);
+        data.extend_from_slice(&[0; 1 << 20]);
+
+        let end = Cell::new(0);
+        let metadata = super::read_metadata(Tracked { inner: Cursor::new(&data), end: &end }, ImageFormat::JPEG).unwrap();
+        assert!(metadata.exif_thumbnail().unwrap().is_some());
+        // Only the segments and the data which backs the frame are read
+        assert!(end.get() < 1 << 16, "{}", end.get());
+    }
+
+    #[test]
+    fn test_apply_orientation() {
+        use metadata::Orientation;
+        use super::DynamicImage;
+
+        // A 3x2 image with distinct pixels, displayed upright it is
+        // 0 1 2
+        // 3 4 5
+        let upright = ::ImageBuffer::from_fn(3, 2, |x, y| ::Luma([(y * 3 + x) as u8]));
+        // Each orientation as stored, rows from top to bottom
+        let stored: [(Orientation, (u32, u32), &[u8]); 8] = [
+            (Orientation::Normal, (3, 2), &[0, 1, 2, 3, 4, 5]),
+            (Orientation::FlipHorizontal, (3, 2), &[2, 1, 0, 5, 4, 3]),
+            (Orientation::Rotate180, (3, 2), &[5, 4, 3, 2, 1, 0]),
+            (Orientation::FlipVertical, (3, 2), &[3, 4, 5, 0, 1, 2]),
+            (Orientation::Transpose, (2, 3), &[0, 3, 1, 4, 2, 5]),
+            (Orientation::Rotate90, (2, 3), &[2, 5, 1, 4, 0, 3]),
+            (Orientation::Transverse, (2, 3), &[5, 2, 4, 1, 3, 0]),
+            (Orientation::Rotate270, (2, 3), &[3, 0, 4, 1, 5, 2]),
+        ];
+        for &(orientation, (width, height), pixels) in &stored {
+            let image = ::ImageBuffer::from_raw(width, height, pixels.to_vec()).unwrap();
+            let image = DynamicImage::ImageLuma8(image).apply_orientation(orientation);
+            assert_eq!(image.to_luma().into_raw(), upright.clone().into_raw(), "{:?}", orientation);
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "mmap", feature = "png_codec", feature = "tiff"))]
+    fn test_open_mmap() {
+        for path in &["tests/images/png/interlaced/lenna_fragment_interlaced.png", "tests/images/tiff/testsuite/lenna.tiff"] {
+            let mapped = super::open_mmap(path).unwrap();
+            let read = super::open(path).unwrap();
+            assert_eq!(mapped.raw_pixels(), read.raw_pixels());
+        }
+        assert!(super::open_mmap("tests/images/does_not_exist.png").is_err());
+    }
 }
@@ -8,12 +8,22 @@
 
 extern crate gif;
 
-use std::io::{Read, Write};
+use std::cmp;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use num_rational::Ratio;
 
 pub use self::gif::Frame;
-use self::gif::{SetParameter, ColorOutput};
+use self::gif::{SetParameter, ColorOutput, Repeat};
 
-use image::{ImageError, ImageResult, DecodingResult, ImageDecoder};
+use animation::{self, AnimationEncoder, LoopCount};
+use buffer::{ImageBuffer, RgbaImage};
+use color::Rgba;
+use image::{GenericImage, ImageError, ImageFormat, ImageResult, DecodingResult, ImageDecoder};
+use indexed::IndexedImage;
 use color;
 
 enum Either<T, U> {
@@ -21,23 +31,49 @@ enum Either<T, U> {
     Right(U)
 }
 
+type GifDecoder<R> = gif::Decoder<LoopScanner<R>>;
+type GifReader<R> = gif::Reader<LoopScanner<R>>;
+
 /// GIF decoder
 pub struct Decoder<R: Read> {
-    inner: Option<Either<gif::Decoder<R>, gif::Reader<R>>>
+    inner: Option<Either<GifDecoder<R>, GifReader<R>>>,
+    loop_extension: Arc<AtomicUsize>,
 }
 
 impl<R: Read> Decoder<R> {
     /// Creates a new decoder that decodes the input steam ```r```
     pub fn new(r: R) -> Decoder<R> {
-        let mut decoder = gif::Decoder::new(r);
+        let loop_extension = Arc::new(AtomicUsize::new(0));
+        let mut decoder = gif::Decoder::new(LoopScanner {
+            inner: r,
+            scan: Some(ExtensionScan::new()),
+            found: loop_extension.clone(),
+        });
         decoder.set(ColorOutput::RGBA);
         Decoder {
-            inner: Some(Either::Left(decoder))
+            inner: Some(Either::Left(decoder)),
+            loop_extension,
         }
     }
 
+    /// Returns how often the animation should be played.
+    ///
+    /// This is read from the NETSCAPE2.0 application extension. A GIF without this
+    /// extension is played once. The extension precedes the first frame so the
+    /// value is known after the header has been read; it has to be queried before
+    /// the decoder is consumed by `into_frames`.
+    pub fn loop_count(&mut self) -> ImageResult<LoopCount> {
+        self.get_reader()?;
+        Ok(match self.loop_extension.load(Ordering::SeqCst) {
+            0 => LoopCount::Finite(1),
+            // The extension stores the number of repetitions after the first play
+            1 => LoopCount::Infinite,
+            n => LoopCount::Finite(n as u32),
+        })
+    }
+
     // Converts the inner decoder to a reader
-    fn get_reader(&mut self) -> Result<&mut gif::Reader<R>, gif::DecodingError> {
+    fn get_reader(&mut self) -> Result<&mut GifReader<R>, gif::DecodingError> {
         let inner = self.inner.take().unwrap();
         self.inner = Some(match inner {
             Either::Left(decoder) => {
@@ -86,27 +122,558 @@ impl<R: Read> ImageDecoder for Decoder<R> {
             Err(ImageError::ImageEnd)
         }
     }
+
+    fn read_image_into(&mut self, buf: &mut [u8]) -> ImageResult<()> {
+        let reader = self.get_reader()?;
+        if reader.next_frame_info()?.is_none() {
+            return Err(ImageError::ImageEnd)
+        }
+        if buf.len() != reader.buffer_size() {
+            return Err(ImageError::DimensionError)
+        }
+        reader.read_into_buffer(buf)?;
+        Ok(())
+    }
+
+    fn is_animated(&mut self) -> ImageResult<bool> {
+        // The number of frames is not stored in the header, the GIF
+        // has to be treated as potentially animated.
+        Ok(true)
+    }
+
+    /// Returns the fully composited frames of the animation.
+    ///
+    /// Each frame has the size of the logical screen and shows the image as it
+    /// is to be displayed, i.e. the partial frames stored in the GIF are drawn over
+    /// the previous content and the disposal method of each frame is applied.
+    /// Use `into_raw_frames` to get the partial frames instead.
+    fn into_frames<'a>(self) -> ImageResult<animation::Frames<'a>> where Self: 'a {
+        let frames = self.raw_frame_iterator()?;
+        let (width, height) = (u32::from(frames.reader.width()), u32::from(frames.reader.height()));
+        Ok(animation::Frames::new(Box::new(animation::Compositor::new(frames, width, height))))
+    }
+}
+
+impl<R: Read> Decoder<R> {
+    /// Returns the frames as they are stored in the GIF.
+    ///
+    /// The frames may only cover parts of the logical screen and may contain transparent
+    /// pixels for areas that do not change. Their offset, disposal and blend methods
+    /// describe how they have to be composited.
+    pub fn into_raw_frames<'a>(self) -> ImageResult<animation::Frames<'a>> where Self: 'a {
+        Ok(animation::Frames::new(Box::new(self.raw_frame_iterator()?)))
+    }
+
+    /// Decodes the first frame to the palette indices of its pixels and its palette,
+    /// instead of expanding the palette to RGBA.
+    ///
+    /// The local palette of the frame is returned if it has one, otherwise the global
+    /// palette. The transparent color of the frame has an alpha value of 0. Returns an
+    /// ```UnsupportedError``` if the header was already read, e.g. by ```dimensions```.
+    pub fn read_indexed(mut self) -> ImageResult<IndexedImage> {
+        let mut decoder = match self.inner.take() {
+            Some(Either::Left(decoder)) => decoder,
+            _ => return Err(ImageError::UnsupportedError(
+                "The palette indices have to be read before the header".to_string()
+            ))
+        };
+        decoder.set(ColorOutput::Indexed);
+        let mut reader = decoder.read_info()?;
+        let (width, height, transparent, palette) = match reader.next_frame_info()? {
+            Some(frame) => (frame.width, frame.height, frame.transparent, frame.palette.clone()),
+            None => return Err(ImageError::ImageEnd)
+        };
+        let mut indices = vec![0; reader.buffer_size()];
+        reader.read_into_buffer(&mut indices)?;
+
+        let palette = match palette {
+            Some(palette) => palette,
+            None => reader.global_palette().map(|palette| palette.to_vec()).unwrap_or_default(),
+        };
+        let palette = (0..palette.len() / 3).map(|i| {
+            let alpha = if transparent == Some(i as u8) { 0 } else { 255 };
+            Rgba([palette[3 * i], palette[3 * i + 1], palette[3 * i + 2], alpha])
+        }).collect();
+        Ok(IndexedImage {
+            width: u32::from(width),
+            height: u32::from(height),
+            indices,
+            palette,
+        })
+    }
+
+    fn raw_frame_iterator(mut self) -> ImageResult<GifFrameIterator<R>> {
+        self.get_reader()?;
+        let reader = match self.inner {
+            Some(Either::Right(reader)) => reader,
+            _ => unreachable!()
+        };
+        Ok(GifFrameIterator {
+            reader,
+            done: false,
+        })
+    }
+}
+
+// The application identifiers of the extension that controls looping, the two bytes
+// following it contain the loop count in little endian. `ANIMEXTS1.0` is an alias used
+// by some older encoders.
+const LOOP_EXTENSIONS: [&[u8]; 2] = [b"NETSCAPE2.0", b"ANIMEXTS1.0"];
+
+// Watches the data passed to the GIF decoder for the looping extension, which the
+// underlying decoder skips. The result is stored as `loop count + 1` in `found`,
+// 0 meaning that the extension was not found (yet).
+struct LoopScanner<R> {
+    inner: R,
+    // The scan of the blocks in front of the first image, until it is done
+    scan: Option<ExtensionScan>,
+    found: Arc<AtomicUsize>,
+}
+
+impl<R: Read> Read for LoopScanner<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            let result = match self.scan {
+                Some(ref mut scan) => scan.push(&buf[..n]),
+                None => return Ok(n),
+            };
+            match result {
+                LoopScan::Incomplete => return Ok(n),
+                LoopScan::Found(count) => self.found.store(usize::from(count) + 1, Ordering::SeqCst),
+                LoopScan::Missing => (),
+            }
+            self.scan = None;
+        }
+        Ok(n)
+    }
+}
+
+enum LoopScan {
+    // More data is needed
+    Incomplete,
+    Found(u16),
+    // The first image, the end of the file or invalid data was reached
+    Missing,
+}
+
+#[derive(Clone, Copy)]
+enum ScanStep {
+    // The header and the logical screen descriptor
+    Header,
+    // The introducer and the label of the next block
+    Extension,
+    // The first sub-block of an application extension
+    Application,
+    // The size of the next sub-block
+    SubBlock,
+}
+
+// Walks the blocks in front of the first image and looks for the looping extension.
+// The data is passed in as it is read, only the bytes of an unfinished step are kept.
+struct ExtensionScan {
+    step: ScanStep,
+    // The number of bytes to skip, e.g. of the global color table or of a sub-block
+    skip: usize,
+    pending: Vec<u8>,
+}
+
+impl ExtensionScan {
+    fn new() -> ExtensionScan {
+        ExtensionScan {
+            step: ScanStep::Header,
+            skip: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, data: &[u8]) -> LoopScan {
+        self.pending.extend_from_slice(data);
+        let mut pos = 0;
+        let result = loop {
+            let data = &self.pending[pos..];
+            if self.skip > 0 {
+                let skipped = cmp::min(self.skip, data.len());
+                if skipped == 0 {
+                    break LoopScan::Incomplete
+                }
+                self.skip -= skipped;
+                pos += skipped;
+                continue
+            }
+            match self.step {
+                ScanStep::Header => {
+                    if data.len() < 13 {
+                        break LoopScan::Incomplete
+                    }
+                    self.skip = 13;
+                    if data[10] & 0x80 != 0 {
+                        // The global color table
+                        self.skip += 3 << ((data[10] & 0x07) + 1);
+                    }
+                    self.step = ScanStep::Extension;
+                },
+                ScanStep::Extension => {
+                    match data.first() {
+                        None => break LoopScan::Incomplete,
+                        Some(&0x21) => (),
+                        _ => break LoopScan::Missing,
+                    }
+                    self.step = match data.get(1) {
+                        None => break LoopScan::Incomplete,
+                        Some(&0xFF) => ScanStep::Application,
+                        Some(_) => ScanStep::SubBlock,
+                    };
+                    self.skip = 2;
+                },
+                ScanStep::Application => {
+                    // An 11 byte identifier, followed by the sub-block 1 with the loop count
+                    if data.len() < 16 {
+                        break LoopScan::Incomplete
+                    }
+                    if data[0] == 11 && data[12] == 3 && data[13] == 1
+                        && LOOP_EXTENSIONS.contains(&&data[1..12]) {
+                        break LoopScan::Found(u16::from(data[14]) | u16::from(data[15]) << 8)
+                    }
+                    self.step = ScanStep::SubBlock;
+                },
+                ScanStep::SubBlock => match data.first() {
+                    None => break LoopScan::Incomplete,
+                    Some(&0) => {
+                        self.skip = 1;
+                        self.step = ScanStep::Extension;
+                    },
+                    Some(&size) => self.skip = 1 + usize::from(size),
+                },
+            }
+        };
+        self.pending.drain(..pos);
+        result
+    }
+}
+
+// Decodes the frames of a GIF on demand
+struct GifFrameIterator<R: Read> {
+    reader: GifReader<R>,
+    done: bool,
+}
+
+impl<R: Read> Iterator for GifFrameIterator<R> {
+    type Item = ImageResult<animation::Frame>;
+
+    fn next(&mut self) -> Option<ImageResult<animation::Frame>> {
+        if self.done {
+            return None
+        }
+        let frame = match self.reader.read_next_frame() {
+            Ok(Some(frame)) => frame,
+            Ok(None) => {
+                self.done = true;
+                return None
+            },
+            Err(err) => {
+                self.done = true;
+                return Some(Err(err.into()))
+            }
+        };
+        // GIF delays are stored in units of 1/100 s
+        let delay = Ratio::new(frame.delay, 100);
+        let buffer = ImageBuffer::from_raw(
+            u32::from(frame.width),
+            u32::from(frame.height),
+            frame.buffer.clone().into_owned()
+        );
+        let disposal = match frame.dispose {
+            gif::DisposalMethod::Any | gif::DisposalMethod::Keep => animation::DisposalMethod::Keep,
+            gif::DisposalMethod::Background => animation::DisposalMethod::Background,
+            gif::DisposalMethod::Previous => animation::DisposalMethod::Previous,
+        };
+        Some(match buffer {
+            Some(buffer) => {
+                let mut frame = animation::Frame::from_parts(
+                    buffer, u32::from(frame.left), u32::from(frame.top), delay
+                );
+                frame.set_disposal(disposal);
+                // GIF frames are always drawn over the canvas, transparent pixels
+                // leave the underlying content visible.
+                frame.set_blend(animation::BlendMethod::Over);
+                Ok(frame)
+            },
+            None => {
+                self.done = true;
+                Err(ImageError::DimensionError)
+            }
+        })
+    }
+
+    fn nth(&mut self, n: usize) -> Option<ImageResult<animation::Frame>> {
+        // Only the headers of skipped frames are read, their pixels are not
+        // converted to RGBA
+        for _ in 0..n {
+            if self.done {
+                return None
+            }
+            match self.reader.next_frame_info() {
+                Ok(Some(_)) => (),
+                Ok(None) => {
+                    self.done = true;
+                    return None
+                },
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err.into()))
+                }
+            }
+        }
+        self.next()
+    }
 }
 
 /// GIF encoder.
+///
+/// Animations encoded with `AnimationEncoder` have to be completed with `finish`,
+/// otherwise the last frame is missing if the frames are optimized.
 pub struct Encoder<W: Write> {
-    w: W,
+    w: Option<W>,
+    gif_encoder: Option<gif::Encoder<W>>,
+    // The logical screen, the extent of the first frame of an animation
+    screen: Option<(u32, u32)>,
+    loop_count: LoopCount,
+    optimize: bool,
+    optimizer: Option<Optimizer>,
+}
+
+// A frame that is written once it is known which disposal method suits the next frame
+struct PendingFrame {
+    left: u32,
+    top: u32,
+    buffer: RgbaImage,
+    delay: u16,
+}
+
+// State of the inter-frame optimization
+struct Optimizer {
+    // Renders the frames that are passed to the encoder
+    canvas: animation::Canvas,
+    // The image that is shown once the pending frame is drawn
+    displayed: RgbaImage,
+    pending: PendingFrame,
 }
 
 impl<W: Write> Encoder<W> {
     /// Creates a new GIF encoder.
     pub fn new(w: W) -> Encoder<W> {
         Encoder {
-            w: w
+            w: Some(w),
+            gif_encoder: None,
+            screen: None,
+            loop_count: LoopCount::Finite(1),
+            optimize: false,
+            optimizer: None,
+        }
+    }
+
+    /// Sets how often an animation encoded with `AnimationEncoder` is played.
+    ///
+    /// The default is to play the animation once. This has to be set before the
+    /// first frame is encoded.
+    pub fn set_loop_count(&mut self, loop_count: LoopCount) {
+        self.loop_count = loop_count;
+    }
+
+    /// Sets whether frames encoded with `AnimationEncoder` are optimized.
+    ///
+    /// If enabled, each frame is compared to the image that is displayed before it and
+    /// only the bounding box of the changed pixels is stored, with unchanged pixels made
+    /// transparent. Frames that do not change the image are merged into the previous
+    /// frame. By default the frames are written as they are.
+    ///
+    /// An optimized frame is only written when the next frame or the end of the
+    /// animation is reached. `finish` has to be called after the last frame to write
+    /// it, dropping the encoder without doing so loses the last frame. This has to be
+    /// set before the first frame is encoded.
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    /// Finishes an animation encoded with `AnimationEncoder`.
+    ///
+    /// Writes the last optimized frame, if any. This is the only place where it is
+    /// written, so it has to be called whenever `set_optimize` is enabled.
+    pub fn finish(mut self) -> ImageResult<()> {
+        match self.optimizer.take() {
+            Some(optimizer) => self.write_frame(optimizer.pending, gif::DisposalMethod::Keep),
+            None => Ok(()),
         }
     }
+
     /// Encodes a frame.
-    pub fn encode(self, frame: Frame) -> ImageResult<()> {
+    ///
+    /// Returns an ```UnsupportedError``` if frames were already encoded with
+    /// ```AnimationEncoder```.
+    pub fn encode(mut self, frame: Frame) -> ImageResult<()> {
+        let w = self.take_writer()?;
         let mut encoder = try!(
-            gif::Encoder::new(self.w, frame.width, frame.height, &[])
+            gif::Encoder::new(w, frame.width, frame.height, &[])
         );
         encoder.write_frame(&frame).map_err(|err| err.into())
     }
+
+    fn optimize_frame(&mut self, frame: &animation::Frame, delay: u16) -> ImageResult<()> {
+        let mut optimizer = match self.optimizer.take() {
+            Some(optimizer) => optimizer,
+            None => {
+                let (width, height) = frame.image().dimensions();
+                let mut canvas = animation::Canvas::new(frame.left() + width, frame.top() + height);
+                let displayed = canvas.render(frame).clone();
+                self.optimizer = Some(Optimizer {
+                    canvas,
+                    pending: PendingFrame { left: 0, top: 0, buffer: displayed.clone(), delay },
+                    displayed,
+                });
+                return Ok(())
+            },
+        };
+
+        let desired = optimizer.canvas.render(frame).clone();
+        if *desired == *optimizer.displayed {
+            optimizer.pending.delay = optimizer.pending.delay.saturating_add(delay);
+            self.optimizer = Some(optimizer);
+            return Ok(())
+        }
+
+        // Transparency can only be restored by clearing the pending frame
+        let clear = desired.pixels().zip(optimizer.displayed.pixels())
+            .any(|(new, old)| new[3] == 0 && old[3] != 0);
+        let disposal = if clear {
+            let pending = &optimizer.pending;
+            for y in pending.top..pending.top + pending.buffer.height() {
+                for x in pending.left..pending.left + pending.buffer.width() {
+                    optimizer.displayed.put_pixel(x, y, Rgba([0, 0, 0, 0]));
+                }
+            }
+            gif::DisposalMethod::Background
+        } else {
+            gif::DisposalMethod::Keep
+        };
+        let pending = changed_pixels(&optimizer.displayed, &desired, delay);
+        let written = mem::replace(&mut optimizer.pending, pending);
+        self.write_frame(written, disposal)?;
+
+        let pending = &optimizer.pending;
+        for (x, y, pixel) in pending.buffer.enumerate_pixels() {
+            if pixel[3] != 0 {
+                optimizer.displayed.put_pixel(pending.left + x, pending.top + y, *pixel);
+            }
+        }
+        self.optimizer = Some(optimizer);
+        Ok(())
+    }
+
+    fn write_frame(&mut self, frame: PendingFrame, dispose: gif::DisposalMethod) -> ImageResult<()> {
+        let (width, height) = frame.buffer.dimensions();
+        let mut pixels = frame.buffer.into_raw();
+        let mut gif_frame = Frame::from_rgba(width as u16, height as u16, &mut pixels);
+        gif_frame.left = frame.left as u16;
+        gif_frame.top = frame.top as u16;
+        gif_frame.delay = frame.delay;
+        gif_frame.dispose = dispose;
+
+        if self.gif_encoder.is_none() {
+            let w = self.take_writer()?;
+            let (width, height) = self.screen.unwrap_or((frame.left + width, frame.top + height));
+            let mut encoder = gif::Encoder::new(w, width as u16, height as u16, &[])?;
+            match self.loop_count {
+                LoopCount::Infinite => encoder.set(Repeat::Infinite)?,
+                // The extension stores the number of repetitions after the first play
+                LoopCount::Finite(n) if n > 1 => {
+                    let repetitions = cmp::min(n - 1, u32::from(u16::MAX));
+                    encoder.set(Repeat::Finite(repetitions as u16))?
+                },
+                LoopCount::Finite(_) => (),
+            }
+            self.gif_encoder = Some(encoder);
+        }
+        let encoder = self.gif_encoder.as_mut().unwrap();
+        encoder.write_frame(&gif_frame).map_err(|err| err.into())
+    }
+
+    // The writer is moved into the GIF encoder when the first frame is written
+    fn take_writer(&mut self) -> ImageResult<W> {
+        self.w.take().ok_or_else(|| ImageError::UnsupportedError(
+            "The GIF encoder was already used to encode an animation".to_string()
+        ))
+    }
+}
+
+// Crops `new` to the pixels that differ from `old` and makes the unchanged ones transparent
+fn changed_pixels(old: &RgbaImage, new: &RgbaImage, delay: u16) -> PendingFrame {
+    let (mut left, mut top, mut right, mut bottom) = (new.width(), new.height(), 0, 0);
+    for (x, y, pixel) in new.enumerate_pixels() {
+        if pixel != old.get_pixel(x, y) {
+            left = cmp::min(left, x);
+            top = cmp::min(top, y);
+            right = cmp::max(right, x + 1);
+            bottom = cmp::max(bottom, y + 1);
+        }
+    }
+    if left >= right {
+        // Nothing changed, a single transparent pixel keeps the image
+        return PendingFrame { left: 0, top: 0, buffer: ImageBuffer::new(1, 1), delay }
+    }
+    let buffer = ImageBuffer::from_fn(right - left, bottom - top, |x, y| {
+        let pixel = *new.get_pixel(left + x, top + y);
+        if pixel == *old.get_pixel(left + x, top + y) {
+            Rgba([0, 0, 0, 0])
+        } else {
+            pixel
+        }
+    });
+    PendingFrame { left, top, buffer, delay }
+}
+
+impl<W: Write> AnimationEncoder for Encoder<W> {
+    /// Encodes a frame of an animation.
+    ///
+    /// The size of the logical screen is taken from the extent of the first frame,
+    /// later frames which do not fit into it are rejected with a ```DimensionError```.
+    /// The colors of each frame are quantized to a local palette of 256 entries.
+    fn encode_frame(&mut self, frame: animation::Frame) -> ImageResult<()> {
+        let (left, top) = (frame.left(), frame.top());
+        let (width, height) = frame.image().dimensions();
+        let extent = match (left.checked_add(width), top.checked_add(height)) {
+            (Some(right), Some(bottom)) => (right, bottom),
+            _ => return Err(ImageError::DimensionError),
+        };
+        let (screen_width, screen_height) = match self.screen {
+            Some(screen) => screen,
+            None if extent.0 <= u32::from(u16::MAX) && extent.1 <= u32::from(u16::MAX) => {
+                self.screen = Some(extent);
+                extent
+            },
+            None => return Err(ImageError::DimensionError),
+        };
+        if extent.0 > screen_width || extent.1 > screen_height {
+            return Err(ImageError::DimensionError)
+        }
+
+        // GIF delays are stored in units of 1/100 s
+        let delay = frame.delay();
+        let delay = (u32::from(*delay.numer()) * 100 + u32::from(*delay.denom()) / 2)
+            / u32::from(*delay.denom());
+        let delay = cmp::min(delay, u32::from(u16::MAX)) as u16;
+        if self.optimize {
+            return self.optimize_frame(&frame, delay)
+        }
+
+        let dispose = match frame.disposal() {
+            animation::DisposalMethod::Keep => gif::DisposalMethod::Keep,
+            animation::DisposalMethod::Background => gif::DisposalMethod::Background,
+            animation::DisposalMethod::Previous => gif::DisposalMethod::Previous,
+        };
+        let buffer = frame.into_buffer();
+        self.write_frame(PendingFrame { left, top, buffer, delay }, dispose)
+    }
 }
 
 impl From<gif::DecodingError> for ImageError {
@@ -114,8 +681,290 @@ impl From<gif::DecodingError> for ImageError {
         use self::gif::DecodingError::*;
         match err {
             Format(desc) |
-            Internal(desc) => ImageError::FormatError(desc.into()),
+            Internal(desc) => ImageError::malformed(ImageFormat::GIF, desc),
             Io(io_err) => ImageError::IoError(io_err),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::gif;
+    use super::{Decoder, Encoder};
+    use num_rational::Ratio;
+    use animation::{AnimationEncoder, BlendMethod, DisposalMethod, Frame, LoopCount};
+    use buffer::ImageBuffer;
+    use color::Rgba;
+    use image::{GenericImage, ImageDecoder};
+
+    const WHITE: [u8; 4] = [255, 255, 255, 255];
+    const BLACK: [u8; 4] = [0, 0, 0, 255];
+    const RED: [u8; 4] = [255, 0, 0, 255];
+
+    // Encodes a three frame animation on a 3x3 screen:
+    // - a white background,
+    // - a black 2x2 square at (1, 1) which is disposed to the previous content,
+    // - a 2x1 frame at (0, 0) with a red and a transparent pixel.
+    fn three_frame_gif() -> Vec<u8> {
+        let mut data = Vec::new();
+        {
+            let mut encoder = gif::Encoder::new(&mut data, 3, 3, &[]).unwrap();
+            let mut first = gif::Frame::from_rgba(3, 3, &mut WHITE.repeat(9));
+            first.delay = 10;
+            encoder.write_frame(&first).unwrap();
+            let mut second = gif::Frame::from_rgba(2, 2, &mut BLACK.repeat(4));
+            second.left = 1;
+            second.top = 1;
+            second.delay = 25;
+            second.dispose = gif::DisposalMethod::Previous;
+            encoder.write_frame(&second).unwrap();
+            let third = gif::Frame::from_rgba(2, 1, &mut [RED, [0; 4]].concat());
+            encoder.write_frame(&third).unwrap();
+        }
+        data
+    }
+
+    #[test]
+    fn test_read_indexed() {
+        use std::borrow::Cow;
+        use image::ImageError;
+
+        let mut data = Vec::new();
+        {
+            let palette = [0, 0, 0, 255, 0, 0, 0, 255, 0, 0, 0, 255];
+            let mut encoder = gif::Encoder::new(&mut data, 3, 2, &palette).unwrap();
+            let frame = gif::Frame {
+                width: 3,
+                height: 2,
+                buffer: Cow::Borrowed(&[0, 1, 2, 3, 2, 1]),
+                transparent: Some(2),
+                ..gif::Frame::default()
+            };
+            encoder.write_frame(&frame).unwrap();
+        }
+        let indexed = Decoder::new(&data[..]).read_indexed().unwrap();
+        assert_eq!((indexed.width, indexed.height), (3, 2));
+        assert_eq!(indexed.indices, vec![0, 1, 2, 3, 2, 1]);
+        assert_eq!(indexed.palette.len(), 4);
+        assert_eq!(indexed.palette[1], Rgba([255, 0, 0, 255]));
+        assert_eq!(indexed.transparent_index(), Some(2));
+
+        let mut decoder = Decoder::new(&data[..]);
+        decoder.dimensions().unwrap();
+        match decoder.read_indexed() {
+            Err(ImageError::UnsupportedError(_)) => (),
+            other => panic!("expected an unsupported error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_raw_frames() {
+        let data = three_frame_gif();
+        let mut frames = Decoder::new(&data[..]).into_raw_frames().unwrap();
+
+        let first = frames.next().unwrap().unwrap();
+        assert_eq!(first.image().dimensions(), (3, 3));
+        assert_eq!(*first.delay().numer() as f32 / *first.delay().denom() as f32, 0.1);
+
+        let second = frames.next().unwrap().unwrap();
+        assert_eq!((second.left(), second.top()), (1, 1));
+        assert_eq!(second.disposal(), DisposalMethod::Previous);
+        assert_eq!(second.blend(), BlendMethod::Over);
+        assert_eq!(second.image().dimensions(), (2, 2));
+        assert_eq!(second.image().get_pixel(0, 0).data, BLACK);
+
+        let third = frames.next().unwrap().unwrap();
+        assert_eq!(third.image().get_pixel(1, 0).data[3], 0);
+
+        assert!(frames.next().is_none());
+    }
+
+    #[test]
+    fn test_composited_frames() {
+        let data = three_frame_gif();
+        let frames = Decoder::new(&data[..]).into_frames().unwrap()
+            .collect_frames().unwrap();
+        assert_eq!(frames.len(), 3);
+        for frame in &frames {
+            assert_eq!(frame.image().dimensions(), (3, 3));
+            assert_eq!((frame.left(), frame.top()), (0, 0));
+        }
+        assert_eq!(frames[1].delay(), Ratio::new(25, 100));
+        assert_eq!(frames[1].image().get_pixel(0, 0).data, WHITE);
+        assert_eq!(frames[1].image().get_pixel(2, 2).data, BLACK);
+        // The black square was disposed and the transparent pixel shows the background
+        // The palette is quantized, so red is only approximated
+        let red = frames[2].image().get_pixel(0, 0).data;
+        assert!(red[0] > 200 && red[1] < 50 && red[2] < 50 && red[3] == 255);
+        assert_eq!(frames[2].image().get_pixel(1, 0).data, WHITE);
+        assert_eq!(frames[2].image().get_pixel(2, 2).data, WHITE);
+    }
+
+    #[test]
+    fn test_nth_frame() {
+        let data = three_frame_gif();
+        let mut frames = Decoder::new(&data[..]).into_raw_frames().unwrap();
+        assert_eq!(frames.nth(2).unwrap().unwrap().image().dimensions(), (2, 1));
+        assert!(frames.next().is_none());
+        assert!(Decoder::new(&data[..]).into_raw_frames().unwrap().nth(3).is_none());
+
+        let expected = Decoder::new(&data[..]).into_frames().unwrap()
+            .collect_frames().unwrap();
+        let mut frames = Decoder::new(&data[..]).into_frames().unwrap();
+        let second = frames.nth(1).unwrap().unwrap();
+        assert_eq!(second.into_buffer().into_raw(), expected[1].clone().into_buffer().into_raw());
+        let third = frames.next().unwrap().unwrap();
+        assert_eq!(third.into_buffer().into_raw(), expected[2].clone().into_buffer().into_raw());
+    }
+
+    #[test]
+    fn test_encode_frames() {
+        let data = three_frame_gif();
+        let frames = Decoder::new(&data[..]).into_raw_frames().unwrap();
+        let mut encoded = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut encoded);
+            encoder.try_encode_frames(frames).unwrap();
+        }
+
+        let frames = Decoder::new(&encoded[..]).into_raw_frames().unwrap()
+            .collect_frames().unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].delay(), Ratio::new(10, 100));
+        assert_eq!((frames[1].left(), frames[1].top()), (1, 1));
+        assert_eq!(frames[1].disposal(), DisposalMethod::Previous);
+        assert_eq!(frames[1].image().get_pixel(1, 1).data, BLACK);
+    }
+
+    #[test]
+    fn test_optimized_frames() {
+        let white = ImageBuffer::from_pixel(4, 4, Rgba(WHITE));
+        let mut black = white.clone();
+        black.put_pixel(2, 1, Rgba(BLACK));
+        let mut clear = white.clone();
+        clear.put_pixel(2, 1, Rgba([0; 4]));
+        let images = [&white, &black, &black, &clear];
+
+        let mut encoded = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut encoded);
+            encoder.set_optimize(true);
+            for image in &images {
+                let mut frame = Frame::from_parts((*image).clone(), 0, 0, Ratio::new(1, 10));
+                frame.set_blend(BlendMethod::Source);
+                encoder.encode_frame(frame).unwrap();
+            }
+            encoder.finish().unwrap();
+        }
+
+        let frames = Decoder::new(&encoded[..]).into_raw_frames().unwrap()
+            .collect_frames().unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].image().dimensions(), (4, 4));
+        // Only the changed pixel is stored and the unchanged frame is merged
+        assert_eq!(frames[1].image().dimensions(), (1, 1));
+        assert_eq!((frames[1].left(), frames[1].top()), (2, 1));
+        assert_eq!(frames[1].delay(), Ratio::new(20, 100));
+        // The pixel is cleared to make it transparent again
+        assert_eq!(frames[1].disposal(), DisposalMethod::Background);
+
+        let frames = Decoder::new(&encoded[..]).into_frames().unwrap()
+            .collect_frames().unwrap();
+        for (frame, image) in frames.into_iter().zip(&[&white, &black, &clear]) {
+            assert_eq!(frame.into_buffer().into_raw(), image.to_vec());
+        }
+    }
+
+    #[test]
+    fn test_encoder_misuse() {
+        use image::ImageError;
+
+        let image = ImageBuffer::from_pixel(2, 2, Rgba(WHITE));
+        let mut encoded = Vec::new();
+        let mut encoder = Encoder::new(&mut encoded);
+        encoder.encode_frame(Frame::from_parts(image.clone(), 1, 1, Ratio::new(0, 1))).unwrap();
+        // Frames outside of the logical screen of the first frame
+        for &(left, top) in &[(2, 1), (0, u32::max_value())] {
+            match encoder.encode_frame(Frame::from_parts(image.clone(), left, top, Ratio::new(0, 1))) {
+                Err(ImageError::DimensionError) => (),
+                other => panic!("expected a dimension error, got {:?}", other),
+            }
+        }
+        match encoder.encode(gif::Frame::from_rgba(1, 1, &mut WHITE.to_vec())) {
+            Err(ImageError::UnsupportedError(_)) => (),
+            other => panic!("expected an unsupported error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_loop_count() {
+        let data = three_frame_gif();
+        assert_eq!(Decoder::new(&data[..]).loop_count().unwrap(), LoopCount::Finite(1));
+
+        for &count in &[LoopCount::Infinite, LoopCount::Finite(3)] {
+            let frames = Decoder::new(&data[..]).into_frames().unwrap();
+            let mut encoded = Vec::new();
+            {
+                let mut encoder = Encoder::new(&mut encoded);
+                encoder.set_loop_count(count);
+                encoder.try_encode_frames(frames).unwrap();
+            }
+            let mut decoder = Decoder::new(&encoded[..]);
+            assert_eq!(decoder.loop_count().unwrap(), count);
+            assert_eq!(decoder.into_frames().unwrap().count(), 3);
+        }
+    }
+
+    #[test]
+    fn test_scan_loop_extension() {
+        use super::{ExtensionScan, LoopScan};
+
+        // Passes the data in pieces of `step` bytes
+        fn scan(data: &[u8], step: usize) -> LoopScan {
+            let mut scan = ExtensionScan::new();
+            for piece in data.chunks(step) {
+                match scan.push(piece) {
+                    LoopScan::Incomplete => (),
+                    result => return result,
+                }
+            }
+            LoopScan::Incomplete
+        }
+
+        let netscape = b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x05\x00\x00";
+        let mut data = b"GIF89a\x01\x00\x01\x00\x80\x00\x00".to_vec();
+        // The global color table of two entries
+        data.extend_from_slice(&[0; 6]);
+        // A comment which contains the extension
+        data.extend_from_slice(&[0x21, 0xFE, netscape.len() as u8]);
+        data.extend_from_slice(netscape);
+        data.push(0);
+        let mut with_image = data.clone();
+        // An image descriptor, the data which follows it is not scanned
+        with_image.push(0x2C);
+        with_image.extend_from_slice(netscape);
+        data.extend_from_slice(netscape);
+
+        for &step in &[1, 3, data.len()] {
+            match scan(&with_image, step) {
+                LoopScan::Missing => (),
+                _ => panic!("expected no loop extension"),
+            }
+            match scan(&data, step) {
+                LoopScan::Found(5) => (),
+                _ => panic!("expected a loop count of 5"),
+            }
+            match scan(&data[..data.len() - 2], step) {
+                LoopScan::Incomplete => (),
+                _ => panic!("expected incomplete data"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_truncated_frames() {
+        let data = three_frame_gif();
+        let frames = Decoder::new(&data[..data.len() - 8]).into_frames().unwrap();
+        assert!(frames.collect_frames().is_err());
+    }
+}
@@ -3,16 +3,20 @@ use num_traits::identities::Zero;
 use Primitive;
 use super::scoped_threadpool::Pool;
 use std::borrow::Cow;
+use std::cmp;
 use std::error::Error;
 use std::path::Path;
 use std::io::{BufRead, self};
 use std::iter::{Iterator};
 
 use color::{ColorType, Rgb};
+use utils;
 use image::{
+    DecodingError,
     DecodingResult,
     ImageDecoder,
     ImageError,
+    ImageFormat,
     ImageResult,
 };
 
@@ -89,6 +93,9 @@ impl<R: BufRead> ImageDecoder for HDRAdapter<R> {
 pub const SIGNATURE: &[u8] = b"#?RADIANCE";
 const SIGNATURE_LENGTH: usize = 10;
 
+/// The number of pixels which are decoded before they are transformed in parallel
+const MAX_BAND_PIXELS: usize = 1 << 16;
+
 /// An Radiance HDR decoder
 #[derive(Debug)]
 pub struct HDRDecoder<R> {
@@ -193,7 +200,7 @@ impl<R: BufRead> HDRDecoder<R> {
                 let mut signature = [0; SIGNATURE_LENGTH];
                 try!(r.read_exact(&mut signature));
                 if signature != SIGNATURE {
-                    return Err(ImageError::FormatError("Radiance HDR signature not found".to_string()));
+                    return Err(ImageError::malformed(ImageFormat::HDR, "Radiance HDR signature not found"));
                 } // no else
                 // skip signature line ending
                 try!(read_line_u8(r));
@@ -205,7 +212,7 @@ impl<R: BufRead> HDRDecoder<R> {
             loop {
                 match try!(read_line_u8(r)) {
                     None => { // EOF before end of header
-                        return Err(ImageError::FormatError("EOF in header".into()));
+                        return Err(ImageError::malformed(ImageFormat::HDR, "EOF in header"));
                     },
                     Some(line) => {
                         if line.is_empty() {
@@ -227,7 +234,7 @@ impl<R: BufRead> HDRDecoder<R> {
             match try!(read_line_u8(&mut reader)) {
                 None => {
                     // EOF instead of image dimensions
-                    return Err(ImageError::FormatError("EOF in dimensions line".into()));
+                    return Err(ImageError::malformed(ImageFormat::HDR, "EOF in dimensions line"));
                 },
                 Some(dimensions) => {
                     let dimensions = String::from_utf8_lossy(&dimensions[..]);
@@ -261,13 +268,12 @@ impl<R: BufRead> HDRDecoder<R> {
         }
         // expression self.width > 0 && self.height > 0 is true from now to the end of this method
         let pixel_count = self.width as usize * self.height as usize;
-        let mut ret = Vec::<RGBE8Pixel>::with_capacity(pixel_count);
-        unsafe {
-            // RGBE8Pixel doesn't implement Drop, so it's Ok to drop half-initialized ret
-            ret.set_len(pixel_count);
-        } // ret contains uninitialized data, so now it's my responsibility to return fully initialized ret
-        for chunk in ret.chunks_mut(self.width as usize) {
-            try!(read_scanline(&mut self.r, chunk));
+        // The result grows with the decoded scanlines, the dimensions come from the header
+        let mut ret = Vec::<RGBE8Pixel>::with_capacity(utils::initial_capacity(pixel_count));
+        let mut scanline = vec![RGBE8Pixel { c: [0; 3], e: 0 }; self.width as usize];
+        for _ in 0..self.height {
+            try!(read_scanline(&mut self.r, &mut scanline));
+            ret.extend_from_slice(&scanline);
         }
         Ok(ret)
     }
@@ -283,32 +289,35 @@ impl<R: BufRead> HDRDecoder<R> {
         let uszwidth = self.width as usize;
 
         let pixel_count = self.width as usize * self.height as usize;
-        let mut ret = Vec::with_capacity(pixel_count);
-        unsafe {
-            // RGBE8Pixel doesn't implement Drop, so it's Ok to drop half-initialized ret
-            ret.set_len(pixel_count);
-        } // ret contains uninitialized data, so now it's my responsibility to return fully initialized ret
-
-        {
-            let chunks_iter = ret.chunks_mut(uszwidth);
-            let mut pool = Pool::new(8); //
-
-            try!(pool.scoped(|scope| {
-                for chunk in chunks_iter {
-                    let mut buf = Vec::<RGBE8Pixel>::with_capacity(uszwidth);
-                    unsafe {
-                        buf.set_len(uszwidth);
-                    }
-                    try!(read_scanline(&mut self.r, &mut buf[..]));
-                    let f = &f;
+        let mut ret = Vec::with_capacity(utils::initial_capacity(pixel_count));
+        let mut pool = Pool::new(8); //
+
+        // The scanlines are decoded in bands, so that the result only grows with the
+        // data which is actually present instead of trusting the dimensions in the header
+        let band_height = cmp::max(1, MAX_BAND_PIXELS / uszwidth);
+        let mut band = Vec::<RGBE8Pixel>::new();
+        let mut scanline = vec![RGBE8Pixel { c: [0; 3], e: 0 }; uszwidth];
+        let mut rows_left = self.height as usize;
+        while rows_left > 0 {
+            band.clear();
+            for _ in 0..cmp::min(band_height, rows_left) {
+                try!(read_scanline(&mut self.r, &mut scanline));
+                band.extend_from_slice(&scanline);
+            }
+            rows_left -= band.len() / uszwidth;
+
+            let mut rows: Vec<Vec<T>> = band.chunks(uszwidth).map(|_| Vec::with_capacity(uszwidth)).collect();
+            let f = &f;
+            pool.scoped(|scope| {
+                for (row, pixels) in rows.iter_mut().zip(band.chunks(uszwidth)) {
                     scope.execute(move || {
-                        for (dst, &pix) in chunk.iter_mut().zip(buf.iter()) {
-                            *dst = f(pix);
-                        }
+                        row.extend(pixels.iter().map(|&pix| f(pix)));
                     });
                 }
-                Ok(())
-            }) as Result<(), ImageError>);
+            });
+            for row in rows {
+                ret.extend(row);
+            }
         }
 
         Ok(ret)
@@ -468,7 +477,7 @@ fn decode_component<R: BufRead, S: FnMut(usize, u8)>(r: &mut R, width: usize, mu
             if rl <= 128 {
                 // sanity check
                 if pos + rl as usize > width {
-                    return Err(ImageError::FormatError("Wrong length of decoded scanline".into()));
+                    return Err(ImageError::malformed(ImageFormat::HDR, "Wrong length of decoded scanline"));
                 }
                 // read values
                 try!(r.read_exact(&mut buf[0..rl as usize]));
@@ -481,7 +490,7 @@ fn decode_component<R: BufRead, S: FnMut(usize, u8)>(r: &mut R, width: usize, mu
                 let rl = rl - 128;
                 // sanity check
                 if pos + rl as usize > width {
-                    return Err(ImageError::FormatError("Wrong length of decoded scanline".into()));
+                    return Err(ImageError::malformed(ImageFormat::HDR, "Wrong length of decoded scanline"));
                 }
                 // fill with same value
                 let value = try!(read_byte(r));
@@ -493,7 +502,7 @@ fn decode_component<R: BufRead, S: FnMut(usize, u8)>(r: &mut R, width: usize, mu
         };
     }
     if pos != width {
-        return Err(ImageError::FormatError("Wrong length of decoded scanline".into()));
+        return Err(ImageError::malformed(ImageFormat::HDR, "Wrong length of decoded scanline"));
     }
     Ok(())
 }
@@ -517,7 +526,7 @@ fn decode_old_rle<R: BufRead>(r: &mut R, fb: RGBE8Pixel, buf: &mut [RGBE8Pixel])
     // first pixel in scanline should not be run length marker
     // it is error if it is
     if rl_marker(fb).is_some() {
-        return Err(ImageError::FormatError("First pixel of a scanline shouldn't be run length marker".into()));
+        return Err(ImageError::malformed(ImageFormat::HDR, "First pixel of a scanline shouldn't be run length marker"));
     }
     buf[0] = fb; // set first pixel of scanline
 
@@ -538,7 +547,7 @@ fn decode_old_rle<R: BufRead>(r: &mut R, fb: RGBE8Pixel, buf: &mut [RGBE8Pixel])
                         *b = prev_pixel;
                     }
                 } else {
-                    return Err(ImageError::FormatError("Wrong length of decoded scanline".into()));
+                    return Err(ImageError::malformed(ImageFormat::HDR, "Wrong length of decoded scanline"));
                 };
                 rl // value to increase x_off by
             } else {
@@ -550,7 +559,7 @@ fn decode_old_rle<R: BufRead>(r: &mut R, fb: RGBE8Pixel, buf: &mut [RGBE8Pixel])
         };
     }
     if x_off != width {
-        return Err(ImageError::FormatError("Wrong length of decoded scanline".into()));
+        return Err(ImageError::malformed(ImageFormat::HDR, "Wrong length of decoded scanline"));
     }
     Ok(())
 }
@@ -631,7 +640,7 @@ impl HDRMetadata {
                     },
                     Err(parse_error) => {
                         if strict {
-                            return Err(ImageError::FormatError(format!("Cannot parse EXPOSURE value: {}", parse_error.description())));
+                            return Err(ImageError::malformed(ImageFormat::HDR, format!("Cannot parse EXPOSURE value: {}", parse_error.description())));
                         } // no else, skip this line in non-strict mode
                     },
                 };
@@ -643,7 +652,7 @@ impl HDRMetadata {
                     },
                     Err(parse_error) => {
                         if strict {
-                            return Err(ImageError::FormatError(format!("Cannot parse PIXASPECT value: {}", parse_error.description())));
+                            return Err(ImageError::malformed(ImageFormat::HDR, format!("Cannot parse PIXASPECT value: {}", parse_error.description())));
                         } // no else, skip this line in non-strict mode
                     },
                 };
@@ -653,7 +662,7 @@ impl HDRMetadata {
                 match parse_space_separated_f32(val, &mut rgbcorr, "COLORCORR") {
                     Ok(extra_numbers) => {
                         if strict && extra_numbers {
-                            return Err(ImageError::FormatError("Extra numbers in COLORCORR".into()));
+                            return Err(ImageError::malformed(ImageFormat::HDR, "Extra numbers in COLORCORR"));
                         } // no else, just ignore extra numbers
                         let (rc, gc, bc) = self.color_correction.unwrap_or((1., 1., 1.));
                         self.color_correction = Some((rc*rgbcorr[0], gc*rgbcorr[1], bc*rgbcorr[2]));
@@ -684,12 +693,12 @@ fn parse_space_separated_f32(line: &str, vals: &mut [f32], name: &str) -> ImageR
             match num.parse::<f32>() {
                 Ok(v) => *val = v,
                 Err(err) => {
-                    return Err(ImageError::FormatError(format!("f32 parse error in {}: {}", name, err.description())));
+                    return Err(ImageError::malformed(ImageFormat::HDR, format!("f32 parse error in {}: {}", name, err.description())));
                 }
             }
         } else {
             // not enough numbers in line
-            return Err(ImageError::FormatError(format!("Not enough numbers in {}", name)));
+            return Err(ImageError::malformed(ImageFormat::HDR, format!("Not enough numbers in {}", name)));
         }
     }
     Ok(nums.next().is_some())
@@ -700,13 +709,13 @@ fn parse_space_separated_f32(line: &str, vals: &mut [f32], name: &str) -> ImageR
 fn parse_dimensions_line<'a>(line: &Cow<'a, str>, strict: bool) -> ImageResult<(u32,u32)> {
     let mut dim_parts = line.split_whitespace();
     let err = "Malformed dimensions line";
-    let c1_tag = try!(dim_parts.next().ok_or(ImageError::FormatError(err.into())));
-    let c1_str = try!(dim_parts.next().ok_or(ImageError::FormatError(err.into())));
-    let c2_tag = try!(dim_parts.next().ok_or(ImageError::FormatError(err.into())));
-    let c2_str = try!(dim_parts.next().ok_or(ImageError::FormatError(err.into())));
+    let c1_tag = try!(dim_parts.next().ok_or_else(|| ImageError::malformed(ImageFormat::HDR, err)));
+    let c1_str = try!(dim_parts.next().ok_or_else(|| ImageError::malformed(ImageFormat::HDR, err)));
+    let c2_tag = try!(dim_parts.next().ok_or_else(|| ImageError::malformed(ImageFormat::HDR, err)));
+    let c2_str = try!(dim_parts.next().ok_or_else(|| ImageError::malformed(ImageFormat::HDR, err)));
     if strict && dim_parts.next().is_some() {
         // extra data in dimensions line
-        return Err(ImageError::FormatError(err.into()));
+        return Err(ImageError::malformed(ImageFormat::HDR, err));
     } // no else
     // dimensions line is in the form "-Y 10 +X 20"
     // There are 8 possible orientations: +Y +X, +X -Y and so on
@@ -719,7 +728,7 @@ fn parse_dimensions_line<'a>(line: &Cow<'a, str>, strict: bool) -> ImageResult<(
             Ok((width, height))
         },
         _ => {
-            Err(ImageError::FormatError(
+            Err(ImageError::malformed(ImageFormat::HDR,
                     format!("Unsupported orientation {} {}",
                         limit_string_len(c1_tag, 4),
                         limit_string_len(c2_tag, 4))))
@@ -733,13 +742,13 @@ trait IntoImageError<T> {
 
 impl<T> IntoImageError<T> for ::std::result::Result<T, ::std::num::ParseFloatError> {
     fn into_image_error(self, description: &str) -> ImageResult<T> {
-        self.map_err(|err| ImageError::FormatError(format!("{} {}", description, err.description())))
+        self.map_err(|err| DecodingError::malformed(ImageFormat::HDR, description).with_source(err).into())
     }
 }
 
 impl<T> IntoImageError<T> for ::std::result::Result<T, ::std::num::ParseIntError> {
     fn into_image_error(self, description: &str) -> ImageResult<T> {
-        self.map_err(|err| ImageError::FormatError(format!("{} {}", description, err.description())))
+        self.map_err(|err| DecodingError::malformed(ImageFormat::HDR, description).with_source(err).into())
     }
 }
 
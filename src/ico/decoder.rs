@@ -1,21 +1,33 @@
 use byteorder::{ReadBytesExt, LittleEndian};
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{Cursor, Read, Seek, SeekFrom};
 
 use color::ColorType;
-use image::{DecodingResult, ImageResult, ImageDecoder, ImageError};
+use image::{DecodingError, DecodingResult, ImageResult, ImageDecoder, ImageError, ImageFormat, MultiImageDecoder};
 
 use bmp::BMPDecoder;
 use png::PNGDecoder;
 use self::InnerDecoder::*;
 
+// The resource types of the ICONDIR structure
+const ICO_IMAGE_TYPE: u16 = 1;
+const CUR_IMAGE_TYPE: u16 = 2;
+
 // http://www.w3.org/TR/PNG-Structure.html
 // The first eight bytes of a PNG file always contain the following (decimal) values:
 const PNG_SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
 
 /// An ico decoder
+///
+/// Decodes both icons (ICO) and cursors (CUR). All images of the file can be decoded
+/// with ```MultiImageDecoder```, initially the image with the highest color depth and
+/// size is selected.
 pub struct ICODecoder<R: Read> {
-    selected_entry: DirEntry,
-    inner_decoder: InnerDecoder<R>,
+    reader: R,
+    cursor: bool,
+    entries: Vec<DirEntry>,
+    selected: usize,
+    // Decodes the data of the selected entry
+    inner_decoder: InnerDecoder<Cursor<Vec<u8>>>,
 }
 
 enum InnerDecoder<R: Read> {
@@ -41,22 +53,48 @@ struct DirEntry {
 impl<R: Read + Seek> ICODecoder<R> {
     /// Create a new decoder that decodes from the stream ```r```
     pub fn new(mut r: R) -> ImageResult<ICODecoder<R>> {
-        let entries = try!(read_entries(&mut r));
-        let entry = try!(best_entry(entries));
-        let decoder = try!(entry.decoder(r));
+        let (cursor, entries) = try!(read_entries(&mut r));
+        let selected = try!(best_entry(&entries, cursor));
+        let decoder = try!(entries[selected].decoder(&mut r));
 
         Ok(ICODecoder {
-            selected_entry: entry,
+            reader: r,
+            cursor,
+            entries,
+            selected,
             inner_decoder: decoder,
         })
     }
+
+    /// Returns true if the file is a cursor (CUR) instead of an icon
+    pub fn is_cursor(&self) -> bool {
+        self.cursor
+    }
+
+    /// Returns the hotspot of the selected cursor image, the position of the pixel
+    /// which points at the target of the cursor. Icons have no hotspot.
+    pub fn hotspot(&self) -> Option<(u16, u16)> {
+        if !self.cursor {
+            return None
+        }
+        let entry = &self.entries[self.selected];
+        Some((entry.num_color_planes, entry.bits_per_pixel))
+    }
 }
 
-fn read_entries<R: Read>(r: &mut R) -> ImageResult<Vec<DirEntry>> {
+// Returns whether the file is a cursor and its entries
+fn read_entries<R: Read>(r: &mut R) -> ImageResult<(bool, Vec<DirEntry>)> {
     let _reserved = try!(r.read_u16::<LittleEndian>());
-    let _type = try!(r.read_u16::<LittleEndian>());
+    let cursor = match try!(r.read_u16::<LittleEndian>()) {
+        ICO_IMAGE_TYPE => false,
+        CUR_IMAGE_TYPE => true,
+        other => return Err(DecodingError::invalid_value(ImageFormat::ICO, "resource type", "1 or 2", other)
+            .with_offset(2)
+            .into())
+    };
     let count = try!(r.read_u16::<LittleEndian>());
-    (0..count).map(|_| read_entry(r)).collect()
+    let entries = try!((0..count).map(|_| read_entry(r)).collect());
+    Ok((cursor, entries))
 }
 
 fn read_entry<R: Read>(r: &mut R) -> ImageResult<DirEntry> {
@@ -72,8 +110,8 @@ fn read_entry<R: Read>(r: &mut R) -> ImageResult<DirEntry> {
     // of the hotspot for CUR files.
     entry.num_color_planes = try!(r.read_u16::<LittleEndian>());
     if entry.num_color_planes > 256 {
-        return Err(ImageError::FormatError(
-            "ICO image entry has a too large color planes/hotspot value".to_string()
+        return Err(ImageError::malformed(ImageFormat::ICO,
+            "ICO image entry has a too large color planes/hotspot value"
         ));
     }
 
@@ -81,8 +119,8 @@ fn read_entry<R: Read>(r: &mut R) -> ImageResult<DirEntry> {
     // or the vertical coordinate of the hotspot for CUR files.
     entry.bits_per_pixel = try!(r.read_u16::<LittleEndian>());
     if entry.bits_per_pixel > 256 {
-        return Err(ImageError::FormatError(
-            "ICO image entry has a too large bits per pixel/hotspot value".to_string()
+        return Err(ImageError::malformed(ImageFormat::ICO,
+            "ICO image entry has a too large bits per pixel/hotspot value"
         ));
     }
 
@@ -92,16 +130,20 @@ fn read_entry<R: Read>(r: &mut R) -> ImageResult<DirEntry> {
     Ok(entry)
 }
 
-/// Find the entry with the highest (color depth, size).
-fn best_entry(mut entries: Vec<DirEntry>) -> ImageResult<DirEntry> {
-    let mut best = try!(entries.pop().ok_or(ImageError::ImageEnd));
-    let mut best_score = (best.bits_per_pixel, best.real_width() as u32 * best.real_height() as u32);
-
-    for entry in entries {
-        let score = (entry.bits_per_pixel, entry.real_width() as u32 * entry.real_height() as u32);
-        if score > best_score {
-            best = entry;
-            best_score = score;
+/// Find the index of the entry with the highest (color depth, size).
+/// The entries of cursors store the hotspot instead of the color depth.
+fn best_entry(entries: &[DirEntry], cursor: bool) -> ImageResult<usize> {
+    let score = |entry: &DirEntry| {
+        let bits_per_pixel = if cursor { 0 } else { entry.bits_per_pixel };
+        (bits_per_pixel, entry.real_width() as u32 * entry.real_height() as u32)
+    };
+    let mut best = try!(entries.len().checked_sub(1).ok_or(ImageError::ImageEnd));
+    let mut best_score = score(&entries[best]);
+
+    for (i, entry) in entries[..best].iter().enumerate() {
+        if score(entry) > best_score {
+            best = i;
+            best_score = score(entry);
         }
     }
     Ok(best)
@@ -133,20 +175,15 @@ impl DirEntry {
         Ok(())
     }
 
-    fn is_png<R: Read + Seek>(&self, r: &mut R) -> ImageResult<bool> {
+    // Reads the data of the entry into memory, so that the other entries can be
+    // selected later on
+    fn decoder<R: Read + Seek>(&self, r: &mut R) -> ImageResult<InnerDecoder<Cursor<Vec<u8>>>> {
         try!(self.seek_to_start(r));
+        let mut data = Vec::new();
+        try!(r.take(u64::from(self.image_length)).read_to_end(&mut data));
 
-        // Read the first 8 bytes to sniff the image.
-        let mut signature = [0u8; 8];
-        try!(r.read_exact(&mut signature));
-
-        Ok(signature == PNG_SIGNATURE)
-    }
-
-    fn decoder<R: Read + Seek>(&self, mut r: R) -> ImageResult<InnerDecoder<R>> {
-        let is_png = try!(self.is_png(&mut r));
-        try!(self.seek_to_start(&mut r));
-
+        let is_png = data.starts_with(&PNG_SIGNATURE);
+        let r = Cursor::new(data);
         if is_png {
             Ok(PNG(PNGDecoder::new(r)))
         } else {
@@ -189,17 +226,17 @@ impl<R: Read + Seek> ImageDecoder for ICODecoder<R> {
     fn read_image(&mut self) -> ImageResult<DecodingResult> {
         match self.inner_decoder {
             PNG(ref mut decoder) => {
-                if self.selected_entry.image_length < PNG_SIGNATURE.len() as u32 {
-                    return Err(ImageError::FormatError(
-                        "Entry specified a length that is shorter than PNG header!".to_string()
+                if self.entries[self.selected].image_length < PNG_SIGNATURE.len() as u32 {
+                    return Err(ImageError::malformed(ImageFormat::ICO,
+                        "Entry specified a length that is shorter than PNG header!"
                     ));
                 }
 
                 // Check if the image dimensions match the ones in the image data.
                 let (width, height) = try!(decoder.dimensions());
-                if !self.selected_entry.matches_dimensions(width, height) {
-                    return Err(ImageError::FormatError(
-                        "Entry and PNG dimensions do not match!".to_string())
+                if !self.entries[self.selected].matches_dimensions(width, height) {
+                    return Err(ImageError::malformed(ImageFormat::ICO,
+                        "Entry and PNG dimensions do not match!")
                     );
 
                 }
@@ -208,8 +245,8 @@ impl<R: Read + Seek> ImageDecoder for ICODecoder<R> {
                 // https://blogs.msdn.microsoft.com/oldnewthing/20101022-00/?p=12473/
                 let color_type = try!(decoder.colortype());
                 if let ColorType::RGBA(8) = color_type {} else {
-                    return Err(ImageError::FormatError(
-                        "The PNG is not in RGBA format!".to_string()
+                    return Err(ImageError::malformed(ImageFormat::ICO,
+                        "The PNG is not in RGBA format!"
                     ));
                 }
 
@@ -217,9 +254,9 @@ impl<R: Read + Seek> ImageDecoder for ICODecoder<R> {
             }
             BMP(ref mut decoder) => {
                 let (width, height) = try!(decoder.dimensions());
-                if !self.selected_entry.matches_dimensions(width, height) {
-                    return Err(ImageError::FormatError(
-                        "Entry({:?}) and BMP({:?}) dimensions do not match!".to_string()
+                if !self.entries[self.selected].matches_dimensions(width, height) {
+                    return Err(ImageError::malformed(ImageFormat::ICO,
+                        "Entry({:?}) and BMP({:?}) dimensions do not match!"
                     ));
                 }
 
@@ -236,8 +273,8 @@ impl<R: Read + Seek> ImageDecoder for ICODecoder<R> {
                 // If there's an AND mask following the image, read and apply it.
                 let r = decoder.reader();
                 let mask_start = try!(r.seek(SeekFrom::Current(0)));
-                let mask_end = (self.selected_entry.image_offset + self.selected_entry.image_length) as u64;
-                let mask_length = mask_end - mask_start;
+                // The data of the entry starts at the beginning of the reader
+                let mask_length = u64::from(self.entries[self.selected].image_length).saturating_sub(mask_start);
 
                 if mask_length > 0 {
                     // A mask row contains 1 bit per pixel, padded to 4 bytes.
@@ -268,3 +305,83 @@ impl<R: Read + Seek> ImageDecoder for ICODecoder<R> {
         }
     }
 }
+
+impl<R: Read + Seek> MultiImageDecoder for ICODecoder<R> {
+    fn image_count(&mut self) -> ImageResult<usize> {
+        Ok(self.entries.len())
+    }
+
+    fn selected_image(&self) -> usize {
+        self.selected
+    }
+
+    fn select_image(&mut self, index: usize) -> ImageResult<()> {
+        let decoder = match self.entries.get(index) {
+            Some(entry) => try!(entry.decoder(&mut self.reader)),
+            None => return Err(ImageError::ImageEnd),
+        };
+        self.selected = index;
+        self.inner_decoder = decoder;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+    use std::io::Cursor;
+
+    use super::ICODecoder;
+    use color::ColorType;
+    use dynimage::guess_format;
+    use ico::ICOEncoder;
+    use image::{DecodingResult, ImageDecoder, ImageError, ImageFormat, MultiImageDecoder};
+
+    #[test]
+    fn test_select_image() {
+        let file = File::open("tests/images/ico/images/smile.ico").unwrap();
+        let mut decoder = ICODecoder::new(file).unwrap();
+        assert_eq!(decoder.image_count().unwrap(), 2);
+        assert_eq!(decoder.selected_image(), 0);
+        assert_eq!(decoder.dimensions().unwrap(), (32, 32));
+        assert_eq!(decoder.image_dimensions(1).unwrap(), (16, 16));
+        assert_eq!(decoder.image_colortype(1).unwrap(), ColorType::RGBA(8));
+        assert_eq!(decoder.selected_image(), 0);
+
+        for &(index, size) in &[(1, 16), (0, 32)] {
+            decoder.select_image(index).unwrap();
+            match decoder.read_image().unwrap() {
+                DecodingResult::U8(data) => assert_eq!(data.len(), size * size * 4),
+                DecodingResult::U16(_) => panic!("expected 8 bit data"),
+            }
+        }
+        match decoder.select_image(2) {
+            Err(ImageError::ImageEnd) => (),
+            _ => panic!("expected the end of the images"),
+        }
+    }
+
+    #[test]
+    fn test_cursor() {
+        let data: Vec<u8> = (0..4 * 5 * 3).map(|i| i as u8).collect();
+        let mut cursor = Vec::new();
+        ICOEncoder::new(&mut cursor).encode_cursor(&data, 5, 3, ColorType::RGBA(8), (4, 1)).unwrap();
+        assert_eq!(guess_format(&cursor).unwrap(), ImageFormat::ICO);
+
+        let mut decoder = ICODecoder::new(Cursor::new(&cursor)).unwrap();
+        assert!(decoder.is_cursor());
+        assert_eq!(decoder.hotspot(), Some((4, 1)));
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(read) => assert_eq!(read, data),
+            DecodingResult::U16(_) => panic!("expected 8 bit data"),
+        }
+
+        let mut icon = Vec::new();
+        ICOEncoder::new(&mut icon).encode(&data, 5, 3, ColorType::RGBA(8)).unwrap();
+        let decoder = ICODecoder::new(Cursor::new(&icon)).unwrap();
+        assert!(!decoder.is_cursor());
+        assert_eq!(decoder.hotspot(), None);
+
+        assert!(ICOEncoder::new(Vec::new()).encode_cursor(&data, 5, 3, ColorType::RGBA(8), (5, 0)).is_err());
+    }
+}
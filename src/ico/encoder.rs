@@ -7,6 +7,8 @@ use png::PNGEncoder;
 
 // Enum value indicating an ICO image (as opposed to a CUR image):
 const ICO_IMAGE_TYPE: u16 = 1;
+// Enum value indicating a CUR image:
+const CUR_IMAGE_TYPE: u16 = 2;
 // The length of an ICO file ICONDIR structure, in bytes:
 const ICO_ICONDIR_SIZE: u32 = 6;
 // The length of an ICO file DIRENTRY structure, in bytes:
@@ -28,14 +30,34 @@ impl<W: Write> ICOEncoder<W> {
     /// Encodes the image ```image``` that has dimensions ```width``` and
     /// ```height``` and ```ColorType``` ```c```.  The dimensions of the image
     /// must be between 1 and 256 (inclusive) or an error will be returned.
-    pub fn encode(mut self, data: &[u8], width: u32, height: u32,
+    pub fn encode(self, data: &[u8], width: u32, height: u32,
                   color: ColorType) -> io::Result<()> {
+        self.encode_image(data, width, height, color, ICO_IMAGE_TYPE,
+                          (0, bits_per_pixel(color) as u16))
+    }
+
+    /// Encodes the image as a cursor (CUR) instead of an icon. ```hotspot``` is the
+    /// position of the pixel which points at the target of the cursor, it must lie
+    /// inside of the image.
+    pub fn encode_cursor(self, data: &[u8], width: u32, height: u32,
+                         color: ColorType, hotspot: (u16, u16)) -> io::Result<()> {
+        if u32::from(hotspot.0) >= width || u32::from(hotspot.1) >= height {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "The hotspot of the cursor is outside of the image"));
+        }
+        self.encode_image(data, width, height, color, CUR_IMAGE_TYPE, hotspot)
+    }
+
+    // The meaning of the two fields of the directory entry depends on the image type,
+    // they hold the color planes and depth of icons and the hotspot of cursors
+    fn encode_image(mut self, data: &[u8], width: u32, height: u32, color: ColorType,
+                    image_type: u16, (planes_or_x, bits_or_y): (u16, u16)) -> io::Result<()> {
         let mut image_data: Vec<u8> = Vec::new();
         try!(PNGEncoder::new(&mut image_data).encode(
             data, width, height, color));
 
-        try!(write_icondir(&mut self.w, 1));
-        try!(write_direntry(&mut self.w, width, height, color,
+        try!(write_icondir(&mut self.w, image_type, 1));
+        try!(write_direntry(&mut self.w, width, height, planes_or_x, bits_or_y,
                             ICO_ICONDIR_SIZE + ICO_DIRENTRY_SIZE,
                             image_data.len() as u32));
         try!(self.w.write_all(&image_data));
@@ -43,18 +65,18 @@ impl<W: Write> ICOEncoder<W> {
     }
 }
 
-fn write_icondir<W: Write>(w: &mut W, num_images: u16) -> io::Result<()> {
+fn write_icondir<W: Write>(w: &mut W, image_type: u16, num_images: u16) -> io::Result<()> {
     // Reserved field (must be zero):
     try!(w.write_u16::<LittleEndian>(0));
     // Image type (ICO or CUR):
-    try!(w.write_u16::<LittleEndian>(ICO_IMAGE_TYPE));
+    try!(w.write_u16::<LittleEndian>(image_type));
     // Number of images in the file:
     try!(w.write_u16::<LittleEndian>(num_images));
     Ok(())
 }
 
 fn write_direntry<W: Write>(w: &mut W, width: u32, height: u32,
-                            color: ColorType, data_start: u32,
+                            planes_or_x: u16, bits_or_y: u16, data_start: u32,
                             data_size: u32) -> io::Result<()> {
     // Image dimensions:
     try!(write_width_or_height(w, width));
@@ -63,10 +85,10 @@ fn write_direntry<W: Write>(w: &mut W, width: u32, height: u32,
     try!(w.write_u8(0));
     // Reserved field (must be zero):
     try!(w.write_u8(0));
-    // Color planes:
-    try!(w.write_u16::<LittleEndian>(0));
-    // Bits per pixel:
-    try!(w.write_u16::<LittleEndian>(bits_per_pixel(color) as u16));
+    // Color planes of icons or the horizontal hotspot coordinate of cursors:
+    try!(w.write_u16::<LittleEndian>(planes_or_x));
+    // Bits per pixel of icons or the vertical hotspot coordinate of cursors:
+    try!(w.write_u16::<LittleEndian>(bits_or_y));
     // Image data size, in bytes:
     try!(w.write_u32::<LittleEndian>(data_size));
     // Image data offset, in bytes:
@@ -1,6 +1,7 @@
 //!  Decoding and Encoding of ICO files
 //!
-//!  A decoder and encoder for ICO (Windows Icon) image container files.
+//!  A decoder and encoder for ICO (Windows Icon) image container files and
+//!  CUR (Windows Cursor) files, which additionally store the hotspot of each image.
 //!
 //!  # Related Links
 //!  * <https://msdn.microsoft.com/en-us/library/ms997538.aspx>
@@ -1,21 +1,35 @@
+use std::cmp;
 use std::fmt;
 use std::mem;
-use std::io;
+use std::io::{self, Read};
+use std::iter;
+use std::ops::{Deref, DerefMut, Index, IndexMut};
 use std::error::Error;
 
+use byteorder::{BigEndian, ByteOrder};
+
 use color;
 use color::ColorType;
 use buffer::{ImageBuffer, Pixel};
 
 use animation::{Frame, Frames};
 use dynimage::decoder_to_image;
+use metadata::Metadata;
 
 /// An enumeration of Image errors
 #[derive(Debug)]
 pub enum ImageError {
     /// The Image is not formatted properly
+    ///
+    /// Since version 0.18 the decoders of this crate return the structured
+    /// ```Decoding``` error instead, code which matches on ```FormatError``` to
+    /// detect malformed images has to handle ```Decoding``` as well. This is kept
+    /// for decoders outside of the crate.
     FormatError(String),
 
+    /// The Image is not formatted properly, see ```DecodingError``` for the details
+    Decoding(DecodingError),
+
     /// The Image's dimensions are either too small or too large
     DimensionError,
 
@@ -36,10 +50,18 @@ pub enum ImageError {
     ImageEnd
 }
 
+impl ImageError {
+    /// Creates a ```Decoding``` error for malformed data of the format ```format```
+    pub fn malformed<S: Into<String>>(format: ImageFormat, message: S) -> ImageError {
+        ImageError::Decoding(DecodingError::malformed(format, message))
+    }
+}
+
 impl fmt::Display for ImageError {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match *self {
             ImageError::FormatError(ref e) => write!(fmt, "Format error: {}", e),
+            ImageError::Decoding(ref e) => e.fmt(fmt),
             ImageError::DimensionError => write!(fmt, "The Image's dimensions are either too \
                                                         small or too large"),
             ImageError::UnsupportedError(ref f) => write!(fmt, "The Decoder does not support the \
@@ -58,6 +80,7 @@ impl Error for ImageError {
     fn description (&self) -> &str {
         match *self {
             ImageError::FormatError(..) => "Format error",
+            ImageError::Decoding(..) => "Decoding error",
             ImageError::DimensionError => "Dimension error",
             ImageError::UnsupportedError(..) => "Unsupported error",
             ImageError::UnsupportedColor(..) => "Unsupported color",
@@ -70,6 +93,7 @@ impl Error for ImageError {
     fn cause (&self) -> Option<&Error> {
         match *self {
             ImageError::IoError(ref e) => Some(e),
+            ImageError::Decoding(ref e) => e.cause(),
             _ => None
         }
     }
@@ -81,6 +105,140 @@ impl From<io::Error> for ImageError {
     }
 }
 
+impl From<DecodingError> for ImageError {
+    fn from(err: DecodingError) -> ImageError {
+        ImageError::Decoding(err)
+    }
+}
+
+/// An error in the data of an image
+#[derive(Debug)]
+pub struct DecodingError {
+    format: ImageFormat,
+    kind: DecodingErrorKind,
+    offset: Option<u64>,
+    source: Option<Box<Error + Send + Sync>>,
+}
+
+/// The kind of a ```DecodingError```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodingErrorKind {
+    /// The data does not start with the signature of the format
+    InvalidSignature,
+
+    /// A field of the image has a value which is not allowed
+    InvalidValue {
+        /// The name of the field
+        field: &'static str,
+        /// A description of the allowed values
+        expected: String,
+        /// The value which was found
+        found: String,
+    },
+
+    /// A checksum does not match the data
+    ChecksumMismatch {
+        /// The checksum stored in the image
+        expected: u32,
+        /// The checksum of the data
+        found: u32,
+    },
+
+    /// The data is malformed in some other way, described by a message
+    Malformed(String),
+}
+
+impl DecodingError {
+    /// Creates an error of the kind ```kind``` in an image of the format ```format```
+    pub fn new(format: ImageFormat, kind: DecodingErrorKind) -> DecodingError {
+        DecodingError {
+            format,
+            kind,
+            offset: None,
+            source: None,
+        }
+    }
+
+    /// Creates a ```Malformed``` error with the message ```message```
+    pub fn malformed<S: Into<String>>(format: ImageFormat, message: S) -> DecodingError {
+        DecodingError::new(format, DecodingErrorKind::Malformed(message.into()))
+    }
+
+    /// Creates an ```InvalidValue``` error
+    pub fn invalid_value<E: fmt::Display, F: fmt::Display>(format: ImageFormat, field: &'static str,
+                                                         expected: E, found: F) -> DecodingError {
+        DecodingError::new(format, DecodingErrorKind::InvalidValue {
+            field,
+            expected: expected.to_string(),
+            found: found.to_string(),
+        })
+    }
+
+    /// Sets the byte offset of the error, relative to the start of the image
+    pub fn with_offset(mut self, offset: u64) -> DecodingError {
+        self.offset = Some(offset);
+        self
+    }
+
+    /// Sets the underlying error, e.g. the error of an external decoder
+    pub fn with_source<E: Into<Box<Error + Send + Sync>>>(mut self, source: E) -> DecodingError {
+        self.source = Some(source.into());
+        self
+    }
+
+    /// Returns the format of the image
+    pub fn format(&self) -> ImageFormat {
+        self.format
+    }
+
+    /// Returns the kind of the error
+    pub fn kind(&self) -> &DecodingErrorKind {
+        &self.kind
+    }
+
+    /// Returns the byte offset of the error if it is known
+    pub fn offset(&self) -> Option<u64> {
+        self.offset
+    }
+}
+
+impl fmt::Display for DecodingError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        #[allow(deprecated)]
+        let format = match self.format {
+            ImageFormat::PPM => ImageFormat::PNM,
+            format => format,
+        };
+        write!(fmt, "{:?} decoding error", format)?;
+        if let Some(offset) = self.offset {
+            write!(fmt, " at byte {}", offset)?;
+        }
+        match self.kind {
+            DecodingErrorKind::InvalidSignature => write!(fmt, ": invalid signature")?,
+            DecodingErrorKind::InvalidValue { field, ref expected, ref found } => {
+                write!(fmt, ": invalid {}, expected {}, found {}", field, expected, found)?
+            }
+            DecodingErrorKind::ChecksumMismatch { expected, found } => {
+                write!(fmt, ": checksum mismatch, expected {:#010x}, found {:#010x}", expected, found)?
+            }
+            DecodingErrorKind::Malformed(ref message) => write!(fmt, ": {}", message)?,
+        }
+        if let Some(ref source) = self.source {
+            write!(fmt, " ({})", source)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for DecodingError {
+    fn description(&self) -> &str {
+        "Decoding error"
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        self.source.as_ref().map(|source| &**source as &Error)
+    }
+}
 
 /// Result of an image decoding/encoding process
 pub type ImageResult<T> = Result<T, ImageError>;
@@ -94,16 +252,11 @@ pub enum DecodingResult {
     U16(Vec<u16>)
 }
 
-// A buffer for image decoding
-pub enum DecodingBuffer<'a> {
-    /// A slice of unsigned bytes
-    U8(&'a mut [u8]),
-    /// A slice of unsigned words
-    U16(&'a mut [u16])
-}
-
 /// An enumeration of supported image formats.
 /// Not all formats support both encoding and decoding.
+///
+/// The variants do not depend on the enabled codec features, a format whose
+/// codec is not compiled in is reported as an ```UnsupportedError```.
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum ImageFormat {
     /// An Image in PNG Format
@@ -134,7 +287,7 @@ pub enum ImageFormat {
     /// An Image in BMP Format
     BMP,
 
-    /// An Image in ICO Format
+    /// An Image in ICO Format, which includes CUR cursors
     ICO,
 
     /// An Image in Radiance HDR Format
@@ -158,6 +311,31 @@ pub trait ImageDecoder: Sized {
     /// Decodes the entire image and return it as a Vector
     fn read_image(&mut self) -> ImageResult<DecodingResult>;
 
+    /// Decodes the entire image into ```buf```, which must be exactly as long
+    /// as the vector returned by ```read_image```. This allows decoding straight
+    /// into the storage of an existing ```ImageBuffer```.
+    ///
+    /// The PNG and GIF decoders write their output to the supplied buffer, the
+    /// default implementation copies the result of ```read_image```. Images
+    /// with more than 8 bits per sample are not supported.
+    fn read_image_into(&mut self, buf: &mut [u8]) -> ImageResult<()> {
+        match self.read_image()? {
+            DecodingResult::U8(ref data) if data.len() == buf.len() => {
+                buf.copy_from_slice(data);
+                Ok(())
+            }
+            DecodingResult::U8(_) => Err(ImageError::DimensionError),
+            DecodingResult::U16(_) => Err(ImageError::UnsupportedColor(self.colortype()?)),
+        }
+    }
+
+    /// Returns the metadata stored in the image, e.g. its EXIF orientation
+    ///
+    /// Formats without metadata return an empty ```Metadata```.
+    fn metadata(&mut self) -> ImageResult<Metadata> {
+        Ok(Metadata::default())
+    }
+
     /// Returns true if the image is animated
     fn is_animated(&mut self) -> ImageResult<bool> {
         // since most image formats do not support animation
@@ -168,10 +346,46 @@ pub trait ImageDecoder: Sized {
     /// Returns the frames of the image
     ///
     /// If the image is not animated it returns a single frame
-    fn into_frames(self) -> ImageResult<Frames> {
-        Ok(Frames::new(vec![
-            Frame::new(try!(decoder_to_image(self)).to_rgba())
-        ]))
+    fn into_frames<'a>(self) -> ImageResult<Frames<'a>> where Self: 'a {
+        let frame = Frame::from_image(decoder_to_image(self)?);
+        Ok(Frames::new(Box::new(iter::once(Ok(frame)))))
+    }
+
+    /// Returns the number of bytes of the decoded image, i.e. the length of the data
+    /// returned by ```read_image``` or ```into_reader```
+    ///
+    /// Every row of the image starts at a byte boundary.
+    fn total_bytes(&mut self) -> ImageResult<u64> {
+        let (width, height) = self.dimensions()?;
+        let row_bits = u64::from(width) * color::bits_per_pixel(self.colortype()?) as u64;
+        Ok((row_bits + 7) / 8 * u64::from(height))
+    }
+
+    /// Returns a reader of the raw decoded samples of the image, see ```Reader```
+    ///
+    /// Decoders which can decode an image row by row, e.g. non-interlaced PNG, only hold
+    /// one row in memory at a time. The default implementation decodes the entire image
+    /// with ```read_image``` first.
+    fn into_reader<'a>(self) -> ImageResult<Reader<'a>> where Self: 'a {
+        Reader::from_image(self)
+    }
+
+    /// Asks the decoder to produce an image of reduced resolution which is at
+    /// least ```width``` x ```height``` pixels large in one of the axes.
+    ///
+    /// This is only a hint for formats which can natively decode smaller versions
+    /// of an image more quickly. Only two decoders reduce the resolution: JPEG scales
+    /// in the inverse DCT and TIFF selects a reduced resolution version stored in the
+    /// file, if there is one. The image is never scaled up. Returns the dimensions the
+    /// image will be decoded with, which are also reported by ```dimensions```.
+    /// Resample the image afterwards to get an exact size.
+    ///
+    /// The default implementation ignores the hint and decodes the full image. All
+    /// other decoders use it, including PNG: skipping rows would still require
+    /// decompressing all of them, so it would not save much over resampling.
+    fn set_target_dimensions(&mut self, width: u32, height: u32) -> ImageResult<(u32, u32)> {
+        let _ = (width, height);
+        self.dimensions()
     }
 
     /// Decodes a specific region of the image, represented by the rectangle
@@ -216,6 +430,128 @@ pub trait ImageDecoder: Sized {
     }
 }
 
+/// A decoder of a container which holds several independent images, e.g. the
+/// sizes of an icon in an ICO file or the pages of a TIFF file
+///
+/// The methods of ```ImageDecoder``` apply to the selected image. Initially this is
+/// the image which the format decodes by default.
+pub trait MultiImageDecoder: ImageDecoder {
+    /// Returns the number of images in the container
+    fn image_count(&mut self) -> ImageResult<usize>;
+
+    /// Returns the index of the selected image
+    fn selected_image(&self) -> usize;
+
+    /// Selects the image with the index ```index```, the following calls of the
+    /// ```ImageDecoder``` methods decode this image.
+    ///
+    /// Returns ```ImageError::ImageEnd``` if there is no such image.
+    fn select_image(&mut self, index: usize) -> ImageResult<()>;
+
+    /// Returns the dimensions of the image with the index ```index```
+    ///
+    /// The default implementation selects the image and restores the selection afterwards.
+    fn image_dimensions(&mut self, index: usize) -> ImageResult<(u32, u32)> {
+        let selected = self.selected_image();
+        self.select_image(index)?;
+        let dimensions = self.dimensions();
+        self.select_image(selected)?;
+        dimensions
+    }
+
+    /// Returns the color type of the image with the index ```index```
+    ///
+    /// The default implementation selects the image and restores the selection afterwards.
+    fn image_colortype(&mut self, index: usize) -> ImageResult<ColorType> {
+        let selected = self.selected_image();
+        self.select_image(index)?;
+        let colortype = self.colortype();
+        self.select_image(selected)?;
+        colortype
+    }
+}
+
+/// A reader of the raw samples of a decoded image, returned by ```ImageDecoder::into_reader```
+///
+/// The rows are read from top to bottom, in the same layout as the data returned by
+/// ```read_image```. Samples of ```DecodingResult::U16``` are big endian, like the 16 bit
+/// samples of PNG images and the input of the encoders. The length of the data is
+/// reported by ```ImageDecoder::total_bytes```. Decoding errors are returned as
+/// ```io::Error``` of the kind ```InvalidData``` which wrap the ```ImageError```.
+pub struct Reader<'a> {
+    inner: Box<Read + 'a>,
+}
+
+impl<'a> Reader<'a> {
+    /// Creates a new ```Reader``` from an implementation specific reader
+    pub fn new(inner: Box<Read + 'a>) -> Reader<'a> {
+        Reader { inner }
+    }
+
+    /// Creates a ```Reader``` which decodes one row at a time with ```read_scanline```.
+    ///
+    /// This must only be used with decoders whose ```read_scanline``` returns complete
+    /// rows from top to bottom.
+    pub fn from_scanlines<D: ImageDecoder + 'a>(mut decoder: D) -> ImageResult<Reader<'a>> {
+        let (_, height) = decoder.dimensions()?;
+        let row_len = decoder.row_len()?;
+        Ok(Reader::new(Box::new(Scanlines {
+            decoder,
+            row: vec![0; row_len],
+            position: row_len,
+            remaining: height,
+        })))
+    }
+
+    /// Creates a ```Reader``` of the image decoded at once with ```read_image```
+    pub fn from_image<D: ImageDecoder>(mut decoder: D) -> ImageResult<Reader<'a>> {
+        let data = match decoder.read_image()? {
+            DecodingResult::U8(data) => data,
+            DecodingResult::U16(samples) => {
+                let mut data = vec![0; samples.len() * 2];
+                BigEndian::write_u16_into(&samples, &mut data);
+                data
+            }
+        };
+        Ok(Reader::new(Box::new(io::Cursor::new(data))))
+    }
+}
+
+impl<'a> Read for Reader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+// Reads the rows of a decoder with read_scanline
+struct Scanlines<D> {
+    decoder: D,
+    row: Vec<u8>,
+    // The number of bytes of the current row which were already read
+    position: usize,
+    // The number of rows which were not decoded yet
+    remaining: u32,
+}
+
+impl<D: ImageDecoder> Read for Scanlines<D> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.row.len() {
+            if self.remaining == 0 {
+                return Ok(0)
+            }
+            self.decoder.read_scanline(&mut self.row).map_err(|err| match err {
+                ImageError::IoError(err) => err,
+                err => io::Error::new(io::ErrorKind::InvalidData, err),
+            })?;
+            self.remaining -= 1;
+            self.position = 0;
+        }
+        let n = cmp::min(buf.len(), self.row.len() - self.position);
+        buf[..n].copy_from_slice(&self.row[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
 
 /// Immutable pixel iterator
 pub struct Pixels<'a, I: 'a> {
@@ -485,6 +821,31 @@ impl<'a, I: GenericImage + 'static> SubImage<'a, I>
     }
 }
 
+impl<'a, P, Container> Index<(u32, u32)> for SubImage<'a, ImageBuffer<P, Container>>
+where P: Pixel + 'static,
+      P::Subpixel: 'static,
+      Container: Deref<Target=[P::Subpixel]> {
+    type Output = P;
+
+    fn index(&self, (x, y): (u32, u32)) -> &P {
+        assert!(x < self.xstride && y < self.ystride,
+                "Image index {:?} out of bounds {:?}", (x, y), (self.xstride, self.ystride));
+        &self.image[(x + self.xoffset, y + self.yoffset)]
+    }
+}
+
+impl<'a, P, Container> IndexMut<(u32, u32)> for SubImage<'a, ImageBuffer<P, Container>>
+where P: Pixel + 'static,
+      P::Subpixel: 'static,
+      Container: Deref<Target=[P::Subpixel]> + DerefMut {
+
+    fn index_mut(&mut self, (x, y): (u32, u32)) -> &mut P {
+        assert!(x < self.xstride && y < self.ystride,
+                "Image index {:?} out of bounds {:?}", (x, y), (self.xstride, self.ystride));
+        &mut self.image[(x + self.xoffset, y + self.yoffset)]
+    }
+}
+
 #[allow(deprecated)]
 // TODO: Is the 'static bound on `I` really required? Can we avoid it?
 impl<'a, I: GenericImage + 'static> GenericImage for SubImage<'a, I>
@@ -545,6 +906,30 @@ mod tests {
         assert!(*target.get_pixel(0, 0) == Rgba([169, 85, 0, 190]));
     }
 
+    #[test]
+    fn test_sub_image_index() {
+        use super::SubImage;
+
+        let mut image = ImageBuffer::new(4, 4);
+        {
+            let mut sub = SubImage::new(&mut image, 1, 2, 2, 2);
+            sub[(1, 0)] = Rgba([1u8, 2, 3, 4]);
+            assert_eq!(sub[(1, 0)], Rgba([1, 2, 3, 4]));
+        }
+        assert_eq!(image[(2, 2)], Rgba([1, 2, 3, 4]));
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn test_sub_image_index_out_of_bounds() {
+        use super::SubImage;
+
+        // The pixel exists in the image, but not in the sub image
+        let mut image: ImageBuffer<Rgba<u8>, _> = ImageBuffer::new(4, 4);
+        let sub = SubImage::new(&mut image, 1, 1, 2, 2);
+        let _ = sub[(2, 0)];
+    }
+
     #[test]
     fn test_in_bounds() {
         let mut target = ImageBuffer::new(2, 2);
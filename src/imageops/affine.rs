@@ -1,8 +1,28 @@
 //! Functions for performing affine transformations.
 
+use num_iter::range_step;
+
 use buffer::{ImageBuffer, Pixel};
 use image::GenericImage;
 
+// The side length of the tiles in which images are rotated by 90 degrees.
+// Walking a tile at a time keeps the source and the destination rows of the
+// tile in the cache, instead of touching a new destination row for every pixel.
+const TILE_SIZE: u32 = 64;
+
+// Calls ```f``` with the coordinates of every pixel, one tile after the other.
+fn for_each_tiled<F: FnMut(u32, u32)>(width: u32, height: u32, mut f: F) {
+    for y0 in range_step(0, height, TILE_SIZE) {
+        for x0 in range_step(0, width, TILE_SIZE) {
+            for y in y0..(y0 + TILE_SIZE).min(height) {
+                for x in x0..(x0 + TILE_SIZE).min(width) {
+                    f(x, y);
+                }
+            }
+        }
+    }
+}
+
 /// Rotate an image 90 degrees clockwise.
 // TODO: Is the 'static bound on `I` really required? Can we avoid it?
 pub fn rotate90<I: GenericImage + 'static>(image:  &I)
@@ -12,12 +32,10 @@ pub fn rotate90<I: GenericImage + 'static>(image:  &I)
     let (width, height) = image.dimensions();
     let mut out = ImageBuffer::new(height, width);
 
-    for y in 0..height {
-        for x in 0..width {
-            let p = image.get_pixel(x, y);
-            out.put_pixel(height - 1 - y, x, p);
-        }
-    }
+    for_each_tiled(width, height, |x, y| {
+        let p = image.get_pixel(x, y);
+        out.put_pixel(height - 1 - y, x, p);
+    });
 
     out
 }
@@ -50,12 +68,10 @@ pub fn rotate270<I: GenericImage + 'static>(image:  &I)
     let (width, height) = image.dimensions();
     let mut out = ImageBuffer::new(height, width);
 
-    for y in 0..height {
-        for x in 0..width {
-            let p = image.get_pixel(x, y);
-            out.put_pixel(y, width - 1 - x, p);
-        }
-    }
+    for_each_tiled(width, height, |x, y| {
+        let p = image.get_pixel(x, y);
+        out.put_pixel(y, width - 1 - x, p);
+    });
 
     out
 }
@@ -199,6 +215,24 @@ mod test {
         assert_pixels_eq!(&flip_vertical(&image), &expected);
     }
 
+    #[test]
+    fn test_rotate_across_tiles() {
+        // Larger than a tile and not a multiple of the tile size
+        let image: GrayImage = ImageBuffer::from_fn(150, 70, |x, y| {
+            ::Luma([(x * 3 + y * 7) as u8])
+        });
+
+        let expected90: GrayImage = ImageBuffer::from_fn(70, 150, |x, y| {
+            *image.get_pixel(y, 69 - x)
+        });
+        let expected270: GrayImage = ImageBuffer::from_fn(70, 150, |x, y| {
+            *image.get_pixel(149 - y, x)
+        });
+
+        assert_pixels_eq!(&rotate90(&image), &expected90);
+        assert_pixels_eq!(&rotate270(&image), &expected270);
+    }
+
     fn pixel_diffs<I, J, P>(left: &I, right: &J) -> Vec<((u32, u32, P), (u32, u32, P))>
         where I: GenericImage<Pixel=P>,
               J: GenericImage<Pixel=P>,
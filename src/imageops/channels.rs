@@ -0,0 +1,93 @@
+//! Splitting images into their channels and back
+
+use buffer::{ImageBuffer, Pixel};
+use color::Luma;
+use image::{GenericImage, ImageError, ImageResult};
+use traits::Primitive;
+
+/// Split the supplied image into one grayscale image per channel.
+/// The images are returned in the order of the channels of the pixel type, e.g. red, green,
+/// blue and alpha for RGBA images.
+pub fn split_channels<I, P, S>(image: &I) -> Vec<ImageBuffer<Luma<S>, Vec<S>>>
+    where I: GenericImage<Pixel=P>,
+          P: Pixel<Subpixel=S> + 'static,
+          S: Primitive + 'static {
+    let (width, height) = image.dimensions();
+    let mut planes: Vec<_> = (0..P::channel_count()).map(|_| ImageBuffer::new(width, height)).collect();
+    for (x, y, pixel) in image.pixels() {
+        for (plane, &c) in planes.iter_mut().zip(pixel.channels()) {
+            plane.put_pixel(x, y, Luma([c]));
+        }
+    }
+    planes
+}
+
+/// Combine grayscale images into one image with a channel for each of them, the inverse of
+/// ```split_channels```.
+/// Returns a ```DimensionError``` if the number of images does not match the number of
+/// channels of the pixel type or if the images have different dimensions.
+pub fn merge_channels<I, P, S>(planes: &[I]) -> ImageResult<ImageBuffer<P, Vec<S>>>
+    where I: GenericImage<Pixel=Luma<S>>,
+          P: Pixel<Subpixel=S> + 'static,
+          S: Primitive + 'static {
+    if planes.len() != P::channel_count() as usize {
+        return Err(ImageError::DimensionError)
+    }
+    let (width, height) = planes[0].dimensions();
+    if planes.iter().any(|plane| plane.dimensions() != (width, height)) {
+        return Err(ImageError::DimensionError)
+    }
+    let mut out: ImageBuffer<P, Vec<S>> = ImageBuffer::new(width, height);
+    for (x, y, pixel) in out.enumerate_pixels_mut() {
+        for (c, plane) in pixel.channels_mut().iter_mut().zip(planes) {
+            *c = plane.get_pixel(x, y)[0];
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{merge_channels, split_channels};
+    use buffer::{GrayImage, ImageBuffer, RgbImage, RgbaImage};
+    use color::{Luma, Rgba};
+    use image::{ImageError, ImageResult};
+    use imageops::blur;
+
+    #[test]
+    fn test_split_merge() {
+        let image: RgbaImage = ImageBuffer::from_fn(3, 2, |x, y| Rgba([x as u8, y as u8, 7, 255 - x as u8]));
+        let planes = split_channels(&image);
+        assert_eq!(planes.len(), 4);
+        assert_eq!(planes[0].as_ref(), &[0, 1, 2, 0, 1, 2][..]);
+        assert_eq!(planes[3].as_ref(), &[255, 254, 253, 255, 254, 253][..]);
+        let merged: RgbaImage = merge_channels(&planes).unwrap();
+        assert_eq!(merged.into_raw(), image.into_raw());
+    }
+
+    #[test]
+    fn test_process_one_channel() {
+        let image: RgbaImage = ImageBuffer::from_fn(8, 8, |x, _| Rgba([10, 20, 30, if x < 4 { 0 } else { 255 }]));
+        let mut planes = split_channels(&image);
+        planes[3] = blur(&planes[3], 1.0);
+        let merged: RgbaImage = merge_channels(&planes).unwrap();
+        assert!(merged.pixels().all(|p| p[0] == 10 && p[1] == 20 && p[2] == 30));
+        assert!(merged[(3, 0)][3] > 0 && merged[(4, 0)][3] < 255);
+    }
+
+    #[test]
+    fn test_merge_mismatch() {
+        let planes = vec![GrayImage::new(2, 2), GrayImage::new(2, 2)];
+        let result: ImageResult<RgbaImage> = merge_channels(&planes);
+        match result {
+            Err(ImageError::DimensionError) => (),
+            _ => panic!("expected a dimension error"),
+        }
+        let planes = vec![GrayImage::new(2, 2), GrayImage::new(2, 2), GrayImage::from_pixel(2, 3, Luma([1]))];
+        let result: ImageResult<RgbImage> = merge_channels(&planes);
+        match result {
+            Err(ImageError::DimensionError) => (),
+            _ => panic!("expected a dimension error"),
+        }
+    }
+}
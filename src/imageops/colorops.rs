@@ -1,15 +1,19 @@
 //! Functions for altering and converting the color of pixelbufs
 
-use color::{Luma, Rgba};
+use color::{Luma, LumaA, Rgb, Rgba};
 use buffer::{ImageBuffer, Pixel};
 use traits::Primitive;
 use image::GenericImage;
 use math::utils::clamp;
 use math::nq;
+use utils::{is_float, normalized_max};
+use std::f64;
 use std::f64::consts::PI;
 use num_traits::{Num, NumCast};
 
 /// Convert the supplied image to grayscale
+/// The channels are weighted according to Rec. 709, see ```grayscale_with_weights``` for
+/// other conventions.
 pub fn grayscale<I: GenericImage>(image: &I)
     -> ImageBuffer<Luma<<I::Pixel as Pixel>::Subpixel>, Vec<<I::Pixel as Pixel>::Subpixel>>
     where <I::Pixel as Pixel>::Subpixel: 'static,
@@ -27,6 +31,73 @@ pub fn grayscale<I: GenericImage>(image: &I)
     out
 }
 
+/// The weights of the red, green and blue channels in a conversion to grayscale
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GrayscaleWeights {
+    /// The luma coefficients of ITU-R BT.601, used by analog TV and JPEG
+    Rec601,
+
+    /// The luma coefficients of ITU-R BT.709 and sRGB, used by ```grayscale```
+    Rec709,
+
+    /// The plain average of the three channels
+    Average,
+
+    /// Custom weights of red, green and blue, they should add up to 1
+    Custom(f32, f32, f32),
+}
+
+impl GrayscaleWeights {
+    fn coefficients(self) -> [f32; 3] {
+        match self {
+            GrayscaleWeights::Rec601 => [0.299, 0.587, 0.114],
+            GrayscaleWeights::Rec709 => [0.2126, 0.7152, 0.0722],
+            GrayscaleWeights::Average => [1.0 / 3.0; 3],
+            GrayscaleWeights::Custom(r, g, b) => [r, g, b],
+        }
+    }
+}
+
+/// Convert the supplied image to grayscale with the given channel weights.
+/// The alpha channel is dropped, use ```grayscale_alpha_with_weights``` to keep it.
+pub fn grayscale_with_weights<I, P, S>(image: &I, weights: GrayscaleWeights)
+    -> ImageBuffer<Luma<S>, Vec<S>>
+    where I: GenericImage<Pixel=P>,
+          P: Pixel<Subpixel=S> + 'static,
+          S: Primitive + 'static {
+    let (width, height) = image.dimensions();
+    ImageBuffer::from_fn(width, height, |x, y| {
+        Luma([weighted_luma(&image.get_pixel(x, y), weights)])
+    })
+}
+
+/// Convert the supplied image to grayscale with an alpha channel with the given channel
+/// weights.
+/// The alpha channel is kept, images without one become fully opaque.
+pub fn grayscale_alpha_with_weights<I, P, S>(image: &I, weights: GrayscaleWeights)
+    -> ImageBuffer<LumaA<S>, Vec<S>>
+    where I: GenericImage<Pixel=P>,
+          P: Pixel<Subpixel=S> + 'static,
+          S: Primitive + 'static {
+    let (width, height) = image.dimensions();
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let pixel = image.get_pixel(x, y);
+        LumaA([weighted_luma(&pixel, weights), pixel.to_rgba()[3]])
+    })
+}
+
+fn weighted_luma<P, S>(pixel: &P, weights: GrayscaleWeights) -> S
+    where P: Pixel<Subpixel=S>,
+          S: Primitive + 'static {
+    let rgb = pixel.to_rgb();
+    let l: f32 = rgb.data.iter().zip(weights.coefficients().iter()).map(|(&c, w)| {
+        let c: f32 = NumCast::from(c).unwrap();
+        c * w
+    }).sum();
+    let l = clamp(l, 0.0, normalized_max::<S>() as f32);
+    NumCast::from(if is_float::<S>() { l } else { l.round() }).unwrap()
+}
+
 /// Invert each pixel within the supplied image.
 /// This function operates in place.
 pub fn invert<I: GenericImage>(image: &mut I) {
@@ -169,6 +240,566 @@ pub fn huerotate<I, P, S>(image: &I, value: i32)
     out
 }
 
+/// Posterize the supplied image.
+/// Each color channel is reduced to ```levels``` evenly spaced values, rounding to the
+/// nearest one.
+///
+/// # Panics
+///
+/// Panics if ```levels``` is less than 2.
+pub fn posterize<I, P, S>(image: &I, levels: u32)
+    -> ImageBuffer<P, Vec<S>>
+    where I: GenericImage<Pixel=P>,
+          P: Pixel<Subpixel=S> + 'static,
+          S: Primitive + 'static {
+    assert!(levels >= 2, "Posterizing needs at least 2 levels");
+    let max = normalized_max::<S>();
+    let steps = (levels - 1) as f64;
+    let round = !is_float::<S>();
+
+    let (width, height) = image.dimensions();
+    ImageBuffer::from_fn(width, height, |x, y| {
+        image.get_pixel(x, y).map_with_alpha(|c| {
+            let c: f64 = NumCast::from(c).unwrap();
+            let v = (c / max * steps).round() * max / steps;
+            NumCast::from(if round { v.round() } else { v }).unwrap()
+        }, |alpha| alpha)
+    })
+}
+
+/// Solarize the supplied image.
+/// Color channel values above ```threshold``` are inverted.
+pub fn solarize<I, P, S>(image: &I, threshold: S)
+    -> ImageBuffer<P, Vec<S>>
+    where I: GenericImage<Pixel=P>,
+          P: Pixel<Subpixel=S> + 'static,
+          S: Primitive + 'static {
+    let max: S = NumCast::from(normalized_max::<S>()).unwrap();
+
+    let (width, height) = image.dimensions();
+    ImageBuffer::from_fn(width, height, |x, y| {
+        image.get_pixel(x, y).map_with_alpha(|c| {
+            if c > threshold { max - c } else { c }
+        }, |alpha| alpha)
+    })
+}
+
+/// Darken the supplied image towards its corners.
+/// ```strength``` is the amount by which the corners are darkened, from 0 (unchanged) to 1
+/// (black). ```falloff``` controls how quickly the darkening sets in with the distance from
+/// the center, e.g. 1 is linear and larger values keep more of the center bright.
+pub fn vignette<I, P, S>(image: &I, strength: f32, falloff: f32)
+    -> ImageBuffer<P, Vec<S>>
+    where I: GenericImage<Pixel=P>,
+          P: Pixel<Subpixel=S> + 'static,
+          S: Primitive + 'static {
+    let round = !is_float::<S>();
+    let (width, height) = image.dimensions();
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let r = center_distance(x, y, width, height);
+        let factor = clamp(1.0 - strength * r.powf(falloff), 0.0, 1.0);
+        image.get_pixel(x, y).map_with_alpha(|c| {
+            let c: f32 = NumCast::from(c).unwrap();
+            NumCast::from(if round { (c * factor).round() } else { c * factor }).unwrap()
+        }, |alpha| alpha)
+    })
+}
+
+/// Create an image filled with a radial gradient from ```inner``` in the center to
+/// ```outer``` in the corners.
+pub fn radial_gradient<P>(width: u32, height: u32, inner: P, outer: P)
+    -> ImageBuffer<P, Vec<P::Subpixel>>
+    where P: Pixel + 'static,
+          P::Subpixel: 'static {
+    let round = !is_float::<P::Subpixel>();
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let r = center_distance(x, y, width, height);
+        inner.map2(&outer, |a, b| {
+            let (a, b): (f32, f32) = (NumCast::from(a).unwrap(), NumCast::from(b).unwrap());
+            let v = a + (b - a) * r;
+            NumCast::from(if round { v.round() } else { v }).unwrap()
+        })
+    })
+}
+
+// The distance of the center of a pixel from the center of the image, relative to the
+// distance of the corners
+fn center_distance(x: u32, y: u32, width: u32, height: u32) -> f32 {
+    let (cx, cy) = (width as f32 / 2.0, height as f32 / 2.0);
+    let (dx, dy) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+    ((dx * dx + dy * dy) / (cx * cx + cy * cy)).sqrt()
+}
+
+/// Make the pixels of the supplied image transparent whose color is close to
+/// ```key_color```, e.g. for green-screen cutouts.
+/// Colors are compared by their Euclidean distance in RGB space with the channels
+/// normalized to the range from 0 to 1. Pixels closer than ```tolerance``` become fully
+/// transparent, over a further distance of ```softness``` the transparency fades out to
+/// give a soft edge. Existing transparency is kept.
+/// This function operates in place.
+pub fn chroma_key<I, S>(image: &mut I, key_color: Rgb<S>, tolerance: f32, softness: f32)
+    where I: GenericImage<Pixel=Rgba<S>>,
+          S: Primitive + 'static {
+    let max = normalized_max::<S>() as f32;
+    let round = !is_float::<S>();
+    let normalize = |c: S| -> f32 { NumCast::from(c).map(|c: f32| c / max).unwrap() };
+    let key: Vec<f32> = key_color.channels().iter().map(|&c| normalize(c)).collect();
+
+    let (width, height) = image.dimensions();
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = image.get_pixel_mut(x, y);
+            let distance = pixel.data[..3].iter().zip(&key).map(|(&c, k)| {
+                (normalize(c) - k) * (normalize(c) - k)
+            }).sum::<f32>().sqrt();
+            let opacity = if distance <= tolerance {
+                0.0
+            } else if distance < tolerance + softness {
+                (distance - tolerance) / softness
+            } else {
+                continue
+            };
+            let alpha: f32 = NumCast::from(pixel.data[3]).unwrap();
+            let alpha = alpha * opacity;
+            pixel.data[3] = NumCast::from(if round { alpha.round() } else { alpha }).unwrap();
+        }
+    }
+}
+
+/// Correct the white balance of the supplied image so that ```white``` becomes a neutral
+/// gray of the same brightness.
+/// ```white``` is typically the color of a patch that is known to be white or gray in the
+/// scene. Only images with RGB colors are changed, others are copied unchanged.
+pub fn white_balance<I, P, S>(image: &I, white: Rgb<S>)
+    -> ImageBuffer<P, Vec<S>>
+    where I: GenericImage<Pixel=P>,
+          P: Pixel<Subpixel=S> + 'static,
+          S: Primitive + 'static {
+    let white: Vec<f32> = white.data.iter().map(|&c| NumCast::from(c).unwrap()).collect();
+    let mean = white.iter().sum::<f32>() / 3.0;
+    let gain = |c: f32| if c > 0.0 { mean / c } else { 1.0 };
+    scale_rgb(image, [gain(white[0]), gain(white[1]), gain(white[2])])
+}
+
+/// Correct the white balance of the supplied image with the gray world assumption, i.e.
+/// that the average color of the scene is a neutral gray.
+/// Only images with RGB colors are changed, others are copied unchanged.
+pub fn gray_world_white_balance<I, P, S>(image: &I)
+    -> ImageBuffer<P, Vec<S>>
+    where I: GenericImage<Pixel=P>,
+          P: Pixel<Subpixel=S> + 'static,
+          S: Primitive + 'static {
+    match average_color(image) {
+        Some(average) => white_balance(image, average.to_rgb()),
+        None => scale_rgb(image, [1.0; 3]),
+    }
+}
+
+/// Adjust the color temperature and tint of the supplied image.
+/// ```kelvin``` is the color temperature of the light the image was taken in, the colors
+/// are corrected so that this light appears white, e.g. 3200 for tungsten light makes the
+/// image cooler and 9000 for shade makes it warmer. 6500 leaves the temperature unchanged.
+/// ```tint``` shifts the colors between green and magenta, positive values make the image
+/// more magenta and 1 halves the green channel, negative values make it more green.
+/// Only images with RGB colors are changed, others are copied unchanged.
+pub fn adjust_temperature<I, P, S>(image: &I, kelvin: f32, tint: f32)
+    -> ImageBuffer<P, Vec<S>>
+    where I: GenericImage<Pixel=P>,
+          P: Pixel<Subpixel=S> + 'static,
+          S: Primitive + 'static {
+    let light = blackbody(kelvin);
+    let neutral = blackbody(6500.0);
+    let mut gains = [0.0; 3];
+    for (g, (&n, &l)) in gains.iter_mut().zip(neutral.iter().zip(light.iter())) {
+        *g = n / l;
+    }
+    gains[1] *= 2f32.powf(-tint);
+    // Keep the overall brightness
+    let mean = (gains[0] + gains[1] + gains[2]) / 3.0;
+    for g in &mut gains {
+        *g /= mean;
+    }
+    scale_rgb(image, gains)
+}
+
+// The approximate relative RGB color of a black body at the given temperature, based on
+// the fit by Tanner Helland
+fn blackbody(kelvin: f32) -> [f32; 3] {
+    let t = clamp(kelvin, 1000.0, 40000.0) / 100.0;
+    let red = if t <= 66.0 { 255.0 } else { 329.699 * (t - 60.0).powf(-0.133_205) };
+    let green = if t <= 66.0 {
+        99.470_8 * t.ln() - 161.119_57
+    } else {
+        288.122_17 * (t - 60.0).powf(-0.075_514_85)
+    };
+    let blue = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.517_73 * (t - 10.0).ln() - 305.044_8
+    };
+    // Very low temperatures have no blue at all, keep the gains finite
+    [clamp(red, 1.0, 255.0), clamp(green, 1.0, 255.0), clamp(blue, 1.0, 255.0)]
+}
+
+// Multiply the red, green and blue channels by the gains
+fn scale_rgb<I, P, S>(image: &I, gains: [f32; 3]) -> ImageBuffer<P, Vec<S>>
+    where I: GenericImage<Pixel=P>,
+          P: Pixel<Subpixel=S> + 'static,
+          S: Primitive + 'static {
+    let is_rgb = P::color_model().starts_with("RGB");
+    let max = normalized_max::<S>() as f32;
+    let round = !is_float::<S>();
+
+    let (width, height) = image.dimensions();
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let mut pixel = image.get_pixel(x, y);
+        if is_rgb {
+            for (c, &g) in pixel.channels_mut().iter_mut().zip(gains.iter()) {
+                let v: f32 = NumCast::from(*c).unwrap();
+                let v = clamp(v * g, 0.0, max);
+                *c = NumCast::from(if round { v.round() } else { v }).unwrap();
+            }
+        }
+        pixel
+    })
+}
+
+/// Replace the color channels of the supplied image by their entries in a lookup table.
+/// The alpha channel is left unchanged. The table needs an entry for every possible value
+/// of the subpixel type, i.e. 256 entries for 8 bit and 65536 for 16 bit images.
+/// This function operates in place.
+///
+/// # Panics
+///
+/// Panics if the table is too short for a value of the image.
+pub fn apply_lut<I, S>(image: &mut I, lut: &[S])
+    where I: GenericImage,
+          I::Pixel: Pixel<Subpixel=S>,
+          S: Primitive {
+    let colors = <I::Pixel as Pixel>::channel_count() as usize - has_alpha::<I::Pixel>() as usize;
+    let luts = vec![lut; colors];
+    apply_channel_luts(image, &luts);
+}
+
+/// Replace every channel of the supplied image by its entry in the lookup table of the
+/// channel, in the order of the channels of the pixel type.
+/// Channels without a table are left unchanged.
+/// This function operates in place.
+///
+/// # Panics
+///
+/// Panics if a table is too short for a value of the image.
+pub fn apply_channel_luts<I, S>(image: &mut I, luts: &[&[S]])
+    where I: GenericImage,
+          I::Pixel: Pixel<Subpixel=S>,
+          S: Primitive {
+    let (width, height) = image.dimensions();
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = image.get_pixel_mut(x, y);
+            for (c, lut) in pixel.channels_mut().iter_mut().zip(luts) {
+                let index: usize = NumCast::from(*c).unwrap();
+                *c = lut[index];
+            }
+        }
+    }
+}
+
+/// Map the luminance of the supplied image through a color ramp, e.g. for duotone or
+/// false-color renderings.
+/// ```stops``` are the positions of the colors of the ramp from 0 (black) to 1 (white) in
+/// increasing order, colors in between are linearly interpolated. Luminances outside of
+/// the stops get the color of the nearest one. The output can have a different pixel type
+/// than the input.
+///
+/// # Panics
+///
+/// Panics if there are no stops.
+pub fn gradient_map<I, Q>(image: &I, stops: &[(f32, Q)]) -> ImageBuffer<Q, Vec<Q::Subpixel>>
+    where I: GenericImage,
+          Q: Pixel + 'static,
+          Q::Subpixel: 'static {
+    assert!(!stops.is_empty(), "A gradient map needs at least one stop");
+    let max = normalized_max::<<I::Pixel as Pixel>::Subpixel>() as f32;
+    let round = !is_float::<Q::Subpixel>();
+
+    let (width, height) = image.dimensions();
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let luma: f32 = NumCast::from(image.get_pixel(x, y).to_luma()[0]).unwrap();
+        let t = luma / max;
+        let next = stops.iter().position(|&(position, _)| position > t).unwrap_or(stops.len());
+        if next == 0 {
+            return stops[0].1
+        } else if next == stops.len() {
+            return stops[stops.len() - 1].1
+        }
+        let ((p0, c0), (p1, c1)) = (stops[next - 1], stops[next]);
+        let f = (t - p0) / (p1 - p0);
+        c0.map2(&c1, |a, b| {
+            let (a, b): (f32, f32) = (NumCast::from(a).unwrap(), NumCast::from(b).unwrap());
+            let v = a + (b - a) * f;
+            NumCast::from(if round { v.round() } else { v }).unwrap()
+        })
+    })
+}
+
+/// Multiply the color channels of the supplied image by its alpha channel.
+/// Images without an alpha channel are copied unchanged. Integer samples are rounded to the
+/// nearest value, floating point samples are expected to be in the range from 0 to 1.
+pub fn premultiply_alpha<I, P, S>(image: &I) -> ImageBuffer<P, Vec<S>>
+    where I: GenericImage<Pixel=P>,
+          P: Pixel<Subpixel=S> + 'static,
+          S: Primitive + 'static {
+    let mut out = copy(image);
+    premultiply_alpha_in_place(&mut out);
+    out
+}
+
+/// Multiply the color channels of the supplied image by its alpha channel.
+/// This function operates in place.
+pub fn premultiply_alpha_in_place<I: GenericImage>(image: &mut I) {
+    map_alpha(image, |c, alpha, max| c * alpha / max);
+}
+
+/// Divide the color channels of the supplied image by its alpha channel, undoing
+/// ```premultiply_alpha```.
+/// Fully transparent pixels become black. Since premultiplication loses precision for
+/// translucent pixels, integer images in general do not get back their exact colors.
+pub fn unpremultiply_alpha<I, P, S>(image: &I) -> ImageBuffer<P, Vec<S>>
+    where I: GenericImage<Pixel=P>,
+          P: Pixel<Subpixel=S> + 'static,
+          S: Primitive + 'static {
+    let mut out = copy(image);
+    unpremultiply_alpha_in_place(&mut out);
+    out
+}
+
+/// Divide the color channels of the supplied image by its alpha channel.
+/// This function operates in place.
+pub fn unpremultiply_alpha_in_place<I: GenericImage>(image: &mut I) {
+    map_alpha(image, |c, alpha, max| {
+        if alpha == 0.0 { 0.0 } else { (c * max / alpha).min(max) }
+    });
+}
+
+fn copy<I, P, S>(image: &I) -> ImageBuffer<P, Vec<S>>
+    where I: GenericImage<Pixel=P>,
+          P: Pixel<Subpixel=S> + 'static,
+          S: Primitive + 'static {
+    let (width, height) = image.dimensions();
+    ImageBuffer::from_fn(width, height, |x, y| image.get_pixel(x, y))
+}
+
+// Replace each color channel c by f(c, alpha, max) for images with an alpha channel
+fn map_alpha<I, F>(image: &mut I, f: F)
+    where I: GenericImage,
+          F: Fn(f64, f64, f64) -> f64 {
+    if !has_alpha::<I::Pixel>() {
+        return
+    }
+    let max = normalized_max::<<I::Pixel as Pixel>::Subpixel>();
+    let round = !is_float::<<I::Pixel as Pixel>::Subpixel>();
+
+    let (width, height) = image.dimensions();
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = image.get_pixel_mut(x, y);
+            let channels = pixel.channels_mut();
+            let (alpha, colors) = channels.split_last_mut().unwrap();
+            let alpha: f64 = NumCast::from(*alpha).unwrap();
+            for c in colors {
+                let v = f(NumCast::from(*c).unwrap(), alpha, max);
+                *c = NumCast::from(if round { v.round() } else { v }).unwrap();
+            }
+        }
+    }
+}
+
+fn has_alpha<P: Pixel>() -> bool {
+    P::color_model().ends_with('A')
+}
+
+/// Compute the average color of the supplied image.
+/// Returns ```None``` for an empty image.
+pub fn average_color<I: GenericImage>(image: &I) -> Option<I::Pixel> {
+    let samples = color_samples(image, 1);
+    mean_color(&samples, <I::Pixel as Pixel>::channel_count() as usize)
+        .map(|mean| to_pixel(image, &mean))
+}
+
+/// Find the ```n``` dominant colors of the supplied image.
+/// Returns the colors together with the fraction of the image they cover, most common
+/// first. The colors are found with k-means clustering on a sample of at most 65536
+/// pixels, so they are the centers of groups of similar colors rather than colors that
+/// necessarily appear in the image. Images with less than ```n``` distinct colors yield
+/// fewer colors.
+pub fn dominant_colors<I: GenericImage>(image: &I, n: usize) -> Vec<(I::Pixel, f32)> {
+    const MAX_SAMPLES: usize = 1 << 16;
+    const MAX_ITERATIONS: usize = 16;
+
+    let channels = <I::Pixel as Pixel>::channel_count() as usize;
+    let (width, height) = image.dimensions();
+    let len = width as usize * height as usize;
+    let samples = color_samples(image, len / MAX_SAMPLES + 1);
+    let distance = |a: &[f64], b: &[f64]| -> f64 {
+        a.iter().zip(b).map(|(a, b)| (a - b) * (a - b)).sum()
+    };
+    let nearest = |centers: &[Vec<f64>], sample: &[f64]| -> usize {
+        let distances = centers.iter().map(|c| distance(c, sample));
+        distances.enumerate().fold((0, f64::INFINITY), |best, (i, d)| {
+            if d < best.1 { (i, d) } else { best }
+        }).0
+    };
+
+    // Start at the mean and then repeatedly add the sample which is farthest from all
+    // centers, this is deterministic and spreads the centers over the color space.
+    let mut centers: Vec<Vec<f64>> = match mean_color(&samples, channels) {
+        Some(mean) if n > 0 => vec![mean],
+        _ => return Vec::new(),
+    };
+    while centers.len() < n {
+        let mut farthest = None;
+        let mut max_distance = 0.0;
+        for sample in samples.chunks(channels) {
+            let d = distance(&centers[nearest(&centers, sample)], sample);
+            if d > max_distance {
+                farthest = Some(sample);
+                max_distance = d;
+            }
+        }
+        match farthest {
+            Some(sample) => centers.push(sample.to_vec()),
+            None => break,
+        }
+    }
+
+    let mut assignment = vec![0; samples.len() / channels];
+    for _ in 0..MAX_ITERATIONS {
+        let mut changed = false;
+        for (a, sample) in assignment.iter_mut().zip(samples.chunks(channels)) {
+            let i = nearest(&centers, sample);
+            changed |= *a != i;
+            *a = i;
+        }
+        if !changed {
+            break
+        }
+        for (i, center) in centers.iter_mut().enumerate() {
+            let members: Vec<f64> = assignment.iter().zip(samples.chunks(channels))
+                .filter(|&(&a, _)| a == i)
+                .flat_map(|(_, sample)| sample.iter().cloned())
+                .collect();
+            if let Some(mean) = mean_color(&members, channels) {
+                *center = mean;
+            }
+        }
+    }
+
+    let mut counts = vec![0usize; centers.len()];
+    for &a in &assignment {
+        counts[a] += 1;
+    }
+    let mut colors: Vec<(I::Pixel, f32)> = centers.iter().zip(counts)
+        .filter(|&(_, count)| count > 0)
+        .map(|(center, count)| (to_pixel(image, center), count as f32 / assignment.len() as f32))
+        .collect();
+    colors.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+    colors
+}
+
+// The channels of every ```step```th pixel of the image
+fn color_samples<I: GenericImage>(image: &I, step: usize) -> Vec<f64> {
+    let (width, height) = image.dimensions();
+    let len = width as usize * height as usize;
+    let mut samples = Vec::new();
+    for i in (0..len).step_by(step) {
+        let (x, y) = ((i % width as usize) as u32, (i / width as usize) as u32);
+        let pixel = image.get_pixel(x, y);
+        samples.extend(pixel.channels().iter().map(|&c| -> f64 { NumCast::from(c).unwrap() }));
+    }
+    samples
+}
+
+fn mean_color(samples: &[f64], channels: usize) -> Option<Vec<f64>> {
+    let count = samples.len() / channels;
+    if count == 0 {
+        return None
+    }
+    let mut sum = vec![0.0; channels];
+    for sample in samples.chunks(channels) {
+        for (s, c) in sum.iter_mut().zip(sample) {
+            *s += c;
+        }
+    }
+    Some(sum.into_iter().map(|s| s / count as f64).collect())
+}
+
+fn to_pixel<I: GenericImage>(image: &I, channels: &[f64]) -> I::Pixel {
+    let mut pixel = image.get_pixel(0, 0);
+    for (c, &v) in pixel.channels_mut().iter_mut().zip(channels) {
+        *c = NumCast::from(v).unwrap();
+    }
+    pixel
+}
+
+/// Remap the channels of ```target``` so that their cumulative histograms match the
+/// corresponding channels of ```reference```.
+/// The images can have different dimensions. This is useful to equalize the exposure of
+/// images of the same scene, e.g. the frames of a timelapse.
+/// This function operates in place.
+pub fn match_histogram<I, J, P>(target: &mut I, reference: &J)
+    where I: GenericImage<Pixel=P>,
+          J: GenericImage<Pixel=P>,
+          P: Pixel<Subpixel=u8> {
+    let channels = P::channel_count() as usize;
+    let target_cdf = cumulative_histograms(target, channels);
+    let reference_cdf = cumulative_histograms(reference, channels);
+    if target_cdf[0][255] == 0 || reference_cdf[0][255] == 0 {
+        return
+    }
+
+    let luts: Vec<Vec<u8>> = target_cdf.iter().zip(&reference_cdf).map(|(target, reference)| {
+        let (target_total, reference_total) = (target[255] as f64, reference[255] as f64);
+        let mut r = 0;
+        target.iter().map(|&t| {
+            let t = t as f64 / target_total;
+            while r < 255 && (reference[r] as f64 / reference_total) < t {
+                r += 1;
+            }
+            r as u8
+        }).collect()
+    }).collect();
+
+    let (width, height) = target.dimensions();
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = target.get_pixel_mut(x, y);
+            for (c, lut) in pixel.channels_mut().iter_mut().zip(&luts) {
+                *c = lut[*c as usize];
+            }
+        }
+    }
+}
+
+// The cumulative histogram of each channel
+fn cumulative_histograms<I: GenericImage>(image: &I, channels: usize) -> Vec<[u64; 256]>
+    where I::Pixel: Pixel<Subpixel=u8> {
+    let mut histograms = vec![[0u64; 256]; channels];
+    for (_, _, pixel) in image.pixels() {
+        for (histogram, &c) in histograms.iter_mut().zip(pixel.channels()) {
+            histogram[c as usize] += 1;
+        }
+    }
+    for histogram in &mut histograms {
+        for i in 1..256 {
+            histogram[i] += histogram[i - 1];
+        }
+    }
+    histograms
+}
+
 /// A color map
 pub trait ColorMap {
     /// The color type on which the map operates on
@@ -306,6 +937,7 @@ where Map: ColorMap<Color=Pix>,
 mod test {
 
     use ImageBuffer;
+    use buffer::{GrayImage, RgbImage, RgbaImage};
     use super::*;
 
     #[test]
@@ -316,4 +948,205 @@ mod test {
         assert_eq!(&*image, &[0, 0xFF, 0xFF, 0]);
         assert_eq!(index_colors(&image, &cmap).into_raw(), vec![0, 1, 1, 0])
     }
+
+    #[test]
+    fn test_match_histogram() {
+        // A dark frame with the same structure as a bright reference frame
+        let reference: GrayImage = ImageBuffer::from_fn(16, 1, |x, _| Luma([100 + x as u8 * 10]));
+        let mut target: GrayImage = ImageBuffer::from_fn(16, 1, |x, _| Luma([x as u8 * 3]));
+        match_histogram(&mut target, &reference);
+        assert_eq!(target.into_raw(), reference.into_raw());
+
+        let reference: RgbImage = ImageBuffer::from_fn(4, 4, |x, y| Rgb([50 + x as u8, 200, y as u8 * 20]));
+        let mut target: RgbImage = ImageBuffer::from_fn(2, 2, |x, _| Rgb([x as u8, 10, 10]));
+        match_histogram(&mut target, &reference);
+        assert_eq!(target.get_pixel(0, 0), &Rgb([51, 200, 60]));
+        assert_eq!(target.get_pixel(1, 1), &Rgb([53, 200, 60]));
+    }
+
+    #[test]
+    fn test_posterize() {
+        let image: GrayImage = ImageBuffer::from_raw(5, 1, vec![0, 60, 64, 200, 255]).unwrap();
+        assert_eq!(posterize(&image, 2).into_raw(), vec![0, 0, 0, 255, 255]);
+        assert_eq!(posterize(&image, 5).into_raw(), vec![0, 64, 64, 191, 255]);
+
+        let image: ImageBuffer<LumaA<f32>, Vec<f32>> = ImageBuffer::from_pixel(1, 1, LumaA([0.3, 0.3]));
+        assert_eq!(posterize(&image, 3)[(0, 0)], LumaA([0.5, 0.3]));
+    }
+
+    #[test]
+    fn test_solarize() {
+        let image: RgbaImage = ImageBuffer::from_pixel(1, 1, Rgba([10, 128, 200, 200]));
+        assert_eq!(solarize(&image, 127)[(0, 0)], Rgba([10, 127, 55, 200]));
+        assert_eq!(solarize(&image, 255)[(0, 0)], image[(0, 0)]);
+    }
+
+    #[test]
+    fn test_vignette() {
+        let image: RgbaImage = ImageBuffer::from_pixel(100, 60, Rgba([200, 200, 200, 255]));
+        let result = vignette(&image, 0.5, 2.0);
+        assert_eq!(result[(50, 30)], Rgba([200, 200, 200, 255]));
+        assert!(result[(0, 0)][0] > 100 && result[(0, 0)][0] < 105);
+        assert!(result[(25, 30)][0] > result[(10, 30)][0]);
+        assert!(result.pixels().all(|p| p[3] == 255));
+        assert_eq!(vignette(&image, 0.0, 1.0).into_raw(), image.into_raw());
+    }
+
+    #[test]
+    fn test_radial_gradient() {
+        let gradient = radial_gradient(101, 101, Luma([255u8]), Luma([0]));
+        assert_eq!(gradient[(50, 50)], Luma([255]));
+        assert!(gradient[(0, 0)][0] < 5);
+        assert_eq!(gradient[(50, 0)], gradient[(0, 50)]);
+        assert!(gradient[(50, 0)][0] > gradient[(0, 0)][0] && gradient[(50, 0)][0] < gradient[(50, 40)][0]);
+    }
+
+    #[test]
+    fn test_chroma_key() {
+        let mut image: RgbaImage = ImageBuffer::from_raw(4, 1, vec![0, 255, 0, 255,
+                                                                    20, 230, 20, 255,
+                                                                    40, 220, 40, 128,
+                                                                    200, 50, 50, 255]).unwrap();
+        chroma_key(&mut image, Rgb([0, 255, 0]), 0.2, 0.2);
+        let alphas: Vec<u8> = image.pixels().map(|p| p[3]).collect();
+        assert_eq!(alphas[0], 0);
+        assert_eq!(alphas[1], 0);
+        // A distance of 0.26 is 30% of the way through the soft edge
+        assert!(alphas[2] > 35 && alphas[2] < 43, "{}", alphas[2]);
+        assert_eq!(alphas[3], 255);
+        assert_eq!(&image.as_ref()[..3], &[0, 255, 0][..]);
+    }
+
+    #[test]
+    fn test_white_balance() {
+        let image: RgbaImage = ImageBuffer::from_raw(2, 1, vec![200, 150, 100, 255,
+                                                                100, 75, 50, 128]).unwrap();
+        let balanced = white_balance(&image, Rgb([200, 150, 100]));
+        assert_eq!(balanced.into_raw(), vec![150, 150, 150, 255, 75, 75, 75, 128]);
+
+        let tinted: RgbImage = ImageBuffer::from_fn(4, 4, |x, _| Rgb([(x * 40 + 20) as u8, (x * 40 + 20) as u8, (x * 20 + 10) as u8]));
+        let balanced = gray_world_white_balance(&tinted);
+        for p in balanced.pixels() {
+            assert!((p[0] as i32 - p[2] as i32).abs() <= 1 && p[0] == p[1], "{:?}", p);
+        }
+
+        let gray: GrayImage = ImageBuffer::from_pixel(2, 2, Luma([77]));
+        assert_eq!(white_balance(&gray, Rgb([1, 2, 3])).into_raw(), gray.into_raw());
+    }
+
+    #[test]
+    fn test_adjust_temperature() {
+        let image: RgbImage = ImageBuffer::from_pixel(1, 1, Rgb([128, 128, 128]));
+        let p = adjust_temperature(&image, 6500.0, 0.0)[(0, 0)];
+        assert!(p.data.iter().all(|&c| (c as i32 - 128).abs() <= 1), "{:?}", p);
+        let cooler = adjust_temperature(&image, 3200.0, 0.0)[(0, 0)];
+        assert!(cooler[2] > cooler[1] && cooler[1] > cooler[0], "{:?}", cooler);
+        let warmer = adjust_temperature(&image, 9000.0, 0.0)[(0, 0)];
+        assert!(warmer[0] > warmer[1] && warmer[1] > warmer[2], "{:?}", warmer);
+        let magenta = adjust_temperature(&image, 6500.0, 1.0)[(0, 0)];
+        assert!(magenta[0] > 128 && magenta[1] < 100, "{:?}", magenta);
+    }
+
+    #[test]
+    fn test_grayscale_with_weights() {
+        let image: RgbaImage = ImageBuffer::from_pixel(1, 1, Rgba([200, 100, 50, 128]));
+        let gray = |weights| grayscale_with_weights(&image, weights)[(0, 0)][0];
+        assert_eq!(gray(GrayscaleWeights::Rec601), 124);
+        assert_eq!(gray(GrayscaleWeights::Rec709), 118);
+        assert_eq!(gray(GrayscaleWeights::Average), 117);
+        assert_eq!(gray(GrayscaleWeights::Custom(0.0, 0.0, 1.0)), 50);
+        assert_eq!(gray(GrayscaleWeights::Custom(1.0, 1.0, 1.0)), 255);
+
+        let gray_alpha = grayscale_alpha_with_weights(&image, GrayscaleWeights::Rec601);
+        assert_eq!(gray_alpha[(0, 0)], LumaA([124, 128]));
+        let opaque: RgbImage = ImageBuffer::from_pixel(1, 1, Rgb([10, 20, 30]));
+        assert_eq!(grayscale_alpha_with_weights(&opaque, GrayscaleWeights::Average)[(0, 0)], LumaA([20, 255]));
+    }
+
+    #[test]
+    fn test_apply_lut() {
+        let inverse: Vec<u8> = (0..256).map(|v| 255 - v as u8).collect();
+        let mut image: RgbaImage = ImageBuffer::from_pixel(2, 1, Rgba([0, 100, 255, 128]));
+        apply_lut(&mut image, &inverse);
+        assert_eq!(image[(1, 0)], Rgba([255, 155, 0, 128]));
+
+        let halve: Vec<u16> = (0..65536).map(|v| (v / 2) as u16).collect();
+        let mut image: ImageBuffer<Rgb<u16>, Vec<u16>> = ImageBuffer::from_pixel(1, 1, Rgb([1000, 2000, 3000]));
+        apply_channel_luts(&mut image, &[&halve[..], &halve[..]]);
+        assert_eq!(image[(0, 0)], Rgb([500, 1000, 3000]));
+    }
+
+    #[test]
+    fn test_gradient_map() {
+        let image: GrayImage = ImageBuffer::from_raw(5, 1, vec![0, 51, 102, 204, 255]).unwrap();
+        let duotone = gradient_map(&image, &[(0.2, Rgb([0u8, 0, 100])), (0.8, Rgb([200, 100, 0]))]);
+        assert_eq!(duotone.into_raw(), vec![0, 0, 100,
+                                            0, 0, 100,
+                                            67, 33, 67,
+                                            200, 100, 0,
+                                            200, 100, 0]);
+        let single = gradient_map(&image, &[(0.5, Luma([7u8]))]);
+        assert!(single.pixels().all(|p| p[0] == 7));
+    }
+
+    #[test]
+    fn test_premultiply_alpha() {
+        let image: RgbaImage = ImageBuffer::from_raw(3, 1, vec![200, 100, 51, 128,
+                                                                255, 255, 255, 0,
+                                                                10, 20, 30, 255]).unwrap();
+        let premultiplied = premultiply_alpha(&image);
+        assert_eq!(premultiplied.as_ref(), &[100, 50, 26, 128, 0, 0, 0, 0, 10, 20, 30, 255][..]);
+        let restored = unpremultiply_alpha(&premultiplied);
+        assert_eq!(restored.as_ref(), &[199, 100, 52, 128, 0, 0, 0, 0, 10, 20, 30, 255][..]);
+
+        let mut gray: ImageBuffer<LumaA<u16>, Vec<u16>> = ImageBuffer::from_pixel(1, 1, LumaA([1000, 0x8000]));
+        premultiply_alpha_in_place(&mut gray);
+        assert_eq!(gray[(0, 0)], LumaA([500, 0x8000]));
+        unpremultiply_alpha_in_place(&mut gray);
+        assert_eq!(gray[(0, 0)], LumaA([1000, 0x8000]));
+    }
+
+    #[test]
+    fn test_premultiply_alpha_other_types() {
+        let image: RgbImage = ImageBuffer::from_pixel(2, 2, Rgb([1, 2, 3]));
+        assert_eq!(premultiply_alpha(&image).into_raw(), image.into_raw());
+        let floats: ImageBuffer<Rgba<f32>, Vec<f32>> = ImageBuffer::from_pixel(1, 1, Rgba([0.5, 1.0, 0.25, 0.5]));
+        assert_eq!(premultiply_alpha(&floats)[(0, 0)], Rgba([0.25, 0.5, 0.125, 0.5]));
+        assert_eq!(unpremultiply_alpha(&premultiply_alpha(&floats)).into_raw(), floats.into_raw());
+    }
+
+    #[test]
+    fn test_average_color() {
+        let image: RgbImage = ImageBuffer::from_fn(2, 2, |x, _| {
+            if x == 0 { Rgb([0, 100, 200]) } else { Rgb([100, 200, 0]) }
+        });
+        assert_eq!(average_color(&image), Some(Rgb([50, 150, 100])));
+        assert_eq!(average_color(&RgbImage::new(0, 0)), None);
+    }
+
+    #[test]
+    fn test_dominant_colors() {
+        let red = Rgb([250, 10, 10]);
+        let blue = Rgb([10, 10, 250]);
+        let image: RgbImage = ImageBuffer::from_fn(40, 10, |x, y| {
+            let noise = ((x * 7 + y * 3) % 5) as u8;
+            let [r, g, b] = if x < 30 { red.data } else { blue.data };
+            Rgb([r - noise, g + noise, b - noise])
+        });
+        let colors = dominant_colors(&image, 2);
+        assert_eq!(colors.len(), 2);
+        assert!((colors[0].1 - 0.75).abs() < 1e-6 && (colors[1].1 - 0.25).abs() < 1e-6);
+        for (&(color, _), expected) in colors.iter().zip(&[red, blue]) {
+            for (&c, &e) in color.data.iter().zip(expected.data.iter()) {
+                assert!((c as i32 - e as i32).abs() <= 5, "{:?} {:?}", color, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_dominant_colors_few_colors() {
+        let image: GrayImage = ImageBuffer::from_fn(4, 4, |x, _| Luma([if x < 1 { 0 } else { 255 }]));
+        assert_eq!(dominant_colors(&image, 5), vec![(Luma([255]), 0.75), (Luma([0]), 0.25)]);
+        assert!(dominant_colors(&image, 0).is_empty());
+    }
 }
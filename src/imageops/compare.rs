@@ -0,0 +1,245 @@
+//! Metrics for the similarity of two images
+//!
+//! All metrics are computed per channel and returned in the order of the channels of
+//! the pixel type. The images must have the same dimensions, otherwise a
+//! ```DimensionError``` is returned.
+
+use std::f64;
+
+use num_traits::{Bounded, NumCast};
+
+use buffer::Pixel;
+use image::{GenericImage, ImageError, ImageResult};
+
+/// The standard deviation of the gaussian window used by ```ssim```
+const SSIM_SIGMA: f64 = 1.5;
+
+/// The radius of the gaussian window used by ```ssim```, the window is 11x11 pixels
+const SSIM_RADIUS: usize = 5;
+
+/// Computes the mean squared error of each channel.
+pub fn mse<I, J, P>(a: &I, b: &J) -> ImageResult<Vec<f64>>
+    where I: GenericImage<Pixel=P>,
+          J: GenericImage<Pixel=P>,
+          P: Pixel {
+    let planes = Planes::new(a, b)?;
+    Ok((0..planes.channels).map(|c| {
+        let (a, b) = planes.channel(c);
+        let sum: f64 = a.iter().zip(b).map(|(a, b)| (a - b) * (a - b)).sum();
+        if a.is_empty() { 0.0 } else { sum / a.len() as f64 }
+    }).collect())
+}
+
+/// Computes the peak signal to noise ratio of each channel in decibels.
+///
+/// The peak is the maximum value of the subpixel type. Identical channels have an
+/// infinite ratio.
+pub fn psnr<I, J, P>(a: &I, b: &J) -> ImageResult<Vec<f64>>
+    where I: GenericImage<Pixel=P>,
+          J: GenericImage<Pixel=P>,
+          P: Pixel {
+    let peak = max_value::<P>();
+    Ok(mse(a, b)?.into_iter().map(|mse| {
+        if mse == 0.0 { f64::INFINITY } else { 10.0 * (peak * peak / mse).log10() }
+    }).collect())
+}
+
+/// Computes the mean structural similarity (SSIM) of each channel.
+///
+/// The local statistics are computed in an 11x11 gaussian window with a standard deviation
+/// of 1.5 as proposed by Wang et al., the window is truncated and renormalized at the
+/// borders of the image. The result is 1 for identical channels and decreases towards 0
+/// (or below for anticorrelated channels) with increasing differences.
+pub fn ssim<I, J, P>(a: &I, b: &J) -> ImageResult<Vec<f64>>
+    where I: GenericImage<Pixel=P>,
+          J: GenericImage<Pixel=P>,
+          P: Pixel {
+    let planes = Planes::new(a, b)?;
+    let peak = max_value::<P>();
+    let c1 = (0.01 * peak) * (0.01 * peak);
+    let c2 = (0.03 * peak) * (0.03 * peak);
+    let window = Window::new(planes.width, planes.height);
+
+    Ok((0..planes.channels).map(|c| {
+        let (a, b) = planes.channel(c);
+        if a.is_empty() {
+            return 1.0
+        }
+        let products = |f: fn(f64, f64) -> f64| -> Vec<f64> {
+            a.iter().zip(b).map(|(&a, &b)| f(a, b)).collect()
+        };
+        let mean_a = window.apply(a);
+        let mean_b = window.apply(b);
+        let mean_aa = window.apply(&products(|a, _| a * a));
+        let mean_bb = window.apply(&products(|_, b| b * b));
+        let mean_ab = window.apply(&products(|a, b| a * b));
+
+        let sum: f64 = (0..a.len()).map(|i| {
+            let (mu_a, mu_b) = (mean_a[i], mean_b[i]);
+            let var_a = mean_aa[i] - mu_a * mu_a;
+            let var_b = mean_bb[i] - mu_b * mu_b;
+            let covariance = mean_ab[i] - mu_a * mu_b;
+            ((2.0 * mu_a * mu_b + c1) * (2.0 * covariance + c2))
+                / ((mu_a * mu_a + mu_b * mu_b + c1) * (var_a + var_b + c2))
+        }).sum();
+        sum / a.len() as f64
+    }).collect())
+}
+
+fn max_value<P: Pixel>() -> f64 {
+    NumCast::from(P::Subpixel::max_value()).unwrap()
+}
+
+// The channels of two images of equal size as planes of floats
+struct Planes {
+    width: usize,
+    height: usize,
+    channels: usize,
+    a: Vec<f64>,
+    b: Vec<f64>,
+}
+
+impl Planes {
+    fn new<I, J, P>(a: &I, b: &J) -> ImageResult<Planes>
+        where I: GenericImage<Pixel=P>,
+              J: GenericImage<Pixel=P>,
+              P: Pixel {
+        let (width, height) = a.dimensions();
+        if b.dimensions() != (width, height) {
+            return Err(ImageError::DimensionError)
+        }
+        let channels = P::channel_count() as usize;
+        let (width, height) = (width as usize, height as usize);
+        Ok(Planes {
+            width,
+            height,
+            channels,
+            a: planar(a, channels),
+            b: planar(b, channels),
+        })
+    }
+
+    fn channel(&self, c: usize) -> (&[f64], &[f64]) {
+        let len = self.width * self.height;
+        (&self.a[c * len..(c + 1) * len], &self.b[c * len..(c + 1) * len])
+    }
+}
+
+// The channels of an image one after another
+fn planar<I: GenericImage>(image: &I, channels: usize) -> Vec<f64> {
+    let (width, height) = image.dimensions();
+    let len = width as usize * height as usize;
+    let mut planes = vec![0.0; len * channels];
+    for (x, y, pixel) in image.pixels() {
+        let index = y as usize * width as usize + x as usize;
+        for (c, &v) in pixel.channels().iter().enumerate() {
+            planes[c * len + index] = NumCast::from(v).unwrap();
+        }
+    }
+    planes
+}
+
+// A separable gaussian window which is renormalized where it is cut off by the borders
+struct Window {
+    width: usize,
+    height: usize,
+    kernel: Vec<f64>,
+    // The weight of the part of the window inside the image for each pixel
+    weights: Vec<f64>,
+}
+
+impl Window {
+    fn new(width: usize, height: usize) -> Window {
+        let kernel: Vec<f64> = (0..2 * SSIM_RADIUS + 1).map(|i| {
+            let d = i as f64 - SSIM_RADIUS as f64;
+            (-d * d / (2.0 * SSIM_SIGMA * SSIM_SIGMA)).exp()
+        }).collect();
+        let mut window = Window { width, height, kernel, weights: Vec::new() };
+        window.weights = window.convolve(&vec![1.0; width * height]);
+        window
+    }
+
+    // Weighted mean of the values in the window around each pixel
+    fn apply(&self, values: &[f64]) -> Vec<f64> {
+        self.convolve(values).iter().zip(&self.weights).map(|(v, w)| v / w).collect()
+    }
+
+    fn convolve(&self, values: &[f64]) -> Vec<f64> {
+        let (width, height) = (self.width, self.height);
+        let mut rows = vec![0.0; values.len()];
+        for y in 0..height {
+            for x in 0..width {
+                rows[y * width + x] = self.sum(x, width, |i| values[y * width + i]);
+            }
+        }
+        let mut result = vec![0.0; values.len()];
+        for y in 0..height {
+            for x in 0..width {
+                result[y * width + x] = self.sum(y, height, |i| rows[i * width + x]);
+            }
+        }
+        result
+    }
+
+    // Sum of the values around ```center``` weighted by the kernel, for a line of ```len``` values
+    fn sum<F: Fn(usize) -> f64>(&self, center: usize, len: usize, value: F) -> f64 {
+        let start = center.saturating_sub(SSIM_RADIUS);
+        let end = (center + SSIM_RADIUS + 1).min(len);
+        (start..end).map(|i| self.kernel[i + SSIM_RADIUS - center] * value(i)).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mse, psnr, ssim};
+    use buffer::{GrayImage, ImageBuffer, RgbImage};
+    use color::{Luma, Rgb};
+    use image::ImageError;
+
+    fn pattern(width: u32, height: u32) -> GrayImage {
+        ImageBuffer::from_fn(width, height, |x, y| Luma([((x * 37 + y * 91) % 256) as u8]))
+    }
+
+    #[test]
+    fn test_identical() {
+        let a = pattern(16, 16);
+        assert_eq!(mse(&a, &a).unwrap(), [0.0]);
+        assert!(psnr(&a, &a).unwrap()[0].is_infinite());
+        assert!((ssim(&a, &a).unwrap()[0] - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_per_channel() {
+        let a: RgbImage = ImageBuffer::from_pixel(4, 4, Rgb([10, 20, 30]));
+        let b: RgbImage = ImageBuffer::from_pixel(4, 4, Rgb([10, 22, 34]));
+        assert_eq!(mse(&a, &b).unwrap(), [0.0, 4.0, 16.0]);
+        let psnr = psnr(&a, &b).unwrap();
+        assert!((psnr[1] - 10.0 * (255.0f64 * 255.0 / 4.0).log10()).abs() < 1e-9);
+        assert!(psnr[1] > psnr[2]);
+    }
+
+    #[test]
+    fn test_ssim_decreases_with_noise() {
+        let a = pattern(32, 32);
+        let mut slightly = a.clone();
+        let mut strongly = a.clone();
+        for (x, y, pixel) in slightly.enumerate_pixels_mut() {
+            pixel[0] = pixel[0].saturating_add(((x ^ y) & 3) as u8);
+        }
+        for (x, y, pixel) in strongly.enumerate_pixels_mut() {
+            pixel[0] = pixel[0].wrapping_add(((x * y) & 63) as u8);
+        }
+        let slight = ssim(&a, &slightly).unwrap()[0];
+        let strong = ssim(&a, &strongly).unwrap()[0];
+        assert!(slight < 1.0 && slight > 0.9, "{}", slight);
+        assert!(strong < slight, "{} {}", strong, slight);
+    }
+
+    #[test]
+    fn test_dimension_mismatch() {
+        match mse(&pattern(4, 4), &pattern(4, 5)) {
+            Err(ImageError::DimensionError) => (),
+            other => panic!("expected a dimension error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,208 @@
+//! Connected-component labeling of binary images
+
+use buffer::ImageBuffer;
+use color::Luma;
+use image::GenericImage;
+
+/// The pixels which are considered adjacent to a pixel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Connectivity {
+    /// The pixels left, right, above and below
+    Four,
+
+    /// The pixels left, right, above and below and the diagonal neighbours
+    Eight,
+}
+
+/// The statistics of a connected component
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Component {
+    /// The label of the component's pixels in the label map
+    pub label: u32,
+
+    /// The number of pixels
+    pub area: u64,
+
+    /// The bounding box as ```(x, y, width, height)```
+    pub bounds: (u32, u32, u32, u32),
+
+    /// The mean position of the pixels
+    pub centroid: (f64, f64),
+}
+
+/// Label the connected components of the foreground of a binary image.
+///
+/// All nonzero pixels are foreground. Returns a label map in which background pixels are 0
+/// and the pixels of each component have the same label, counting up from 1 in the order
+/// the components are first encountered row by row. The statistics of the components are
+/// returned in the same order.
+pub fn connected_components<I>(image: &I, connectivity: Connectivity)
+    -> (ImageBuffer<Luma<u32>, Vec<u32>>, Vec<Component>)
+    where I: GenericImage<Pixel=Luma<u8>> {
+    let (width, height) = image.dimensions();
+    let mut labels: ImageBuffer<Luma<u32>, Vec<u32>> = ImageBuffer::new(width, height);
+    // The parent of each provisional label, label 0 is the background
+    let mut parents = vec![0u32];
+
+    // First pass: assign provisional labels and record which of them touch
+    for y in 0..height {
+        for x in 0..width {
+            if image.get_pixel(x, y)[0] == 0 {
+                continue
+            }
+            let mut neighbours = [0u32; 4];
+            if x > 0 {
+                neighbours[0] = labels[(x - 1, y)][0];
+            }
+            if y > 0 {
+                neighbours[1] = labels[(x, y - 1)][0];
+                if connectivity == Connectivity::Eight {
+                    if x > 0 {
+                        neighbours[2] = labels[(x - 1, y - 1)][0];
+                    }
+                    if x + 1 < width {
+                        neighbours[3] = labels[(x + 1, y - 1)][0];
+                    }
+                }
+            }
+            let label = match neighbours.iter().filter(|&&n| n != 0).min() {
+                Some(&label) => label,
+                None => {
+                    parents.push(parents.len() as u32);
+                    parents.len() as u32 - 1
+                }
+            };
+            for &n in neighbours.iter().filter(|&&n| n != 0) {
+                union(&mut parents, label, n);
+            }
+            labels[(x, y)] = Luma([label]);
+        }
+    }
+
+    // Number the sets of provisional labels consecutively
+    let mut final_labels = vec![0u32; parents.len()];
+    let mut count = 0;
+    for label in 1..parents.len() {
+        let root = find(&mut parents, label as u32) as usize;
+        if final_labels[root] == 0 {
+            count += 1;
+            final_labels[root] = count;
+        }
+        final_labels[label] = final_labels[root];
+    }
+
+    // Second pass: relabel and gather the statistics
+    let mut components: Vec<Component> = (1..count + 1).map(|label| Component {
+        label,
+        area: 0,
+        bounds: (width, height, 0, 0),
+        centroid: (0.0, 0.0),
+    }).collect();
+    for (x, y, pixel) in labels.enumerate_pixels_mut() {
+        if pixel[0] == 0 {
+            continue
+        }
+        let label = final_labels[pixel[0] as usize];
+        *pixel = Luma([label]);
+
+        let component = &mut components[label as usize - 1];
+        component.area += 1;
+        component.centroid.0 += x as f64;
+        component.centroid.1 += y as f64;
+        // Track the bounding box as (min x, min y, max x, max y) until all pixels are seen
+        let bounds = &mut component.bounds;
+        *bounds = (bounds.0.min(x), bounds.1.min(y), bounds.2.max(x), bounds.3.max(y));
+    }
+    for component in &mut components {
+        let (x0, y0, x1, y1) = component.bounds;
+        component.bounds = (x0, y0, x1 - x0 + 1, y1 - y0 + 1);
+        component.centroid.0 /= component.area as f64;
+        component.centroid.1 /= component.area as f64;
+    }
+
+    (labels, components)
+}
+
+fn find(parents: &mut [u32], mut label: u32) -> u32 {
+    while parents[label as usize] != label {
+        // Path halving
+        let grandparent = parents[parents[label as usize] as usize];
+        parents[label as usize] = grandparent;
+        label = grandparent;
+    }
+    label
+}
+
+fn union(parents: &mut [u32], a: u32, b: u32) {
+    let (a, b) = (find(parents, a), find(parents, b));
+    // The smaller label becomes the root so labels are numbered in scan order
+    if a < b {
+        parents[b as usize] = a;
+    } else {
+        parents[a as usize] = b;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{connected_components, Component, Connectivity};
+    use buffer::{GrayImage, ImageBuffer};
+
+    fn image(rows: &[&str]) -> GrayImage {
+        let data = rows.iter().flat_map(|row| row.bytes().map(|b| if b == b'#' { 255 } else { 0 })).collect();
+        ImageBuffer::from_raw(rows[0].len() as u32, rows.len() as u32, data).unwrap()
+    }
+
+    #[test]
+    fn test_connectivity() {
+        let image = image(&[
+            "#..##",
+            ".#..#",
+            "...#.",
+        ]);
+        let (labels, components) = connected_components(&image, Connectivity::Four);
+        assert_eq!(components.len(), 4);
+        assert_eq!(labels.into_raw(), vec![1, 0, 0, 2, 2,
+                                           0, 3, 0, 0, 2,
+                                           0, 0, 0, 4, 0]);
+
+        let (labels, components) = connected_components(&image, Connectivity::Eight);
+        assert_eq!(components.len(), 2);
+        assert_eq!(labels.into_raw(), vec![1, 0, 0, 2, 2,
+                                           0, 1, 0, 0, 2,
+                                           0, 0, 0, 2, 0]);
+    }
+
+    #[test]
+    fn test_merged_labels() {
+        // The arms get different provisional labels that are only joined in the last row
+        let image = image(&[
+            "#.#.#",
+            "#.#.#",
+            "#####",
+        ]);
+        let (labels, components) = connected_components(&image, Connectivity::Four);
+        assert!(labels.pixels().all(|p| p[0] <= 1));
+        assert_eq!(components, vec![Component {
+            label: 1,
+            area: 11,
+            bounds: (0, 0, 5, 3),
+            centroid: (2.0, 13.0 / 11.0),
+        }]);
+    }
+
+    #[test]
+    fn test_statistics() {
+        let image = image(&[
+            ".....",
+            ".##..",
+            ".##.#",
+        ]);
+        let (_, components) = connected_components(&image, Connectivity::Eight);
+        assert_eq!(components[0].area, 4);
+        assert_eq!(components[0].bounds, (1, 1, 2, 2));
+        assert_eq!(components[0].centroid, (1.5, 1.5));
+        assert_eq!(components[1].bounds, (4, 2, 1, 1));
+        assert!(connected_components(&GrayImage::new(3, 3), Connectivity::Four).1.is_empty());
+    }
+}
@@ -0,0 +1,143 @@
+//! Euclidean distance transform of binary images
+
+use std::f64;
+
+use buffer::ImageBuffer;
+use color::Luma;
+use image::GenericImage;
+
+/// Compute the Euclidean distance of every pixel to the nearest foreground pixel.
+///
+/// All nonzero pixels of the image are foreground and have a distance of 0. If there is no
+/// foreground pixel at all, every distance is infinite. The distances are exact, computed
+/// in linear time with the algorithm of Felzenszwalb and Huttenlocher.
+///
+/// A signed distance field is the difference of the transform of a mask and of its
+/// inverse.
+pub fn distance_transform<I>(image: &I) -> ImageBuffer<Luma<f32>, Vec<f32>>
+    where I: GenericImage<Pixel=Luma<u8>> {
+    let (width, height) = image.dimensions();
+    let (w, h) = (width as usize, height as usize);
+
+    // Squared distances, first along the columns and then along the rows
+    let mut squared = vec![f64::INFINITY; w * h];
+    for (x, y, pixel) in image.pixels() {
+        if pixel[0] != 0 {
+            squared[y as usize * w + x as usize] = 0.0;
+        }
+    }
+    let mut line = Vec::with_capacity(w.max(h));
+    let mut buffer = LowerEnvelope::new(w.max(h));
+    for x in 0..w {
+        line.clear();
+        line.extend((0..h).map(|y| squared[y * w + x]));
+        buffer.transform(&line, |y, d| squared[y * w + x] = d);
+    }
+    for y in 0..h {
+        line.clear();
+        line.extend_from_slice(&squared[y * w..(y + 1) * w]);
+        buffer.transform(&line, |x, d| squared[y * w + x] = d);
+    }
+
+    ImageBuffer::from_raw(width, height, squared.into_iter().map(|d| d.sqrt() as f32).collect())
+        .unwrap()
+}
+
+// The lower envelope of the parabolas rooted at the samples of a line
+struct LowerEnvelope {
+    // The positions of the parabolas which form the envelope
+    vertices: Vec<usize>,
+    // The boundaries between the parabolas
+    boundaries: Vec<f64>,
+}
+
+impl LowerEnvelope {
+    fn new(capacity: usize) -> LowerEnvelope {
+        LowerEnvelope {
+            vertices: Vec::with_capacity(capacity),
+            boundaries: Vec::with_capacity(capacity + 1),
+        }
+    }
+
+    // Computes the one dimensional squared distance transform of ```f``` and passes each
+    // result to ```output```
+    fn transform<F: FnMut(usize, f64)>(&mut self, f: &[f64], mut output: F) {
+        let (v, z) = (&mut self.vertices, &mut self.boundaries);
+        v.clear();
+        z.clear();
+        let intersection = |q: usize, p: usize| {
+            let (q, p, fq, fp) = (q as f64, p as f64, f[q], f[p]);
+            ((fq + q * q) - (fp + p * p)) / (2.0 * (q - p))
+        };
+
+        for (q, &fq) in f.iter().enumerate() {
+            if fq == f64::INFINITY {
+                continue
+            }
+            loop {
+                match v.last() {
+                    Some(&p) => {
+                        let s = intersection(q, p);
+                        if s <= z[z.len() - 1] {
+                            v.pop();
+                            z.pop();
+                        } else {
+                            v.push(q);
+                            z.push(s);
+                            break
+                        }
+                    }
+                    None => {
+                        v.push(q);
+                        z.push(f64::NEG_INFINITY);
+                        break
+                    }
+                }
+            }
+        }
+
+        if v.is_empty() {
+            for q in 0..f.len() {
+                output(q, f64::INFINITY);
+            }
+            return
+        }
+        let mut k = 0;
+        for q in 0..f.len() {
+            while k + 1 < v.len() && z[k + 1] < q as f64 {
+                k += 1;
+            }
+            let d = q as f64 - v[k] as f64;
+            output(q, d * d + f[v[k]]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::distance_transform;
+    use buffer::{GrayImage, ImageBuffer};
+    use color::Luma;
+
+    #[test]
+    fn test_distance_transform() {
+        let mut image = GrayImage::new(7, 5);
+        image.put_pixel(1, 1, Luma([255]));
+        image.put_pixel(5, 4, Luma([1]));
+        let distances = distance_transform(&image);
+        for (x, y, d) in distances.enumerate_pixels() {
+            let to = |px: u32, py: u32| ((x as f32 - px as f32).powi(2) + (y as f32 - py as f32).powi(2)).sqrt();
+            let expected = to(1, 1).min(to(5, 4));
+            assert!((d[0] - expected).abs() < 1e-6, "{:?} {} {}", (x, y), d[0], expected);
+        }
+        assert_eq!(distances[(1, 1)][0], 0.0);
+        assert_eq!(distances[(6, 4)][0], 1.0);
+    }
+
+    #[test]
+    fn test_no_foreground() {
+        let image: GrayImage = ImageBuffer::new(3, 2);
+        assert!(distance_transform(&image).pixels().all(|d| d[0] == ::std::f32::INFINITY));
+        assert_eq!(distance_transform(&GrayImage::new(0, 0)).dimensions(), (0, 0));
+    }
+}
@@ -0,0 +1,142 @@
+//! Perceptual hashes for finding near-duplicate images
+//!
+//! Each hash reduces an image to 64 bits which change little under scaling, compression
+//! or small color adjustments. Bits are stored row by row, the first bit being the most
+//! significant one. Two images are similar if the ```hamming_distance``` of their hashes
+//! is small, a distance of up to about 10 usually indicates the same picture.
+
+use std::f64::consts::PI;
+
+use num_traits::{Num, NumCast};
+
+use buffer::Pixel;
+use image::GenericImage;
+use imageops::{grayscale, resize, FilterType};
+
+/// Computes the average hash (aHash) of an image.
+///
+/// The image is reduced to 8x8 grayscale pixels, every bit tells whether a pixel is
+/// brighter than the mean. Fast, but sensitive to changes of the brightness distribution.
+pub fn average_hash<I: GenericImage>(image: &I) -> u64
+    where <I::Pixel as Pixel>::Subpixel: 'static,
+          <<I::Pixel as Pixel>::Subpixel as Num>::FromStrRadixErr: 'static {
+    let values = luma_values(image, 8, 8);
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    bits(values.iter().map(|&v| v > mean))
+}
+
+/// Computes the difference hash (dHash) of an image.
+///
+/// The image is reduced to 9x8 grayscale pixels, every bit tells whether a pixel is
+/// brighter than its left neighbour.
+pub fn difference_hash<I: GenericImage>(image: &I) -> u64
+    where <I::Pixel as Pixel>::Subpixel: 'static,
+          <<I::Pixel as Pixel>::Subpixel as Num>::FromStrRadixErr: 'static {
+    let values = luma_values(image, 9, 8);
+    bits((0..8).flat_map(|y| (0..8).map(move |x| (x, y)))
+               .map(|(x, y)| values[y * 9 + x + 1] > values[y * 9 + x]))
+}
+
+/// Computes the perceptual hash (pHash) of an image.
+///
+/// The image is reduced to 32x32 grayscale pixels and transformed with a discrete cosine
+/// transform. Every bit tells whether one of the 8x8 lowest frequencies is above their
+/// median. Slower than the other hashes, but the most robust against gamma and
+/// histogram adjustments.
+pub fn perceptual_hash<I: GenericImage>(image: &I) -> u64
+    where <I::Pixel as Pixel>::Subpixel: 'static,
+          <<I::Pixel as Pixel>::Subpixel as Num>::FromStrRadixErr: 'static {
+    const SIZE: usize = 32;
+    let values = luma_values(image, SIZE as u32, SIZE as u32);
+
+    // Only the lowest 8 frequencies in each direction are needed
+    let cosines: Vec<f64> = (0..8 * SIZE).map(|i| {
+        let (u, x) = (i / SIZE, i % SIZE);
+        ((2 * x + 1) as f64 * u as f64 * PI / (2 * SIZE) as f64).cos()
+    }).collect();
+    let rows: Vec<f64> = (0..SIZE * 8).map(|i| {
+        let (y, u) = (i / 8, i % 8);
+        (0..SIZE).map(|x| values[y * SIZE + x] * cosines[u * SIZE + x]).sum()
+    }).collect();
+    let coefficients: Vec<f64> = (0..64).map(|i| {
+        let (v, u) = (i / 8, i % 8);
+        (0..SIZE).map(|y| rows[y * 8 + u] * cosines[v * SIZE + y]).sum()
+    }).collect();
+
+    // The constant term only reflects the overall brightness and is left out of the median
+    let mut sorted = coefficients[1..].to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let median = sorted[sorted.len() / 2];
+    bits(coefficients.iter().map(|&c| c > median))
+}
+
+/// Returns the number of bits in which two hashes differ.
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+// The grayscale values of the image resized to ```width``` x ```height```, row by row
+fn luma_values<I: GenericImage>(image: &I, width: u32, height: u32) -> Vec<f64>
+    where <I::Pixel as Pixel>::Subpixel: 'static,
+          <<I::Pixel as Pixel>::Subpixel as Num>::FromStrRadixErr: 'static {
+    let small = resize(&grayscale(image), width, height, FilterType::Triangle);
+    small.pixels().map(|p| NumCast::from(p[0]).unwrap()).collect()
+}
+
+fn bits<T: Iterator<Item=bool>>(bits: T) -> u64 {
+    bits.fold(0, |hash, bit| hash << 1 | bit as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{average_hash, difference_hash, perceptual_hash, hamming_distance};
+    use buffer::{GrayImage, ImageBuffer, RgbImage};
+    use color::{Luma, Rgb};
+
+    fn photo(width: u32, height: u32) -> RgbImage {
+        ImageBuffer::from_fn(width, height, |x, y| {
+            let (fx, fy) = (x as f32 / width as f32, y as f32 / height as f32);
+            let v = ((fx * 7.0).sin() * (fy * 5.0).cos() * 100.0 + 128.0) as u8;
+            Rgb([v, v / 2, 255 - v])
+        })
+    }
+
+    #[test]
+    fn test_hamming_distance() {
+        assert_eq!(hamming_distance(0, 0), 0);
+        assert_eq!(hamming_distance(0b1011, 0b0110), 3);
+        assert_eq!(hamming_distance(0, !0), 64);
+    }
+
+    #[test]
+    fn test_average_hash() {
+        let image: GrayImage = ImageBuffer::from_fn(16, 16, |x, _| Luma([if x < 8 { 0 } else { 255 }]));
+        assert_eq!(average_hash(&image), 0x0F0F_0F0F_0F0F_0F0F);
+    }
+
+    #[test]
+    fn test_difference_hash() {
+        let image: GrayImage = ImageBuffer::from_fn(90, 80, |x, _| Luma([(x * 2) as u8]));
+        assert_eq!(difference_hash(&image), !0);
+    }
+
+    #[test]
+    fn test_near_duplicates() {
+        let original = photo(200, 150);
+        let mut brighter = original.clone();
+        for pixel in brighter.pixels_mut() {
+            for c in pixel.data.iter_mut() {
+                *c = c.saturating_add(8);
+            }
+        }
+        let smaller = ::imageops::resize(&original, 120, 90, ::imageops::Nearest);
+        let different = ::imageops::rotate90(&original);
+
+        for hash in &[average_hash::<RgbImage>, difference_hash::<RgbImage>, perceptual_hash::<RgbImage>] {
+            let h = hash(&original);
+            assert!(hamming_distance(h, hash(&brighter)) <= 4);
+            assert!(hamming_distance(h, hash(&smaller)) <= 4);
+            assert!(hamming_distance(h, hash(&different)) > 10);
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! Summed-area tables
+
+use buffer::ImageBuffer;
+use color::Luma;
+use image::GenericImage;
+use traits::Primitive;
+
+/// A summed-area table, see ```integral_image```
+pub type IntegralImage = ImageBuffer<Luma<u64>, Vec<u64>>;
+
+/// Compute the integral image (summed-area table) of a grayscale image with 8 or 16 bits
+/// per pixel.
+///
+/// The result is one pixel wider and higher than the image, the pixel at ```(x, y)``` holds
+/// the sum of all pixels above and to the left of it, i.e. the first row and column are 0.
+/// Use ```integral_sum``` to get the sum of a rectangle in constant time.
+pub fn integral_image<I, S>(image: &I) -> IntegralImage
+    where I: GenericImage<Pixel=Luma<S>>,
+          S: Primitive + Into<u16> + 'static {
+    integral(image, |v| v)
+}
+
+/// Compute the integral image of the squares of the pixels of a grayscale image.
+///
+/// Together with ```integral_image``` this gives the variance of any rectangle in constant
+/// time.
+pub fn integral_squared_image<I, S>(image: &I) -> IntegralImage
+    where I: GenericImage<Pixel=Luma<S>>,
+          S: Primitive + Into<u16> + 'static {
+    integral(image, |v| v * v)
+}
+
+/// Returns the sum of the pixels in the rectangle at ```(x, y)``` with the given size, using
+/// an integral image computed by ```integral_image``` or ```integral_squared_image```.
+///
+/// # Panics
+///
+/// Panics if the rectangle is not inside the original image.
+pub fn integral_sum(integral: &IntegralImage, x: u32, y: u32, width: u32, height: u32) -> u64 {
+    let (right, bottom) = (x + width, y + height);
+    assert!(right < integral.width() && bottom < integral.height(),
+            "Rectangle {:?} out of bounds {:?}", (x, y, width, height),
+            (integral.width() - 1, integral.height() - 1));
+    integral[(right, bottom)][0] + integral[(x, y)][0]
+        - integral[(right, y)][0] - integral[(x, bottom)][0]
+}
+
+fn integral<I, S, F>(image: &I, f: F) -> IntegralImage
+    where I: GenericImage<Pixel=Luma<S>>,
+          S: Primitive + Into<u16> + 'static,
+          F: Fn(u64) -> u64 {
+    let (width, height) = image.dimensions();
+    let mut out: IntegralImage = ImageBuffer::new(width + 1, height + 1);
+    for y in 0..height {
+        let mut row_sum = 0u64;
+        for x in 0..width {
+            row_sum += f(u64::from(image.get_pixel(x, y)[0].into()));
+            let above = out[(x + 1, y)][0];
+            out[(x + 1, y + 1)] = Luma([above + row_sum]);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{integral_image, integral_squared_image, integral_sum};
+    use buffer::{GrayImage, ImageBuffer};
+    use color::Luma;
+
+    #[test]
+    fn test_integral_image() {
+        let image: GrayImage = ImageBuffer::from_raw(3, 2, vec![1, 2, 3,
+                                                                4, 5, 6]).unwrap();
+        let integral = integral_image(&image);
+        assert_eq!(integral.dimensions(), (4, 3));
+        assert_eq!(integral.into_raw(), vec![0, 0, 0, 0,
+                                             0, 1, 3, 6,
+                                             0, 5, 12, 21]);
+    }
+
+    #[test]
+    fn test_integral_sum() {
+        let image: ImageBuffer<Luma<u16>, Vec<u16>> = ImageBuffer::from_fn(10, 8, |x, y| Luma([(x * 300 + y) as u16]));
+        let integral = integral_image(&image);
+        let squared = integral_squared_image(&image);
+        for &(x, y, w, h) in &[(0, 0, 10, 8), (3, 2, 4, 5), (9, 7, 1, 1), (5, 5, 0, 3)] {
+            let image = &image;
+            let pixels = || (x..x + w).flat_map(|px| (y..y + h).map(move |py| image[(px, py)][0] as u64));
+            assert_eq!(integral_sum(&integral, x, y, w, h), pixels().sum::<u64>());
+            assert_eq!(integral_sum(&squared, x, y, w, h), pixels().map(|v| v * v).sum::<u64>());
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_integral_sum_out_of_bounds() {
+        integral_sum(&integral_image(&GrayImage::new(4, 4)), 2, 2, 3, 1);
+    }
+}
@@ -35,13 +35,79 @@ pub use self::sample:: {
     unsharpen,
 };
 
+/// Parallel image sampling
+#[cfg(feature = "rayon")]
+pub use self::sample:: {
+    filter3x3_parallel,
+    resize_parallel,
+    blur_parallel,
+};
+
+/// Connected-component labeling
+pub use self::components:: {
+    connected_components,
+    Component,
+    Connectivity,
+};
+
+/// Distance transform
+pub use self::distance::distance_transform;
+
+/// Summed-area tables
+pub use self::integral:: {
+    integral_image,
+    integral_squared_image,
+    integral_sum,
+    IntegralImage,
+};
+
+/// Template matching
+pub use self::template:: {
+    match_template,
+    find_best_match,
+};
+
+/// Channel operations
+pub use self::channels:: {
+    split_channels,
+    merge_channels,
+};
+
+/// Noise generation
+pub use self::noise:: {
+    gaussian_noise,
+    salt_and_pepper_noise,
+    value_noise,
+};
+
 /// Color operations
 pub use self::colorops:: {
     grayscale,
+    grayscale_with_weights,
+    grayscale_alpha_with_weights,
+    GrayscaleWeights,
     invert,
     contrast,
     brighten,
     huerotate,
+    posterize,
+    solarize,
+    vignette,
+    radial_gradient,
+    chroma_key,
+    white_balance,
+    gray_world_white_balance,
+    adjust_temperature,
+    apply_lut,
+    apply_channel_luts,
+    gradient_map,
+    premultiply_alpha,
+    premultiply_alpha_in_place,
+    unpremultiply_alpha,
+    unpremultiply_alpha_in_place,
+    average_color,
+    dominant_colors,
+    match_histogram,
     ColorMap,
     BiLevel,
     dither,
@@ -49,10 +115,18 @@ pub use self::colorops:: {
 };
 
 mod affine;
+mod channels;
 // Public only because of Rust bug:
 // https://github.com/rust-lang/rust/issues/18241
 pub mod colorops;
+pub mod compare;
+mod components;
+mod distance;
+mod integral;
+mod noise;
+pub mod hash;
 mod sample;
+mod template;
 
 /// Return a mutable view into an image
 // TODO: Is a 'static bound on `I` really required? Acn we avoid it?
@@ -0,0 +1,196 @@
+//! Noise generation
+//!
+//! All functions take a seed and produce the same output for the same seed on every
+//! platform, which makes them suitable for reproducible test inputs.
+
+use std::f64::consts::PI;
+
+use num_traits::NumCast;
+
+use buffer::{GrayImage, ImageBuffer, Pixel};
+use color::Luma;
+use image::GenericImage;
+use math::utils::clamp;
+use traits::Primitive;
+use utils::{is_float, normalized_max};
+
+/// Add gaussian noise to the color channels of an image.
+/// ```mean``` and ```stddev``` are in units of the subpixel values, e.g. a standard
+/// deviation of 10 for an 8 bit image or of 0.04 for a float image, whose values are
+/// normalized to 1. Integer results are rounded and clamped to the range of the subpixel
+/// type, float results are kept as they are. The alpha channel is left unchanged.
+/// This function operates in place.
+pub fn gaussian_noise<I, P, S>(image: &mut I, mean: f64, stddev: f64, seed: u64)
+    where I: GenericImage<Pixel=P>,
+          P: Pixel<Subpixel=S> + 'static,
+          S: Primitive + 'static {
+    let mut rng = Rng::new(seed);
+    let max: f64 = NumCast::from(S::max_value()).unwrap();
+    let min: f64 = NumCast::from(S::min_value()).unwrap();
+    let float = is_float::<S>();
+
+    let (width, height) = image.dimensions();
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = image.get_pixel(x, y).map_with_alpha(|c| {
+                let c: f64 = NumCast::from(c).unwrap();
+                let v = c + mean + stddev * rng.next_gaussian();
+                NumCast::from(if float { v } else { clamp(v.round(), min, max) }).unwrap()
+            }, |alpha| alpha);
+            image.put_pixel(x, y, pixel);
+        }
+    }
+}
+
+/// Add salt-and-pepper noise to the supplied image.
+/// Every pixel is with a probability of ```rate``` replaced by either black or white, which
+/// is 1 for float images. The alpha channel is left unchanged.
+/// This function operates in place.
+pub fn salt_and_pepper_noise<I, P, S>(image: &mut I, rate: f64, seed: u64)
+    where I: GenericImage<Pixel=P>,
+          P: Pixel<Subpixel=S> + 'static,
+          S: Primitive + 'static {
+    let mut rng = Rng::new(seed);
+    let white: S = NumCast::from(normalized_max::<S>()).unwrap();
+    let (width, height) = image.dimensions();
+    for y in 0..height {
+        for x in 0..width {
+            if rng.next_f64() >= rate {
+                continue
+            }
+            let value = if rng.next_u64() & 1 == 0 { S::zero() } else { white };
+            let pixel = image.get_pixel(x, y).map_with_alpha(|_| value, |alpha| alpha);
+            image.put_pixel(x, y, pixel);
+        }
+    }
+}
+
+/// Create a grayscale image of smooth value noise.
+/// ```scale``` is the size of the coarsest features in pixels. Every further octave adds
+/// details of half the size and half the amplitude, so more ```octaves``` give a rougher,
+/// cloud-like texture.
+pub fn value_noise(width: u32, height: u32, scale: f64, octaves: u32, seed: u64) -> GrayImage {
+    let octaves = octaves.max(1);
+    let amplitude_sum: f64 = (0..octaves).map(|o| 0.5f64.powi(o as i32)).sum();
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let mut value = 0.0;
+        for octave in 0..octaves {
+            let size = scale / (1u64 << octave.min(63)) as f64;
+            let (fx, fy) = (x as f64 / size, y as f64 / size);
+            value += 0.5f64.powi(octave as i32) * lattice_noise(fx, fy, seed.wrapping_add(octave as u64));
+        }
+        Luma([(value / amplitude_sum * 255.0).round() as u8])
+    })
+}
+
+// Noise in [0, 1] interpolated between random values at the integer coordinates
+fn lattice_noise(x: f64, y: f64, seed: u64) -> f64 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (tx, ty) = (smoothstep(x - x0), smoothstep(y - y0));
+    let corner = |dx: i64, dy: i64| {
+        let (cx, cy) = (x0 as i64 + dx, y0 as i64 + dy);
+        let hash = seed ^ (cx as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+                        ^ (cy as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+        Rng::new(hash).next_f64()
+    };
+    let top = corner(0, 0) + (corner(1, 0) - corner(0, 0)) * tx;
+    let bottom = corner(0, 1) + (corner(1, 1) - corner(0, 1)) * tx;
+    top + (bottom - top) * ty
+}
+
+fn smoothstep(t: f64) -> f64 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+// A small SplitMix64 generator, good enough for noise and independent of external crates
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // Uniformly distributed in [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Standard normal distribution, using the Box-Muller transform
+    fn next_gaussian(&mut self) -> f64 {
+        let u = 1.0 - self.next_f64();
+        let v = self.next_f64();
+        (-2.0 * u.ln()).sqrt() * (2.0 * PI * v).cos()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{gaussian_noise, salt_and_pepper_noise, value_noise};
+    use buffer::{GrayImage, ImageBuffer, RgbaImage};
+    use color::{Luma, Rgba};
+
+    #[test]
+    fn test_gaussian_noise() {
+        let mut image: GrayImage = ImageBuffer::from_pixel(100, 100, Luma([128]));
+        gaussian_noise(&mut image, 0.0, 10.0, 42);
+        let values: Vec<f64> = image.pixels().map(|p| p[0] as f64).collect();
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        let variance = values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / values.len() as f64;
+        assert!((mean - 128.0).abs() < 0.5, "{}", mean);
+        assert!((variance.sqrt() - 10.0).abs() < 0.5, "{}", variance.sqrt());
+
+        let mut again: GrayImage = ImageBuffer::from_pixel(100, 100, Luma([128]));
+        gaussian_noise(&mut again, 0.0, 10.0, 42);
+        assert_eq!(again.into_raw(), image.into_raw());
+    }
+
+    #[test]
+    fn test_salt_and_pepper_noise() {
+        let mut image: RgbaImage = ImageBuffer::from_pixel(100, 100, Rgba([100, 100, 100, 200]));
+        salt_and_pepper_noise(&mut image, 0.1, 7);
+        let white = image.pixels().filter(|p| p.data == [255, 255, 255, 200]).count();
+        let black = image.pixels().filter(|p| p.data == [0, 0, 0, 200]).count();
+        let unchanged = image.pixels().filter(|p| p.data == [100, 100, 100, 200]).count();
+        assert_eq!(white + black + unchanged, 10000);
+        assert!(white > 400 && black > 400 && white + black < 1200, "{} {}", white, black);
+    }
+
+    #[test]
+    fn test_float_noise() {
+        let mut image: ImageBuffer<Luma<f32>, Vec<f32>> = ImageBuffer::from_pixel(50, 50, Luma([0.5]));
+        salt_and_pepper_noise(&mut image, 0.5, 3);
+        assert!(image.pixels().all(|p| p[0] == 0.0 || p[0] == 0.5 || p[0] == 1.0));
+        assert!(image.pixels().any(|p| p[0] == 1.0));
+
+        let mut image: ImageBuffer<Luma<f32>, Vec<f32>> = ImageBuffer::from_pixel(50, 50, Luma([0.5]));
+        gaussian_noise(&mut image, 0.0, 0.01, 3);
+        assert!(image.pixels().all(|p| p[0] > 0.4 && p[0] < 0.6));
+        assert!(image.pixels().any(|p| p[0] != 0.5));
+    }
+
+    #[test]
+    fn test_value_noise() {
+        let noise = value_noise(64, 64, 16.0, 3, 1);
+        assert_eq!(noise.as_ref(), value_noise(64, 64, 16.0, 3, 1).as_ref());
+        assert!(noise.as_ref() != value_noise(64, 64, 16.0, 3, 2).as_ref());
+
+        // Neighbouring pixels of a single octave differ only slightly
+        let smooth = value_noise(64, 64, 32.0, 1, 5);
+        for (x, y, p) in smooth.enumerate_pixels().filter(|&(x, _, _)| x > 0) {
+            assert!((p[0] as i32 - smooth[(x - 1, y)][0] as i32).abs() < 16);
+        }
+        let min = smooth.pixels().map(|p| p[0]).min().unwrap();
+        let max = smooth.pixels().map(|p| p[0]).max().unwrap();
+        assert!(max - min > 64);
+    }
+}
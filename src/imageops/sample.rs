@@ -7,6 +7,9 @@ use std::f32;
 
 use num_traits::NumCast;
 
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
 use buffer::{ImageBuffer, Pixel};
 use traits::Primitive;
 use image::GenericImage;
@@ -126,6 +129,138 @@ pub fn box_kernel(x: f32) -> f32 {
     }
 }
 
+// Creates an image of the given size whose rows are filled by `fill_row`,
+// which is called with the index of the row and its subpixels.
+fn map_rows<P, S, F>(width: u32, height: u32, fill_row: F) -> ImageBuffer<P, Vec<S>>
+    where P: Pixel<Subpixel=S> + 'static,
+          S: Primitive + 'static,
+          F: Fn(u32, &mut [S]) {
+
+    let mut out = ImageBuffer::new(width, height);
+    let row_len = width as usize * P::channel_count() as usize;
+    if row_len > 0 {
+        for (y, row) in out.chunks_mut(row_len).enumerate() {
+            fill_row(y as u32, row);
+        }
+    }
+    out
+}
+
+// Like `map_rows`, but the rows are filled by the threads of the rayon thread pool.
+#[cfg(feature = "rayon")]
+fn map_rows_parallel<P, S, F>(width: u32, height: u32, fill_row: F) -> ImageBuffer<P, Vec<S>>
+    where P: Pixel<Subpixel=S> + 'static,
+          S: Primitive + Send + 'static,
+          F: Fn(u32, &mut [S]) + Sync {
+
+    let mut out = ImageBuffer::new(width, height);
+    let row_len = width as usize * P::channel_count() as usize;
+    if row_len > 0 {
+        out.par_chunks_mut(row_len).enumerate().for_each(|(y, row)| fill_row(y as u32, row));
+    }
+    out
+}
+
+// Sample row ```y``` of the supplied image using the provided filter.
+// ```row``` receives the new row, its length determines the new width.
+fn horizontal_sample_row<I, P, S, K>(image: &I, y: u32, support: f32, kernel: &K, row: &mut [S])
+    where I: GenericImage<Pixel=P>,
+          P: Pixel<Subpixel=S> + 'static,
+          S: Primitive + 'static,
+          K: Fn(f32) -> f32 + ?Sized {
+
+    let width = image.width();
+    let channels = P::channel_count() as usize;
+    let new_width = (row.len() / channels) as u32;
+
+    let max = S::max_value();
+    let max: f32 = NumCast::from(max).unwrap();
+
+    let ratio = width as f32 / new_width as f32;
+
+    for outx in 0..new_width {
+
+        // Find the point in the input image corresponding to the centre
+        // of the current pixel in the output image.
+        //
+        // Then go half a pixel to the left (hence the `- 0.5`).
+        //
+        // The reason for subtracting 0.5 is because the filter kernel
+        // treats the centre of a pixel as 0. When finding the left and
+        // right limits below, we're interested in the range of input
+        // pixels whose colour can influence the colour of the current
+        // output pixel. This is equivalent to the range of input
+        // pixels for which inputx lies within the filter.support-sized
+        // region centered at the centre of that pixel. Subtracting
+        // 0.5 here simplifies the rounding operations below.
+        //
+        let inputx = (outx as f32 + 0.5) * ratio - 0.5;
+
+        // Find the index of the left-most input pixel which can influence
+        // the colour of the current output pixel. A point on the right
+        // side of a pixel is considered to be part of that pixel.
+        let left  = (inputx - support).ceil() as i64;
+        let left  = clamp(left, 0, width as i64 - 1) as u32;
+
+        // Find the index of the right-most input pixel which can influence
+        // the colour of the current output pixel. A point on the left side
+        // of a pixel is NOT considered to be part of that pixel. This is
+        // important because:
+        //  - If we included the point in both neighbouring pixels it would
+        //    force the output pixel to be influenced be both input pixels,
+        //    and force filters which don't desire this (e.g. Nearest),
+        //    to make sure they only sample from one side in such cases.
+        //  - If we included the point in neither neighbouring pixel it
+        //    would cause output pixels corresponding to input pixel
+        //    boundaries to be black regardless of the input pixel colours.
+        //
+        // The choice of right vs left is arbitrary.
+        let right = {
+            let real_right = inputx + support;
+            if real_right.fract() == 0.0 {
+                (real_right - 1.0) as i64
+            } else {
+                real_right.floor() as i64
+            }
+        };
+        let right = clamp(right, 0, width as i64 - 1) as u32;
+
+        let mut sum = 0.;
+
+        let mut t = (0., 0., 0., 0.);
+
+        for i in left..right + 1 {
+            let w = kernel(i as f32 - inputx);
+            sum += w;
+
+            let x0  = clamp(i, 0, width - 1);
+            let p = image.get_pixel(x0, y);
+
+            let (k1, k2, k3, k4) = p.channels4();
+            let vec: (f32, f32, f32, f32) = (
+                NumCast::from(k1).unwrap(),
+                NumCast::from(k2).unwrap(),
+                NumCast::from(k3).unwrap(),
+                NumCast::from(k4).unwrap()
+            );
+
+            t.0 += vec.0 * w; t.1 += vec.1 * w;
+            t.2 += vec.2 * w; t.3 += vec.3 * w;
+        }
+
+        let (t1, t2, t3, t4) = (t.0 / sum, t.1 / sum, t.2 / sum, t.3 / sum);
+        let t = Pixel::from_channels(
+            NumCast::from(clamp(t1, 0.0, max)).unwrap(),
+            NumCast::from(clamp(t2, 0.0, max)).unwrap(),
+            NumCast::from(clamp(t3, 0.0, max)).unwrap(),
+            NumCast::from(clamp(t4, 0.0, max)).unwrap()
+        );
+
+        let outx = outx as usize * channels;
+        *P::from_slice_mut(&mut row[outx..outx + channels]) = t;
+    }
+}
+
 // Sample the rows of the supplied image using the provided filter.
 // The height of the image remains unchanged.
 // ```new_width``` is the desired width of the new image
@@ -138,98 +273,86 @@ fn horizontal_sample<I, P, S>(image: &I, new_width: u32,
           P: Pixel<Subpixel=S> + 'static,
           S: Primitive + 'static {
 
+    let kernel = &*filter.kernel;
+    map_rows(new_width, image.height(), |y, row| {
+        horizontal_sample_row(image, y, filter.support, kernel, row)
+    })
+}
+
+// Sample row ```outy``` of the new image from the columns of the supplied
+// image using the provided filter.
+// ```row``` receives the new row, which has the width of the supplied image.
+fn vertical_sample_row<I, P, S, K>(image: &I, outy: u32, new_height: u32,
+                                   support: f32, kernel: &K, row: &mut [S])
+    where I: GenericImage<Pixel=P>,
+          P: Pixel<Subpixel=S> + 'static,
+          S: Primitive + 'static,
+          K: Fn(f32) -> f32 + ?Sized {
+
     let (width, height) = image.dimensions();
-    let mut out = ImageBuffer::new(new_width, height);
+    let channels = P::channel_count() as usize;
 
-    for y in 0..height {
-        let max = S::max_value();
-        let max: f32 = NumCast::from(max).unwrap();
-
-        let ratio = width as f32 / new_width as f32;
-
-        for outx in 0..new_width {
-
-            // Find the point in the input image corresponding to the centre
-            // of the current pixel in the output image.
-            //
-            // Then go half a pixel to the left (hence the `- 0.5`).
-            //
-            // The reason for subtracting 0.5 is because the filter kernel
-            // treats the centre of a pixel as 0. When finding the left and
-            // right limits below, we're interested in the range of input
-            // pixels whose colour can influence the colour of the current
-            // output pixel. This is equivalent to the range of input
-            // pixels for which inputx lies within the filter.support-sized
-            // region centered at the centre of that pixel. Subtracting
-            // 0.5 here simplifies the rounding operations below.
-            //
-            let inputx = (outx as f32 + 0.5) * ratio - 0.5;
-
-            // Find the index of the left-most input pixel which can influence
-            // the colour of the current output pixel. A point on the right
-            // side of a pixel is considered to be part of that pixel.
-            let left  = (inputx - filter.support).ceil() as i64;
-            let left  = clamp(left, 0, width as i64 - 1) as u32;
-
-            // Find the index of the right-most input pixel which can influence
-            // the colour of the current output pixel. A point on the left side
-            // of a pixel is NOT considered to be part of that pixel. This is
-            // important because:
-            //  - If we included the point in both neighbouring pixels it would
-            //    force the output pixel to be influenced be both input pixels,
-            //    and force filters which don't desire this (e.g. Nearest),
-            //    to make sure they only sample from one side in such cases.
-            //  - If we included the point in neither neighbouring pixel it
-            //    would cause output pixels corresponding to input pixel
-            //    boundaries to be black regardless of the input pixel colours.
-            //
-            // The choice of right vs left is arbitrary.
-            let right = {
-                let real_right = inputx + filter.support;
-                if real_right.fract() == 0.0 {
-                    (real_right - 1.0) as i64
-                } else {
-                    real_right.floor() as i64
-                }
-            };
-            let right = clamp(right, 0, width as i64 - 1) as u32;
+    let max = S::max_value();
+    let max: f32 = NumCast::from(max).unwrap();
 
-            let mut sum = 0.;
+    let ratio = height as f32 / new_height as f32;
 
-            let mut t = (0., 0., 0., 0.);
+    // For an explanation of this algorithm, see the comments
+    // in horizontal_sample_row.
 
-            for i in left..right + 1 {
-                let w = (filter.kernel)(i as f32 - inputx);
-                sum += w;
+    let inputy = (outy as f32 + 0.5) * ratio - 0.5;
 
-                let x0  = clamp(i, 0, width - 1);
-                let p = image.get_pixel(x0, y);
+    let left  = (inputy - support).ceil() as i64;
+    let left  = clamp(left, 0, height as i64 - 1) as u32;
 
-                let (k1, k2, k3, k4) = p.channels4();
-                let vec: (f32, f32, f32, f32) = (
-                    NumCast::from(k1).unwrap(),
-                    NumCast::from(k2).unwrap(),
-                    NumCast::from(k3).unwrap(),
-                    NumCast::from(k4).unwrap()
-                );
+    let right = {
+        // A point above a pixel is NOT part of that pixel.
+        let real_right = inputy + support;
+        if real_right.fract() == 0.0 {
+            (real_right - 1.0) as i64
+        } else {
+            real_right.floor() as i64
+        }
+    };
+    let right = clamp(right, 0, height as i64 - 1) as u32;
 
-                t.0 += vec.0 * w; t.1 += vec.1 * w;
-                t.2 += vec.2 * w; t.3 += vec.3 * w;
-            }
+    // The weights are the same for every column of the row
+    let weights: Vec<f32> = (left..right + 1).map(|i| kernel(i as f32 - inputy)).collect();
 
-            let (t1, t2, t3, t4) = (t.0 / sum, t.1 / sum, t.2 / sum, t.3 / sum);
-            let t = Pixel::from_channels(
-                NumCast::from(clamp(t1, 0.0, max)).unwrap(),
-                NumCast::from(clamp(t2, 0.0, max)).unwrap(),
-                NumCast::from(clamp(t3, 0.0, max)).unwrap(),
-                NumCast::from(clamp(t4, 0.0, max)).unwrap()
+    for x in 0..width {
+        let mut sum = 0.;
+
+        let mut t = (0., 0., 0., 0.);
+
+        for (i, &w) in (left..right + 1).zip(weights.iter()) {
+            sum += w;
+
+            let y0  = clamp(i, 0, height - 1);
+            let p = image.get_pixel(x, y0);
+
+            let (k1, k2, k3, k4) = p.channels4();
+            let vec: (f32, f32, f32, f32) = (
+                NumCast::from(k1).unwrap(),
+                NumCast::from(k2).unwrap(),
+                NumCast::from(k3).unwrap(),
+                NumCast::from(k4).unwrap()
             );
 
-            out.put_pixel(outx, y, t);
+            t.0 += vec.0 * w; t.1 += vec.1 * w;
+            t.2 += vec.2 * w; t.3 += vec.3 * w;
         }
-    }
 
-    out
+        let (t1, t2, t3, t4) = (t.0 / sum, t.1 / sum, t.2 / sum, t.3 / sum);
+        let t = Pixel::from_channels(
+            NumCast::from(clamp(t1, 0.0, max)).unwrap(),
+            NumCast::from(clamp(t2, 0.0, max)).unwrap(),
+            NumCast::from(clamp(t3, 0.0, max)).unwrap(),
+            NumCast::from(clamp(t4, 0.0, max)).unwrap()
+        );
+
+        let x = x as usize * channels;
+        *P::from_slice_mut(&mut row[x..x + channels]) = t;
+    }
 }
 
 // Sample the columns of the supplied image using the provided filter.
@@ -244,73 +367,77 @@ fn vertical_sample<I, P, S>(image: &I, new_height: u32,
           P: Pixel<Subpixel=S> + 'static,
           S: Primitive + 'static {
 
-    let (width, height) = image.dimensions();
-    let mut out = ImageBuffer::new(width, new_height);
-
-
-    for x in 0..width {
-        let max = S::max_value();
-        let max: f32 = NumCast::from(max).unwrap();
+    let kernel = &*filter.kernel;
+    map_rows(image.width(), new_height, |y, row| {
+        vertical_sample_row(image, y, new_height, filter.support, kernel, row)
+    })
+}
 
-        let ratio = height as f32 / new_height as f32;
+// The kernel's input positions relative to the current pixel.
+const TAPS_3X3: [(isize, isize); 9] = [
+    (-1, -1), ( 0, -1), ( 1, -1),
+    (-1,  0), ( 0,  0), ( 1,  0),
+    (-1,  1), ( 0,  1), ( 1,  1),
+  ];
+
+// Applies a 3x3 kernel to row ```y``` of the supplied image, leaving the
+// first and the last pixel of ```row``` untouched.
+fn filter3x3_row<I, P, S>(image: &I, y: u32, kernel: &[f32], row: &mut [S])
+    where I: GenericImage<Pixel=P>,
+          P: Pixel<Subpixel=S> + 'static,
+          S: Primitive + 'static {
 
-        for outy in 0..new_height {
+    let width = image.width();
+    let channels = P::channel_count() as usize;
 
-            // For an explanation of this algorithm, see the comments
-            // in horizontal_sample.
+    let max = S::max_value();
+    let max: f32 = NumCast::from(max).unwrap();
 
-            let inputy = (outy as f32 + 0.5) * ratio - 0.5;
+    let sum = match kernel.iter().fold(0.0, |s, &item| s + item) {
+        x if x == 0.0 => 1.0,
+        sum => sum
+    };
+    let sum = (sum, sum, sum, sum);
 
-            let left  = (inputy - filter.support).ceil() as i64;
-            let left  = clamp(left, 0, height as i64 - 1) as u32;
+    for x in 1..width - 1 {
+        let mut t = (0., 0., 0., 0.);
 
-            let right = {
-                // A point above a pixel is NOT part of that pixel.
-                let real_right = inputy + filter.support;
-                if real_right.fract() == 0.0 {
-                    (real_right - 1.0) as i64
-                } else {
-                    real_right.floor() as i64
-                }
-            };
-            let right = clamp(right, 0, height as i64 - 1) as u32;
 
-            let mut sum = 0.;
+        // TODO: There is no need to recalculate the kernel for each pixel.
+        // Only a subtract and addition is needed for pixels after the first
+        // in each row.
+        for (&k, &(a, b)) in kernel.iter().zip(TAPS_3X3.iter()) {
+            let k = (k, k, k, k);
+            let x0 = x as isize + a;
+            let y0 = y as isize + b;
 
-            let mut t = (0., 0., 0., 0.);
+            let p = image.get_pixel(x0 as u32, y0 as u32);
 
-            for i in left..right + 1 {
-                let w = (filter.kernel)(i as f32 - inputy);
-                sum += w;
+            let (k1, k2, k3, k4) = p.channels4();
 
-                let y0  = clamp(i, 0, height - 1);
-                let p = image.get_pixel(x, y0);
+            let vec: (f32, f32, f32, f32) = (
+                NumCast::from(k1).unwrap(),
+                NumCast::from(k2).unwrap(),
+                NumCast::from(k3).unwrap(),
+                NumCast::from(k4).unwrap()
+            );
 
-                let (k1, k2, k3, k4) = p.channels4();
-                let vec: (f32, f32, f32, f32) = (
-                    NumCast::from(k1).unwrap(),
-                    NumCast::from(k2).unwrap(),
-                    NumCast::from(k3).unwrap(),
-                    NumCast::from(k4).unwrap()
-                );
+            t.0 += vec.0 * k.0; t.1 += vec.1 * k.1;
+            t.2 += vec.2 * k.2; t.3 += vec.3 * k.3;
+        }
 
-                t.0 += vec.0 * w; t.1 += vec.1 * w;
-                t.2 += vec.2 * w; t.3 += vec.3 * w;
-            }
+        let (t1, t2, t3, t4) = (t.0 / sum.0, t.1 / sum.1, t.2 / sum.2, t.3 / sum.3);
 
-            let (t1, t2, t3, t4) = (t.0 / sum, t.1 / sum, t.2 / sum, t.3 / sum);
-            let t = Pixel::from_channels(
-                NumCast::from(clamp(t1, 0.0, max)).unwrap(),
-                NumCast::from(clamp(t2, 0.0, max)).unwrap(),
-                NumCast::from(clamp(t3, 0.0, max)).unwrap(),
-                NumCast::from(clamp(t4, 0.0, max)).unwrap()
-            );
+        let t = Pixel::from_channels(
+            NumCast::from(clamp(t1, 0.0, max)).unwrap(),
+            NumCast::from(clamp(t2, 0.0, max)).unwrap(),
+            NumCast::from(clamp(t3, 0.0, max)).unwrap(),
+            NumCast::from(clamp(t4, 0.0, max)).unwrap()
+        );
 
-            out.put_pixel(x, outy, t);
-        }
+        let x = x as usize * channels;
+        *P::from_slice_mut(&mut row[x..x + channels]) = t;
     }
-
-    out
 }
 
 /// Perform a 3x3 box filter on the supplied image.
@@ -322,67 +449,69 @@ pub fn filter3x3<I, P, S>(image: &I, kernel: &[f32])
           P: Pixel<Subpixel=S> + 'static,
           S: Primitive + 'static {
 
-    // The kernel's input positions relative to the current pixel.
-    let taps: &[(isize, isize)] = &[
-        (-1, -1), ( 0, -1), ( 1, -1),
-        (-1,  0), ( 0,  0), ( 1,  0),
-        (-1,  1), ( 0,  1), ( 1,  1),
-      ];
-
     let (width, height) = image.dimensions();
+    map_rows(width, height, |y, row| {
+        if y > 0 && y < height - 1 {
+            filter3x3_row(image, y, kernel, row)
+        }
+    })
+}
 
-    let mut out = ImageBuffer::new(width, height);
-
-    let max = S::max_value();
-    let max: f32 = NumCast::from(max).unwrap();
-
-    let sum = match kernel.iter().fold(0.0, |s, &item| s + item) {
-        x if x == 0.0 => 1.0,
-        sum => sum
-    };
-    let sum = (sum, sum, sum, sum);
-
-    for y in 1..height - 1 {
-        for x in 1..width - 1 {
-            let mut t = (0., 0., 0., 0.);
-
-
-            // TODO: There is no need to recalculate the kernel for each pixel.
-            // Only a subtract and addition is needed for pixels after the first
-            // in each row.
-            for (&k, &(a, b)) in kernel.iter().zip(taps.iter()) {
-                let k = (k, k, k, k);
-                let x0 = x as isize + a;
-                let y0 = y as isize + b;
+// The kernel and the support of a filter type
+fn filter_kernel(filter: FilterType) -> (fn(f32) -> f32, f32) {
+    match filter {
+        FilterType::Nearest    => (box_kernel, 0.5),
+        FilterType::Triangle   => (triangle_kernel, 1.0),
+        FilterType::CatmullRom => (catmullrom_kernel, 2.0),
+        FilterType::Gaussian   => (gaussian_kernel, 3.0),
+        FilterType::Lanczos3   => (lanczos3_kernel, 3.0),
+    }
+}
 
-                let p = image.get_pixel(x0 as u32, y0 as u32);
+// The input pixel nearest to each of the ```new_len``` output pixels. This is
+// the only pixel which has a weight in ```horizontal_sample_row``` when it is
+// used with the box kernel and a support of 0.5.
+fn nearest_indices(len: u32, new_len: u32) -> Vec<u32> {
+    let ratio = len as f32 / new_len as f32;
+
+    (0..new_len).map(|out| {
+        let input = (out as f32 + 0.5) * ratio - 0.5;
+        let left = (input - 0.5).ceil() as i64;
+        clamp(left, 0, len as i64 - 1) as u32
+    }).collect()
+}
 
-                let (k1, k2, k3, k4) = p.channels4();
+// Nearest neighbor resizing, which copies pixels instead of weighting them.
+// Output rows which sample the same input row are duplicated.
+fn resize_nearest<I, P, S>(image: &I, nwidth: u32, nheight: u32)
+    -> ImageBuffer<P, Vec<S>>
+    where I: GenericImage<Pixel=P>,
+          P: Pixel<Subpixel=S> + 'static,
+          S: Primitive + 'static {
 
-                let vec: (f32, f32, f32, f32) = (
-                    NumCast::from(k1).unwrap(),
-                    NumCast::from(k2).unwrap(),
-                    NumCast::from(k3).unwrap(),
-                    NumCast::from(k4).unwrap()
-                );
+    let mut out = ImageBuffer::new(nwidth, nheight);
+    let channels = P::channel_count() as usize;
+    let row_len = nwidth as usize * channels;
+    if row_len == 0 {
+        return out
+    }
 
-                t.0 += vec.0 * k.0; t.1 += vec.1 * k.1;
-                t.2 += vec.2 * k.2; t.3 += vec.3 * k.3;
-            }
+    let xs = nearest_indices(image.width(), nwidth);
+    let ys = nearest_indices(image.height(), nheight);
 
-            let (t1, t2, t3, t4) = (t.0 / sum.0, t.1 / sum.1, t.2 / sum.2, t.3 / sum.3);
+    for (y, &iny) in ys.iter().enumerate() {
+        let (done, rest) = out.split_at_mut(y * row_len);
+        let row = &mut rest[..row_len];
 
-            let t = Pixel::from_channels(
-                NumCast::from(clamp(t1, 0.0, max)).unwrap(),
-                NumCast::from(clamp(t2, 0.0, max)).unwrap(),
-                NumCast::from(clamp(t3, 0.0, max)).unwrap(),
-                NumCast::from(clamp(t4, 0.0, max)).unwrap()
-            );
+        if y > 0 && ys[y - 1] == iny {
+            row.copy_from_slice(&done[(y - 1) * row_len..]);
+            continue
+        }
 
-            out.put_pixel(x, y, t);
+        for (pixel, &inx) in row.chunks_mut(channels).zip(xs.iter()) {
+            *P::from_slice_mut(pixel) = image.get_pixel(inx, iny);
         }
     }
-
     out
 }
 
@@ -396,28 +525,15 @@ pub fn resize<I: GenericImage + 'static>(image: &I, nwidth: u32, nheight: u32,
     where I::Pixel: 'static,
           <I::Pixel as Pixel>::Subpixel: 'static {
 
-    let mut method = match filter {
-        FilterType::Nearest    =>   Filter {
-            kernel: Box::new(box_kernel),
-            support: 0.5
-        },
-        FilterType::Triangle   => Filter {
-            kernel: Box::new(triangle_kernel),
-            support: 1.0
-        },
-        FilterType::CatmullRom => Filter {
-            kernel: Box::new(catmullrom_kernel),
-            support: 2.0
-        },
-        FilterType::Gaussian   => Filter {
-            kernel: Box::new(gaussian_kernel),
-            support: 3.0
-        },
-        FilterType::Lanczos3   => Filter {
-            kernel: Box::new(lanczos3_kernel),
-            support: 3.0
-        },
-};
+    if let FilterType::Nearest = filter {
+        return resize_nearest(image, nwidth, nheight)
+    }
+
+    let (kernel, support) = filter_kernel(filter);
+    let mut method = Filter {
+        kernel: Box::new(kernel),
+        support
+    };
 
     let tmp = vertical_sample(image, nheight, &mut method);
     horizontal_sample(&tmp, nwidth, &mut method)
@@ -450,6 +566,76 @@ pub fn blur<I: GenericImage + 'static>(image: &I, sigma: f32)
     horizontal_sample(&tmp, width, &mut method)
 }
 
+/// Like ```filter3x3```, but the rows of the result are computed in parallel
+/// on the threads of the rayon thread pool.
+#[cfg(feature = "rayon")]
+pub fn filter3x3_parallel<I, P, S>(image: &I, kernel: &[f32])
+    -> ImageBuffer<P, Vec<S>>
+    where I: GenericImage<Pixel=P> + Sync + 'static,
+          P: Pixel<Subpixel=S> + 'static,
+          S: Primitive + Send + 'static {
+
+    let (width, height) = image.dimensions();
+    map_rows_parallel(width, height, |y, row| {
+        if y > 0 && y < height - 1 {
+            filter3x3_row(image, y, kernel, row)
+        }
+    })
+}
+
+// Resamples the image in both directions, computing the rows of each pass in parallel.
+#[cfg(feature = "rayon")]
+fn sample_parallel<I, P, S, K>(image: &I, nwidth: u32, nheight: u32, support: f32, kernel: &K)
+    -> ImageBuffer<P, Vec<S>>
+    where I: GenericImage<Pixel=P> + Sync + 'static,
+          P: Pixel<Subpixel=S> + Sync + 'static,
+          S: Primitive + Send + Sync + 'static,
+          K: Fn(f32) -> f32 + Sync {
+
+    let tmp: ImageBuffer<P, Vec<S>> = map_rows_parallel(image.width(), nheight, |y, row| {
+        vertical_sample_row(image, y, nheight, support, kernel, row)
+    });
+    map_rows_parallel(nwidth, nheight, |y, row| {
+        horizontal_sample_row(&tmp, y, support, kernel, row)
+    })
+}
+
+/// Like ```resize```, but the rows of the result are computed in parallel
+/// on the threads of the rayon thread pool.
+#[cfg(feature = "rayon")]
+pub fn resize_parallel<I: GenericImage + Sync + 'static>(image: &I, nwidth: u32, nheight: u32,
+                                                         filter: FilterType)
+    -> ImageBuffer<I::Pixel, Vec<<I::Pixel as Pixel>::Subpixel>>
+    where I::Pixel: Sync + 'static,
+          <I::Pixel as Pixel>::Subpixel: Send + Sync + 'static {
+
+    // Nearest neighbor resizing only copies memory
+    if let FilterType::Nearest = filter {
+        return resize_nearest(image, nwidth, nheight)
+    }
+
+    let (kernel, support) = filter_kernel(filter);
+    sample_parallel(image, nwidth, nheight, support, &kernel)
+}
+
+/// Like ```blur```, but the rows of the result are computed in parallel
+/// on the threads of the rayon thread pool.
+#[cfg(feature = "rayon")]
+pub fn blur_parallel<I: GenericImage + Sync + 'static>(image: &I, sigma: f32)
+    -> ImageBuffer<I::Pixel, Vec<<I::Pixel as Pixel>::Subpixel>>
+    where I::Pixel: Sync + 'static,
+          <I::Pixel as Pixel>::Subpixel: Send + Sync + 'static {
+
+    let sigma = if sigma < 0.0 {
+        1.0
+    } else {
+        sigma
+    };
+
+    let (width, height) = image.dimensions();
+    sample_parallel(image, width, height, 2.0 * sigma, &|x| gaussian(x, sigma))
+}
+
 /// Performs an unsharpen mask on the supplied image.
 /// ```sigma``` is the amount to blur the image by.
 /// ```threshold``` is the threshold for the difference between
@@ -519,4 +705,42 @@ mod tests {
         let _ = resize(&img, 50, 50, FilterType::Lanczos3);
     }
 
+    #[test]
+    fn test_nearest_matches_box_filter() {
+        use super::{box_kernel, horizontal_sample, vertical_sample, Filter};
+
+        let img: RgbImage = ImageBuffer::from_fn(13, 7, |x, y| {
+            ::Rgb([(x * 19) as u8, (y * 37) as u8, (x ^ y) as u8])
+        });
+        for &(width, height) in &[(1, 1), (5, 3), (13, 7), (26, 14), (40, 9), (7, 31)] {
+            let mut method = Filter {
+                kernel: Box::new(box_kernel),
+                support: 0.5
+            };
+            let tmp = vertical_sample(&img, height, &mut method);
+            let filtered = horizontal_sample(&tmp, width, &mut method);
+            assert_eq!(resize(&img, width, height, FilterType::Nearest).into_raw(),
+                       filtered.into_raw());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn test_parallel_matches_sequential() {
+        use super::{blur, blur_parallel, filter3x3, filter3x3_parallel, resize_parallel};
+
+        let img: RgbImage = ImageBuffer::from_fn(37, 23, |x, y| {
+            ::Rgb([(x * 7) as u8, (y * 11) as u8, (x * y) as u8])
+        });
+        for &filter in &[FilterType::Nearest, FilterType::Triangle, FilterType::Lanczos3] {
+            for &(width, height) in &[(15, 40), (74, 9)] {
+                assert_eq!(resize_parallel(&img, width, height, filter).into_raw(),
+                           resize(&img, width, height, filter).into_raw());
+            }
+        }
+        assert_eq!(blur_parallel(&img, 1.5).into_raw(), blur(&img, 1.5).into_raw());
+        let kernel = [0.0, -1.0, 0.0, -1.0, 5.0, -1.0, 0.0, -1.0, 0.0];
+        assert_eq!(filter3x3_parallel(&img, &kernel).into_raw(), filter3x3(&img, &kernel).into_raw());
+    }
+
 }
@@ -0,0 +1,114 @@
+//! Template matching by normalized cross-correlation
+
+use buffer::ImageBuffer;
+use color::Luma;
+use image::{GenericImage, ImageError, ImageResult};
+use imageops::integral::{integral_image, integral_squared_image, integral_sum};
+use traits::Primitive;
+
+/// Match a template against every position of a grayscale image with 8 or 16 bits per pixel.
+///
+/// Returns a map of the zero-normalized cross-correlation of the template with the part of
+/// the image below it, with one score per position at which the template fits into the
+/// image. A score of 1 is a perfect match, 0 means no correlation and -1 a perfect match of
+/// the inverted template. Positions where either the template or the image is uniform have
+/// a score of 0. The scores are invariant to changes of brightness and contrast.
+/// Returns a ```DimensionError``` if the template is empty or larger than the image.
+pub fn match_template<I, T, S>(image: &I, template: &T) -> ImageResult<ImageBuffer<Luma<f32>, Vec<f32>>>
+    where I: GenericImage<Pixel=Luma<S>>,
+          T: GenericImage<Pixel=Luma<S>>,
+          S: Primitive + Into<u16> + 'static {
+    let (width, height) = image.dimensions();
+    let (t_width, t_height) = template.dimensions();
+    if t_width == 0 || t_height == 0 || t_width > width || t_height > height {
+        return Err(ImageError::DimensionError)
+    }
+
+    let value = |v: S| f64::from(v.into());
+    let n = (t_width as u64 * t_height as u64) as f64;
+    let template_values: Vec<f64> = template.pixels().map(|(_, _, p)| value(p[0])).collect();
+    let t_mean = template_values.iter().sum::<f64>() / n;
+    let t_deviations: Vec<f64> = template_values.iter().map(|t| t - t_mean).collect();
+    let t_norm = t_deviations.iter().map(|t| t * t).sum::<f64>().sqrt();
+
+    let sums = integral_image(image);
+    let squares = integral_squared_image(image);
+    let mut scores = ImageBuffer::new(width - t_width + 1, height - t_height + 1);
+    for (x, y, score) in scores.enumerate_pixels_mut() {
+        let sum = integral_sum(&sums, x, y, t_width, t_height) as f64;
+        let square_sum = integral_sum(&squares, x, y, t_width, t_height) as f64;
+        // The sum of the squared deviations of the window from its mean
+        let i_norm = (square_sum - sum * sum / n).max(0.0).sqrt();
+        if t_norm == 0.0 || i_norm <= 1e-9 * (1.0 + square_sum.sqrt()) {
+            continue
+        }
+        // The mean of the window cancels out because the deviations of the template sum to 0
+        let mut correlation = 0.0;
+        for ty in 0..t_height {
+            for tx in 0..t_width {
+                let i = value(image.get_pixel(x + tx, y + ty)[0]);
+                correlation += i * t_deviations[(ty * t_width + tx) as usize];
+            }
+        }
+        *score = Luma([(correlation / (i_norm * t_norm)) as f32]);
+    }
+    Ok(scores)
+}
+
+/// Find the position with the highest score in a map computed by ```match_template```.
+/// Returns the position and its score or ```None``` if the map is empty.
+pub fn find_best_match(scores: &ImageBuffer<Luma<f32>, Vec<f32>>) -> Option<(u32, u32, f32)> {
+    scores.enumerate_pixels().fold(None, |best, (x, y, score)| match best {
+        Some((_, _, best_score)) if best_score >= score[0] => best,
+        _ => Some((x, y, score[0])),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_best_match, match_template};
+    use buffer::{GrayImage, ImageBuffer};
+    use color::Luma;
+    use image::{GenericImage, ImageError};
+
+    fn scene() -> GrayImage {
+        ImageBuffer::from_fn(40, 30, |x, y| Luma([((x * x + 3 * y * y + x * y) % 200) as u8]))
+    }
+
+    #[test]
+    fn test_exact_match() {
+        let mut image = scene();
+        let template = image.sub_image(17, 9, 6, 5).to_image();
+        let scores = match_template(&image, &template).unwrap();
+        assert_eq!(scores.dimensions(), (35, 26));
+        let (x, y, score) = find_best_match(&scores).unwrap();
+        assert_eq!((x, y), (17, 9));
+        assert!((score - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_brightness_and_contrast_invariance() {
+        let mut image = scene();
+        let template = image.sub_image(5, 20, 8, 8).to_image();
+        let adjusted: GrayImage = ImageBuffer::from_fn(8, 8, |x, y| Luma([template[(x, y)][0] / 2 + 40]));
+        let (x, y, score) = find_best_match(&match_template(&image, &adjusted).unwrap()).unwrap();
+        assert_eq!((x, y), (5, 20));
+        assert!(score > 0.99);
+    }
+
+    #[test]
+    fn test_uniform() {
+        let image: GrayImage = ImageBuffer::from_pixel(8, 8, Luma([7]));
+        let scores = match_template(&image, &scene().sub_image(0, 0, 3, 3).to_image()).unwrap();
+        assert!(scores.pixels().all(|p| p[0] == 0.0));
+    }
+
+    #[test]
+    fn test_template_too_large() {
+        match match_template(&scene(), &GrayImage::new(41, 2)) {
+            Err(ImageError::DimensionError) => (),
+            other => panic!("expected a dimension error, got {:?}", other.map(|s| s.dimensions())),
+        }
+        assert_eq!(find_best_match(&ImageBuffer::new(0, 0)), None);
+    }
+}
@@ -0,0 +1,60 @@
+//! Images which store indices into a palette instead of colors
+
+use buffer::{ImageBuffer, RgbaImage};
+use color::Rgba;
+
+/// An image whose pixels are indices into a palette of colors
+///
+/// Paletted PNG and GIF images are decoded to this with `read_indexed`, instead of
+/// expanding the palette to RGB or RGBA. This allows editing the pixels and encoding
+/// them again without changing the palette.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexedImage {
+    /// The width of the image
+    pub width: u32,
+    /// The height of the image
+    pub height: u32,
+    /// The palette index of every pixel, row by row from the top
+    pub indices: Vec<u8>,
+    /// The colors of the palette, the alpha channel holds the transparency of each entry
+    pub palette: Vec<Rgba<u8>>,
+}
+
+impl IndexedImage {
+    /// Returns the index of the first fully transparent palette entry, e.g. the
+    /// transparent color of a GIF
+    pub fn transparent_index(&self) -> Option<u8> {
+        self.palette.iter().position(|color| color[3] == 0).map(|index| index as u8)
+    }
+
+    /// Expands the palette to an RGBA image
+    ///
+    /// Indices outside of the palette are transparent black.
+    pub fn to_rgba(&self) -> RgbaImage {
+        let mut data = Vec::with_capacity(self.indices.len() * 4);
+        for &index in &self.indices {
+            let color = self.palette.get(index as usize).cloned().unwrap_or(Rgba([0, 0, 0, 0]));
+            data.extend_from_slice(&color.data);
+        }
+        ImageBuffer::from_raw(self.width, self.height, data).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IndexedImage;
+    use color::Rgba;
+
+    #[test]
+    fn test_to_rgba() {
+        let image = IndexedImage {
+            width: 2,
+            height: 2,
+            indices: vec![0, 1, 2, 1],
+            palette: vec![Rgba([255, 0, 0, 255]), Rgba([0, 255, 0, 0])],
+        };
+        assert_eq!(image.transparent_index(), Some(1));
+        assert_eq!(image.to_rgba().into_raw(), vec![255, 0, 0, 255, 0, 255, 0, 0,
+                                                    0, 0, 0, 0, 0, 255, 0, 0]);
+    }
+}
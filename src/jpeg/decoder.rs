@@ -1,58 +1,275 @@
 extern crate jpeg_decoder;
 
-use std::io::Read;
+use std::cmp;
+use std::io::{self, Cursor, Read};
+
+use byteorder::{BigEndian, ByteOrder};
+#[cfg(feature = "jpeg_rayon")]
+use rayon::{ThreadPool, ThreadPoolBuilder};
 
 use color::{self, ColorType};
-use image::{DecodingResult, ImageDecoder, ImageError, ImageResult};
+use image::{DecodingError, DecodingErrorKind, DecodingResult, ImageDecoder, ImageError, ImageFormat,
+            ImageResult};
+use super::lossless::ceil_div;
+use metadata::{Metadata, PixelDensity, PixelDensityUnit, EXIF_HEADER, ICC_HEADER, XMP_HEADER};
+
+// The marker segments are read before the image data, the bytes consumed while
+// reading them are passed on to the decoder in front of the rest of the stream
+type JpegStream<R> = io::Chain<Cursor<Vec<u8>>, R>;
+
+// Progressive frames with more blocks are checked against the size of the image data
+const MAX_UNCHECKED_BLOCKS: usize = 1 << 16;
+
+// Progressive DCT, Huffman coding
+const SOF2: u8 = 0xC2;
+
+// Runs the decoder inside of a thread pool, this requires `R: Send`
+#[cfg(feature = "jpeg_rayon")]
+type PoolDecode<R> = fn(&ThreadPool, &mut jpeg_decoder::Decoder<JpegStream<R>>) -> Result<Vec<u8>, jpeg_decoder::Error>;
 
 /// JPEG decoder
 pub struct JPEGDecoder<R> {
-    decoder: jpeg_decoder::Decoder<R>,
-    metadata: Option<jpeg_decoder::ImageInfo>,
+    reader: Option<R>,
+    decoder: Option<jpeg_decoder::Decoder<JpegStream<R>>>,
+    // The APPn and COM segments in front of the frame, as (marker, data)
+    segments: Vec<(u8, Vec<u8>)>,
+    info: Option<jpeg_decoder::ImageInfo>,
+    #[cfg(feature = "jpeg_rayon")]
+    pool: Option<(ThreadPool, PoolDecode<R>)>,
 }
 
 impl<R: Read> JPEGDecoder<R> {
     /// Create a new decoder that decodes from the stream ```r```
     pub fn new(r: R) -> JPEGDecoder<R> {
         JPEGDecoder {
-            decoder: jpeg_decoder::Decoder::new(r),
-            metadata: None,
+            reader: Some(r),
+            decoder: None,
+            segments: Vec::new(),
+            info: None,
+            #[cfg(feature = "jpeg_rayon")]
+            pool: None,
+        }
+    }
+
+    /// Configures the decoder to scale the image down while decoding it.
+    ///
+    /// The image is scaled by the smallest of the factors 1/8, 1/4, 1/2 and 1
+    /// which keeps it at least as large as the requested size in one of the axes.
+    /// Scaling is done in the inverse DCT, so a reduced size decode is much faster
+    /// than decoding the full image and resizing it. Use a resampling filter to get
+    /// an exact size afterwards.
+    ///
+    /// Returns the dimensions of the decoded image, which are also reported by
+    /// ```dimensions```.
+    pub fn scale(&mut self, requested_width: u16, requested_height: u16) -> ImageResult<(u16, u16)> {
+        let size = self.decoder()?.scale(requested_width, requested_height)?;
+        self.info = None;
+        Ok(size)
+    }
+
+    // Reads the marker segments in front of the frame on first use
+    fn decoder(&mut self) -> ImageResult<&mut jpeg_decoder::Decoder<JpegStream<R>>> {
+        if let Some(mut r) = self.reader.take() {
+            let mut buffer = Vec::new();
+            let (segments, frame) = read_segments(&mut r, &mut buffer)?;
+            self.segments = segments;
+
+            // The decoder allocates the coefficients of progressive images before it reads
+            // the scans, so a large progressive frame has to be backed by enough data. Every
+            // block is coded with at least one bit.
+            let blocks = match frame {
+                Some((SOF2, ref frame)) => frame_blocks(frame).unwrap_or(0),
+                _ => 0,
+            };
+            if blocks > MAX_UNCHECKED_BLOCKS && !read_bytes(&mut r, blocks / 8, &mut buffer)? {
+                return Err(ImageError::malformed(ImageFormat::JPEG, "Frame is larger than the image data"))
+            }
+            self.decoder = Some(jpeg_decoder::Decoder::new(Cursor::new(buffer).chain(r)));
+        }
+        Ok(self.decoder.as_mut().unwrap())
+    }
+
+    #[cfg(feature = "jpeg_rayon")]
+    fn decode(&mut self) -> ImageResult<Vec<u8>> {
+        self.decoder()?;
+        let decoder = self.decoder.as_mut().unwrap();
+        match self.pool {
+            Some((ref pool, decode)) => Ok(decode(pool, decoder)?),
+            None => Ok(decoder.decode()?),
+        }
+    }
+
+    #[cfg(not(feature = "jpeg_rayon"))]
+    fn decode(&mut self) -> ImageResult<Vec<u8>> {
+        Ok(self.decoder()?.decode()?)
+    }
+
+    // Returns the data of the first segment with the marker ```marker``` which starts with ```prefix```
+    fn segment(&self, marker: u8, prefix: &[u8]) -> Option<&[u8]> {
+        self.segments.iter()
+            .find(|segment| segment.0 == marker && segment.1.starts_with(prefix))
+            .map(|segment| &segment.1[..])
+    }
+
+    // Joins the parts of the ICC profile, which is split into APP2 segments
+    fn icc_profile(&self) -> ImageResult<Option<Vec<u8>>> {
+        let mut parts: Vec<&[u8]> = self.segments.iter()
+            .filter(|segment| segment.0 == 0xE2 && segment.1.starts_with(ICC_HEADER))
+            .map(|segment| &segment.1[ICC_HEADER.len()..])
+            .collect();
+        if parts.is_empty() {
+            return Ok(None)
+        }
+        if parts.iter().any(|part| part.len() < 2 || usize::from(part[1]) != parts.len()) {
+            return Err(ImageError::malformed(ImageFormat::JPEG, "Invalid ICC profile segments"))
+        }
+
+        // The sequence numbers start at 1
+        parts.sort_by_key(|part| part[0]);
+        if parts.iter().enumerate().any(|(i, part)| usize::from(part[0]) != i + 1) {
+            return Err(ImageError::malformed(ImageFormat::JPEG, "Invalid ICC profile segments"))
         }
+        Ok(Some(parts.iter().flat_map(|part| part[2..].iter().cloned()).collect()))
     }
 
-    fn metadata(&mut self) -> ImageResult<jpeg_decoder::ImageInfo> {
-        match self.metadata {
-            Some(metadata) => Ok(metadata),
+    fn info(&mut self) -> ImageResult<jpeg_decoder::ImageInfo> {
+        match self.info {
+            Some(info) => Ok(info),
             None => {
-                try!(self.decoder.read_info());
-                let mut metadata = self.decoder.info().unwrap();
+                let decoder = self.decoder()?;
+                decoder.read_info()?;
+                let mut info = decoder.info().unwrap();
 
                 // We convert CMYK data to RGB before returning it to the user.
-                if metadata.pixel_format == jpeg_decoder::PixelFormat::CMYK32 {
-                    metadata.pixel_format = jpeg_decoder::PixelFormat::RGB24;
+                if info.pixel_format == jpeg_decoder::PixelFormat::CMYK32 {
+                    info.pixel_format = jpeg_decoder::PixelFormat::RGB24;
                 }
 
-                self.metadata = Some(metadata);
-                Ok(metadata)
+                self.info = Some(info);
+                Ok(info)
             },
         }
     }
 }
 
+// Appends up to ```n``` bytes of ```r``` to ```buffer```, returns false at the end of the stream
+fn read_bytes<R: Read>(r: &mut R, n: usize, buffer: &mut Vec<u8>) -> io::Result<bool> {
+    let start = buffer.len();
+    r.by_ref().take(n as u64).read_to_end(buffer)?;
+    Ok(buffer.len() - start == n)
+}
+
+// The APPn and COM segments, and the marker and frame header if one was found
+type Segments = (Vec<(u8, Vec<u8>)>, Option<(u8, Vec<u8>)>);
+
+// Reads the segments up to and including the first frame header, returns the APPn
+// and COM segments and the frame header. Malformed data ends the search, the decoder
+// reports those errors.
+fn read_segments<R: Read>(r: &mut R, buffer: &mut Vec<u8>) -> io::Result<Segments> {
+    let mut segments = Vec::new();
+
+    // SOI
+    if !read_bytes(r, 2, buffer)? || buffer[..] != [0xFF, 0xD8] {
+        return Ok((segments, None))
+    }
+
+    loop {
+        if !read_bytes(r, 1, buffer)? || buffer[buffer.len() - 1] != 0xFF {
+            return Ok((segments, None))
+        }
+        // Markers may be preceded by any number of fill bytes
+        let mut marker = 0xFF;
+        while marker == 0xFF {
+            if !read_bytes(r, 1, buffer)? {
+                return Ok((segments, None))
+            }
+            marker = buffer[buffer.len() - 1];
+        }
+
+        match marker {
+            // TEM and RSTn have no length
+            0x01 | 0xD0 ..= 0xD7 => continue,
+            // SOS and EOI
+            0xDA | 0xD9 => return Ok((segments, None)),
+            _ => (),
+        }
+
+        if !read_bytes(r, 2, buffer)? {
+            return Ok((segments, None))
+        }
+        let length = BigEndian::read_u16(&buffer[buffer.len() - 2..]) as usize;
+        let start = buffer.len();
+        if length < 2 || !read_bytes(r, length - 2, buffer)? {
+            return Ok((segments, None))
+        }
+
+        match marker {
+            // APPn and COM
+            0xE0 ..= 0xEF | 0xFE => segments.push((marker, buffer[start..].to_vec())),
+            // SOFn
+            0xC0 ..= 0xC3 | 0xC5 ..= 0xC7 | 0xC9 ..= 0xCB | 0xCD ..= 0xCF => {
+                return Ok((segments, Some((marker, buffer[start..].to_vec()))))
+            }
+            _ => (),
+        }
+    }
+}
+
+// Returns the number of blocks inside of the components of a frame, or None if the
+// frame header is invalid
+fn frame_blocks(frame: &[u8]) -> Option<usize> {
+    if frame.len() < 6 || frame.len() < 6 + 3 * frame[5] as usize {
+        return None
+    }
+    let height = BigEndian::read_u16(&frame[1..]) as usize;
+    let width = BigEndian::read_u16(&frame[3..]) as usize;
+    let sampling: Vec<(usize, usize)> = frame[6..6 + 3 * frame[5] as usize].chunks(3)
+        .map(|c| ((c[1] >> 4) as usize, (c[1] & 0x0F) as usize))
+        .collect();
+    let hmax = sampling.iter().map(|s| s.0).max()?;
+    let vmax = sampling.iter().map(|s| s.1).max()?;
+    if sampling.iter().any(|s| s.0 == 0 || s.1 == 0) {
+        return None
+    }
+    Some(sampling.iter().map(|&(h, v)| {
+        ceil_div(ceil_div(width * h, hmax), 8) * ceil_div(ceil_div(height * v, vmax), 8)
+    }).sum())
+}
+
+#[cfg(feature = "jpeg_rayon")]
+impl<R: Read + Send> JPEGDecoder<R> {
+    /// Decodes the image with a dedicated pool of `threads` threads.
+    ///
+    /// Upsampling and color conversion are split into rows which are processed in
+    /// parallel. By default the global rayon thread pool is used. The inverse DCT
+    /// always runs on one thread per color component.
+    pub fn set_threads(&mut self, threads: usize) -> ImageResult<()> {
+        fn decode<R: Read + Send>(pool: &ThreadPool, decoder: &mut jpeg_decoder::Decoder<JpegStream<R>>)
+            -> Result<Vec<u8>, jpeg_decoder::Error> {
+            pool.install(|| decoder.decode())
+        }
+
+        let pool = ThreadPoolBuilder::new().num_threads(threads).build()
+            .map_err(|err| ImageError::IoError(io::Error::new(io::ErrorKind::Other, err)))?;
+        self.pool = Some((pool, decode::<R>));
+        Ok(())
+    }
+}
+
 impl<R: Read> ImageDecoder for JPEGDecoder<R> {
     fn dimensions(&mut self) -> ImageResult<(u32, u32)> {
-        let metadata = try!(self.metadata());
-        Ok((metadata.width as u32, metadata.height as u32))
+        let info = self.info()?;
+        Ok((info.width as u32, info.height as u32))
     }
 
     fn colortype(&mut self) -> ImageResult<ColorType> {
-        let metadata = try!(self.metadata());
-        Ok(metadata.pixel_format.into())
+        let info = self.info()?;
+        Ok(info.pixel_format.into())
     }
 
     fn row_len(&mut self) -> ImageResult<usize> {
-        let metadata = try!(self.metadata());
-        Ok(metadata.width as usize * color::num_components(metadata.pixel_format.into()))
+        let info = self.info()?;
+        Ok(info.width as usize * color::num_components(info.pixel_format.into()))
     }
 
     fn read_scanline(&mut self, _buf: &mut [u8]) -> ImageResult<u32> {
@@ -60,39 +277,66 @@ impl<R: Read> ImageDecoder for JPEGDecoder<R> {
     }
 
     fn read_image(&mut self) -> ImageResult<DecodingResult> {
-        let mut data = try!(self.decoder.decode());
-        data = match self.decoder.info().unwrap().pixel_format {
+        let mut data = self.decode()?;
+        data = match self.decoder()?.info().unwrap().pixel_format {
             jpeg_decoder::PixelFormat::CMYK32 => cmyk_to_rgb(&data),
             _ => data,
         };
 
         Ok(DecodingResult::U8(data))
     }
-}
 
-fn cmyk_to_rgb(input: &[u8]) -> Vec<u8> {
-    let size = input.len() - input.len() / 4;
-    let mut output = Vec::with_capacity(size);
+    fn set_target_dimensions(&mut self, width: u32, height: u32) -> ImageResult<(u32, u32)> {
+        let clamp = |v: u32| cmp::min(v, u32::from(u16::MAX)) as u16;
+        let (width, height) = self.scale(clamp(width), clamp(height))?;
+        Ok((u32::from(width), u32::from(height)))
+    }
 
-    for pixel in input.chunks(4) {
-        let c = pixel[0] as f32 / 255.0;
-        let m = pixel[1] as f32 / 255.0;
-        let y = pixel[2] as f32 / 255.0;
-        let k = pixel[3] as f32 / 255.0;
+    fn metadata(&mut self) -> ImageResult<Metadata> {
+        self.decoder()?;
+        let mut metadata = match self.segment(0xE1, EXIF_HEADER) {
+            // The EXIF data is a TIFF structure, its errors are reported as errors of the JPEG image
+            Some(exif) => Metadata::from_exif(exif).map_err(|err| match err {
+                ImageError::Decoding(err) => DecodingError::malformed(ImageFormat::JPEG, "invalid EXIF data")
+                    .with_source(err)
+                    .into(),
+                err => err,
+            })?,
+            None => Metadata::default(),
+        };
+        metadata.xmp = self.segment(0xE1, XMP_HEADER).map(|data| data[XMP_HEADER.len()..].to_vec());
+        metadata.icc_profile = self.icc_profile()?;
 
-        // CMYK -> CMY
-        let c = c * (1.0 - k) + k;
-        let m = m * (1.0 - k) + k;
-        let y = y * (1.0 - k) + k;
+        // The density of the JFIF header takes precedence over the EXIF data,
+        // unless it is the default square pixel aspect ratio
+        if let Some(data) = self.segment(0xE0, b"JFIF\0").filter(|data| data.len() >= 12) {
+            let x = f64::from(BigEndian::read_u16(&data[8..]));
+            let y = f64::from(BigEndian::read_u16(&data[10..]));
+            let unit = match data[7] {
+                1 => Some(PixelDensityUnit::Inch),
+                2 => Some(PixelDensityUnit::Centimeter),
+                _ if x == y || metadata.density.is_some() => None,
+                _ => Some(PixelDensityUnit::None),
+            };
+            if let Some(unit) = unit {
+                metadata.density = Some(PixelDensity { x, y, unit });
+            }
+        }
 
-        // CMY -> RGB
-        let r = (1.0 - c) * 255.0;
-        let g = (1.0 - m) * 255.0;
-        let b = (1.0 - y) * 255.0;
+        Ok(metadata)
+    }
+}
 
-        output.push(r as u8);
-        output.push(g as u8);
-        output.push(b as u8);
+fn cmyk_to_rgb(input: &[u8]) -> Vec<u8> {
+    let mut output = vec![0; input.len() / 4 * 3];
+
+    // CMYK -> CMY -> RGB simplifies to (1 - c) * (1 - k) for each channel,
+    // computed in fixed point so the loop can be vectorized
+    for (cmyk, rgb) in input.chunks_exact(4).zip(output.chunks_exact_mut(3)) {
+        let k = 255 - u32::from(cmyk[3]);
+        rgb[0] = ((255 - u32::from(cmyk[0])) * k / 255) as u8;
+        rgb[1] = ((255 - u32::from(cmyk[1])) * k / 255) as u8;
+        rgb[2] = ((255 - u32::from(cmyk[2])) * k / 255) as u8;
     }
 
     output
@@ -113,10 +357,161 @@ impl From<jpeg_decoder::Error> for ImageError {
     fn from(err: jpeg_decoder::Error) -> ImageError {
         use self::jpeg_decoder::Error::*;
         match err {
-            Format(desc)      => ImageError::FormatError(desc),
+            Format(desc)      => ImageError::malformed(ImageFormat::JPEG, desc),
             Unsupported(desc) => ImageError::UnsupportedError(format!("{:?}", desc)),
             Io(err)           => ImageError::IoError(err),
-            Internal(err)     => ImageError::FormatError(err.description().to_owned()),
+            Internal(err)     => {
+                DecodingError::new(ImageFormat::JPEG, DecodingErrorKind::Malformed(err.description().to_owned()))
+                    .with_source(err)
+                    .into()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "jpeg_rayon")]
+    #[test]
+    fn test_threaded_decode() {
+        use std::fs::File;
+        use std::io::BufReader;
+        use image::{DecodingResult, ImageDecoder};
+        use super::JPEGDecoder;
+
+        let open = || BufReader::new(File::open("tests/images/jpg/progressive/cat.jpg").unwrap());
+        let mut decoder = JPEGDecoder::new(open());
+        decoder.set_threads(3).unwrap();
+        let threaded = decoder.read_image().unwrap();
+        let expected = JPEGDecoder::new(open()).read_image().unwrap();
+        match (threaded, expected) {
+            (DecodingResult::U8(threaded), DecodingResult::U8(expected)) => assert!(threaded == expected),
+            _ => panic!("unexpected decoding result"),
+        }
+    }
+
+    #[test]
+    fn test_scaled_decode() {
+        use std::fs::File;
+        use std::io::BufReader;
+        use image::{DecodingResult, ImageDecoder};
+        use super::JPEGDecoder;
+
+        let open = || BufReader::new(File::open("tests/images/jpg/progressive/cat.jpg").unwrap());
+        let (width, height) = JPEGDecoder::new(open()).dimensions().unwrap();
+
+        let mut decoder = JPEGDecoder::new(open());
+        assert_eq!(decoder.dimensions().unwrap(), (width, height));
+        let (scaled_width, scaled_height) = decoder.scale(width as u16 / 8, height as u16 / 8).unwrap();
+        assert_eq!((u32::from(scaled_width), u32::from(scaled_height)), ((width + 7) / 8, (height + 7) / 8));
+        assert_eq!(decoder.dimensions().unwrap(), (u32::from(scaled_width), u32::from(scaled_height)));
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data.len(), decoder.row_len().unwrap() * scaled_height as usize),
+            _ => panic!("unexpected decoding result"),
+        }
+
+        let mut decoder = JPEGDecoder::new(open());
+        assert_eq!(decoder.scale(width as u16, height as u16).unwrap(), (width as u16, height as u16));
+
+        let mut decoder = JPEGDecoder::new(open());
+        assert_eq!(decoder.set_target_dimensions(width / 3, height / 3).unwrap(), ((width + 1) / 2, (height + 1) / 2));
+        let mut decoder = JPEGDecoder::new(open());
+        assert_eq!(decoder.set_target_dimensions(100_000, 100_000).unwrap(), (width, height));
+    }
+
+    #[test]
+    fn test_exif_metadata() {
+        use std::io::Cursor;
+        use color::ColorType;
+        use image::{DecodingResult, ImageDecoder, ImageError, ImageFormat};
+        use jpeg::JPEGEncoder;
+        use metadata::{Metadata, Orientation};
+        use super::JPEGDecoder;
+
+        let pixels = vec![127; 16 * 8];
+        let mut jpeg = Vec::new();
+        JPEGEncoder::new(&mut jpeg).encode(&pixels, 16, 8, ColorType::Gray(8)).unwrap();
+
+        let mut decoder = JPEGDecoder::new(Cursor::new(&jpeg));
+        assert_eq!(decoder.metadata().unwrap(), Metadata::default());
+
+        // Orientation 8 in a little endian IFD0 with a single entry
+        let exif = b"Exif\0\0II*\0\x08\0\0\0\x01\0\x12\x01\x03\0\x01\0\0\0\x08\0\0\0\0\0\0\0";
+        let mut with_exif = jpeg[..2].to_vec();
+        with_exif.extend_from_slice(&[0xFF, 0xE1, 0, exif.len() as u8 + 2]);
+        with_exif.extend_from_slice(exif);
+        with_exif.extend_from_slice(&jpeg[2..]);
+
+        let mut decoder = JPEGDecoder::new(Cursor::new(&with_exif));
+        let metadata = decoder.metadata().unwrap();
+        assert_eq!(metadata.orientation, Some(Orientation::Rotate270));
+        assert_eq!(metadata.exif.as_ref().map(|exif| exif.len()), Some(exif.len() - 6));
+        assert_eq!(decoder.dimensions().unwrap(), (16, 8));
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data.len(), pixels.len()),
+            _ => panic!("unexpected decoding result"),
+        }
+
+        // An invalid byte order mark
+        with_exif[12] = b'X';
+        match JPEGDecoder::new(Cursor::new(&with_exif)).metadata() {
+            Err(ImageError::Decoding(ref err)) if err.format() == ImageFormat::JPEG => (),
+            other => panic!("expected a JPEG decoding error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_frame_larger_than_data() {
+        use color::ColorType;
+        use image::{ImageDecoder, ImageError};
+        use jpeg::JPEGEncoder;
+        use super::JPEGDecoder;
+
+        let mut jpeg = Vec::new();
+        JPEGEncoder::new(&mut jpeg).encode(&[127; 16 * 8], 16, 8, ColorType::Gray(8)).unwrap();
+        // Claim a size of 65535x65535 in the frame header
+        let sof = jpeg.windows(2).position(|w| w == [0xFF, 0xC0]).unwrap();
+        for byte in &mut jpeg[sof + 5..sof + 9] {
+            *byte = 0xFF;
+        }
+        // Only the coefficients of progressive frames are allocated up front
+        assert_eq!(JPEGDecoder::new(&jpeg[..]).dimensions().unwrap(), (65535, 65535));
+        jpeg[sof + 1] = 0xC2;
+        match JPEGDecoder::new(&jpeg[..]).dimensions() {
+            Err(ImageError::Decoding(_)) => (),
+            other => panic!("expected a decoding error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_restart_markers() {
+        use std::fs::File;
+        use std::io::Read;
+        use image::{DecodingResult, ImageDecoder};
+        use super::JPEGDecoder;
+
+        // Written by Photoshop with a restart interval of 21 MCUs, the pixels
+        // below are the output of libjpeg
+        let mut jpeg = Vec::new();
+        File::open("tests/images/jpg/restart/flower-of-life.jpg").unwrap().read_to_end(&mut jpeg).unwrap();
+        let dri = jpeg.windows(2).position(|w| w == [0xFF, 0xDD]).unwrap();
+        assert_eq!(&jpeg[dri + 2..dri + 6], &[0, 4, 0, 21]);
+        assert!(jpeg.windows(2).any(|w| w == [0xFF, 0xD7]));
+
+        let mut decoder = JPEGDecoder::new(&jpeg[..]);
+        assert_eq!(decoder.dimensions().unwrap(), (161, 161));
+        let data = match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => data,
+            _ => panic!("unexpected decoding result"),
+        };
+        assert_eq!(data.len(), 161 * 161 * 3);
+        let expected = [((0, 0), 255), ((87, 4), 0), ((65, 33), 2), ((100, 43), 231),
+                        ((48, 95), 6), ((92, 108), 55), ((37, 120), 241), ((160, 160), 255)];
+        for &((x, y), value) in &expected {
+            let offset = (y * 161 + x) * 3;
+            for &channel in &data[offset..offset + 3] {
+                assert!((i32::from(channel) - value).abs() <= 1, "pixel ({}, {}) is {}", x, y, channel);
+            }
         }
     }
 }
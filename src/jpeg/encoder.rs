@@ -4,25 +4,40 @@ use num_iter::range_step;
 use math::utils::clamp;
 
 use color;
+use metadata::{exif_with_orientation, set_exif_orientation, Orientation, PixelDensity, PixelDensityUnit, EXIF_HEADER,
+               ICC_HEADER, XMP_HEADER};
 
 use super::transform;
 use super::entropy::build_huff_lut;
 
 // Markers
 // Baseline DCT
-static SOF0: u8 = 0xC0;
+pub static SOF0: u8 = 0xC0;
 // Huffman Tables
-static DHT: u8 = 0xC4;
+pub static DHT: u8 = 0xC4;
 // Start of Image (standalone)
-static SOI: u8 = 0xD8;
+pub static SOI: u8 = 0xD8;
 // End of image (standalone)
-static EOI: u8 = 0xD9;
+pub static EOI: u8 = 0xD9;
 // Start of Scan
-static SOS: u8 = 0xDA;
+pub static SOS: u8 = 0xDA;
 // Quantization Tables
-static DQT: u8 = 0xDB;
+pub static DQT: u8 = 0xDB;
+// Define Restart Interval
+pub static DRI: u8 = 0xDD;
+// The first of the eight restart markers RST0 to RST7 (standalone)
+static RST0: u8 = 0xD0;
 // Application segments start and end
 static APP0: u8 = 0xE0;
+// EXIF data
+static APP1: u8 = 0xE1;
+// ICC profiles
+static APP2: u8 = 0xE2;
+// Comment
+static COM: u8 = 0xFE;
+
+// The largest payload of a segment, its length field includes itself
+const MAX_SEGMENT_SIZE: usize = 65533;
 
 // section K.1
 // table K.1
@@ -51,34 +66,34 @@ static STD_CHROMA_QTABLE: [u8; 64] = [
 
 // section K.3
 // Code lengths and values for table K.3
-static STD_LUMA_DC_CODE_LENGTHS: [u8; 16] = [
+pub static STD_LUMA_DC_CODE_LENGTHS: [u8; 16] = [
     0x00, 0x01, 0x05, 0x01, 0x01, 0x01, 0x01, 0x01,
     0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00
 ];
 
-static STD_LUMA_DC_VALUES: [u8; 12] = [
+pub static STD_LUMA_DC_VALUES: [u8; 12] = [
     0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07,
     0x08, 0x09, 0x0A, 0x0B
 ];
 
 // Code lengths and values for table K.4
-static STD_CHROMA_DC_CODE_LENGTHS: [u8; 16] = [
+pub static STD_CHROMA_DC_CODE_LENGTHS: [u8; 16] = [
     0x00, 0x03, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
     0x01, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00
 ];
 
-static STD_CHROMA_DC_VALUES: [u8; 12] = [
+pub static STD_CHROMA_DC_VALUES: [u8; 12] = [
     0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07,
     0x08, 0x09, 0x0A, 0x0B
 ];
 
 // Code lengths and values for table k.5
-static STD_LUMA_AC_CODE_LENGTHS: [u8; 16] = [
+pub static STD_LUMA_AC_CODE_LENGTHS: [u8; 16] = [
     0x00, 0x02, 0x01, 0x03, 0x03, 0x02, 0x04, 0x03,
     0x05, 0x05, 0x04, 0x04, 0x00, 0x00, 0x01, 0x7D
 ];
 
-static STD_LUMA_AC_VALUES: [u8; 162] = [
+pub static STD_LUMA_AC_VALUES: [u8; 162] = [
     0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07,
     0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xA1, 0x08, 0x23, 0x42, 0xB1, 0xC1, 0x15, 0x52, 0xD1, 0xF0,
     0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0A, 0x16, 0x17, 0x18, 0x19, 0x1A, 0x25, 0x26, 0x27, 0x28,
@@ -93,11 +108,11 @@ static STD_LUMA_AC_VALUES: [u8; 162] = [
 ];
 
 // Code lengths and values for table k.6
-static STD_CHROMA_AC_CODE_LENGTHS: [u8; 16] = [
+pub static STD_CHROMA_AC_CODE_LENGTHS: [u8; 16] = [
     0x00, 0x02, 0x01, 0x02, 0x04, 0x04, 0x03, 0x04,
     0x07, 0x05, 0x04, 0x04, 0x00, 0x01, 0x02, 0x77,
 ];
-static STD_CHROMA_AC_VALUES: [u8; 162] = [
+pub static STD_CHROMA_AC_VALUES: [u8; 162] = [
     0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71,
     0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xA1, 0xB1, 0xC1, 0x09, 0x23, 0x33, 0x52, 0xF0,
     0x15, 0x62, 0x72, 0xD1, 0x0A, 0x16, 0x24, 0x34, 0xE1, 0x25, 0xF1, 0x17, 0x18, 0x19, 0x1A, 0x26,
@@ -111,18 +126,18 @@ static STD_CHROMA_AC_VALUES: [u8; 162] = [
     0xF9, 0xFA,
 ];
 
-static DCCLASS: u8 = 0;
-static ACCLASS: u8 = 1;
+pub static DCCLASS: u8 = 0;
+pub static ACCLASS: u8 = 1;
 
-static LUMADESTINATION: u8 = 0;
-static CHROMADESTINATION: u8 = 1;
+pub static LUMADESTINATION: u8 = 0;
+pub static CHROMADESTINATION: u8 = 1;
 
 static LUMAID: u8 = 1;
 static CHROMABLUEID: u8 = 2;
 static CHROMAREDID: u8 = 3;
 
 /// The permutation of dct coefficients.
-static UNZIGZAG: [u8; 64] = [
+pub static UNZIGZAG: [u8; 64] = [
     0,  1,  8, 16,  9,  2,  3, 10,
     17, 24, 32, 25, 18, 11,  4,  5,
     12, 19, 26, 33, 40, 48, 41, 34,
@@ -165,7 +180,7 @@ pub struct BitWriter<'a, W: 'a> {
 }
 
 impl<'a, W: Write + 'a> BitWriter<'a, W> {
-    fn new(w: &'a mut W) -> Self {
+    pub fn new(w: &'a mut W) -> Self {
         BitWriter {
             w: w,
             accumulator: 0,
@@ -196,10 +211,26 @@ impl<'a, W: Write + 'a> BitWriter<'a, W> {
         Ok(())
     }
 
-    fn pad_byte(&mut self) -> io::Result<()> {
+    pub fn pad_byte(&mut self) -> io::Result<()> {
         self.write_bits(0x7F, 7)
     }
 
+    /// Writes a restart marker in front of the MCU with the index ```mcu``` if a
+    /// restart interval of ```interval``` MCUs ends there. Returns true if a marker
+    /// was written, the DC predictions have to be reset then.
+    pub fn write_restart_marker(&mut self, mcu: usize, interval: u16) -> io::Result<bool> {
+        let interval = interval as usize;
+        if interval > 0 && mcu > 0 && mcu % interval == 0 {
+            // The entropy coded data of each interval ends at a byte boundary
+            try!(self.pad_byte());
+            self.accumulator = 0;
+            self.nbits = 0;
+            try!(self.w.write_all(&[0xFF, RST0 + ((mcu / interval - 1) % 8) as u8]));
+            return Ok(true)
+        }
+        Ok(false)
+    }
+
     fn huffman_encode(&mut self, val: u8, table: &[(u8, u16)]) -> io::Result<()> {
         let (size, code) = table[val as usize];
 
@@ -210,7 +241,7 @@ impl<'a, W: Write + 'a> BitWriter<'a, W> {
         self.write_bits(code, size)
     }
 
-    fn write_block(
+    pub fn write_block(
         &mut self,
         block: &[i32],
         prevdc: i32,
@@ -262,7 +293,7 @@ impl<'a, W: Write + 'a> BitWriter<'a, W> {
         Ok(dcval)
     }
 
-    fn write_segment(&mut self, marker: u8, data: Option<&[u8]>) -> io::Result<()> {
+    pub fn write_segment(&mut self, marker: u8, data: Option<&[u8]>) -> io::Result<()> {
         try!(self.w.write_all(&[0xFF]));
         try!(self.w.write_all(&[marker]));
 
@@ -285,6 +316,14 @@ pub struct JPEGEncoder<'a, W: 'a> {
     luma_actable: Vec<(u8, u16)>,
     chroma_dctable: Vec<(u8, u16)>,
     chroma_actable: Vec<(u8, u16)>,
+
+    exif: Option<Vec<u8>>,
+    orientation: Option<Orientation>,
+    comment: Option<String>,
+    density: Option<PixelDensity>,
+    xmp: Option<Vec<u8>>,
+    icc_profile: Option<Vec<u8>>,
+    restart_interval: u16,
 }
 
 impl<'a, W: Write> JPEGEncoder<'a, W> {
@@ -338,9 +377,67 @@ impl<'a, W: Write> JPEGEncoder<'a, W> {
             luma_actable: la,
             chroma_dctable: cd,
             chroma_actable: ca,
+
+            exif: None,
+            orientation: None,
+            comment: None,
+            density: None,
+            xmp: None,
+            icc_profile: None,
+            restart_interval: 0,
         }
     }
 
+    /// Embeds EXIF data in the output, e.g. the ```exif``` field of the
+    /// ```Metadata``` of a decoded image. The data is a TIFF structure without
+    /// the ```Exif\0\0``` header and must not be larger than 65527 bytes.
+    pub fn set_exif(&mut self, exif: Vec<u8>) {
+        self.exif = Some(exif);
+    }
+
+    /// Sets the EXIF orientation of the output.
+    ///
+    /// It replaces the orientation tag of the data given to ```set_exif```,
+    /// which must have one. Without other EXIF data a minimal EXIF segment
+    /// with the orientation is written.
+    pub fn set_orientation(&mut self, orientation: Orientation) {
+        self.orientation = Some(orientation);
+    }
+
+    /// Sets the physical resolution which is stored in the JFIF header.
+    ///
+    /// The density is rounded to whole pixels per inch or centimeter,
+    /// densities per meter are converted to centimeters.
+    pub fn set_pixel_density(&mut self, density: PixelDensity) {
+        self.density = Some(density);
+    }
+
+    /// Embeds an XMP packet in the output, e.g. the ```xmp``` field of the
+    /// ```Metadata``` of a decoded image. Packets larger than 65504 bytes
+    /// would have to be split into extended XMP, which is not supported.
+    pub fn set_xmp(&mut self, xmp: Vec<u8>) {
+        self.xmp = Some(xmp);
+    }
+
+    /// Embeds an ICC profile in the output, which describes the color space of
+    /// the pixels. Large profiles are split into several segments.
+    pub fn set_icc_profile(&mut self, profile: Vec<u8>) {
+        self.icc_profile = Some(profile);
+    }
+
+    /// Embeds a comment in the output, long comments are split into several segments
+    pub fn set_comment(&mut self, comment: String) {
+        self.comment = Some(comment);
+    }
+
+    /// Inserts a restart marker after every ```interval``` MCUs, i.e. blocks of 8x8
+    /// pixels. Decoders can resynchronize at the markers after corrupted data or decode
+    /// the intervals in parallel, at the cost of a slightly larger file. An interval of
+    /// 0, the default, writes no restart markers.
+    pub fn set_restart_interval(&mut self, interval: u16) {
+        self.restart_interval = interval;
+    }
+
     /// Encodes the image ```image```
     /// that has dimensions ```width``` and ```height```
     /// and ```ColorType``` ```c```
@@ -356,13 +453,20 @@ impl<'a, W: Write> JPEGEncoder<'a, W> {
         let num_components = if n == 1 || n == 2 {1}
                              else {3};
 
+        // The metadata is checked before anything is written
+        let metadata = self.build_metadata_segments()?;
+
         try!(self.writer.write_segment(SOI, None));
 
         let mut buf = Vec::new();
 
-        build_jfif_header(&mut buf);
+        build_jfif_header(&mut buf, self.density);
         try!(self.writer.write_segment(APP0, Some(&buf)));
 
+        for &(marker, ref data) in &metadata {
+            self.writer.write_segment(marker, Some(data))?;
+        }
+
         build_frame_header(&mut buf, 8, width as u16, height as u16, &self.components[..num_components]);
         try!(self.writer.write_segment(SOF0, Some(&buf)));
 
@@ -393,6 +497,11 @@ impl<'a, W: Write> JPEGEncoder<'a, W> {
             try!(self.writer.write_segment(DHT, Some(&buf)));
         }
 
+        if self.restart_interval > 0 {
+            let interval = [(self.restart_interval >> 8) as u8, self.restart_interval as u8];
+            try!(self.writer.write_segment(DRI, Some(&interval)));
+        }
+
         build_scan_header(&mut buf, &self.components[..num_components]);
         try!(self.writer.write_segment(SOS, Some(&buf)));
 
@@ -412,13 +521,82 @@ impl<'a, W: Write> JPEGEncoder<'a, W> {
         Ok(())
     }
 
+    // Builds the EXIF, ICC profile, XMP and comment segments as (marker, data)
+    fn build_metadata_segments(&self) -> io::Result<Vec<(u8, Vec<u8>)>> {
+        let mut segments = Vec::new();
+
+        if let Some(exif) = self.build_exif_segment()? {
+            segments.push((APP1, exif));
+        }
+
+        if let Some(ref profile) = self.icc_profile {
+            // Each segment stores the header, its sequence number and the number of segments
+            let parts = profile.chunks(MAX_SEGMENT_SIZE - ICC_HEADER.len() - 2);
+            let count = parts.len();
+            if count > 255 {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "The ICC profile is too large"))
+            }
+            for (i, part) in parts.enumerate() {
+                let mut buf = ICC_HEADER.to_vec();
+                buf.extend_from_slice(&[i as u8 + 1, count as u8]);
+                buf.extend_from_slice(part);
+                segments.push((APP2, buf));
+            }
+        }
+
+        if let Some(ref xmp) = self.xmp {
+            if XMP_HEADER.len() + xmp.len() > MAX_SEGMENT_SIZE {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "The XMP packet is too large"))
+            }
+            let mut buf = XMP_HEADER.to_vec();
+            buf.extend_from_slice(xmp);
+            segments.push((APP1, buf));
+        }
+
+        if let Some(ref comment) = self.comment {
+            for chunk in comment.as_bytes().chunks(MAX_SEGMENT_SIZE) {
+                segments.push((COM, chunk.to_vec()));
+            }
+        }
+        Ok(segments)
+    }
+
+    // Builds the APP1 segment, returns None if there is no EXIF data to write
+    fn build_exif_segment(&self) -> io::Result<Option<Vec<u8>>> {
+        let mut exif = match (self.exif.clone(), self.orientation) {
+            (Some(exif), _) => exif,
+            (None, Some(orientation)) => exif_with_orientation(orientation),
+            (None, None) => return Ok(None),
+        };
+        if let (Some(orientation), Some(_)) = (self.orientation, self.exif.as_ref()) {
+            let replaced = set_exif_orientation(&mut exif, orientation)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
+            if !replaced {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "The EXIF data has no orientation tag"))
+            }
+        }
+
+        if EXIF_HEADER.len() + exif.len() > MAX_SEGMENT_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "The EXIF data is too large"))
+        }
+        let mut m = EXIF_HEADER.to_vec();
+        m.extend_from_slice(&exif);
+        Ok(Some(m))
+    }
+
     fn encode_gray(&mut self, image: &[u8], width: usize, height: usize, bpp: usize) -> io::Result<()> {
         let mut yblock     = [0u8; 64];
         let mut y_dcprev   = 0;
         let mut dct_yblock = [0i32; 64];
+        let mut mcu = 0;
 
         for y in range_step(0, height, 8) {
             for x in range_step(0, width, 8) {
+                if try!(self.writer.write_restart_marker(mcu, self.restart_interval)) {
+                    y_dcprev = 0;
+                }
+                mcu += 1;
+
                 // RGB -> YCbCr
                 copy_blocks_gray(image, x, y, width, bpp, &mut yblock);
 
@@ -453,9 +631,17 @@ impl<'a, W: Write> JPEGEncoder<'a, W> {
         let mut yblock   = [0u8; 64];
         let mut cb_block = [0u8; 64];
         let mut cr_block = [0u8; 64];
+        let mut mcu = 0;
 
         for y in range_step(0, height, 8) {
             for x in range_step(0, width, 8) {
+                if try!(self.writer.write_restart_marker(mcu, self.restart_interval)) {
+                    y_dcprev = 0;
+                    cb_dcprev = 0;
+                    cr_dcprev = 0;
+                }
+                mcu += 1;
+
                 // RGB -> YCbCr
                 copy_blocks_ycbcr(image, x, y, width, bpp, &mut yblock, &mut cb_block, &mut cr_block);
 
@@ -487,16 +673,28 @@ impl<'a, W: Write> JPEGEncoder<'a, W> {
     }
 }
 
-fn build_jfif_header(m: &mut Vec<u8>) {
+fn build_jfif_header(m: &mut Vec<u8>, density: Option<PixelDensity>) {
     m.clear();
 
+    // Square pixels without a unit by default
+    let (units, x, y) = match density {
+        None => (0, 1.0, 1.0),
+        Some(PixelDensity { x, y, unit }) => match unit {
+            PixelDensityUnit::None => (0, x, y),
+            PixelDensityUnit::Inch => (1, x, y),
+            PixelDensityUnit::Centimeter => (2, x, y),
+            PixelDensityUnit::Meter => (2, x / 100.0, y / 100.0),
+        },
+    };
+    let density = |d: f64| clamp(d.round(), 1.0, 65535.0) as u16;
+
     let _ = write!(m, "JFIF");
     let _ = m.write_all(&[0]);
     let _ = m.write_all(&[0x01]);
     let _ = m.write_all(&[0x02]);
-    let _ = m.write_all(&[0]);
-    let _ = m.write_u16::<BigEndian>(1);
-    let _ = m.write_u16::<BigEndian>(1);
+    let _ = m.write_all(&[units]);
+    let _ = m.write_u16::<BigEndian>(density(x));
+    let _ = m.write_u16::<BigEndian>(density(y));
     let _ = m.write_all(&[0]);
     let _ = m.write_all(&[0]);
 }
@@ -538,7 +736,7 @@ fn build_scan_header(m: &mut Vec<u8>, components: &[Component]) {
     let _ = m.write_all(&[0]);
 }
 
-fn build_huffman_segment(m: &mut Vec<u8>,
+pub fn build_huffman_segment(m: &mut Vec<u8>,
                          class: u8,
                          destination: u8,
                          numcodes: &[u8],
@@ -708,4 +906,123 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_write_metadata() {
+        use metadata::{exif_with_orientation, Orientation};
+
+        let img = [127u8; 64];
+        let encode = |encoder: &mut JPEGEncoder<Vec<u8>>| encoder.encode(&img, 8, 8, ColorType::Gray(8));
+
+        let mut encoded_img = Vec::new();
+        {
+            let mut encoder = JPEGEncoder::new(&mut encoded_img);
+            encoder.set_exif(exif_with_orientation(Orientation::Rotate90));
+            encoder.set_orientation(Orientation::FlipHorizontal);
+            encoder.set_comment("a comment".to_string());
+            encode(&mut encoder).unwrap();
+        }
+
+        let mut decoder = JPEGDecoder::new(Cursor::new(&encoded_img));
+        let metadata = decoder.metadata().unwrap();
+        assert_eq!(metadata.orientation, Some(Orientation::FlipHorizontal));
+        assert_eq!(decoder.dimensions().unwrap(), (8, 8));
+        assert!(encoded_img.windows(13).any(|w| w == b"\xFF\xFE\0\x0ba comment"));
+        assert_eq!(metadata.xmp, None);
+
+        // Only the orientation and an XMP packet
+        let xmp = b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"></x:xmpmeta>".to_vec();
+        let mut encoded_img = Vec::new();
+        {
+            let mut encoder = JPEGEncoder::new(&mut encoded_img);
+            encoder.set_orientation(Orientation::Rotate270);
+            encoder.set_xmp(xmp.clone());
+            encode(&mut encoder).unwrap();
+        }
+        let metadata = JPEGDecoder::new(Cursor::new(&encoded_img)).metadata().unwrap();
+        assert_eq!(metadata.orientation, Some(Orientation::Rotate270));
+        assert_eq!(metadata.xmp, Some(xmp));
+
+        // EXIF data without an orientation tag can not be changed, nothing is written then
+        let mut encoded_img = Vec::new();
+        {
+            let mut encoder = JPEGEncoder::new(&mut encoded_img);
+            encoder.set_exif(b"MM\0*\0\0\0\x08\0\0\0\0\0\0".to_vec());
+            encoder.set_orientation(Orientation::Rotate270);
+            assert!(encode(&mut encoder).is_err());
+        }
+        assert!(encoded_img.is_empty());
+        {
+            let mut encoder = JPEGEncoder::new(&mut encoded_img);
+            encoder.set_xmp(vec![0; 70_000]);
+            assert!(encode(&mut encoder).is_err());
+        }
+        assert!(encoded_img.is_empty());
+    }
+
+    #[test]
+    fn test_pixel_density() {
+        use metadata::{PixelDensity, PixelDensityUnit};
+
+        let encode = |density| {
+            let mut encoded_img = Vec::new();
+            {
+                let mut encoder = JPEGEncoder::new(&mut encoded_img);
+                encoder.set_pixel_density(density);
+                encoder.encode(&[127u8; 64], 8, 8, ColorType::Gray(8)).unwrap();
+            }
+            JPEGDecoder::new(Cursor::new(encoded_img)).metadata().unwrap().density
+        };
+
+        assert_eq!(encode(PixelDensity::dpi(300.0, 150.0)), Some(PixelDensity::dpi(300.0, 150.0)));
+        let density = PixelDensity { x: 11811.0, y: 11811.0, unit: PixelDensityUnit::Meter };
+        assert_eq!(encode(density), Some(PixelDensity { x: 118.0, y: 118.0, unit: PixelDensityUnit::Centimeter }));
+        let density = PixelDensity { x: 2.0, y: 1.0, unit: PixelDensityUnit::None };
+        assert_eq!(encode(density), Some(density));
+        // The default square aspect ratio is not reported
+        let density = PixelDensity { x: 1.0, y: 1.0, unit: PixelDensityUnit::None };
+        assert_eq!(encode(density), None);
+    }
+
+    #[test]
+    fn test_icc_profile() {
+        // Large enough to be split into three segments
+        let profile: Vec<u8> = (0..150_000).map(|i| (i % 251) as u8).collect();
+        let mut encoded_img = Vec::new();
+        {
+            let mut encoder = JPEGEncoder::new(&mut encoded_img);
+            encoder.set_icc_profile(profile.clone());
+            encoder.encode(&[127u8; 64], 8, 8, ColorType::Gray(8)).unwrap();
+        }
+        let metadata = JPEGDecoder::new(Cursor::new(&encoded_img)).metadata().unwrap();
+        assert_eq!(metadata.icc_profile, Some(profile));
+    }
+
+    #[test]
+    fn test_restart_interval() {
+        let img: Vec<u8> = (0..40 * 24 * 3).map(|i| (i * 7 % 256) as u8).collect();
+        let encode = |interval| {
+            let mut encoded_img = Vec::new();
+            {
+                let mut encoder = JPEGEncoder::new(&mut encoded_img);
+                encoder.set_restart_interval(interval);
+                encoder.encode(&img, 40, 24, ColorType::RGB(8)).unwrap();
+            }
+            encoded_img
+        };
+        let decode = |data: &[u8]| match JPEGDecoder::new(Cursor::new(data)).read_image().unwrap() {
+            DecodingResult::U8(decoded) => decoded,
+            _ => panic!("expected 8 bit samples"),
+        };
+
+        // 15 MCUs need 14 markers, which count from RST0 to RST7 twice
+        let with_markers = encode(1);
+        let markers: Vec<u8> = with_markers.windows(2)
+            .filter(|w| w[0] == 0xFF && w[1] >= 0xD0 && w[1] <= 0xD7)
+            .map(|w| w[1] - 0xD0)
+            .collect();
+        assert_eq!(markers, vec![0, 1, 2, 3, 4, 5, 6, 7, 0, 1, 2, 3, 4, 5]);
+        assert_eq!(decode(&with_markers), decode(&encode(0)));
+        assert_eq!(decode(&encode(4)), decode(&encode(0)));
+    }
 }
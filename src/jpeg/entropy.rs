@@ -59,3 +59,51 @@ pub fn build_huff_lut(bits: &[u8], huffval: &[u8]) -> Vec<(u8, u16)> {
 
     lut
 }
+
+/// A huffman table for decoding, see Annex F.2.2.3 of the JPEG spec
+pub struct HuffDecodeTable {
+    values: Vec<u8>,
+    /// The smallest code of each length
+    mincode: [i32; 16],
+    /// The largest code of each length, -1 if there are no codes of the length
+    maxcode: [i32; 16],
+    /// The index of the value of the smallest code of each length
+    valptr: [usize; 16],
+}
+
+impl HuffDecodeTable {
+    /// Returns the value of ```code``` if it is a complete code of ```length``` bits
+    pub fn lookup(&self, code: i32, length: usize) -> Option<u8> {
+        let l = length - 1;
+        if code <= self.maxcode[l] {
+            self.values.get(self.valptr[l] + (code - self.mincode[l]) as usize).cloned()
+        } else {
+            None
+        }
+    }
+}
+
+pub fn build_huff_decode_table(bits: &[u8], huffval: &[u8]) -> HuffDecodeTable {
+    let mut table = HuffDecodeTable {
+        values: huffval.to_vec(),
+        mincode: [0; 16],
+        maxcode: [-1; 16],
+        valptr: [0; 16],
+    };
+
+    // Figure F.15
+    let mut code = 0i32;
+    let mut k = 0usize;
+    for (l, &count) in bits.iter().enumerate().take(16) {
+        table.valptr[l] = k;
+        table.mincode[l] = code;
+        code += i32::from(count);
+        k += count as usize;
+        if count > 0 {
+            table.maxcode[l] = code - 1;
+        }
+        code <<= 1;
+    }
+
+    table
+}
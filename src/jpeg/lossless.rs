@@ -0,0 +1,851 @@
+//! Lossless transformations of JPEG images
+//!
+//! The quantized DCT coefficients of the image are rearranged instead of
+//! decoding and encoding the pixels again, so no quality is lost.
+
+use std::io::{self, Read, Write};
+use byteorder::{BigEndian, ByteOrder};
+
+use image::{ImageError, ImageFormat, ImageResult};
+use metadata::{set_exif_orientation, Metadata, Orientation, EXIF_HEADER};
+
+use super::encoder::{BitWriter, build_huffman_segment, SOI, EOI, SOF0, DHT, DQT, DRI, SOS,
+                     DCCLASS, ACCLASS, LUMADESTINATION, CHROMADESTINATION, UNZIGZAG,
+                     STD_LUMA_DC_CODE_LENGTHS, STD_LUMA_DC_VALUES, STD_LUMA_AC_CODE_LENGTHS,
+                     STD_LUMA_AC_VALUES, STD_CHROMA_DC_CODE_LENGTHS, STD_CHROMA_DC_VALUES,
+                     STD_CHROMA_AC_CODE_LENGTHS, STD_CHROMA_AC_VALUES};
+use super::entropy::{build_huff_decode_table, build_huff_lut, HuffDecodeTable};
+
+// Extended sequential DCT, needed for 16 bit quantization tables
+static SOF1: u8 = 0xC1;
+
+type Block = [i32; 64];
+
+/// A transformation which can be applied to a JPEG image without loss
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LosslessTransform {
+    /// Mirror the image horizontally
+    FlipHorizontal,
+    /// Mirror the image vertically
+    FlipVertical,
+    /// Rotate the image 90 degrees clockwise
+    Rotate90,
+    /// Rotate the image 180 degrees
+    Rotate180,
+    /// Rotate the image 270 degrees clockwise
+    Rotate270,
+}
+
+/// Applies ```transform``` to the JPEG image read from ```r``` and writes the
+/// result to ```w```.
+///
+/// Only sequential Huffman coded images are supported. The entropy coded data is
+/// written again with the standard Huffman tables, all application segments and
+/// comments are copied. The orientation tag of the EXIF metadata is changed so the
+/// transformed image is displayed like the original, e.g. it becomes ```Normal```
+/// when an image tagged ```Rotate90``` is rotated by 90 degrees.
+///
+/// Blocks can only be moved as a whole, so partial blocks at the right or bottom
+/// edge which would end up on the left or top edge are removed. E.g. rotating a
+/// 4:2:0 image of 100x100 pixels by 90 degrees results in a 96x100 image.
+pub fn transform_lossless<R: Read, W: Write>(mut r: R, w: &mut W, transform: LosslessTransform)
+    -> ImageResult<()> {
+
+    let mut data = Vec::new();
+    r.read_to_end(&mut data)?;
+    let image = Coefficients::read(&data)?;
+    image.transform(transform)?.write(w)?;
+    Ok(())
+}
+
+// Changes the orientation tag of EXIF data to the orientation of the transformed
+// image. Malformed EXIF data and data without the tag is left unchanged.
+fn update_orientation(exif: &mut [u8], transform: LosslessTransform) {
+    let orientation = match Metadata::from_exif(exif) {
+        Ok(Metadata { orientation: Some(orientation), .. }) => orientation,
+        _ => return,
+    };
+    // The stored pixels are transformed, so the transformation which displays them
+    // upright is the old one preceded by the inverse of ```transform```
+    let (a, b) = (orientation_matrix(orientation), transform_matrix(transform));
+    let inverse = [[b[0][0], b[1][0]], [b[0][1], b[1][1]]];
+    let mut product = [[0; 2]; 2];
+    for i in 0..2 {
+        for j in 0..2 {
+            product[i][j] = a[i][0] * inverse[0][j] + a[i][1] * inverse[1][j];
+        }
+    }
+    let composed = (1..9)
+        .filter_map(Orientation::from_exif)
+        .find(|&o| orientation_matrix(o) == product)
+        .unwrap();
+    let _ = set_exif_orientation(exif, composed);
+}
+
+// The matrix which maps pixel positions, with y pointing down, like ```orientation```
+fn orientation_matrix(orientation: Orientation) -> [[i8; 2]; 2] {
+    match orientation {
+        Orientation::Normal => [[1, 0], [0, 1]],
+        Orientation::FlipHorizontal => [[-1, 0], [0, 1]],
+        Orientation::Rotate180 => [[-1, 0], [0, -1]],
+        Orientation::FlipVertical => [[1, 0], [0, -1]],
+        Orientation::Transpose => [[0, 1], [1, 0]],
+        Orientation::Rotate90 => [[0, -1], [1, 0]],
+        Orientation::Transverse => [[0, -1], [-1, 0]],
+        Orientation::Rotate270 => [[0, 1], [-1, 0]],
+    }
+}
+
+fn transform_matrix(transform: LosslessTransform) -> [[i8; 2]; 2] {
+    orientation_matrix(match transform {
+        LosslessTransform::FlipHorizontal => Orientation::FlipHorizontal,
+        LosslessTransform::FlipVertical => Orientation::FlipVertical,
+        LosslessTransform::Rotate90 => Orientation::Rotate90,
+        LosslessTransform::Rotate180 => Orientation::Rotate180,
+        LosslessTransform::Rotate270 => Orientation::Rotate270,
+    })
+}
+
+/// A color component and its quantized coefficients in natural order
+struct Component {
+    id: u8,
+    h: u8,
+    v: u8,
+    tq: u8,
+    blocks_w: usize,
+    blocks_h: usize,
+    blocks: Vec<Block>,
+}
+
+/// The parts of a JPEG image which are kept by a lossless transform
+struct Coefficients {
+    width: u16,
+    height: u16,
+    components: Vec<Component>,
+    /// Quantization tables in natural order
+    qtables: [Option<[u16; 64]>; 4],
+    /// Application and comment segments
+    segments: Vec<(u8, Vec<u8>)>,
+    /// The number of MCUs between restart markers, 0 if there are none
+    restart_interval: u16,
+}
+
+impl Coefficients {
+    fn read(data: &[u8]) -> ImageResult<Coefficients> {
+        if !data.starts_with(&[0xFF, SOI]) {
+            return Err(ImageError::malformed(ImageFormat::JPEG, "Not a JPEG image"))
+        }
+
+        let mut image = Coefficients {
+            width: 0,
+            height: 0,
+            components: Vec::new(),
+            qtables: [None; 4],
+            segments: Vec::new(),
+            restart_interval: 0,
+        };
+        let mut huffman_tables: Vec<Option<HuffDecodeTable>> = (0..8).map(|_| None).collect();
+
+        let mut pos = 2;
+        loop {
+            if data.get(pos) != Some(&0xFF) {
+                return Err(ImageError::malformed(ImageFormat::JPEG, "Expected a JPEG marker"))
+            }
+            // Markers may be preceded by any number of fill bytes
+            while data.get(pos) == Some(&0xFF) {
+                pos += 1;
+            }
+            let marker = *data.get(pos).ok_or(ImageError::ImageEnd)?;
+            pos += 1;
+
+            match marker {
+                // EOI
+                0xD9 => break,
+                // Standalone markers
+                0x01 | 0xD0..=0xD7 => continue,
+                _ => (),
+            }
+
+            if data.len() < pos + 2 {
+                return Err(ImageError::ImageEnd)
+            }
+            let length = BigEndian::read_u16(&data[pos..]) as usize;
+            if length < 2 || data.len() < pos + length {
+                return Err(ImageError::ImageEnd)
+            }
+            let segment = &data[pos + 2..pos + length];
+            pos += length;
+
+            match marker {
+                // APPn and COM
+                0xE0..=0xEF | 0xFE => image.segments.push((marker, segment.to_vec())),
+                // DQT
+                0xDB => image.read_quantization_tables(segment)?,
+                // DHT
+                0xC4 => read_huffman_tables(segment, &mut huffman_tables)?,
+                // DRI
+                0xDD if segment.len() >= 2 => image.restart_interval = BigEndian::read_u16(segment),
+                // Baseline and extended sequential DCT
+                0xC0 | 0xC1 => image.read_frame(segment, data.len() - pos)?,
+                // Progressive, lossless and arithmetic coded images
+                0xC2 | 0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => {
+                    return Err(ImageError::UnsupportedError(
+                        "Only sequential Huffman coded JPEG images can be transformed losslessly".to_string()
+                    ))
+                }
+                // SOS
+                0xDA => {
+                    if image.components.is_empty() {
+                        return Err(ImageError::malformed(ImageFormat::JPEG, "Scan before the frame header"))
+                    }
+                    let restart_interval = image.restart_interval as usize;
+                    pos = image.read_scan(data, pos, segment, &huffman_tables, restart_interval)?;
+                }
+                _ => (),
+            }
+        }
+
+        if image.components.is_empty() {
+            return Err(ImageError::malformed(ImageFormat::JPEG, "Missing frame header"))
+        }
+        Ok(image)
+    }
+
+    fn read_quantization_tables(&mut self, mut segment: &[u8]) -> ImageResult<()> {
+        while !segment.is_empty() {
+            let precision = segment[0] >> 4;
+            let id = (segment[0] & 0x0F) as usize;
+            let size = if precision == 0 { 1 } else { 2 };
+            if id > 3 || segment.len() < 1 + 64 * size {
+                return Err(ImageError::malformed(ImageFormat::JPEG, "Invalid quantization table"))
+            }
+
+            let mut table = [0u16; 64];
+            for i in 0..64 {
+                table[UNZIGZAG[i] as usize] = if size == 1 {
+                    u16::from(segment[1 + i])
+                } else {
+                    BigEndian::read_u16(&segment[1 + 2 * i..])
+                };
+            }
+            self.qtables[id] = Some(table);
+            segment = &segment[1 + 64 * size..];
+        }
+        Ok(())
+    }
+
+    fn read_frame(&mut self, segment: &[u8], remaining: usize) -> ImageResult<()> {
+        if segment.len() < 6 || segment.len() < 6 + 3 * segment[5] as usize {
+            return Err(ImageError::malformed(ImageFormat::JPEG, "Invalid frame header"))
+        }
+        if segment[0] != 8 {
+            return Err(ImageError::UnsupportedError(
+                format!("{} bit JPEG images can not be transformed losslessly", segment[0])
+            ))
+        }
+
+        self.height = BigEndian::read_u16(&segment[1..]);
+        self.width = BigEndian::read_u16(&segment[3..]);
+        if self.width == 0 || self.height == 0 {
+            return Err(ImageError::DimensionError)
+        }
+
+        let count = segment[5] as usize;
+        if count == 0 || count > 4 {
+            return Err(ImageError::malformed(ImageFormat::JPEG, format!("Invalid number of components {}", count)))
+        }
+        self.components = segment[6..6 + 3 * count].chunks(3).map(|c| Component {
+            id: c[0],
+            h: c[1] >> 4,
+            v: c[1] & 0x0F,
+            tq: c[2],
+            blocks_w: 0,
+            blocks_h: 0,
+            blocks: Vec::new(),
+        }).collect();
+
+        if self.components.iter().any(|c| c.h == 0 || c.h > 4 || c.v == 0 || c.v > 4 || c.tq > 3) {
+            return Err(ImageError::malformed(ImageFormat::JPEG, "Invalid component in frame header"))
+        }
+        // A single component is never subsampled
+        if count == 1 {
+            self.components[0].h = 1;
+            self.components[0].v = 1;
+        }
+
+        // Every block is coded with at least two bits, so the frame can not contain
+        // more blocks than the data which follows its header can hold
+        let coded: usize = self.components.iter().map(|c| {
+            let (w, h) = self.component_blocks(c);
+            w * h
+        }).sum();
+        if coded / 4 > remaining {
+            return Err(ImageError::malformed(ImageFormat::JPEG, "Frame is larger than the image data"))
+        }
+
+        self.allocate_blocks();
+        Ok(())
+    }
+
+    /// The largest horizontal and vertical sampling factors
+    fn max_sampling(&self) -> (usize, usize) {
+        (self.components.iter().map(|c| c.h as usize).max().unwrap_or(1),
+         self.components.iter().map(|c| c.v as usize).max().unwrap_or(1))
+    }
+
+    /// The number of MCUs in a row and in a column
+    fn mcus(&self) -> (usize, usize) {
+        let (hmax, vmax) = self.max_sampling();
+        (ceil_div(self.width as usize, 8 * hmax), ceil_div(self.height as usize, 8 * vmax))
+    }
+
+    /// The number of blocks in a row and in a column of a component, without padding
+    fn component_blocks(&self, c: &Component) -> (usize, usize) {
+        let (hmax, vmax) = self.max_sampling();
+        let width = ceil_div(self.width as usize * c.h as usize, hmax);
+        let height = ceil_div(self.height as usize * c.v as usize, vmax);
+        (ceil_div(width, 8), ceil_div(height, 8))
+    }
+
+    // Makes room for the blocks of all MCUs, including the blocks which pad
+    // the components to a multiple of the MCU size
+    fn allocate_blocks(&mut self) {
+        let (mcus_x, mcus_y) = self.mcus();
+        for c in &mut self.components {
+            c.blocks_w = mcus_x * c.h as usize;
+            c.blocks_h = mcus_y * c.v as usize;
+            c.blocks = vec![[0; 64]; c.blocks_w * c.blocks_h];
+        }
+    }
+
+    // Decodes the scan starting at ```pos``` and returns the position of the next marker
+    fn read_scan(&mut self, data: &[u8], pos: usize, header: &[u8],
+                 huffman_tables: &[Option<HuffDecodeTable>], restart_interval: usize)
+        -> ImageResult<usize> {
+
+        let invalid = || ImageError::malformed(ImageFormat::JPEG, "Invalid scan header");
+
+        let count = *header.first().ok_or_else(invalid)? as usize;
+        if count == 0 || header.len() < 1 + 2 * count + 3 {
+            return Err(invalid())
+        }
+        let mut scan = Vec::with_capacity(count);
+        for c in header[1..1 + 2 * count].chunks(2) {
+            let index = self.components.iter().position(|component| component.id == c[0])
+                .ok_or_else(invalid)?;
+            let dc = huffman_tables[(c[1] >> 4) as usize & 3].as_ref();
+            let ac = huffman_tables[4 + (c[1] & 3) as usize].as_ref();
+            match (dc, ac) {
+                (Some(dc), Some(ac)) => scan.push((index, dc, ac)),
+                _ => return Err(ImageError::malformed(ImageFormat::JPEG, "Missing Huffman table")),
+            }
+        }
+
+        let (mcus_x, mcus_y) = if count == 1 {
+            // A non-interleaved scan contains only the blocks inside of the component
+            self.component_blocks(&self.components[scan[0].0])
+        } else {
+            self.mcus()
+        };
+
+        let mut reader = EntropyReader { data, pos, bits: 0, nbits: 0 };
+        let mut predictions = vec![0; count];
+        for mcu in 0..mcus_x * mcus_y {
+            if restart_interval > 0 && mcu > 0 && mcu % restart_interval == 0 {
+                reader.restart(mcu / restart_interval - 1)?;
+                predictions = vec![0; count];
+            }
+            let (mcu_x, mcu_y) = (mcu % mcus_x, mcu / mcus_x);
+
+            for (&(index, dc, ac), prediction) in scan.iter().zip(predictions.iter_mut()) {
+                let c = &mut self.components[index];
+                let (h, v) = if count == 1 { (1, 1) } else { (c.h as usize, c.v as usize) };
+                for y in 0..v {
+                    for x in 0..h {
+                        let block = (mcu_y * v + y) * c.blocks_w + mcu_x * h + x;
+                        *prediction = reader.read_block(&mut c.blocks[block], *prediction, dc, ac)?;
+                    }
+                }
+            }
+        }
+
+        // Skip the remaining bits and look for the next marker
+        let mut pos = reader.pos;
+        while pos + 1 < data.len() {
+            match (data[pos], data[pos + 1]) {
+                (0xFF, 0x00) | (0xFF, 0xD0..=0xD7) | (0xFF, 0xFF) => pos += 1,
+                (0xFF, _) => return Ok(pos),
+                _ => pos += 1,
+            }
+        }
+        Err(ImageError::ImageEnd)
+    }
+
+    fn transform(&self, transform: LosslessTransform) -> ImageResult<Coefficients> {
+        use self::LosslessTransform::*;
+
+        // Only whole MCUs can be moved to the other edge
+        let (hmax, vmax) = self.max_sampling();
+        let (trim_x, trim_y) = match transform {
+            FlipHorizontal | Rotate270 => (true, false),
+            FlipVertical | Rotate90 => (false, true),
+            Rotate180 => (true, true),
+        };
+        let width = if trim_x { self.width as usize / (8 * hmax) * 8 * hmax } else { self.width as usize };
+        let height = if trim_y { self.height as usize / (8 * vmax) * 8 * vmax } else { self.height as usize };
+        if width == 0 || height == 0 {
+            return Err(ImageError::DimensionError)
+        }
+
+        let transpose = transform == Rotate90 || transform == Rotate270;
+        let mut qtables = self.qtables;
+        if transpose {
+            for table in qtables.iter_mut().filter_map(|t| t.as_mut()) {
+                for v in 0..8 {
+                    for u in 0..v {
+                        table.swap(v * 8 + u, u * 8 + v);
+                    }
+                }
+            }
+        }
+
+        let mut out = Coefficients {
+            width: if transpose { height } else { width } as u16,
+            height: if transpose { width } else { height } as u16,
+            components: self.components.iter().map(|c| Component {
+                id: c.id,
+                h: if transpose { c.v } else { c.h },
+                v: if transpose { c.h } else { c.v },
+                tq: c.tq,
+                blocks_w: 0,
+                blocks_h: 0,
+                blocks: Vec::new(),
+            }).collect(),
+            qtables,
+            segments: self.segments.clone(),
+            restart_interval: self.restart_interval,
+        };
+        for &mut (marker, ref mut segment) in &mut out.segments {
+            if marker == 0xE1 && segment.starts_with(EXIF_HEADER) {
+                update_orientation(&mut segment[EXIF_HEADER.len()..], transform);
+            }
+        }
+        out.allocate_blocks();
+
+        for (src, dst) in self.components.iter().zip(out.components.iter_mut()) {
+            // The size of the kept part of the component in blocks
+            let blocks_w = width / (8 * hmax) * src.h as usize;
+            let blocks_h = height / (8 * vmax) * src.v as usize;
+
+            for y in 0..dst.blocks_h {
+                for x in 0..dst.blocks_w {
+                    let (src_x, src_y) = match transform {
+                        FlipHorizontal => (blocks_w - 1 - x, y),
+                        FlipVertical => (x, blocks_h - 1 - y),
+                        Rotate90 => (y, blocks_h - 1 - x),
+                        Rotate180 => (blocks_w - 1 - x, blocks_h - 1 - y),
+                        Rotate270 => (blocks_w - 1 - y, x),
+                    };
+                    if src_x >= src.blocks_w || src_y >= src.blocks_h {
+                        continue
+                    }
+                    let block = &src.blocks[src_y * src.blocks_w + src_x];
+                    transform_block(block, &mut dst.blocks[y * dst.blocks_w + x], transform);
+                }
+            }
+        }
+
+        Ok(out)
+    }
+
+    fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut writer = BitWriter::new(w);
+        writer.write_segment(SOI, None)?;
+
+        for &(marker, ref segment) in &self.segments {
+            writer.write_segment(marker, Some(segment))?;
+        }
+
+        let mut buf = Vec::new();
+        let mut extended = false;
+        for (id, table) in self.qtables.iter().enumerate() {
+            if let Some(ref table) = *table {
+                let precision = if table.iter().any(|&q| q > 255) { 1 } else { 0 };
+                extended |= precision == 1;
+
+                buf.clear();
+                buf.push((precision << 4) | id as u8);
+                for &i in UNZIGZAG.iter() {
+                    let q = table[i as usize];
+                    if precision == 1 {
+                        buf.push((q >> 8) as u8);
+                    }
+                    buf.push(q as u8);
+                }
+                writer.write_segment(DQT, Some(&buf))?;
+            }
+        }
+
+        buf.clear();
+        buf.push(8);
+        buf.extend_from_slice(&[(self.height >> 8) as u8, self.height as u8]);
+        buf.extend_from_slice(&[(self.width >> 8) as u8, self.width as u8]);
+        buf.push(self.components.len() as u8);
+        for c in &self.components {
+            buf.extend_from_slice(&[c.id, (c.h << 4) | c.v, c.tq]);
+        }
+        writer.write_segment(if extended { SOF1 } else { SOF0 }, Some(&buf))?;
+
+        build_huffman_segment(&mut buf, DCCLASS, LUMADESTINATION,
+                              &STD_LUMA_DC_CODE_LENGTHS, &STD_LUMA_DC_VALUES);
+        writer.write_segment(DHT, Some(&buf))?;
+        build_huffman_segment(&mut buf, ACCLASS, LUMADESTINATION,
+                              &STD_LUMA_AC_CODE_LENGTHS, &STD_LUMA_AC_VALUES);
+        writer.write_segment(DHT, Some(&buf))?;
+        if self.components.len() > 1 {
+            build_huffman_segment(&mut buf, DCCLASS, CHROMADESTINATION,
+                                  &STD_CHROMA_DC_CODE_LENGTHS, &STD_CHROMA_DC_VALUES);
+            writer.write_segment(DHT, Some(&buf))?;
+            build_huffman_segment(&mut buf, ACCLASS, CHROMADESTINATION,
+                                  &STD_CHROMA_AC_CODE_LENGTHS, &STD_CHROMA_AC_VALUES);
+            writer.write_segment(DHT, Some(&buf))?;
+        }
+
+        // The first component uses the luma tables, all others the chroma tables
+        buf.clear();
+        buf.push(self.components.len() as u8);
+        for (i, c) in self.components.iter().enumerate() {
+            buf.extend_from_slice(&[c.id, if i == 0 { 0x00 } else { 0x11 }]);
+        }
+        buf.extend_from_slice(&[0, 63, 0]);
+        if self.restart_interval > 0 {
+            let interval = [(self.restart_interval >> 8) as u8, self.restart_interval as u8];
+            writer.write_segment(DRI, Some(&interval))?;
+        }
+        writer.write_segment(SOS, Some(&buf))?;
+
+        let luma = (build_huff_lut(&STD_LUMA_DC_CODE_LENGTHS, &STD_LUMA_DC_VALUES),
+                    build_huff_lut(&STD_LUMA_AC_CODE_LENGTHS, &STD_LUMA_AC_VALUES));
+        let chroma = (build_huff_lut(&STD_CHROMA_DC_CODE_LENGTHS, &STD_CHROMA_DC_VALUES),
+                      build_huff_lut(&STD_CHROMA_AC_CODE_LENGTHS, &STD_CHROMA_AC_VALUES));
+
+        let (mcus_x, mcus_y) = self.mcus();
+        let mut predictions = vec![0; self.components.len()];
+        for mcu_y in 0..mcus_y {
+            for mcu_x in 0..mcus_x {
+                if writer.write_restart_marker(mcu_y * mcus_x + mcu_x, self.restart_interval)? {
+                    predictions = vec![0; self.components.len()];
+                }
+                for (i, (c, prediction)) in self.components.iter().zip(predictions.iter_mut()).enumerate() {
+                    let (ref dc, ref ac) = if i == 0 { &luma } else { &chroma };
+                    let (h, v) = (c.h as usize, c.v as usize);
+                    for y in 0..v {
+                        for x in 0..h {
+                            let block = &c.blocks[(mcu_y * v + y) * c.blocks_w + mcu_x * h + x];
+                            *prediction = writer.write_block(block, *prediction, dc, ac)?;
+                        }
+                    }
+                }
+            }
+        }
+
+        writer.pad_byte()?;
+        writer.write_segment(EOI, None)
+    }
+}
+
+pub fn ceil_div(a: usize, b: usize) -> usize {
+    let (quotient, remainder) = (a / b, a % b);
+    if remainder > 0 { quotient + 1 } else { quotient }
+}
+
+fn read_huffman_tables(mut segment: &[u8], tables: &mut [Option<HuffDecodeTable>]) -> ImageResult<()> {
+    while !segment.is_empty() {
+        let class = (segment[0] >> 4) as usize;
+        let id = (segment[0] & 0x0F) as usize;
+        if class > 1 || id > 3 || segment.len() < 17 {
+            return Err(ImageError::malformed(ImageFormat::JPEG, "Invalid Huffman table"))
+        }
+
+        let bits = &segment[1..17];
+        let count = bits.iter().map(|&b| b as usize).sum::<usize>();
+        if count > 256 || segment.len() < 17 + count {
+            return Err(ImageError::malformed(ImageFormat::JPEG, "Invalid Huffman table"))
+        }
+        tables[class * 4 + id] = Some(build_huff_decode_table(bits, &segment[17..17 + count]));
+        segment = &segment[17 + count..];
+    }
+    Ok(())
+}
+
+// Moves the coefficients of ```src``` to ```dst```. Flipping a block negates the
+// odd frequencies along the flipped axis, rotating it by 90 degrees also
+// transposes it.
+fn transform_block(src: &Block, dst: &mut Block, transform: LosslessTransform) {
+    use self::LosslessTransform::*;
+
+    for v in 0..8 {
+        for u in 0..8 {
+            let (value, negate) = match transform {
+                FlipHorizontal => (src[v * 8 + u], u % 2 == 1),
+                FlipVertical => (src[v * 8 + u], v % 2 == 1),
+                Rotate90 => (src[u * 8 + v], u % 2 == 1),
+                Rotate180 => (src[v * 8 + u], (u + v) % 2 == 1),
+                Rotate270 => (src[u * 8 + v], v % 2 == 1),
+            };
+            dst[v * 8 + u] = if negate { -value } else { value };
+        }
+    }
+}
+
+/// Reads the entropy coded data of a scan
+struct EntropyReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits: u8,
+    nbits: u8,
+}
+
+impl<'a> EntropyReader<'a> {
+    fn read_bit(&mut self) -> ImageResult<i32> {
+        if self.nbits == 0 {
+            let byte = *self.data.get(self.pos).ok_or(ImageError::ImageEnd)?;
+            if byte == 0xFF {
+                // A stuffed zero byte follows data bytes of 0xFF
+                if self.data.get(self.pos + 1) != Some(&0) {
+                    return Err(ImageError::malformed(ImageFormat::JPEG, "Unexpected marker in scan data"))
+                }
+                self.pos += 1;
+            }
+            self.pos += 1;
+            self.bits = byte;
+            self.nbits = 8;
+        }
+        self.nbits -= 1;
+        Ok(i32::from((self.bits >> self.nbits) & 1))
+    }
+
+    fn decode(&mut self, table: &HuffDecodeTable) -> ImageResult<u8> {
+        let mut code = 0;
+        for length in 1..17 {
+            code = (code << 1) | self.read_bit()?;
+            if let Some(value) = table.lookup(code, length) {
+                return Ok(value)
+            }
+        }
+        Err(ImageError::malformed(ImageFormat::JPEG, "Invalid Huffman code"))
+    }
+
+    // Reads a coefficient of ```size``` bits, see Figure F.12
+    fn receive_extend(&mut self, size: u8) -> ImageResult<i32> {
+        let mut value = 0;
+        for _ in 0..size {
+            value = (value << 1) | self.read_bit()?;
+        }
+        if size > 0 && value < 1 << (size - 1) {
+            value -= (1 << size) - 1;
+        }
+        Ok(value)
+    }
+
+    // Reads a block and returns its DC coefficient, see Figure F.13
+    fn read_block(&mut self, block: &mut Block, prediction: i32,
+                  dc: &HuffDecodeTable, ac: &HuffDecodeTable) -> ImageResult<i32> {
+        let size = self.decode(dc)?;
+        if size > 11 {
+            return Err(ImageError::malformed(ImageFormat::JPEG, "Invalid DC difference"))
+        }
+        block[0] = prediction + self.receive_extend(size)?;
+
+        let mut k = 1;
+        while k < 64 {
+            let rs = self.decode(ac)?;
+            let (run, size) = ((rs >> 4) as usize, rs & 0x0F);
+            if size == 0 {
+                if run != 15 {
+                    break
+                }
+                k += 16;
+                continue
+            }
+            k += run;
+            if k > 63 {
+                return Err(ImageError::malformed(ImageFormat::JPEG, "Invalid AC coefficient"))
+            }
+            block[UNZIGZAG[k] as usize] = self.receive_extend(size)?;
+            k += 1;
+        }
+        Ok(block[0])
+    }
+
+    // Skips the restart marker which follows the data of the restart interval with
+    // the index ```interval```, the markers count from RST0 to RST7 and start over
+    fn restart(&mut self, interval: usize) -> ImageResult<()> {
+        self.nbits = 0;
+        if self.data.get(self.pos) != Some(&0xFF) {
+            return Err(ImageError::malformed(ImageFormat::JPEG, "Missing restart marker"))
+        }
+        // Markers may be preceded by any number of fill bytes
+        while self.data.get(self.pos) == Some(&0xFF) {
+            self.pos += 1;
+        }
+        match self.data.get(self.pos) {
+            Some(&marker) if marker == 0xD0 + (interval % 8) as u8 => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(&(0xD0..=0xD7)) => Err(ImageError::malformed(ImageFormat::JPEG, "Restart marker out of order")),
+            _ => Err(ImageError::malformed(ImageFormat::JPEG, "Missing restart marker")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{transform_lossless, LosslessTransform};
+    use buffer::{ImageBuffer, RgbImage};
+    use color::ColorType;
+    use image::{ImageDecoder, ImageError};
+    use imageops;
+    use jpeg::{JPEGDecoder, JPEGEncoder};
+
+    fn encode(width: u32, height: u32) -> Vec<u8> {
+        let image: RgbImage = ImageBuffer::from_fn(width, height, |x, y| {
+            ::Rgb([(x * 255 / width) as u8, (y * 255 / height) as u8, ((x + y) * 8) as u8])
+        });
+        let mut data = Vec::new();
+        JPEGEncoder::new_with_quality(&mut data, 90)
+            .encode(&image, width, height, ColorType::RGB(8)).unwrap();
+        data
+    }
+
+    fn decode(data: &[u8]) -> RgbImage {
+        let image = ::dynimage::decoder_to_image(JPEGDecoder::new(data)).unwrap();
+        image.to_rgb()
+    }
+
+    fn transform(data: &[u8], transform: LosslessTransform) -> Vec<u8> {
+        let mut out = Vec::new();
+        transform_lossless(data, &mut out, transform).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_transforms_match_pixel_operations() {
+        let data = encode(24, 16);
+        let original = decode(&data);
+        let expected: [(LosslessTransform, RgbImage); 5] = [
+            (LosslessTransform::FlipHorizontal, imageops::flip_horizontal(&original)),
+            (LosslessTransform::FlipVertical, imageops::flip_vertical(&original)),
+            (LosslessTransform::Rotate90, imageops::rotate90(&original)),
+            (LosslessTransform::Rotate180, imageops::rotate180(&original)),
+            (LosslessTransform::Rotate270, imageops::rotate270(&original)),
+        ];
+        for &(op, ref expected) in &expected {
+            let transformed = decode(&transform(&data, op));
+            assert_eq!(transformed.dimensions(), expected.dimensions());
+            // Transposed blocks are rounded differently by the inverse DCT,
+            // which processes rows before columns
+            for (a, b) in transformed.iter().zip(expected.iter()) {
+                assert!((i32::from(*a) - i32::from(*b)).abs() <= 3, "{:?}: {} != {}", op, a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_transforms_are_reversible() {
+        let data = encode(24, 16);
+        let rotated = transform(&transform(&data, LosslessTransform::Rotate90), LosslessTransform::Rotate270);
+        assert_eq!(*decode(&rotated), *decode(&data));
+        let flipped = transform(&transform(&data, LosslessTransform::FlipVertical), LosslessTransform::FlipVertical);
+        assert_eq!(*decode(&flipped), *decode(&data));
+    }
+
+    #[test]
+    fn test_exif_orientation_is_updated() {
+        use metadata::Orientation;
+
+        let image: RgbImage = ImageBuffer::new(16, 16);
+        let mut data = Vec::new();
+        {
+            let mut encoder = JPEGEncoder::new(&mut data);
+            encoder.set_orientation(Orientation::Rotate90);
+            encoder.encode(&image, 16, 16, ColorType::RGB(8)).unwrap();
+        }
+        let orientation = |op| JPEGDecoder::new(&transform(&data, op)[..]).metadata().unwrap().orientation;
+        assert_eq!(orientation(LosslessTransform::Rotate90), Some(Orientation::Normal));
+        assert_eq!(orientation(LosslessTransform::Rotate180), Some(Orientation::Rotate270));
+        assert_eq!(orientation(LosslessTransform::FlipHorizontal), Some(Orientation::Transverse));
+    }
+
+    #[test]
+    fn test_partial_blocks_are_trimmed() {
+        let data = encode(20, 13);
+        let dimensions = |op| JPEGDecoder::new(&transform(&data, op)[..]).dimensions().unwrap();
+        assert_eq!(dimensions(LosslessTransform::FlipHorizontal), (16, 13));
+        assert_eq!(dimensions(LosslessTransform::FlipVertical), (20, 8));
+        assert_eq!(dimensions(LosslessTransform::Rotate90), (8, 20));
+        assert_eq!(dimensions(LosslessTransform::Rotate180), (16, 8));
+        assert_eq!(dimensions(LosslessTransform::Rotate270), (13, 16));
+
+        // The kept part of the image is not changed
+        let original: RgbImage = decode(&data);
+        let flipped = decode(&transform(&data, LosslessTransform::FlipHorizontal));
+        let expected: RgbImage = imageops::flip_horizontal(&imageops::crop(&mut original.clone(), 0, 0, 16, 13).to_image());
+        for (a, b) in flipped.iter().zip(expected.iter()) {
+            assert!((i32::from(*a) - i32::from(*b)).abs() <= 2);
+        }
+    }
+
+    #[test]
+    fn test_restart_interval() {
+        let image: RgbImage = ImageBuffer::from_fn(40, 24, |x, y| ::Rgb([(x * 6) as u8, (y * 10) as u8, (x * y) as u8]));
+        let encode = |interval| {
+            let mut data = Vec::new();
+            let mut encoder = JPEGEncoder::new_with_quality(&mut data, 90);
+            encoder.set_restart_interval(interval);
+            encoder.encode(&image, 40, 24, ColorType::RGB(8)).unwrap();
+            data
+        };
+        let plain = transform(&encode(0), LosslessTransform::Rotate90);
+        let mut data = encode(3);
+        // Fill bytes may precede the markers
+        let rst = data.windows(2).position(|w| w == [0xFF, 0xD0]).unwrap();
+        data.insert(rst, 0xFF);
+
+        let rotated = transform(&data, LosslessTransform::Rotate90);
+        assert!(rotated.windows(6).any(|w| w == [0xFF, 0xDD, 0, 4, 0, 3]));
+        assert!(rotated.windows(2).any(|w| w == [0xFF, 0xD0]));
+        assert_eq!(*decode(&rotated), *decode(&plain));
+
+        // Markers which are out of order are rejected
+        let rst = data.windows(2).position(|w| w == [0xFF, 0xD1]).unwrap();
+        data[rst + 1] = 0xD2;
+        match transform_lossless(&data[..], &mut Vec::new(), LosslessTransform::Rotate90) {
+            Err(ImageError::Decoding(_)) => (),
+            other => panic!("expected a decoding error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_progressive_is_unsupported() {
+        let data = ::std::fs::read("tests/images/jpg/progressive/cat.jpg").unwrap();
+        match transform_lossless(&data[..], &mut Vec::new(), LosslessTransform::Rotate90) {
+            Err(ImageError::UnsupportedError(_)) => (),
+            other => panic!("expected an unsupported error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_frame_larger_than_data() {
+        let mut data = encode(24, 16);
+        let sof = data.windows(2).position(|w| w == [0xFF, 0xC0]).unwrap();
+        for byte in &mut data[sof + 5..sof + 9] {
+            *byte = 0xFF;
+        }
+        match transform_lossless(&data[..], &mut Vec::new(), LosslessTransform::Rotate90) {
+            Err(ImageError::Decoding(_)) => (),
+            other => panic!("expected a decoding error, got {:?}", other),
+        }
+    }
+}
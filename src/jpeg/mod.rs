@@ -9,8 +9,10 @@
 
 pub use self::decoder::JPEGDecoder;
 pub use self::encoder::JPEGEncoder;
+pub use self::lossless::{transform_lossless, LosslessTransform};
 
 mod encoder;
 mod decoder;
 mod entropy;
 mod transform;
+mod lossless;
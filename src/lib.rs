@@ -12,8 +12,14 @@ extern crate num_iter;
 extern crate num_rational;
 extern crate num_traits;
 extern crate lzw;
-#[macro_use]
+#[cfg_attr(feature = "tiff", macro_use)]
 extern crate enum_primitive;
+#[cfg(feature = "mmap")]
+extern crate memmap;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "rustc-serialize")]
+extern crate rustc_serialize;
 #[cfg(all(test, feature = "benchmarks"))]
 extern crate test;
 
@@ -39,7 +45,11 @@ pub use image::{
     ImageDecoder,
     ImageError,
     ImageResult,
+    DecodingError,
+    DecodingErrorKind,
     DecodingResult,
+    MultiImageDecoder,
+    Reader,
     SubImage,
     GenericImage,
     // Iterators
@@ -89,9 +99,15 @@ pub use dynimage::{
     load_from_memory,
     load_from_memory_with_format,
     guess_format,
+    open_with_orientation,
+    open_thumbnail,
+    open_exif_thumbnail,
     save_buffer
 };
 
+#[cfg(feature = "mmap")]
+pub use dynimage::open_mmap;
+
 pub use dynimage::DynamicImage::{
     self,
     ImageRgb8,
@@ -100,9 +116,27 @@ pub use dynimage::DynamicImage::{
     ImageLumaA8
 };
 
+pub use metadata::{
+    GpsInfo,
+    Metadata,
+    Orientation,
+    PixelDensity,
+    PixelDensityUnit
+};
+
+pub use indexed::IndexedImage;
+
+pub use pipeline::{convert_scanlines, ScanlineEncoder};
+
+pub use tiled::{Tile, TiledImage};
+
 pub use animation::{
+    AnimationEncoder,
+    BlendMethod,
+    DisposalMethod,
     Frame,
-    Frames
+    Frames,
+    LoopCount
 };
 
 // Math utils
@@ -142,6 +176,10 @@ mod color;
 mod buffer;
 mod traits;
 mod animation;
+mod metadata;
+mod indexed;
+mod pipeline;
+mod tiled;
 
 // Copies data from `src` to `dst`
 //
@@ -0,0 +1,691 @@
+//! Metadata which is stored alongside the pixels of an image
+//!
+//! # Related Links
+//! * <http://www.cipa.jp/std/documents/e/DC-008-2012_E.pdf> - The EXIF specification
+
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+
+use image::{ImageError, ImageFormat, ImageResult};
+
+/// Metadata of an image, e.g. the camera settings stored in its EXIF data
+///
+/// Decoders return it through `ImageDecoder::metadata`, fields which are not
+/// stored in the image are `None`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Metadata {
+    /// The raw EXIF data, a TIFF structure without the `Exif\0\0` header of JPEG images
+    pub exif: Option<Vec<u8>>,
+    /// How the stored pixels have to be transformed to display the image upright
+    pub orientation: Option<Orientation>,
+    /// The date and time the image was last changed, formatted as `YYYY:MM:DD HH:MM:SS`
+    pub date_time: Option<String>,
+    /// The date and time the image was taken, formatted as `YYYY:MM:DD HH:MM:SS`
+    pub date_time_original: Option<String>,
+    /// The manufacturer of the camera
+    pub camera_make: Option<String>,
+    /// The model name of the camera
+    pub camera_model: Option<String>,
+    /// The location the image was taken at
+    pub gps: Option<GpsInfo>,
+    /// The physical resolution of the image
+    pub density: Option<PixelDensity>,
+    /// The raw XMP packet, an XML document
+    pub xmp: Option<Vec<u8>>,
+    /// The ICC profile which describes the color space of the image
+    pub icc_profile: Option<Vec<u8>>,
+}
+
+impl Metadata {
+    /// Parses EXIF data, a TIFF structure starting with its byte order mark.
+    ///
+    /// The `Exif\0\0` header of the JPEG APP1 segment is skipped if present.
+    pub fn from_exif(exif: &[u8]) -> ImageResult<Metadata> {
+        let exif = if exif.starts_with(EXIF_HEADER) { &exif[EXIF_HEADER.len()..] } else { exif };
+        let mut metadata = read_tiff_metadata(&mut Cursor::new(exif))?;
+        metadata.exif = Some(exif.to_vec());
+        Ok(metadata)
+    }
+
+    /// Returns the JPEG thumbnail which cameras embed in the EXIF data.
+    ///
+    /// The thumbnail is a complete JPEG image stored in the second directory of the
+    /// EXIF data, so it can be decoded without decoding the image itself. Returns
+    /// `None` if there is no EXIF data or it contains no JPEG thumbnail.
+    pub fn exif_thumbnail(&self) -> ImageResult<Option<&[u8]>> {
+        let exif = match self.exif {
+            Some(ref exif) => exif,
+            None => return Ok(None),
+        };
+        let thumbnail = read_exif_thumbnail(&mut Cursor::new(exif))?;
+        match thumbnail {
+            Some((offset, length)) => {
+                let (offset, length) = (offset as usize, length as usize);
+                if exif.len() < offset || exif.len() - offset < length {
+                    return Err(ImageError::malformed(ImageFormat::TIFF, "EXIF thumbnail is out of bounds"))
+                }
+                Ok(Some(&exif[offset..offset + length]))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// The orientation of the stored pixels, as defined by the EXIF orientation tag
+///
+/// Each variant names the transformation which displays the image upright.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Orientation {
+    /// The image is stored upright (1)
+    Normal,
+    /// The image has to be mirrored horizontally (2)
+    FlipHorizontal,
+    /// The image has to be rotated by 180 degrees (3)
+    Rotate180,
+    /// The image has to be mirrored vertically (4)
+    FlipVertical,
+    /// The image has to be mirrored along the diagonal from the top left to the bottom right (5)
+    Transpose,
+    /// The image has to be rotated by 90 degrees clockwise (6)
+    Rotate90,
+    /// The image has to be mirrored along the diagonal from the top right to the bottom left (7)
+    Transverse,
+    /// The image has to be rotated by 270 degrees clockwise (8)
+    Rotate270,
+}
+
+impl Orientation {
+    /// Converts the value of an EXIF orientation tag
+    pub fn from_exif(value: u16) -> Option<Orientation> {
+        use self::Orientation::*;
+        Some(match value {
+            1 => Normal,
+            2 => FlipHorizontal,
+            3 => Rotate180,
+            4 => FlipVertical,
+            5 => Transpose,
+            6 => Rotate90,
+            7 => Transverse,
+            8 => Rotate270,
+            _ => return None,
+        })
+    }
+
+    /// Returns the value of the EXIF orientation tag
+    pub fn to_exif(self) -> u16 {
+        use self::Orientation::*;
+        match self {
+            Normal => 1,
+            FlipHorizontal => 2,
+            Rotate180 => 3,
+            FlipVertical => 4,
+            Transpose => 5,
+            Rotate90 => 6,
+            Transverse => 7,
+            Rotate270 => 8,
+        }
+    }
+}
+
+/// The physical resolution of an image, e.g. the DPI of a scan
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PixelDensity {
+    /// The number of pixels per unit in the horizontal direction
+    pub x: f64,
+    /// The number of pixels per unit in the vertical direction
+    pub y: f64,
+    /// The unit of the density
+    pub unit: PixelDensityUnit,
+}
+
+/// The unit of a ```PixelDensity```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PixelDensityUnit {
+    /// The density has no unit, it only defines the aspect ratio of the pixels
+    None,
+    /// Pixels per inch
+    Inch,
+    /// Pixels per centimeter
+    Centimeter,
+    /// Pixels per meter
+    Meter,
+}
+
+impl PixelDensity {
+    /// Creates a density in dots per inch
+    pub fn dpi(x: f64, y: f64) -> PixelDensity {
+        PixelDensity { x, y, unit: PixelDensityUnit::Inch }
+    }
+
+    /// Returns the density in dots per inch, or None if it has no unit
+    pub fn to_dpi(&self) -> Option<(f64, f64)> {
+        let inch = match self.unit {
+            PixelDensityUnit::None => return None,
+            PixelDensityUnit::Inch => 1.0,
+            PixelDensityUnit::Centimeter => 2.54,
+            PixelDensityUnit::Meter => 0.0254,
+        };
+        Some((self.x * inch, self.y * inch))
+    }
+}
+
+/// The GPS information of an image
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GpsInfo {
+    /// The latitude in degrees, negative values are south of the equator
+    pub latitude: Option<f64>,
+    /// The longitude in degrees, negative values are west of the prime meridian
+    pub longitude: Option<f64>,
+    /// The altitude in meters, negative values are below sea level
+    pub altitude: Option<f64>,
+    /// The UTC date and time of the GPS fix, formatted as `YYYY:MM:DD HH:MM:SS`
+    pub date_time: Option<String>,
+}
+
+/// The header of EXIF data in a JPEG APP1 segment
+pub const EXIF_HEADER: &[u8] = b"Exif\0\0";
+
+/// The header of an XMP packet in a JPEG APP1 segment
+#[cfg_attr(not(feature = "jpeg"), allow(dead_code))]
+pub const XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+/// The header of a part of an ICC profile in a JPEG APP2 segment, it is followed
+/// by the sequence number of the part and the number of parts
+#[cfg_attr(not(feature = "jpeg"), allow(dead_code))]
+pub const ICC_HEADER: &[u8] = b"ICC_PROFILE\0";
+
+// Tags of the primary image directory
+const TAG_MAKE: u16 = 0x010F;
+const TAG_MODEL: u16 = 0x0110;
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_X_RESOLUTION: u16 = 0x011A;
+const TAG_Y_RESOLUTION: u16 = 0x011B;
+const TAG_RESOLUTION_UNIT: u16 = 0x0128;
+const TAG_DATE_TIME: u16 = 0x0132;
+const TAG_EXIF_IFD: u16 = 0x8769;
+const TAG_GPS_IFD: u16 = 0x8825;
+const TAG_ICC_PROFILE: u16 = 0x8773;
+
+// Tags of the thumbnail directory
+const TAG_JPEG_INTERCHANGE_FORMAT: u16 = 0x0201;
+const TAG_JPEG_INTERCHANGE_FORMAT_LENGTH: u16 = 0x0202;
+
+// Tags of the EXIF directory
+const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+
+// Tags of the GPS directory
+const TAG_GPS_LATITUDE_REF: u16 = 0x0001;
+const TAG_GPS_LATITUDE: u16 = 0x0002;
+const TAG_GPS_LONGITUDE_REF: u16 = 0x0003;
+const TAG_GPS_LONGITUDE: u16 = 0x0004;
+const TAG_GPS_ALTITUDE_REF: u16 = 0x0005;
+const TAG_GPS_ALTITUDE: u16 = 0x0006;
+const TAG_GPS_TIME_STAMP: u16 = 0x0007;
+const TAG_GPS_DATE_STAMP: u16 = 0x001D;
+
+// Values larger than this are not read, they are not used by any of the
+// supported tags and corrupt files could request huge allocations otherwise
+const MAX_VALUE_SIZE: usize = 1 << 16;
+// ICC profiles are the exception, they can contain large lookup tables
+const MAX_ICC_PROFILE_SIZE: usize = 1 << 24;
+
+// An entry of an image file directory
+struct Entry {
+    tag: u16,
+    kind: u16,
+    count: u32,
+    // The value if it fits into 4 bytes, otherwise its offset
+    value: [u8; 4],
+}
+
+// Reads the IFDs of a TIFF structure which starts at the current position of ```r```
+struct TiffReader<'a, R: 'a> {
+    r: &'a mut R,
+    base: u64,
+    big_endian: bool,
+}
+
+impl<'a, R: Read + Seek> TiffReader<'a, R> {
+    fn u16(&self, bytes: &[u8]) -> u16 {
+        if self.big_endian { BigEndian::read_u16(bytes) } else { LittleEndian::read_u16(bytes) }
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        if self.big_endian { BigEndian::read_u32(bytes) } else { LittleEndian::read_u32(bytes) }
+    }
+
+    fn read_ifd(&mut self, offset: u32) -> ImageResult<Vec<Entry>> {
+        self.r.seek(SeekFrom::Start(self.base + u64::from(offset)))?;
+        let mut count = [0; 2];
+        self.r.read_exact(&mut count)?;
+
+        let mut entries = Vec::new();
+        for _ in 0..self.u16(&count) {
+            let mut entry = [0; 12];
+            self.r.read_exact(&mut entry)?;
+            entries.push(Entry {
+                tag: self.u16(&entry[0..]),
+                kind: self.u16(&entry[2..]),
+                count: self.u32(&entry[4..]),
+                value: [entry[8], entry[9], entry[10], entry[11]],
+            });
+        }
+        Ok(entries)
+    }
+
+    // Returns the offset of the directory which follows the one at ```offset```
+    fn next_ifd(&mut self, offset: u32) -> ImageResult<u32> {
+        self.r.seek(SeekFrom::Start(self.base + u64::from(offset)))?;
+        let mut count = [0; 2];
+        self.r.read_exact(&mut count)?;
+        self.r.seek(SeekFrom::Current(12 * i64::from(self.u16(&count))))?;
+        let mut next = [0; 4];
+        self.r.read_exact(&mut next)?;
+        Ok(self.u32(&next))
+    }
+
+    // Returns the bytes of the value of ```entry```
+    fn read_value(&mut self, entry: &Entry) -> ImageResult<Vec<u8>> {
+        self.read_value_limited(entry, MAX_VALUE_SIZE)
+    }
+
+    fn read_value_limited(&mut self, entry: &Entry, limit: usize) -> ImageResult<Vec<u8>> {
+        let size = match entry.kind {
+            // BYTE, ASCII, SBYTE, UNDEFINED
+            1 | 2 | 6 | 7 => 1,
+            // SHORT, SSHORT
+            3 | 8 => 2,
+            // LONG, SLONG, FLOAT
+            4 | 9 | 11 => 4,
+            // RATIONAL, SRATIONAL, DOUBLE
+            5 | 10 | 12 => 8,
+            _ => return Err(ImageError::malformed(ImageFormat::TIFF, format!("Unknown EXIF type {}", entry.kind))),
+        };
+        let length = match (entry.count as usize).checked_mul(size) {
+            Some(length) if length <= limit => length,
+            _ => return Err(ImageError::malformed(ImageFormat::TIFF, "EXIF value is too large")),
+        };
+        if length <= 4 {
+            return Ok(entry.value[..length].to_vec())
+        }
+
+        let offset = self.u32(&entry.value);
+        self.r.seek(SeekFrom::Start(self.base + u64::from(offset)))?;
+        let mut value = vec![0; length];
+        self.r.read_exact(&mut value)?;
+        Ok(value)
+    }
+
+    fn read_ascii(&mut self, entry: &Entry) -> ImageResult<String> {
+        let value = self.read_value(entry)?;
+        let end = value.iter().position(|&b| b == 0).unwrap_or(value.len());
+        Ok(String::from_utf8_lossy(&value[..end]).trim_right().to_string())
+    }
+
+    // Reads a BYTE, SHORT or LONG value, writers do not agree on the types of some tags
+    fn read_unsigned(&mut self, entry: &Entry) -> ImageResult<u32> {
+        let value = self.read_value(entry)?;
+        match entry.kind {
+            3 if value.len() >= 2 => Ok(u32::from(self.u16(&value))),
+            4 if value.len() >= 4 => Ok(self.u32(&value)),
+            1 if !value.is_empty() => Ok(u32::from(value[0])),
+            _ => Err(ImageError::malformed(ImageFormat::TIFF, format!("Invalid value of EXIF tag {:#x}", entry.tag))),
+        }
+    }
+
+    fn read_rationals(&mut self, entry: &Entry) -> ImageResult<Vec<f64>> {
+        if entry.kind != 5 {
+            return Err(ImageError::malformed(ImageFormat::TIFF, format!("Invalid value of EXIF tag {:#x}", entry.tag)))
+        }
+        let value = self.read_value(entry)?;
+        Ok(value.chunks(8).map(|r| {
+            let denominator = self.u32(&r[4..]);
+            if denominator == 0 { 0.0 } else { f64::from(self.u32(r)) / f64::from(denominator) }
+        }).collect())
+    }
+}
+
+/// Reads the metadata of the first image of a TIFF structure, e.g. EXIF data.
+///
+/// The TIFF structure starts at the current position of ```r```, which is also
+/// the base of all offsets.
+pub fn read_tiff_metadata<R: Read + Seek>(r: &mut R) -> ImageResult<Metadata> {
+    let base = r.seek(SeekFrom::Current(0))?;
+    let (big_endian, ifd0) = read_tiff_header(r)?;
+    let mut reader = TiffReader { r, base, big_endian };
+    let mut metadata = Metadata::default();
+    // The resolution unit defaults to inches
+    let (mut x_resolution, mut y_resolution, mut resolution_unit) = (None, None, 2);
+
+    for entry in reader.read_ifd(ifd0)? {
+        match entry.tag {
+            TAG_MAKE => metadata.camera_make = Some(reader.read_ascii(&entry)?),
+            TAG_MODEL => metadata.camera_model = Some(reader.read_ascii(&entry)?),
+            TAG_DATE_TIME => metadata.date_time = Some(reader.read_ascii(&entry)?),
+            TAG_ORIENTATION => {
+                metadata.orientation = Orientation::from_exif(reader.read_unsigned(&entry)? as u16)
+            }
+            TAG_X_RESOLUTION => x_resolution = reader.read_rationals(&entry)?.first().cloned(),
+            TAG_Y_RESOLUTION => y_resolution = reader.read_rationals(&entry)?.first().cloned(),
+            TAG_RESOLUTION_UNIT => resolution_unit = reader.read_unsigned(&entry)?,
+            TAG_ICC_PROFILE => {
+                metadata.icc_profile = Some(reader.read_value_limited(&entry, MAX_ICC_PROFILE_SIZE)?)
+            }
+            TAG_EXIF_IFD => {
+                let offset = reader.read_unsigned(&entry)?;
+                for entry in reader.read_ifd(offset)? {
+                    if entry.tag == TAG_DATE_TIME_ORIGINAL {
+                        metadata.date_time_original = Some(reader.read_ascii(&entry)?);
+                    }
+                }
+            }
+            TAG_GPS_IFD => {
+                let offset = reader.read_unsigned(&entry)?;
+                metadata.gps = Some(read_gps(&mut reader, offset)?);
+            }
+            _ => (),
+        }
+    }
+
+    if let (Some(x), Some(y)) = (x_resolution, y_resolution) {
+        let unit = match resolution_unit {
+            2 => PixelDensityUnit::Inch,
+            3 => PixelDensityUnit::Centimeter,
+            _ => PixelDensityUnit::None,
+        };
+        metadata.density = Some(PixelDensity { x, y, unit });
+    }
+
+    Ok(metadata)
+}
+
+// Returns the offset and the length of the JPEG thumbnail in the second directory of
+// a TIFF structure
+fn read_exif_thumbnail<R: Read + Seek>(r: &mut R) -> ImageResult<Option<(u32, u32)>> {
+    let (big_endian, ifd0) = read_tiff_header(r)?;
+    let mut reader = TiffReader { r, base: 0, big_endian };
+    let ifd1 = reader.next_ifd(ifd0)?;
+    if ifd1 == 0 {
+        return Ok(None)
+    }
+
+    let (mut offset, mut length) = (None, None);
+    for entry in reader.read_ifd(ifd1)? {
+        match entry.tag {
+            TAG_JPEG_INTERCHANGE_FORMAT => offset = Some(reader.read_unsigned(&entry)?),
+            TAG_JPEG_INTERCHANGE_FORMAT_LENGTH => length = Some(reader.read_unsigned(&entry)?),
+            _ => (),
+        }
+    }
+    match (offset, length) {
+        (Some(offset), Some(length)) if length > 0 => Ok(Some((offset, length))),
+        _ => Ok(None),
+    }
+}
+
+// Returns the byte order and the offset of the first directory
+fn read_tiff_header<R: Read>(r: &mut R) -> ImageResult<(bool, u32)> {
+    let mut header = [0; 8];
+    r.read_exact(&mut header)?;
+    match &header[..4] {
+        b"II*\0" => Ok((false, LittleEndian::read_u32(&header[4..]))),
+        b"MM\0*" => Ok((true, BigEndian::read_u32(&header[4..]))),
+        _ => Err(ImageError::malformed(ImageFormat::TIFF, "Invalid TIFF header in EXIF data")),
+    }
+}
+
+/// Builds EXIF data which only contains an orientation tag
+#[cfg_attr(not(feature = "jpeg"), allow(dead_code))]
+pub fn exif_with_orientation(orientation: Orientation) -> Vec<u8> {
+    // A big endian header and a directory with a single SHORT entry
+    let mut exif = b"MM\0*\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01".to_vec();
+    let mut value = [0; 2];
+    BigEndian::write_u16(&mut value, orientation.to_exif());
+    exif.extend_from_slice(&value);
+    exif.extend_from_slice(&[0; 6]);
+    exif
+}
+
+/// Replaces the value of the orientation tag in EXIF data.
+///
+/// Returns false if the first directory has no orientation tag.
+#[cfg_attr(not(feature = "jpeg"), allow(dead_code))]
+pub fn set_exif_orientation(exif: &mut [u8], orientation: Orientation) -> ImageResult<bool> {
+    let (big_endian, position) = {
+        let mut r = Cursor::new(&*exif);
+        let (big_endian, ifd0) = read_tiff_header(&mut r)?;
+        let mut reader = TiffReader { r: &mut r, base: 0, big_endian };
+        let index = reader.read_ifd(ifd0)?.iter()
+            .position(|entry| entry.tag == TAG_ORIENTATION && entry.kind == 3 && entry.count == 1);
+        match index {
+            // The value follows the tag, type and count of the entry
+            Some(index) => (big_endian, ifd0 as usize + 2 + 12 * index + 8),
+            None => return Ok(false),
+        }
+    };
+
+    let value = &mut exif[position..position + 2];
+    if big_endian {
+        BigEndian::write_u16(value, orientation.to_exif());
+    } else {
+        LittleEndian::write_u16(value, orientation.to_exif());
+    }
+    Ok(true)
+}
+
+fn read_gps<R: Read + Seek>(reader: &mut TiffReader<R>, offset: u32) -> ImageResult<GpsInfo> {
+    let mut gps = GpsInfo::default();
+    let (mut latitude_ref, mut longitude_ref, mut below_sea_level) = (None, None, false);
+    let (mut date, mut time) = (None, None);
+
+    // Degrees, minutes and seconds
+    let degrees = |values: Vec<f64>| {
+        values.iter().zip(&[1.0, 60.0, 3600.0]).map(|(v, d)| v / d).sum::<f64>()
+    };
+
+    for entry in reader.read_ifd(offset)? {
+        match entry.tag {
+            TAG_GPS_LATITUDE_REF => latitude_ref = Some(reader.read_ascii(&entry)?),
+            TAG_GPS_LATITUDE => gps.latitude = Some(degrees(reader.read_rationals(&entry)?)),
+            TAG_GPS_LONGITUDE_REF => longitude_ref = Some(reader.read_ascii(&entry)?),
+            TAG_GPS_LONGITUDE => gps.longitude = Some(degrees(reader.read_rationals(&entry)?)),
+            TAG_GPS_ALTITUDE_REF => below_sea_level = reader.read_unsigned(&entry)? == 1,
+            TAG_GPS_ALTITUDE => gps.altitude = reader.read_rationals(&entry)?.first().cloned(),
+            TAG_GPS_TIME_STAMP => time = Some(reader.read_rationals(&entry)?),
+            TAG_GPS_DATE_STAMP => date = Some(reader.read_ascii(&entry)?),
+            _ => (),
+        }
+    }
+
+    if latitude_ref.as_ref().map(|r| &r[..]) == Some("S") {
+        gps.latitude = gps.latitude.map(|l| -l);
+    }
+    if longitude_ref.as_ref().map(|r| &r[..]) == Some("W") {
+        gps.longitude = gps.longitude.map(|l| -l);
+    }
+    if below_sea_level {
+        gps.altitude = gps.altitude.map(|a| -a);
+    }
+    if let (Some(date), Some(time)) = (date, time) {
+        if time.len() == 3 {
+            gps.date_time = Some(format!("{} {:02}:{:02}:{:02}",
+                                         date, time[0] as u32, time[1] as u32, time[2] as u32));
+        }
+    }
+
+    Ok(gps)
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{BigEndian, ByteOrder};
+
+    use super::{exif_with_orientation, set_exif_orientation, GpsInfo, Metadata, Orientation, PixelDensity,
+                PixelDensityUnit, EXIF_HEADER};
+
+    // Builds a big endian TIFF structure with the given directories. Each entry is
+    // (tag, type, count, value), values of more than 4 bytes are appended after
+    // the directories.
+    fn tiff(directories: &[&[(u16, u16, u32, Vec<u8>)]]) -> Vec<u8> {
+        let mut data = b"MM\0*\0\0\0\x08".to_vec();
+        let sizes: Vec<usize> = directories.iter().map(|d| 2 + 12 * d.len() + 4).collect();
+        let mut extra_offset = 8 + sizes.iter().sum::<usize>();
+        let mut extra = Vec::new();
+        for directory in directories {
+            let mut count = [0; 2];
+            BigEndian::write_u16(&mut count, directory.len() as u16);
+            data.extend_from_slice(&count);
+            for &(tag, kind, count, ref value) in directory.iter() {
+                let mut entry = [0; 12];
+                BigEndian::write_u16(&mut entry[0..], tag);
+                BigEndian::write_u16(&mut entry[2..], kind);
+                BigEndian::write_u32(&mut entry[4..], count);
+                if value.len() <= 4 {
+                    entry[8..8 + value.len()].copy_from_slice(value);
+                } else {
+                    BigEndian::write_u32(&mut entry[8..], extra_offset as u32);
+                    extra.extend_from_slice(value);
+                    extra_offset += value.len();
+                }
+                data.extend_from_slice(&entry);
+            }
+            data.extend_from_slice(&[0; 4]);
+        }
+        data.extend_from_slice(&extra);
+        data
+    }
+
+    fn long(value: u32) -> Vec<u8> {
+        let mut bytes = vec![0; 4];
+        BigEndian::write_u32(&mut bytes, value);
+        bytes
+    }
+
+    fn rationals(values: &[(u32, u32)]) -> Vec<u8> {
+        values.iter().flat_map(|&(n, d)| long(n).into_iter().chain(long(d))).collect()
+    }
+
+    #[test]
+    fn test_parse_exif() {
+        // IFD0 at 8 with 9 entries ends at 8 + 2 + 108 + 4 = 122, the EXIF IFD with
+        // one entry ends at 122 + 18 = 140
+        let data = tiff(&[
+            &[
+                (0x010F, 2, 6, b"Canon\0".to_vec()),
+                (0x0110, 2, 4, b"EOS\0".to_vec()),
+                (0x0112, 3, 1, vec![0, 6]),
+                (0x011A, 5, 1, rationals(&[(300, 1)])),
+                (0x011B, 5, 1, rationals(&[(600, 2)])),
+                (0x0128, 3, 1, vec![0, 3]),
+                (0x0132, 2, 20, b"2018:02:03 04:05:06\0".to_vec()),
+                (0x8769, 4, 1, long(122)),
+                (0x8825, 4, 1, long(140)),
+            ],
+            &[(0x9003, 2, 20, b"2018:01:02 03:04:05\0".to_vec())],
+            &[
+                (0x0001, 2, 2, b"S\0".to_vec()),
+                (0x0002, 5, 3, rationals(&[(52, 1), (30, 1), (36, 1)])),
+                (0x0003, 2, 2, b"W\0".to_vec()),
+                (0x0004, 5, 3, rationals(&[(13, 1), (15, 2), (0, 1)])),
+                (0x0005, 1, 1, vec![1]),
+                (0x0006, 5, 1, rationals(&[(25, 2)])),
+                (0x0007, 5, 3, rationals(&[(14, 1), (3, 1), (9, 1)])),
+                (0x001D, 2, 11, b"2018:01:02\0".to_vec()),
+            ],
+        ]);
+
+        let mut app1 = EXIF_HEADER.to_vec();
+        app1.extend_from_slice(&data);
+        let metadata = Metadata::from_exif(&app1).unwrap();
+        assert_eq!(metadata, Metadata {
+            exif: Some(data),
+            orientation: Some(Orientation::Rotate90),
+            date_time: Some("2018:02:03 04:05:06".to_string()),
+            date_time_original: Some("2018:01:02 03:04:05".to_string()),
+            camera_make: Some("Canon".to_string()),
+            camera_model: Some("EOS".to_string()),
+            gps: Some(GpsInfo {
+                latitude: Some(-52.51),
+                longitude: Some(-13.125),
+                altitude: Some(-12.5),
+                date_time: Some("2018:01:02 14:03:09".to_string()),
+            }),
+            density: Some(PixelDensity { x: 300.0, y: 300.0, unit: PixelDensityUnit::Centimeter }),
+            xmp: None,
+            icc_profile: None,
+        });
+    }
+
+    #[test]
+    fn test_exif_thumbnail() {
+        // IFD0 with one entry ends at 8 + 18 = 26, IFD1 with two entries at 26 + 30 = 56
+        let mut data = tiff(&[
+            &[(0x0112, 3, 1, vec![0, 1])],
+            &[(0x0201, 4, 1, long(56)), (0x0202, 4, 1, long(4))],
+        ]);
+        let metadata = Metadata::from_exif(&data).unwrap();
+        assert_eq!(metadata.exif_thumbnail().unwrap(), None);
+
+        // Link IFD0 to IFD1 and append the thumbnail
+        data[22..26].copy_from_slice(&long(26));
+        data.extend_from_slice(&[0xFF, 0xD8, 0xFF, 0xD9]);
+        let metadata = Metadata::from_exif(&data).unwrap();
+        assert_eq!(metadata.exif_thumbnail().unwrap(), Some(&[0xFF, 0xD8, 0xFF, 0xD9][..]));
+
+        data.pop();
+        assert!(Metadata::from_exif(&data).unwrap().exif_thumbnail().is_err());
+        assert_eq!(Metadata::default().exif_thumbnail().unwrap(), None);
+    }
+
+    #[test]
+    fn test_invalid_exif() {
+        assert!(Metadata::from_exif(b"").is_err());
+        assert!(Metadata::from_exif(b"XX\0*\0\0\0\x08").is_err());
+        // The directory is out of bounds
+        assert!(Metadata::from_exif(b"MM\0*\0\0\x10\0").is_err());
+        // The value of the tag is out of bounds
+        let data = tiff(&[&[(0x010F, 2, 16, b"a very long name".to_vec())]]);
+        assert!(Metadata::from_exif(&data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_write_orientation() {
+        let mut exif = exif_with_orientation(Orientation::Rotate90);
+        assert_eq!(Metadata::from_exif(&exif).unwrap().orientation, Some(Orientation::Rotate90));
+        assert!(set_exif_orientation(&mut exif, Orientation::FlipVertical).unwrap());
+        assert_eq!(Metadata::from_exif(&exif).unwrap().orientation, Some(Orientation::FlipVertical));
+
+        let mut exif = tiff(&[&[(0x0110, 2, 4, b"EOS\0".to_vec())]]);
+        assert!(!set_exif_orientation(&mut exif, Orientation::Rotate90).unwrap());
+        assert_eq!(exif, tiff(&[&[(0x0110, 2, 4, b"EOS\0".to_vec())]]));
+    }
+
+    #[test]
+    fn test_icc_profile() {
+        // ICC profiles may be larger than other values
+        let profile: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+        let data = tiff(&[&[(0x8773, 7, profile.len() as u32, profile.clone())]]);
+        assert_eq!(Metadata::from_exif(&data).unwrap().icc_profile, Some(profile));
+        let data = tiff(&[&[(0x0110, 7, 100_000, vec![0; 100_000])]]);
+        assert!(Metadata::from_exif(&data).is_err());
+    }
+
+    #[test]
+    fn test_density_to_dpi() {
+        assert_eq!(PixelDensity::dpi(72.0, 96.0).to_dpi(), Some((72.0, 96.0)));
+        let density = PixelDensity { x: 100.0, y: 50.0, unit: PixelDensityUnit::Centimeter };
+        assert_eq!(density.to_dpi(), Some((254.0, 127.0)));
+        let density = PixelDensity { x: 1.0, y: 2.0, unit: PixelDensityUnit::None };
+        assert_eq!(density.to_dpi(), None);
+    }
+
+    #[test]
+    fn test_orientation_values() {
+        for value in 1..9 {
+            assert_eq!(Orientation::from_exif(value).unwrap().to_exif(), value);
+        }
+        assert_eq!(Orientation::from_exif(0), None);
+        assert_eq!(Orientation::from_exif(9), None);
+    }
+}
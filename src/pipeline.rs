@@ -0,0 +1,209 @@
+//! Converting images row by row
+//!
+//! Decoding an image into a `DynamicImage` needs memory for all of its pixels. The
+//! functions of this module read the rows of a decoder one at a time instead, convert
+//! them and pass them to an encoder right away, so only a single row is held in memory
+//! by decoders which support it, see `ImageDecoder::into_reader`.
+
+use std::io::Read;
+
+use byteorder::{BigEndian, ByteOrder};
+
+use buffer::Pixel;
+use color::{self, ColorType, LumaA, Rgba};
+use image::{ImageDecoder, ImageError, ImageResult};
+
+/// An encoder which accepts an image one row at a time
+pub trait ScanlineEncoder {
+    /// Starts an image with the given dimensions and color type, before its first row
+    fn start_image(&mut self, width: u32, height: u32, color: ColorType) -> ImageResult<()>;
+
+    /// Encodes the next row of the image, rows are written from top to bottom.
+    /// 16 bit samples are big endian.
+    fn write_scanline(&mut self, row: &[u8]) -> ImageResult<()>;
+
+    /// Finishes the image after its last row
+    fn finish_image(&mut self) -> ImageResult<()> {
+        Ok(())
+    }
+}
+
+/// Converts the image of ```decoder``` to the color type ```color``` and encodes it
+/// row by row with ```encoder```.
+///
+/// Gray, gray alpha, RGB and RGBA images with 8 or 16 bits per sample and gray images
+/// with fewer bits are converted to any of these color types with 8 or 16 bits per
+/// sample. Returns an ```UnsupportedColor``` error for other color types, e.g. palettes.
+pub fn convert_scanlines<D, E>(decoder: D, encoder: &mut E, color: ColorType) -> ImageResult<()>
+    where D: ImageDecoder,
+          E: ScanlineEncoder {
+    let mut decoder = decoder;
+    let (width, height) = decoder.dimensions()?;
+    let from = decoder.colortype()?;
+    check_color(from, true)?;
+    check_color(color, false)?;
+
+    let mut row = vec![0; (width as usize * color::bits_per_pixel(from) + 7) / 8];
+    let mut converted = vec![0; width as usize * color::bits_per_pixel(color) / 8];
+    let mut reader = decoder.into_reader()?;
+    encoder.start_image(width, height, color)?;
+    for _ in 0..height {
+        reader.read_exact(&mut row)?;
+        convert_row(&row, from, &mut converted, color);
+        encoder.write_scanline(&converted)?;
+    }
+    encoder.finish_image()
+}
+
+// Checks if rows of ```color``` can be converted, sources may have fewer bits per sample
+pub fn check_color(color: ColorType, source: bool) -> ImageResult<()> {
+    match color {
+        ColorType::Gray(1) | ColorType::Gray(2) | ColorType::Gray(4) if source => Ok(()),
+        ColorType::Gray(8) | ColorType::GrayA(8) | ColorType::RGB(8) | ColorType::RGBA(8) |
+        ColorType::Gray(16) | ColorType::GrayA(16) | ColorType::RGB(16) | ColorType::RGBA(16) => Ok(()),
+        _ => Err(ImageError::UnsupportedColor(color)),
+    }
+}
+
+fn bit_depth(color: ColorType) -> u8 {
+    match color {
+        ColorType::Gray(n) | ColorType::GrayA(n) | ColorType::RGB(n) |
+        ColorType::RGBA(n) | ColorType::Palette(n) => n,
+    }
+}
+
+// Converts a row of pixels, the samples are scaled to 16 bits in between
+pub fn convert_row(input: &[u8], from: ColorType, output: &mut [u8], to: ColorType) {
+    let (in_channels, in_depth) = (color::num_components(from), bit_depth(from) as usize);
+    let (out_channels, out_depth) = (color::num_components(to), bit_depth(to) as usize);
+    let max = (1u32 << in_depth) - 1;
+
+    let read = |index: usize| -> u16 {
+        match in_depth {
+            16 => BigEndian::read_u16(&input[2 * index..]),
+            8 => u16::from(input[index]) * 257,
+            _ => {
+                let bit = index * in_depth;
+                let value = u32::from(input[bit / 8] >> (8 - in_depth - bit % 8)) & max;
+                (value * 0xFFFF / max) as u16
+            }
+        }
+    };
+
+    for i in 0..output.len() * 8 / (out_channels * out_depth) {
+        let s = |c: usize| read(i * in_channels + c);
+        let pixel = match from {
+            ColorType::Gray(_) => Rgba([s(0), s(0), s(0), 0xFFFF]),
+            ColorType::GrayA(_) => Rgba([s(0), s(0), s(0), s(1)]),
+            ColorType::RGB(_) => Rgba([s(0), s(1), s(2), 0xFFFF]),
+            _ => Rgba([s(0), s(1), s(2), s(3)]),
+        };
+        let samples = match to {
+            ColorType::Gray(_) | ColorType::GrayA(_) => {
+                let luma = if in_channels <= 2 { LumaA([pixel[0], pixel[3]]) } else { pixel.to_luma_alpha() };
+                [luma[0], luma[1], 0, 0]
+            }
+            _ => pixel.data,
+        };
+
+        for (c, &sample) in samples[..out_channels].iter().enumerate() {
+            let index = i * out_channels + c;
+            if out_depth == 16 {
+                BigEndian::write_u16(&mut output[2 * index..], sample);
+            } else {
+                output[index] = ((u32::from(sample) + 128) / 257) as u8;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{convert_row, convert_scanlines, ScanlineEncoder};
+    use color::ColorType;
+    use image::{ImageError, ImageResult};
+
+    #[derive(Default)]
+    struct Collector {
+        header: Option<(u32, u32, ColorType)>,
+        rows: Vec<Vec<u8>>,
+        finished: bool,
+    }
+
+    impl ScanlineEncoder for Collector {
+        fn start_image(&mut self, width: u32, height: u32, color: ColorType) -> ImageResult<()> {
+            self.header = Some((width, height, color));
+            Ok(())
+        }
+
+        fn write_scanline(&mut self, row: &[u8]) -> ImageResult<()> {
+            self.rows.push(row.to_vec());
+            Ok(())
+        }
+
+        fn finish_image(&mut self) -> ImageResult<()> {
+            self.finished = true;
+            Ok(())
+        }
+    }
+
+    fn convert(input: &[u8], from: ColorType, to: ColorType, width: usize) -> Vec<u8> {
+        let mut output = vec![0; width * ::color::bits_per_pixel(to) / 8];
+        convert_row(input, from, &mut output, to);
+        output
+    }
+
+    #[test]
+    fn test_convert_row() {
+        assert_eq!(convert(&[10, 20, 30, 40], ColorType::GrayA(8), ColorType::RGBA(8), 2),
+                   vec![10, 10, 10, 20, 30, 30, 30, 40]);
+        assert_eq!(convert(&[1, 2, 3, 4, 5, 6], ColorType::RGB(8), ColorType::RGBA(16), 2),
+                   vec![1, 1, 2, 2, 3, 3, 255, 255, 4, 4, 5, 5, 6, 6, 255, 255]);
+        assert_eq!(convert(&[0x12, 0x80, 0xAB, 0x7F], ColorType::Gray(16), ColorType::Gray(8), 2),
+                   vec![0x12, 0xAB]);
+        assert_eq!(convert(&[0b1001_0000], ColorType::Gray(1), ColorType::Gray(8), 4),
+                   vec![255, 0, 0, 255]);
+        assert_eq!(convert(&[0b1101_0000], ColorType::Gray(4), ColorType::GrayA(8), 2),
+                   vec![221, 255, 0, 255]);
+        // Colors are converted with the luma weights of ```Pixel::to_luma```
+        assert_eq!(convert(&[255, 0, 0, 128, 0, 0, 255, 255], ColorType::RGBA(8), ColorType::GrayA(8), 2),
+                   vec![54, 128, 18, 255]);
+    }
+
+    #[test]
+    #[cfg(feature = "png_codec")]
+    fn test_convert_scanlines() {
+        use std::fs::File;
+        use std::io::BufReader;
+        use dynimage::decoder_to_image;
+        use png::PNGDecoder;
+
+        let path = "tests/images/png/transparency/tp0n2c08.png";
+        let open = || PNGDecoder::new(BufReader::new(File::open(path).unwrap()));
+        let image = decoder_to_image(open()).unwrap();
+
+        let mut collector = Collector::default();
+        convert_scanlines(open(), &mut collector, ColorType::RGBA(8)).unwrap();
+        assert_eq!(collector.header, Some((32, 32, ColorType::RGBA(8))));
+        assert_eq!(collector.rows.len(), 32);
+        assert!(collector.finished);
+        assert_eq!(collector.rows.concat(), image.to_rgba().into_raw());
+
+        // The luma is computed with 16 bits, which may round differently
+        let mut collector = Collector::default();
+        convert_scanlines(open(), &mut collector, ColorType::Gray(8)).unwrap();
+        for (a, b) in collector.rows.concat().iter().zip(image.to_luma().iter()) {
+            assert!((i32::from(*a) - i32::from(*b)).abs() <= 1, "{} != {}", a, b);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "pnm")]
+    fn test_unsupported_color() {
+        let decoder = ::pnm::PNMDecoder::new(&b"P2 1 1 255 7"[..]).unwrap();
+        match convert_scanlines(decoder, &mut Collector::default(), ColorType::Palette(8)) {
+            Err(ImageError::UnsupportedColor(ColorType::Palette(8))) => (),
+            other => panic!("expected an unsupported color error, got {:?}", other),
+        }
+    }
+}
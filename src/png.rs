@@ -7,69 +7,352 @@
 //! * <http://www.w3.org/TR/PNG/> - The PNG Specification
 //!
 
+extern crate deflate;
+extern crate inflate;
 extern crate png;
 
 use self::png::HasParameters;
 
-use std::io::{self, Read, Write};
+use std::cmp;
+use std::io::{self, Cursor, Read, Write};
+use std::mem;
+use std::sync::{Arc, Mutex};
 
-use image::{ImageError, ImageResult, DecodingResult, ImageDecoder};
-use color::ColorType;
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt};
+use num_rational::Ratio;
 
-enum Either<T, U> {
-    Left(T),
-    Right(U)
+use animation::{self, BlendMethod, DisposalMethod, Frame, Frames};
+use dynimage::decoder_to_image;
+use image::{DecodingError, DecodingErrorKind, DecodingResult, ImageDecoder, ImageError, ImageFormat, ImageResult, Reader};
+use color::{ColorType, Rgba};
+use indexed::IndexedImage;
+use metadata::{Metadata, PixelDensity, PixelDensityUnit};
+
+const SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+// The input stream, preceded by the bytes which were already read from it
+type PngStream<R> = io::Chain<Cursor<Vec<u8>>, R>;
+
+enum State<R: Read> {
+    Stream(R),
+    Preamble(Preamble, R),
+    Reader(png::Reader<PngStream<SharedTap<R>>>),
+}
+
+// The chunks in front of the image data
+struct Preamble {
+    // Everything that was read, including the header of the first IDAT chunk
+    bytes: Vec<u8>,
+    ihdr: Vec<u8>,
+    // Chunks that are required to decode each frame of an APNG, e.g. PLTE and tRNS
+    ancillary: Vec<u8>,
+    animated: bool,
+    // Set if the default image is the first frame of the animation
+    control: Option<FrameControl>,
+    // The tEXt, zTXt and iTXt chunks as (type, data)
+    text: Vec<([u8; 4], Vec<u8>)>,
+    density: Option<PixelDensity>,
+    // The data of the iCCP chunk
+    icc_profile: Option<Vec<u8>>,
+    unknown: Vec<UnknownChunk>,
+}
+
+impl Preamble {
+    fn read<R: Read>(r: &mut R) -> ImageResult<Preamble> {
+        let mut bytes = vec![0; SIGNATURE.len()];
+        r.read_exact(&mut bytes)?;
+        if bytes != SIGNATURE {
+            return Err(DecodingError::new(ImageFormat::PNG, DecodingErrorKind::InvalidSignature)
+                .with_offset(0)
+                .into())
+        }
+        let mut preamble = Preamble {
+            bytes: Vec::new(),
+            ihdr: Vec::new(),
+            ancillary: Vec::new(),
+            animated: false,
+            control: None,
+            text: Vec::new(),
+            density: None,
+            icc_profile: None,
+            unknown: Vec::new(),
+        };
+        loop {
+            let start = bytes.len();
+            let (length, kind) = read_chunk_header(r)?;
+            bytes.write_all(&[0; 4])?;
+            BigEndian::write_u32(&mut bytes[start..], length);
+            bytes.extend_from_slice(&kind);
+            if &kind == b"IDAT" {
+                break
+            }
+            read_chunk_data(r, length, &mut bytes)?;
+            {
+                let chunk = &bytes[start..];
+                let data = &chunk[8..chunk.len() - 4];
+                match &kind {
+                    b"IHDR" => preamble.ihdr = data.to_vec(),
+                    b"acTL" => preamble.animated = true,
+                    b"fcTL" => {
+                        let control = FrameControl::parse(data).map_err(|e| e.with_offset(start as u64))?;
+                        preamble.control = Some(control);
+                    }
+                    b"IEND" => return Err(chunk_error("IDAT chunk missing").with_offset(start as u64).into()),
+                    b"tEXt" | b"zTXt" | b"iTXt" => preamble.text.push((kind, data.to_vec())),
+                    b"iCCP" => preamble.icc_profile = Some(data.to_vec()),
+                    b"pHYs" if data.len() == 9 => {
+                        let unit = if data[8] == 1 { PixelDensityUnit::Meter } else { PixelDensityUnit::None };
+                        preamble.density = Some(PixelDensity {
+                            x: f64::from(BigEndian::read_u32(&data[0..])),
+                            y: f64::from(BigEndian::read_u32(&data[4..])),
+                            unit,
+                        });
+                    }
+                    _ => {
+                        // Ancillary chunks have a lowercase first letter
+                        if kind[0].is_ascii_lowercase() && &kind != b"tRNS" {
+                            preamble.unknown.push(UnknownChunk {
+                                kind,
+                                data: data.to_vec(),
+                                position: ChunkPosition::BeforeImageData,
+                            });
+                        }
+                        preamble.ancillary.extend_from_slice(chunk)
+                    }
+                }
+            }
+        }
+        if preamble.ihdr.len() != 13 {
+            return Err(ImageError::malformed(ImageFormat::PNG, "IHDR chunk missing"))
+        }
+        preamble.bytes = bytes;
+        Ok(preamble)
+    }
+
+    fn dimensions(&self) -> (u32, u32) {
+        (BigEndian::read_u32(&self.ihdr[0..]), BigEndian::read_u32(&self.ihdr[4..]))
+    }
 }
 
 /// PNG decoder
+///
+/// Animated PNGs (APNG) are supported through `ImageDecoder::into_frames`, all other
+/// methods decode the default image only.
 pub struct PNGDecoder<R: Read> {
-    inner: Option<Either<png::Decoder<R>, png::Reader<R>>>
+    inner: Option<State<R>>,
+    text: Vec<([u8; 4], Vec<u8>)>,
+    density: Option<PixelDensity>,
+    icc_profile: Option<Vec<u8>>,
+    unknown: Vec<UnknownChunk>,
+    // Follows the chunks behind the image data while it is read
+    tap: Option<Arc<Mutex<ChunkTap<R>>>>,
 }
 
 impl<R: Read> PNGDecoder<R> {
     /// Creates a new decoder that decodes from the stream ```r```
     pub fn new(r: R) -> PNGDecoder<R> {
         PNGDecoder {
-            inner: Some(Either::Left(png::Decoder::new(r)))
+            inner: Some(State::Stream(r)),
+            text: Vec::new(),
+            density: None,
+            icc_profile: None,
+            unknown: Vec::new(),
+            tap: None,
+        }
+    }
+
+    // Reads the chunks in front of the image data if the image data was not read yet
+    fn get_preamble(&mut self) -> ImageResult<Option<&Preamble>> {
+        if let Some(State::Stream(_)) = self.inner {
+            let mut r = match self.inner.take() {
+                Some(State::Stream(r)) => r,
+                _ => unreachable!()
+            };
+            let mut preamble = Preamble::read(&mut r)?;
+            mem::swap(&mut self.text, &mut preamble.text);
+            self.density = preamble.density;
+            self.icc_profile = preamble.icc_profile.take();
+            mem::swap(&mut self.unknown, &mut preamble.unknown);
+            self.inner = Some(State::Preamble(preamble, r));
+        }
+        match self.inner {
+            Some(State::Preamble(ref preamble, _)) => Ok(Some(preamble)),
+            _ => Ok(None)
         }
     }
 
     // Converts the inner decoder to a reader
-    fn get_reader(&mut self) -> Result<&mut png::Reader<R>, png::DecodingError> {
-        let inner = self.inner.take().unwrap();
-        self.inner = Some(match inner {
-            Either::Left(decoder) => {
-                let (_, reader) = try!(decoder.read_info());
-                Either::Right(reader)
-            },
-            Either::Right(reader) => Either::Right(reader)
-        });
+    fn get_reader(&mut self) -> ImageResult<&mut png::Reader<PngStream<SharedTap<R>>>> {
+        self.get_preamble()?;
+        if let Some(State::Preamble(..)) = self.inner {
+            let (preamble, r) = match self.inner.take() {
+                Some(State::Preamble(preamble, r)) => (preamble, r),
+                _ => unreachable!()
+            };
+            // The stream continues after the header of the first IDAT chunk
+            let length = BigEndian::read_u32(&preamble.bytes[preamble.bytes.len() - 8..]);
+            let tap = Arc::new(Mutex::new(ChunkTap::new(r, length)));
+            self.tap = Some(tap.clone());
+            let decoder = png::Decoder::new(Cursor::new(preamble.bytes).chain(SharedTap(tap)));
+            let (_, reader) = decoder.read_info()?;
+            self.inner = Some(State::Reader(reader));
+        }
         match self.inner {
-            Some(Either::Right(ref mut reader)) => Ok(reader),
+            Some(State::Reader(ref mut reader)) => Ok(reader),
             _ => unreachable!()
         }
     }
+
+    /// Returns the textual chunks, e.g. the author or the software which created
+    /// the image.
+    ///
+    /// Chunks which follow the image data are only returned once the image was
+    /// decoded with ```read_image``` or ```read_image_into```.
+    pub fn text_chunks(&mut self) -> ImageResult<Vec<TextChunk>> {
+        self.get_preamble()?;
+        self.text.iter().map(|chunk| TextChunk::parse(&chunk.0, &chunk.1)).collect()
+    }
+
+    /// Returns the ancillary chunks which are not interpreted by this crate, in the
+    /// order they are stored in the file.
+    ///
+    /// Chunks which follow the image data are only returned once the image was
+    /// decoded with ```read_image``` or ```read_image_into```. They can be written
+    /// back with ```PNGEncoder::add_unknown_chunk```.
+    pub fn unknown_chunks(&mut self) -> ImageResult<&[UnknownChunk]> {
+        self.get_preamble()?;
+        Ok(&self.unknown)
+    }
+
+    // Reads the rest of the file after the image data was decoded and keeps the
+    // ancillary chunks which follow it
+    fn read_trailing_chunks(&mut self) -> ImageResult<()> {
+        let tap = match self.tap.take() {
+            Some(tap) => tap,
+            None => return Ok(()),
+        };
+        let mut tap = tap.lock().unwrap();
+        tap.finish()?;
+        for (kind, data) in tap.trailing.drain(..) {
+            match &kind {
+                b"tEXt" | b"zTXt" | b"iTXt" => self.text.push((kind, data)),
+                _ => self.unknown.push(UnknownChunk { kind, data, position: ChunkPosition::AfterImageData }),
+            }
+        }
+        Ok(())
+    }
+
+    /// Decodes a paletted image to the palette indices of its pixels and the palette,
+    /// instead of expanding the palette to RGB or RGBA.
+    ///
+    /// The transparency of the palette entries is taken from the tRNS chunk. Returns an
+    /// ```UnsupportedColor``` error if the image is not paletted and an ```UnsupportedError```
+    /// if the image data was already read, e.g. by ```colortype```.
+    pub fn read_indexed(mut self) -> ImageResult<IndexedImage> {
+        self.get_preamble()?;
+        let (preamble, r) = match self.inner.take() {
+            Some(State::Preamble(preamble, r)) => (preamble, r),
+            _ => return Err(ImageError::UnsupportedError(
+                "The palette indices have to be read before the image data".to_string()
+            ))
+        };
+        let mut decoder = png::Decoder::new(Cursor::new(preamble.bytes).chain(r));
+        decoder.set(png::Transformations::IDENTITY);
+        let (info, mut reader) = decoder.read_info()?;
+        if info.color_type != png::ColorType::Indexed {
+            return Err(ImageError::UnsupportedColor((info.color_type, info.bit_depth).into()))
+        }
+        let mut data = vec![0; info.buffer_size()];
+        reader.next_frame(&mut data)?;
+
+        // Rows of images with less than 8 bits per pixel pack several indices into a byte
+        let bits = info.bit_depth as usize;
+        let mask = ((1u16 << bits) - 1) as u8;
+        let mut indices = Vec::with_capacity(info.width as usize * info.height as usize);
+        for row in data.chunks(info.line_size) {
+            indices.extend((0..info.width as usize).map(|x| {
+                let bit = x * bits;
+                (row[bit / 8] >> (8 - bits - bit % 8)) & mask
+            }));
+        }
+
+        let png_info = reader.info();
+        let alpha = png_info.trns.as_ref().map_or(&[][..], |trns| &trns[..]);
+        let palette = png_info.palette.as_ref().map_or(&[][..], |palette| &palette[..]);
+        let palette = (0..palette.len() / 3).map(|i| {
+            let rgb = &palette[3 * i..3 * i + 3];
+            Rgba([rgb[0], rgb[1], rgb[2], alpha.get(i).cloned().unwrap_or(255)])
+        }).collect();
+        Ok(IndexedImage {
+            width: info.width,
+            height: info.height,
+            indices,
+            palette,
+        })
+    }
+
+    /// Returns the frames of an APNG as they are stored in the file.
+    ///
+    /// The frames may only cover parts of the canvas. Their offset, disposal and blend
+    /// methods describe how they have to be composited. If the image is not animated
+    /// the default image is returned as the only frame.
+    ///
+    /// This has to be called before the image data is read, otherwise only the
+    /// default image is returned.
+    pub fn into_raw_frames<'a>(mut self) -> ImageResult<Frames<'a>> where R: 'a {
+        let animated = match self.get_preamble()? {
+            Some(preamble) => preamble.animated,
+            None => false,
+        };
+        if !animated {
+            let frame = Frame::from_image(decoder_to_image(self)?);
+            return Ok(Frames::new(Box::new(Some(Ok(frame)).into_iter())))
+        }
+        let (preamble, r) = match self.inner.take() {
+            Some(State::Preamble(preamble, r)) => (preamble, r),
+            _ => unreachable!()
+        };
+        // Continue with the header of the first IDAT chunk
+        let idat_header = preamble.bytes[preamble.bytes.len() - 8..].to_vec();
+        let mut frames = ApngFrames {
+            stream: Cursor::new(idat_header).chain(r),
+            ihdr: preamble.ihdr,
+            ancillary: preamble.ancillary,
+            control: None,
+            sequence: 0,
+            data: Vec::new(),
+            first: true,
+            done: false,
+        };
+        if let Some(control) = preamble.control {
+            frames.start_frame(control)?;
+        }
+        Ok(Frames::new(Box::new(frames)))
+    }
 }
 
 impl<R: Read> ImageDecoder for PNGDecoder<R> {
     fn dimensions(&mut self) -> ImageResult<(u32, u32)> {
-        let reader = try!(self.get_reader());
+        if let Some(preamble) = self.get_preamble()? {
+            return Ok(preamble.dimensions())
+        }
+        let reader = self.get_reader()?;
         Ok(reader.info().size())
     }
 
     fn colortype(&mut self) -> ImageResult<ColorType> {
-        let reader = try!(self.get_reader());
+        let reader = self.get_reader()?;
         Ok(reader.output_color_type().into())
     }
 
     fn row_len(&mut self) -> ImageResult<usize> {
-        let reader = try!(self.get_reader());
+        let reader = self.get_reader()?;
         let width = reader.info().width;
         Ok(reader.output_line_size(width))
     }
 
     fn read_scanline(&mut self, buf: &mut [u8]) -> ImageResult<u32> {
-        match try!(try!(self.get_reader()).next_row()) {
+        match self.get_reader()?.next_row()? {
             Some(line) => {
                 ::copy_memory(line, &mut buf[..line.len()]);
                 Ok(line.len() as u32)
@@ -79,35 +362,750 @@ impl<R: Read> ImageDecoder for PNGDecoder<R> {
     }
 
     fn read_image(&mut self) -> ImageResult<DecodingResult> {
-        let reader = try!(self.get_reader());
-        let mut data = vec![0; reader.output_buffer_size()];
-        try!(reader.next_frame(&mut data));
+        let data = {
+            let reader = self.get_reader()?;
+            let mut data = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut data)?;
+            data
+        };
+        self.read_trailing_chunks()?;
         Ok(DecodingResult::U8(data))
     }
+
+    fn read_image_into(&mut self, buf: &mut [u8]) -> ImageResult<()> {
+        {
+            let reader = self.get_reader()?;
+            if buf.len() != reader.output_buffer_size() {
+                return Err(ImageError::DimensionError)
+            }
+            reader.next_frame(buf)?;
+        }
+        self.read_trailing_chunks()
+    }
+
+    fn metadata(&mut self) -> ImageResult<Metadata> {
+        self.get_preamble()?;
+        let xmp = self.text.iter()
+            .find(|chunk| &chunk.0 == b"iTXt" && chunk.1.starts_with(XMP_KEYWORD.as_bytes()) &&
+                          chunk.1.get(XMP_KEYWORD.len()) == Some(&0));
+        let xmp = match xmp {
+            Some(chunk) => Some(TextChunk::parse(&chunk.0, &chunk.1)?.text.into_bytes()),
+            None => None,
+        };
+        let icc_profile = match self.icc_profile {
+            Some(ref data) => Some(parse_icc_chunk(data)?),
+            None => None,
+        };
+        Ok(Metadata {
+            density: self.density,
+            xmp,
+            icc_profile,
+            ..Metadata::default()
+        })
+    }
+
+    fn is_animated(&mut self) -> ImageResult<bool> {
+        if let Some(preamble) = self.get_preamble()? {
+            return Ok(preamble.animated)
+        }
+        Ok(self.get_reader()?.info().animation_control().is_some())
+    }
+
+    /// Returns the fully composited frames of an APNG.
+    ///
+    /// Each frame has the size of the canvas and shows the image as it is to be
+    /// displayed. Use `into_raw_frames` to get the partial frames instead.
+    fn into_frames<'a>(mut self) -> ImageResult<Frames<'a>> where Self: 'a {
+        let (width, height) = self.dimensions()?;
+        let frames = self.into_raw_frames()?;
+        Ok(Frames::new(Box::new(animation::Compositor::new(frames, width, height))))
+    }
+
+    /// Streams the rows of non-interlaced images, interlaced images are decoded at once.
+    fn into_reader<'a>(mut self) -> ImageResult<Reader<'a>> where Self: 'a {
+        if self.get_reader()?.info().interlaced {
+            Reader::from_image(self)
+        } else {
+            Reader::from_scanlines(self)
+        }
+    }
+}
+
+// The content of an fcTL chunk
+struct FrameControl {
+    sequence: u32,
+    width: u32,
+    height: u32,
+    left: u32,
+    top: u32,
+    delay: Ratio<u16>,
+    disposal: DisposalMethod,
+    blend: BlendMethod,
+}
+
+impl FrameControl {
+    fn parse(data: &[u8]) -> Result<FrameControl, DecodingError> {
+        if data.len() != 26 {
+            return Err(DecodingError::invalid_value(ImageFormat::PNG, "fcTL chunk length", 26, data.len()))
+        }
+        let (numer, denom) = (BigEndian::read_u16(&data[20..]), BigEndian::read_u16(&data[22..]));
+        // A denominator of 0 means 1/100 s
+        let delay = Ratio::new(numer, if denom == 0 { 100 } else { denom });
+        let disposal = match data[24] {
+            0 => DisposalMethod::Keep,
+            1 => DisposalMethod::Background,
+            2 => DisposalMethod::Previous,
+            dispose => return Err(DecodingError::invalid_value(ImageFormat::PNG, "dispose operation", "0 to 2", dispose)),
+        };
+        let blend = match data[25] {
+            0 => BlendMethod::Source,
+            1 => BlendMethod::Over,
+            blend => return Err(DecodingError::invalid_value(ImageFormat::PNG, "blend operation", "0 or 1", blend)),
+        };
+        Ok(FrameControl {
+            sequence: BigEndian::read_u32(&data[0..]),
+            width: BigEndian::read_u32(&data[4..]),
+            height: BigEndian::read_u32(&data[8..]),
+            left: BigEndian::read_u32(&data[12..]),
+            top: BigEndian::read_u32(&data[16..]),
+            delay,
+            disposal,
+            blend,
+        })
+    }
+}
+
+// Decodes the frames of an APNG one at a time.
+//
+// The image data of each frame is packaged as a standalone PNG, together with
+// the ancillary chunks of the original image, and decoded by a `PNGDecoder`.
+struct ApngFrames<R: Read> {
+    stream: PngStream<R>,
+    ihdr: Vec<u8>,
+    ancillary: Vec<u8>,
+    // Control of the frame whose data is currently collected
+    control: Option<FrameControl>,
+    // The expected sequence number of the next fcTL or fdAT chunk
+    sequence: u32,
+    data: Vec<u8>,
+    first: bool,
+    done: bool,
+}
+
+impl<R: Read> ApngFrames<R> {
+    // Reads the chunks up to the end of the next frame and returns its control,
+    // the image data is left in `self.data`
+    fn read_frame(&mut self) -> ImageResult<Option<FrameControl>> {
+        let mut chunk = Vec::new();
+        loop {
+            let (length, kind) = read_chunk_header(&mut self.stream)?;
+            chunk.clear();
+            read_chunk_data(&mut self.stream, length, &mut chunk)?;
+            let expected = BigEndian::read_u32(&chunk[length as usize..]);
+            let found = crc32(&kind, &chunk[..length as usize]);
+            if expected != found {
+                let kind = DecodingErrorKind::ChecksumMismatch { expected, found };
+                return Err(DecodingError::new(ImageFormat::PNG, kind).into())
+            }
+            let data = &chunk[..length as usize];
+            match &kind {
+                // The default image is only part of the animation if it has a fcTL chunk
+                b"IDAT" => if self.control.is_some() {
+                    self.data.extend_from_slice(data)
+                },
+                b"fdAT" => {
+                    if data.len() < 4 || self.control.is_none() {
+                        return Err(ImageError::malformed(ImageFormat::PNG, "invalid fdAT chunk"))
+                    }
+                    self.check_sequence(BigEndian::read_u32(data))?;
+                    self.data.extend_from_slice(&data[4..])
+                },
+                b"fcTL" => {
+                    let control = FrameControl::parse(data)?;
+                    if let Some(current) = self.start_frame(control)? {
+                        return Ok(Some(current))
+                    }
+                },
+                b"IEND" => {
+                    self.done = true;
+                    return Ok(self.control.take())
+                },
+                _ => (),
+            }
+        }
+    }
+
+    // Checks the control of the next frame and returns the control of the current one
+    fn start_frame(&mut self, control: FrameControl) -> ImageResult<Option<FrameControl>> {
+        self.check_sequence(control.sequence)?;
+        let (width, height) = (BigEndian::read_u32(&self.ihdr[0..]), BigEndian::read_u32(&self.ihdr[4..]));
+        let fits = |offset: u32, size: u32, canvas: u32| match offset.checked_add(size) {
+            Some(end) => size > 0 && end <= canvas,
+            None => false,
+        };
+        if !fits(control.left, control.width, width) || !fits(control.top, control.height, height) {
+            return Err(ImageError::malformed(ImageFormat::PNG, "frame outside of the canvas"))
+        }
+        Ok(mem::replace(&mut self.control, Some(control)))
+    }
+
+    // The fcTL and fdAT chunks are numbered consecutively, starting at 0
+    fn check_sequence(&mut self, sequence: u32) -> ImageResult<()> {
+        if sequence != self.sequence {
+            return Err(ImageError::malformed(ImageFormat::PNG, format!(
+                "sequence number {} out of order, expected {}", sequence, self.sequence
+            )))
+        }
+        self.sequence = self.sequence.wrapping_add(1);
+        Ok(())
+    }
+
+    fn decode_frame(&mut self, control: FrameControl) -> ImageResult<Frame> {
+        let data = mem::replace(&mut self.data, Vec::new());
+        let mut ihdr = self.ihdr.clone();
+        BigEndian::write_u32(&mut ihdr[0..], control.width);
+        BigEndian::write_u32(&mut ihdr[4..], control.height);
+
+        let mut png = SIGNATURE.to_vec();
+        write_chunk(&mut png, b"IHDR", &ihdr);
+        png.extend_from_slice(&self.ancillary);
+        write_chunk(&mut png, b"IDAT", &data);
+        write_chunk(&mut png, b"IEND", &[]);
+        let image = decoder_to_image(PNGDecoder::new(Cursor::new(png)))?;
+
+        let mut frame = Frame::from_image(image);
+        frame.set_offset(control.left, control.top);
+        frame.set_delay(control.delay);
+        frame.set_blend(control.blend);
+        // There is nothing to restore for the first frame, it is cleared instead
+        frame.set_disposal(match control.disposal {
+            DisposalMethod::Previous if self.first => DisposalMethod::Background,
+            disposal => disposal,
+        });
+        self.first = false;
+        Ok(frame)
+    }
+}
+
+impl<R: Read> Iterator for ApngFrames<R> {
+    type Item = ImageResult<Frame>;
+
+    fn next(&mut self) -> Option<ImageResult<Frame>> {
+        if self.done {
+            return None
+        }
+        let result = match self.read_frame() {
+            Ok(Some(control)) => self.decode_frame(control),
+            Ok(None) => return None,
+            Err(err) => Err(err),
+        };
+        if result.is_err() {
+            self.done = true;
+        }
+        Some(result)
+    }
+
+    fn nth(&mut self, n: usize) -> Option<ImageResult<Frame>> {
+        // The data of skipped frames is not decompressed
+        for _ in 0..n {
+            if self.done {
+                return None
+            }
+            match self.read_frame() {
+                Ok(Some(_)) => {
+                    self.data.clear();
+                    self.first = false;
+                },
+                Ok(None) => return None,
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err))
+                }
+            }
+        }
+        self.next()
+    }
+}
+
+// Follows the chunks which are read by the png crate, starting in the first IDAT chunk,
+// and keeps the ancillary chunks which follow the image data as (type, data)
+struct ChunkTap<R> {
+    inner: R,
+    // The bytes left of the data and the CRC of the current chunk
+    remaining: u64,
+    // The header of the next chunk, while it is incomplete
+    header: Vec<u8>,
+    // The chunk which is kept, while its data and CRC are incomplete
+    current: Option<([u8; 4], Vec<u8>)>,
+    trailing: Vec<([u8; 4], Vec<u8>)>,
+    // Set at the end of the file or at the frames of an animation, which are not kept
+    done: bool,
+}
+
+impl<R: Read> ChunkTap<R> {
+    fn new(inner: R, idat_length: u32) -> ChunkTap<R> {
+        ChunkTap {
+            inner,
+            remaining: u64::from(idat_length) + 4,
+            header: Vec::new(),
+            current: None,
+            trailing: Vec::new(),
+            done: false,
+        }
+    }
+
+    fn follow(&mut self, mut data: &[u8]) {
+        while !data.is_empty() && !self.done {
+            if self.remaining > 0 {
+                let n = cmp::min(self.remaining, data.len() as u64) as usize;
+                if let Some((_, ref mut chunk)) = self.current {
+                    chunk.extend_from_slice(&data[..n]);
+                }
+                data = &data[n..];
+                self.remaining -= n as u64;
+                if self.remaining == 0 {
+                    if let Some((kind, mut chunk)) = self.current.take() {
+                        // Without the CRC
+                        chunk.truncate(chunk.len() - 4);
+                        self.trailing.push((kind, chunk));
+                    }
+                }
+                continue
+            }
+            let n = cmp::min(8 - self.header.len(), data.len());
+            self.header.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if self.header.len() == 8 {
+                let mut kind = [0; 4];
+                kind.copy_from_slice(&self.header[4..]);
+                self.remaining = u64::from(BigEndian::read_u32(&self.header)) + 4;
+                self.header.clear();
+                match &kind {
+                    b"IEND" | b"fcTL" | b"fdAT" => self.done = true,
+                    b"IDAT" => (),
+                    _ => if kind[0].is_ascii_lowercase() {
+                        self.current = Some((kind, Vec::new()));
+                    },
+                }
+            }
+        }
+    }
+
+    // Reads the chunks which were not read by the png crate
+    fn finish(&mut self) -> io::Result<()> {
+        let mut buf = [0; 4096];
+        while !self.done {
+            let n = self.inner.read(&mut buf)?;
+            if n == 0 {
+                break
+            }
+            self.follow(&buf[..n]);
+        }
+        Ok(())
+    }
+}
+
+// The stream passed to the png crate, the decoder keeps another reference to the tap
+struct SharedTap<R>(Arc<Mutex<ChunkTap<R>>>);
+
+impl<R: Read> Read for SharedTap<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut tap = self.0.lock().unwrap();
+        let n = tap.inner.read(buf)?;
+        tap.follow(&buf[..n]);
+        Ok(n)
+    }
+}
+
+fn chunk_error(message: &str) -> DecodingError {
+    DecodingError::new(ImageFormat::PNG, DecodingErrorKind::Malformed(message.into()))
+}
+
+fn read_chunk_header<R: Read>(r: &mut R) -> ImageResult<(u32, [u8; 4])> {
+    let length = r.read_u32::<BigEndian>()?;
+    let mut kind = [0; 4];
+    r.read_exact(&mut kind)?;
+    Ok((length, kind))
+}
+
+// Appends the chunk data and the CRC to `buf`
+fn read_chunk_data<R: Read>(r: &mut R, length: u32, buf: &mut Vec<u8>) -> ImageResult<()> {
+    let expected = u64::from(length) + 4;
+    if r.take(expected).read_to_end(buf)? as u64 != expected {
+        return Err(ImageError::NotEnoughData)
+    }
+    Ok(())
+}
+
+fn write_chunk(buf: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    let start = buf.len();
+    buf.extend_from_slice(&[0; 4]);
+    BigEndian::write_u32(&mut buf[start..], data.len() as u32);
+    buf.extend_from_slice(kind);
+    buf.extend_from_slice(data);
+    let crc = crc32(kind, data);
+    let start = buf.len();
+    buf.extend_from_slice(&[0; 4]);
+    BigEndian::write_u32(&mut buf[start..], crc);
+}
+
+// The CRC of each byte value, for the polynomial 0xEDB8_8320 of the PNG specification
+static CRC_TABLE: [u32; 256] = [
+    0x0000_0000, 0x7707_3096, 0xEE0E_612C, 0x9909_51BA, 0x076D_C419, 0x706A_F48F,
+    0xE963_A535, 0x9E64_95A3, 0x0EDB_8832, 0x79DC_B8A4, 0xE0D5_E91E, 0x97D2_D988,
+    0x09B6_4C2B, 0x7EB1_7CBD, 0xE7B8_2D07, 0x90BF_1D91, 0x1DB7_1064, 0x6AB0_20F2,
+    0xF3B9_7148, 0x84BE_41DE, 0x1ADA_D47D, 0x6DDD_E4EB, 0xF4D4_B551, 0x83D3_85C7,
+    0x136C_9856, 0x646B_A8C0, 0xFD62_F97A, 0x8A65_C9EC, 0x1401_5C4F, 0x6306_6CD9,
+    0xFA0F_3D63, 0x8D08_0DF5, 0x3B6E_20C8, 0x4C69_105E, 0xD560_41E4, 0xA267_7172,
+    0x3C03_E4D1, 0x4B04_D447, 0xD20D_85FD, 0xA50A_B56B, 0x35B5_A8FA, 0x42B2_986C,
+    0xDBBB_C9D6, 0xACBC_F940, 0x32D8_6CE3, 0x45DF_5C75, 0xDCD6_0DCF, 0xABD1_3D59,
+    0x26D9_30AC, 0x51DE_003A, 0xC8D7_5180, 0xBFD0_6116, 0x21B4_F4B5, 0x56B3_C423,
+    0xCFBA_9599, 0xB8BD_A50F, 0x2802_B89E, 0x5F05_8808, 0xC60C_D9B2, 0xB10B_E924,
+    0x2F6F_7C87, 0x5868_4C11, 0xC161_1DAB, 0xB666_2D3D, 0x76DC_4190, 0x01DB_7106,
+    0x98D2_20BC, 0xEFD5_102A, 0x71B1_8589, 0x06B6_B51F, 0x9FBF_E4A5, 0xE8B8_D433,
+    0x7807_C9A2, 0x0F00_F934, 0x9609_A88E, 0xE10E_9818, 0x7F6A_0DBB, 0x086D_3D2D,
+    0x9164_6C97, 0xE663_5C01, 0x6B6B_51F4, 0x1C6C_6162, 0x8565_30D8, 0xF262_004E,
+    0x6C06_95ED, 0x1B01_A57B, 0x8208_F4C1, 0xF50F_C457, 0x65B0_D9C6, 0x12B7_E950,
+    0x8BBE_B8EA, 0xFCB9_887C, 0x62DD_1DDF, 0x15DA_2D49, 0x8CD3_7CF3, 0xFBD4_4C65,
+    0x4DB2_6158, 0x3AB5_51CE, 0xA3BC_0074, 0xD4BB_30E2, 0x4ADF_A541, 0x3DD8_95D7,
+    0xA4D1_C46D, 0xD3D6_F4FB, 0x4369_E96A, 0x346E_D9FC, 0xAD67_8846, 0xDA60_B8D0,
+    0x4404_2D73, 0x3303_1DE5, 0xAA0A_4C5F, 0xDD0D_7CC9, 0x5005_713C, 0x2702_41AA,
+    0xBE0B_1010, 0xC90C_2086, 0x5768_B525, 0x206F_85B3, 0xB966_D409, 0xCE61_E49F,
+    0x5EDE_F90E, 0x29D9_C998, 0xB0D0_9822, 0xC7D7_A8B4, 0x59B3_3D17, 0x2EB4_0D81,
+    0xB7BD_5C3B, 0xC0BA_6CAD, 0xEDB8_8320, 0x9ABF_B3B6, 0x03B6_E20C, 0x74B1_D29A,
+    0xEAD5_4739, 0x9DD2_77AF, 0x04DB_2615, 0x73DC_1683, 0xE363_0B12, 0x9464_3B84,
+    0x0D6D_6A3E, 0x7A6A_5AA8, 0xE40E_CF0B, 0x9309_FF9D, 0x0A00_AE27, 0x7D07_9EB1,
+    0xF00F_9344, 0x8708_A3D2, 0x1E01_F268, 0x6906_C2FE, 0xF762_575D, 0x8065_67CB,
+    0x196C_3671, 0x6E6B_06E7, 0xFED4_1B76, 0x89D3_2BE0, 0x10DA_7A5A, 0x67DD_4ACC,
+    0xF9B9_DF6F, 0x8EBE_EFF9, 0x17B7_BE43, 0x60B0_8ED5, 0xD6D6_A3E8, 0xA1D1_937E,
+    0x38D8_C2C4, 0x4FDF_F252, 0xD1BB_67F1, 0xA6BC_5767, 0x3FB5_06DD, 0x48B2_364B,
+    0xD80D_2BDA, 0xAF0A_1B4C, 0x3603_4AF6, 0x4104_7A60, 0xDF60_EFC3, 0xA867_DF55,
+    0x316E_8EEF, 0x4669_BE79, 0xCB61_B38C, 0xBC66_831A, 0x256F_D2A0, 0x5268_E236,
+    0xCC0C_7795, 0xBB0B_4703, 0x2202_16B9, 0x5505_262F, 0xC5BA_3BBE, 0xB2BD_0B28,
+    0x2BB4_5A92, 0x5CB3_6A04, 0xC2D7_FFA7, 0xB5D0_CF31, 0x2CD9_9E8B, 0x5BDE_AE1D,
+    0x9B64_C2B0, 0xEC63_F226, 0x756A_A39C, 0x026D_930A, 0x9C09_06A9, 0xEB0E_363F,
+    0x7207_6785, 0x0500_5713, 0x95BF_4A82, 0xE2B8_7A14, 0x7BB1_2BAE, 0x0CB6_1B38,
+    0x92D2_8E9B, 0xE5D5_BE0D, 0x7CDC_EFB7, 0x0BDB_DF21, 0x86D3_D2D4, 0xF1D4_E242,
+    0x68DD_B3F8, 0x1FDA_836E, 0x81BE_16CD, 0xF6B9_265B, 0x6FB0_77E1, 0x18B7_4777,
+    0x8808_5AE6, 0xFF0F_6A70, 0x6606_3BCA, 0x1101_0B5C, 0x8F65_9EFF, 0xF862_AE69,
+    0x616B_FFD3, 0x166C_CF45, 0xA00A_E278, 0xD70D_D2EE, 0x4E04_8354, 0x3903_B3C2,
+    0xA767_2661, 0xD060_16F7, 0x4969_474D, 0x3E6E_77DB, 0xAED1_6A4A, 0xD9D6_5ADC,
+    0x40DF_0B66, 0x37D8_3BF0, 0xA9BC_AE53, 0xDEBB_9EC5, 0x47B2_CF7F, 0x30B5_FFE9,
+    0xBDBD_F21C, 0xCABA_C28A, 0x53B3_9330, 0x24B4_A3A6, 0xBAD0_3605, 0xCDD7_0693,
+    0x54DE_5729, 0x23D9_67BF, 0xB366_7A2E, 0xC461_4AB8, 0x5D68_1B02, 0x2A6F_2B94,
+    0xB40B_BE37, 0xC30C_8EA1, 0x5A05_DF1B, 0x2D02_EF8D,
+];
+
+fn crc32(kind: &[u8], data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in kind.iter().chain(data) {
+        crc = CRC_TABLE[((crc ^ u32::from(byte)) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// An ancillary PNG chunk which is not interpreted by this crate
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnknownChunk {
+    /// The chunk type, four ASCII letters
+    pub kind: [u8; 4],
+    /// The chunk data, without the length and the CRC
+    pub data: Vec<u8>,
+    /// Whether the chunk is stored in front of or behind the image data
+    pub position: ChunkPosition,
+}
+
+/// The position of an ```UnknownChunk``` relative to the image data
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChunkPosition {
+    /// Between the header and the IDAT chunks
+    BeforeImageData,
+    /// Between the IDAT chunks and the end of the file
+    AfterImageData,
+}
+
+impl UnknownChunk {
+    /// Returns true if the chunk may be copied to an image whose pixels were changed,
+    /// e.g. by cropping or resizing it. Other chunks may depend on the image data.
+    pub fn is_safe_to_copy(&self) -> bool {
+        self.kind[3].is_ascii_lowercase()
+    }
+}
+
+// The keyword of the iTXt chunk which stores the XMP packet
+const XMP_KEYWORD: &str = "XML:com.adobe.xmp";
+
+// Compressed text and ICC profiles are not decompressed beyond this size
+const MAX_TEXT_SIZE: usize = 1 << 24;
+const MAX_ICC_PROFILE_SIZE: usize = 1 << 24;
+
+/// A textual chunk of a PNG image, a key-value pair such as the author of the image
+///
+/// The keywords defined by the specification are ```Title```, ```Author```,
+/// ```Description```, ```Copyright```, ```Creation Time```, ```Software```,
+/// ```Disclaimer```, ```Warning```, ```Source``` and ```Comment```.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TextChunk {
+    /// The keyword, 1 to 79 Latin-1 characters
+    pub keyword: String,
+    /// The text
+    pub text: String,
+    /// If the text is compressed, i.e. stored in a zTXt chunk or a compressed iTXt chunk
+    pub compressed: bool,
+    /// The language of the text, only stored in iTXt chunks
+    pub language_tag: Option<String>,
+    /// The keyword translated into the language of the text, only stored in iTXt chunks
+    pub translated_keyword: Option<String>,
+}
+
+impl TextChunk {
+    /// Creates an uncompressed text chunk
+    pub fn new(keyword: String, text: String) -> TextChunk {
+        TextChunk {
+            keyword,
+            text,
+            compressed: false,
+            language_tag: None,
+            translated_keyword: None,
+        }
+    }
+
+    fn parse(kind: &[u8; 4], data: &[u8]) -> ImageResult<TextChunk> {
+        let invalid = || chunk_error(&format!("invalid {} chunk", String::from_utf8_lossy(kind)));
+        let mut fields = data.splitn(2, |&b| b == 0);
+        let keyword = decode_latin1(fields.next().unwrap());
+        let rest = fields.next().ok_or_else(invalid)?;
+        let mut chunk = TextChunk::new(keyword, String::new());
+
+        match kind {
+            b"tEXt" => chunk.text = decode_latin1(rest),
+            b"zTXt" => {
+                // The compression method is always 0, zlib
+                if rest.first() != Some(&0) {
+                    return Err(invalid().into())
+                }
+                chunk.compressed = true;
+                chunk.text = decode_latin1(&decompress(&rest[1..], MAX_TEXT_SIZE)?);
+            }
+            _ => {
+                if rest.len() < 2 || rest[1] != 0 {
+                    return Err(invalid().into())
+                }
+                chunk.compressed = rest[0] == 1;
+                let mut fields = rest[2..].splitn(3, |&b| b == 0);
+                let language_tag = fields.next().unwrap();
+                let translated_keyword = fields.next().ok_or_else(invalid)?;
+                let text = fields.next().ok_or_else(invalid)?;
+                let text = if chunk.compressed { decompress(text, MAX_TEXT_SIZE)? } else { text.to_vec() };
+                let utf8 = |bytes: Vec<u8>| String::from_utf8(bytes).map_err(|err| invalid().with_source(err));
+                chunk.language_tag = Some(utf8(language_tag.to_vec())?);
+                chunk.translated_keyword = Some(utf8(translated_keyword.to_vec())?);
+                chunk.text = utf8(text)?;
+            }
+        }
+        Ok(chunk)
+    }
+
+    // Returns the chunk type and data. Text which can not be stored as Latin-1 is written as iTXt.
+    fn build(&self) -> io::Result<([u8; 4], Vec<u8>)> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidInput, message);
+        let keyword = match encode_latin1(&self.keyword) {
+            Some(ref keyword) if !keyword.is_empty() && keyword.len() < 80 && !keyword.contains(&0) => keyword.clone(),
+            _ => return Err(invalid("text chunk keywords must be 1 to 79 Latin-1 characters")),
+        };
+        let latin1 = encode_latin1(&self.text);
+
+        let mut data = keyword;
+        data.push(0);
+        match latin1 {
+            Some(text) if self.language_tag.is_none() && self.translated_keyword.is_none() => {
+                if self.compressed {
+                    data.push(0);
+                    data.extend_from_slice(&deflate::deflate_bytes_zlib(&text));
+                    Ok((*b"zTXt", data))
+                } else {
+                    data.extend_from_slice(&text);
+                    Ok((*b"tEXt", data))
+                }
+            }
+            _ => {
+                data.extend_from_slice(&[self.compressed as u8, 0]);
+                for field in &[&self.language_tag, &self.translated_keyword] {
+                    let field = field.as_ref().map_or("", |f| &f[..]);
+                    if field.contains('\0') {
+                        return Err(invalid("iTXt fields must not contain null characters"))
+                    }
+                    data.extend_from_slice(field.as_bytes());
+                    data.push(0);
+                }
+                if self.compressed {
+                    data.extend_from_slice(&deflate::deflate_bytes_zlib(self.text.as_bytes()));
+                } else {
+                    data.extend_from_slice(self.text.as_bytes());
+                }
+                Ok((*b"iTXt", data))
+            }
+        }
+    }
+}
+
+fn decode_latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+// Returns None if ```text``` has characters which are not part of Latin-1
+fn encode_latin1(text: &str) -> Option<Vec<u8>> {
+    text.chars().map(|c| if (c as u32) < 256 { Some(c as u8) } else { None }).collect()
+}
+
+// Decompresses the zlib stream of zTXt, iTXt and iCCP chunks
+fn decompress(data: &[u8], limit: usize) -> ImageResult<Vec<u8>> {
+    let mut stream = inflate::InflateStream::from_zlib();
+    let mut output = Vec::new();
+    let mut position = 0;
+    while position < data.len() {
+        let (read, decompressed) = stream.update(&data[position..])
+            .map_err(|err| ImageError::malformed(ImageFormat::PNG, err))?;
+        position += read;
+        output.extend_from_slice(decompressed);
+        if output.len() > limit {
+            return Err(ImageError::malformed(ImageFormat::PNG, "compressed chunk is too large"))
+        }
+    }
+    // The stream only ends with the checksum of the data if it is complete
+    if data.len() < 4 || stream.current_checksum() != BigEndian::read_u32(&data[data.len() - 4..]) {
+        return Err(ImageError::malformed(ImageFormat::PNG, "compressed chunk is truncated"))
+    }
+    Ok(output)
+}
+
+// Returns the profile of an iCCP chunk, it is stored after its name and the compression method
+fn parse_icc_chunk(data: &[u8]) -> ImageResult<Vec<u8>> {
+    match data.iter().position(|&b| b == 0) {
+        Some(end) if data.get(end + 1) == Some(&0) => decompress(&data[end + 2..], MAX_ICC_PROFILE_SIZE),
+        _ => Err(ImageError::malformed(ImageFormat::PNG, "invalid iCCP chunk")),
+    }
+}
+
+fn build_icc_chunk(profile: &[u8]) -> Vec<u8> {
+    let mut data = b"ICC profile\0\0".to_vec();
+    data.extend_from_slice(&deflate::deflate_bytes_zlib(profile));
+    data
+}
+
+fn build_physical_chunk(density: PixelDensity) -> Vec<u8> {
+    let (meter, unit) = match density.unit {
+        PixelDensityUnit::None => (1.0, 0),
+        PixelDensityUnit::Inch => (1.0 / 0.0254, 1),
+        PixelDensityUnit::Centimeter => (100.0, 1),
+        PixelDensityUnit::Meter => (1.0, 1),
+    };
+    let mut data = vec![0; 9];
+    BigEndian::write_u32(&mut data[0..], (density.x * meter).round() as u32);
+    BigEndian::write_u32(&mut data[4..], (density.y * meter).round() as u32);
+    data[8] = unit;
+    data
 }
 
 /// PNG encoder
 pub struct PNGEncoder<W: Write> {
-    w: W
+    w: W,
+    text: Vec<TextChunk>,
+    density: Option<PixelDensity>,
+    xmp: Option<Vec<u8>>,
+    icc_profile: Option<Vec<u8>>,
+    unknown: Vec<UnknownChunk>,
 }
 
 impl<W: Write> PNGEncoder<W> {
     /// Create a new encoder that writes its output to ```w```
     pub fn new(w: W) -> PNGEncoder<W> {
         PNGEncoder {
-            w: w
+            w,
+            text: Vec::new(),
+            density: None,
+            xmp: None,
+            icc_profile: None,
+            unknown: Vec::new(),
         }
     }
 
+    /// Adds a chunk returned by ```PNGDecoder::unknown_chunks```. The chunks are
+    /// written in the order they were added, in front of or behind the image data
+    /// as their ```position``` demands.
+    ///
+    /// Only ancillary chunks can be added. Chunks which are not safe to copy
+    /// should be dropped if the pixels were changed.
+    pub fn add_unknown_chunk(&mut self, chunk: UnknownChunk) {
+        self.unknown.push(chunk);
+    }
+
+    /// Embeds an ICC profile, which describes the color space of the pixels
+    pub fn set_icc_profile(&mut self, profile: Vec<u8>) {
+        self.icc_profile = Some(profile);
+    }
+
+    /// Embeds an XMP packet, e.g. the ```xmp``` field of the ```Metadata``` of a
+    /// decoded image. It is stored as an iTXt chunk and has to be valid UTF-8.
+    pub fn set_xmp(&mut self, xmp: Vec<u8>) {
+        self.xmp = Some(xmp);
+    }
+
+    /// Sets the physical resolution which is stored in a pHYs chunk.
+    ///
+    /// The density is converted to whole pixels per meter.
+    pub fn set_pixel_density(&mut self, density: PixelDensity) {
+        self.density = Some(density);
+    }
+
+    /// Adds a textual chunk, which is written in front of the image data
+    pub fn add_text_chunk(&mut self, chunk: TextChunk) {
+        self.text.push(chunk);
+    }
+
     /// Encodes the image ```image```
     /// that has dimensions ```width``` and ```height```
     /// and ```ColorType``` ```c```
     pub fn encode(self, data: &[u8], width: u32, height: u32, color: ColorType) -> io::Result<()> {
+        let mut text = self.text;
+        if let Some(xmp) = self.xmp {
+            let xmp = String::from_utf8(xmp)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "XMP packets must be UTF-8"))?;
+            let mut chunk = TextChunk::new(XMP_KEYWORD.to_string(), xmp);
+            chunk.language_tag = Some(String::new());
+            chunk.translated_keyword = Some(String::new());
+            text.push(chunk);
+        }
+        let chunks = text.iter().map(TextChunk::build).collect::<io::Result<Vec<_>>>()?;
+        for chunk in &self.unknown {
+            if !chunk.kind.iter().all(u8::is_ascii_alphabetic) || !chunk.kind[0].is_ascii_lowercase() {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "only ancillary chunks can be added"))
+            }
+        }
+
         let (ct, bits) = color.into();
         let mut encoder = png::Encoder::new(self.w, width, height);
         encoder.set(ct).set(bits);
         let mut writer = try!(encoder.write_header());
-        writer.write_image_data(data).map_err(|e| e.into())
+        if let Some(profile) = self.icc_profile {
+            writer.write_chunk(*b"iCCP", &build_icc_chunk(&profile))?;
+        }
+        if let Some(density) = self.density {
+            writer.write_chunk(*b"pHYs", &build_physical_chunk(density))?;
+        }
+        for (kind, data) in chunks {
+            writer.write_chunk(kind, &data)?;
+        }
+        let (before, after): (Vec<_>, Vec<_>) = self.unknown.into_iter()
+            .partition(|chunk| chunk.position == ChunkPosition::BeforeImageData);
+        for chunk in before {
+            writer.write_chunk(chunk.kind, &chunk.data)?;
+        }
+        writer.write_image_data(data)?;
+        for chunk in after {
+            writer.write_chunk(chunk.kind, &chunk.data)?;
+        }
+        Ok(())
     }
 }
 
@@ -142,13 +1140,463 @@ impl From<ColorType> for (png::ColorType, png::BitDepth) {
 impl From<png::DecodingError> for ImageError {
     fn from(err: png::DecodingError) -> ImageError {
         use self::png::DecodingError::*;
-        match err {
-            IoError(err) => ImageError::IoError(err),
-            Format(desc) => ImageError::FormatError(desc.into_owned()),
-            InvalidSignature => ImageError::FormatError("invalid signature".into()),
-            CrcMismatch { .. } => ImageError::FormatError("CRC error".into()),
-            Other(desc) => ImageError::FormatError(desc.into_owned()),
-            CorruptFlateStream => ImageError::FormatError("compressed data stream corrupted".into())
+        let kind = match err {
+            IoError(err) => return ImageError::IoError(err),
+            Format(desc) | Other(desc) => DecodingErrorKind::Malformed(desc.into_owned()),
+            InvalidSignature => DecodingErrorKind::InvalidSignature,
+            CrcMismatch { crc_val, crc_sum, .. } => {
+                DecodingErrorKind::ChecksumMismatch { expected: crc_val, found: crc_sum }
+            }
+            CorruptFlateStream => DecodingErrorKind::Malformed("compressed data stream corrupted".into()),
+        };
+        DecodingError::new(ImageFormat::PNG, kind).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decompress, write_chunk, ChunkPosition, PNGDecoder, PNGEncoder, TextChunk, UnknownChunk, SIGNATURE};
+    use byteorder::{BigEndian, ByteOrder};
+    use color::ColorType;
+    use image::{DecodingErrorKind, GenericImage, ImageDecoder, ImageError, ImageFormat};
+    use num_rational::Ratio;
+
+    // Encodes an RGBA image and returns its IHDR and image data
+    fn encode(width: u32, height: u32, pixel: [u8; 4]) -> (Vec<u8>, Vec<u8>) {
+        let mut png = Vec::new();
+        let data = pixel.repeat((width * height) as usize);
+        PNGEncoder::new(&mut png).encode(&data, width, height, ColorType::RGBA(8)).unwrap();
+
+        let (mut ihdr, mut idat) = (Vec::new(), Vec::new());
+        let mut chunks = &png[SIGNATURE.len()..];
+        while !chunks.is_empty() {
+            let length = BigEndian::read_u32(chunks) as usize;
+            let data = &chunks[8..8 + length];
+            match &chunks[4..8] {
+                b"IHDR" => ihdr.extend_from_slice(data),
+                b"IDAT" => idat.extend_from_slice(data),
+                _ => (),
+            }
+            chunks = &chunks[12 + length..];
+        }
+        (ihdr, idat)
+    }
+
+    fn fctl(sequence: u32, (width, height): (u32, u32), (left, top): (u32, u32), dispose: u8) -> Vec<u8> {
+        let mut data = vec![0; 26];
+        for (i, &value) in [sequence, width, height, left, top].iter().enumerate() {
+            BigEndian::write_u32(&mut data[4 * i..], value);
         }
+        BigEndian::write_u16(&mut data[20..], 1);
+        BigEndian::write_u16(&mut data[22..], 10);
+        data[24] = dispose;
+        data[25] = 1;
+        data
+    }
+
+    // A 2x2 white default image which is the first frame, followed by a black
+    // pixel at (1, 1) which is disposed to the background and a red pixel at (0, 0)
+    fn apng() -> Vec<u8> {
+        let (ihdr, white) = encode(2, 2, [255, 255, 255, 255]);
+        let (_, black) = encode(1, 1, [0, 0, 0, 255]);
+        let (_, red) = encode(1, 1, [255, 0, 0, 255]);
+        let fdat = |sequence: u32, data: &[u8]| {
+            let mut fdat = vec![0; 4];
+            BigEndian::write_u32(&mut fdat, sequence);
+            fdat.extend_from_slice(data);
+            fdat
+        };
+
+        let mut png = SIGNATURE.to_vec();
+        write_chunk(&mut png, b"IHDR", &ihdr);
+        write_chunk(&mut png, b"acTL", &[0, 0, 0, 3, 0, 0, 0, 0]);
+        write_chunk(&mut png, b"fcTL", &fctl(0, (2, 2), (0, 0), 0));
+        write_chunk(&mut png, b"IDAT", &white);
+        write_chunk(&mut png, b"fcTL", &fctl(1, (1, 1), (1, 1), 1));
+        write_chunk(&mut png, b"fdAT", &fdat(2, &black));
+        write_chunk(&mut png, b"fcTL", &fctl(3, (1, 1), (0, 0), 0));
+        write_chunk(&mut png, b"fdAT", &fdat(4, &red));
+        write_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+
+    #[test]
+    fn test_read_image_into() {
+        use buffer::RgbaImage;
+        use image::{DecodingResult, ImageError};
+
+        let mut png = Vec::new();
+        let data: Vec<u8> = (0..4 * 3 * 2).collect();
+        PNGEncoder::new(&mut png).encode(&data, 3, 2, ColorType::RGBA(8)).unwrap();
+
+        let mut image = RgbaImage::new(3, 2);
+        PNGDecoder::new(&png[..]).read_image_into(&mut image).unwrap();
+        assert_eq!(image.into_raw(), data);
+        match PNGDecoder::new(&png[..]).read_image().unwrap() {
+            DecodingResult::U8(read) => assert_eq!(read, data),
+            DecodingResult::U16(_) => panic!("expected 8 bit data"),
+        }
+
+        let mut small = RgbaImage::new(3, 1);
+        match PNGDecoder::new(&png[..]).read_image_into(&mut small) {
+            Err(ImageError::DimensionError) => (),
+            other => panic!("expected a dimension error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_read_indexed() {
+        use std::fs::File;
+        use dynimage::open;
+
+        for name in &["tm3n3p02", "tbbn3p08"] {
+            let path = format!("tests/images/png/transparency/{}.png", name);
+            let indexed = PNGDecoder::new(File::open(&path).unwrap()).read_indexed().unwrap();
+            assert_eq!((indexed.width, indexed.height), (32, 32));
+            assert_eq!(indexed.to_rgba().into_raw(), open(&path).unwrap().to_rgba().into_raw());
+        }
+
+        let path = "tests/images/png/transparency/tp0n2c08.png";
+        match PNGDecoder::new(File::open(path).unwrap()).read_indexed() {
+            Err(ImageError::UnsupportedColor(ColorType::RGB(8))) => (),
+            other => panic!("expected an unsupported color error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_into_reader() {
+        use std::fs::File;
+        use std::io::Read;
+        use image::DecodingResult;
+
+        let mut png = Vec::new();
+        let data: Vec<u8> = (0..3 * 100 * 7).map(|i| i as u8).collect();
+        PNGEncoder::new(&mut png).encode(&data, 100, 7, ColorType::RGB(8)).unwrap();
+        assert_eq!(PNGDecoder::new(&png[..]).total_bytes().unwrap(), data.len() as u64);
+
+        // Read in chunks which do not line up with the rows
+        let mut reader = PNGDecoder::new(&png[..]).into_reader().unwrap();
+        let (mut read, mut chunk) = (Vec::new(), [0; 64]);
+        loop {
+            match reader.read(&mut chunk).unwrap() {
+                0 => break,
+                n => read.extend_from_slice(&chunk[..n]),
+            }
+        }
+        assert_eq!(read, data);
+
+        let path = "tests/images/png/interlaced/lenna_fragment_interlaced.png";
+        let expected = match PNGDecoder::new(File::open(path).unwrap()).read_image().unwrap() {
+            DecodingResult::U8(data) => data,
+            DecodingResult::U16(_) => panic!("expected 8 bit data"),
+        };
+        let mut decoder = PNGDecoder::new(File::open(path).unwrap());
+        assert_eq!(decoder.total_bytes().unwrap(), expected.len() as u64);
+        let mut read = Vec::new();
+        decoder.into_reader().unwrap().read_to_end(&mut read).unwrap();
+        assert_eq!(read, expected);
+    }
+
+    #[test]
+    fn test_text_chunks() {
+        let mut chunks = vec![
+            TextChunk::new("Author".to_string(), "Åsa".to_string()),
+            TextChunk::new("Source".to_string(), "https://example.com/".repeat(10)),
+            TextChunk::new("Title".to_string(), "Ελληνικά".to_string()),
+            TextChunk::new("Description".to_string(), "日本語".repeat(10)),
+        ];
+        chunks[1].compressed = true;
+        chunks[2].language_tag = Some("el".to_string());
+        chunks[2].translated_keyword = Some("Τίτλος".to_string());
+        chunks[3].compressed = true;
+
+        let mut png = Vec::new();
+        {
+            let mut encoder = PNGEncoder::new(&mut png);
+            for chunk in &chunks {
+                encoder.add_text_chunk(chunk.clone());
+            }
+            encoder.encode(&[1, 2, 3], 1, 1, ColorType::RGB(8)).unwrap();
+        }
+        for kind in &[b"tEXt", b"zTXt", b"iTXt"] {
+            assert!(png.windows(4).any(|w| w == &kind[..]));
+        }
+
+        // The text chunks do not affect decoding the image
+        let mut decoder = PNGDecoder::new(&png[..]);
+        chunks[3].language_tag = Some(String::new());
+        chunks[3].translated_keyword = Some(String::new());
+        assert_eq!(decoder.text_chunks().unwrap(), chunks);
+        match decoder.read_image().unwrap() {
+            ::image::DecodingResult::U8(data) => assert_eq!(data, [1, 2, 3]),
+            ::image::DecodingResult::U16(_) => panic!("expected 8 bit data"),
+        }
+        assert_eq!(decoder.text_chunks().unwrap(), chunks);
+
+        let mut encoder = PNGEncoder::new(Vec::new());
+        encoder.add_text_chunk(TextChunk::new(String::new(), "text".to_string()));
+        assert!(encoder.encode(&[1, 2, 3], 1, 1, ColorType::RGB(8)).is_err());
+    }
+
+    #[test]
+    fn test_pixel_density() {
+        use metadata::{PixelDensity, PixelDensityUnit};
+
+        let mut png = Vec::new();
+        {
+            let mut encoder = PNGEncoder::new(&mut png);
+            encoder.set_pixel_density(PixelDensity::dpi(72.0, 300.0));
+            encoder.encode(&[1, 2, 3], 1, 1, ColorType::RGB(8)).unwrap();
+        }
+        let metadata = PNGDecoder::new(&png[..]).metadata().unwrap();
+        assert_eq!(metadata.xmp, None);
+        let density = metadata.density.unwrap();
+        assert_eq!(density, PixelDensity { x: 2835.0, y: 11811.0, unit: PixelDensityUnit::Meter });
+        let (x, y) = density.to_dpi().unwrap();
+        assert_eq!((x.round(), y.round()), (72.0, 300.0));
+    }
+
+    #[test]
+    fn test_xmp() {
+        let xmp = "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><dc:title>Ελληνικά</dc:title></x:xmpmeta>";
+        let mut png = Vec::new();
+        {
+            let mut encoder = PNGEncoder::new(&mut png);
+            encoder.set_xmp(xmp.as_bytes().to_vec());
+            encoder.encode(&[1, 2, 3], 1, 1, ColorType::RGB(8)).unwrap();
+        }
+        let metadata = PNGDecoder::new(&png[..]).metadata().unwrap();
+        assert_eq!(metadata.xmp, Some(xmp.as_bytes().to_vec()));
+
+        let mut encoder = PNGEncoder::new(Vec::new());
+        encoder.set_xmp(vec![0xFF]);
+        assert!(encoder.encode(&[1, 2, 3], 1, 1, ColorType::RGB(8)).is_err());
+    }
+
+    #[test]
+    fn test_icc_profile() {
+        let profile: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+        let mut png = Vec::new();
+        {
+            let mut encoder = PNGEncoder::new(&mut png);
+            encoder.set_icc_profile(profile.clone());
+            encoder.encode(&[1, 2, 3], 1, 1, ColorType::RGB(8)).unwrap();
+        }
+        let mut decoder = PNGDecoder::new(&png[..]);
+        assert_eq!(decoder.metadata().unwrap().icc_profile, Some(profile));
+        match decoder.read_image().unwrap() {
+            ::image::DecodingResult::U8(data) => assert_eq!(data, [1, 2, 3]),
+            ::image::DecodingResult::U16(_) => panic!("expected 8 bit data"),
+        }
+    }
+
+    #[test]
+    fn test_truncated_compressed_data() {
+        let compressed = super::deflate::deflate_bytes_zlib(&[7; 1000]);
+        assert_eq!(decompress(&compressed, 1000).unwrap(), vec![7; 1000]);
+        for &length in &[0, 2, compressed.len() / 2, compressed.len() - 1] {
+            match decompress(&compressed[..length], 1000) {
+                Err(ImageError::Decoding(ref err)) if err.format() == ImageFormat::PNG => (),
+                other => panic!("expected a decoding error, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_unknown_chunks() {
+        use image::ImageDecoder;
+
+        let chunk = |kind: &[u8; 4], data: &[u8], position| UnknownChunk { kind: *kind, data: data.to_vec(), position };
+        let chunks = vec![
+            chunk(b"gAMA", &[0, 0, 0xB1, 0x8F], ChunkPosition::BeforeImageData),
+            chunk(b"prVt", b"private data", ChunkPosition::BeforeImageData),
+            chunk(b"sRGB", &[0], ChunkPosition::BeforeImageData),
+            chunk(b"tIME", &[7, 226, 1, 2, 3, 4, 5], ChunkPosition::AfterImageData),
+            chunk(b"prVt", b"trailing data", ChunkPosition::AfterImageData),
+        ];
+        let mut png = Vec::new();
+        {
+            let mut encoder = PNGEncoder::new(&mut png);
+            encoder.add_text_chunk(TextChunk::new("Software".to_string(), "image".to_string()));
+            // The chunks are sorted by their position
+            for chunk in chunks.iter().rev() {
+                encoder.add_unknown_chunk(chunk.clone());
+            }
+            encoder.encode(&[1, 2, 3], 1, 1, ColorType::RGB(8)).unwrap();
+        }
+        let position = |kind: &[u8]| png.windows(4).position(|w| w == kind).unwrap();
+        assert!(position(b"gAMA") < position(b"IDAT") && position(b"tIME") > position(b"IDAT"));
+
+        let mut decoder = PNGDecoder::new(&png[..]);
+        let mut before = chunks[..3].to_vec();
+        before.reverse();
+        assert_eq!(decoder.unknown_chunks().unwrap(), &before[..]);
+        let safe: Vec<bool> = chunks.iter().map(UnknownChunk::is_safe_to_copy).collect();
+        assert_eq!(safe, [false, true, false, false, true]);
+        assert_eq!(decoder.text_chunks().unwrap().len(), 1);
+
+        // The chunks behind the image data are read with it
+        decoder.read_image().unwrap();
+        let mut after = chunks[3..].to_vec();
+        after.reverse();
+        assert_eq!(decoder.unknown_chunks().unwrap(), &[before, after].concat()[..]);
+
+        for kind in &[b"IDAT", b"a b!"] {
+            let mut encoder = PNGEncoder::new(Vec::new());
+            encoder.add_unknown_chunk(chunk(kind, &[], ChunkPosition::BeforeImageData));
+            assert!(encoder.encode(&[1, 2, 3], 1, 1, ColorType::RGB(8)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_trailing_text_chunks() {
+        use image::ImageDecoder;
+
+        // A text chunk between the image data and the end of the file
+        let mut png = Vec::new();
+        PNGEncoder::new(&mut png).encode(&[1, 2, 3], 1, 1, ColorType::RGB(8)).unwrap();
+        let iend = png.split_off(png.len() - 12);
+        write_chunk(&mut png, b"tEXt", b"Comment\0trailing");
+        png.extend_from_slice(&iend);
+
+        let mut decoder = PNGDecoder::new(&png[..]);
+        assert!(decoder.text_chunks().unwrap().is_empty());
+        let mut image = [0; 3];
+        decoder.read_image_into(&mut image).unwrap();
+        assert_eq!(image, [1, 2, 3]);
+        let text = decoder.text_chunks().unwrap();
+        assert_eq!(text, vec![TextChunk::new("Comment".to_string(), "trailing".to_string())]);
+        assert!(decoder.unknown_chunks().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_decoding_errors() {
+        let mut png = SIGNATURE.to_vec();
+        png[1] = b'Q';
+        match PNGDecoder::new(&png[..]).dimensions() {
+            Err(ImageError::Decoding(err)) => {
+                assert_eq!(*err.kind(), DecodingErrorKind::InvalidSignature);
+                assert_eq!(err.offset(), Some(0));
+                assert_eq!(err.to_string(), "PNG decoding error at byte 0: invalid signature");
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let (ihdr, _) = encode(1, 1, [0; 4]);
+        let mut png = SIGNATURE.to_vec();
+        write_chunk(&mut png, b"IHDR", &ihdr);
+        write_chunk(&mut png, b"acTL", &[0, 0, 0, 1, 0, 0, 0, 0]);
+        let offset = png.len() as u64;
+        write_chunk(&mut png, b"fcTL", &fctl(0, (1, 1), (0, 0), 5));
+        match PNGDecoder::new(&png[..]).dimensions() {
+            Err(ImageError::Decoding(err)) => {
+                assert_eq!(err.format(), ImageFormat::PNG);
+                assert_eq!(err.offset(), Some(offset));
+                match *err.kind() {
+                    DecodingErrorKind::InvalidValue { field, ref found, .. } => {
+                        assert_eq!((field, &**found), ("dispose operation", "5"))
+                    }
+                    ref kind => panic!("unexpected kind {:?}", kind),
+                }
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_apng_default_image() {
+        let data = apng();
+        let mut decoder = PNGDecoder::new(&data[..]);
+        assert!(decoder.is_animated().unwrap());
+        assert_eq!(decoder.dimensions().unwrap(), (2, 2));
+        assert_eq!(decoder.colortype().unwrap(), ColorType::RGBA(8));
+
+        let (_, white) = encode(1, 1, [255, 255, 255, 255]);
+        let mut png = SIGNATURE.to_vec();
+        write_chunk(&mut png, b"IHDR", &encode(1, 1, [0; 4]).0);
+        write_chunk(&mut png, b"IDAT", &white);
+        write_chunk(&mut png, b"IEND", &[]);
+        assert!(!PNGDecoder::new(&png[..]).is_animated().unwrap());
+        assert_eq!(PNGDecoder::new(&png[..]).into_frames().unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_apng_frames() {
+        let data = apng();
+        let frames = PNGDecoder::new(&data[..]).into_raw_frames().unwrap()
+            .collect_frames().unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].image().dimensions(), (2, 2));
+        assert_eq!(frames[1].image().dimensions(), (1, 1));
+        assert_eq!((frames[1].left(), frames[1].top()), (1, 1));
+        assert_eq!(frames[1].delay(), Ratio::new(1, 10));
+
+        let frames = PNGDecoder::new(&data[..]).into_frames().unwrap()
+            .collect_frames().unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[1].image().get_pixel(1, 1).data, [0, 0, 0, 255]);
+        assert_eq!(frames[2].image().get_pixel(0, 0).data, [255, 0, 0, 255]);
+        // The black pixel was disposed to the background
+        assert_eq!(frames[2].image().get_pixel(1, 1).data, [0, 0, 0, 0]);
+        assert_eq!(frames[2].image().get_pixel(1, 0).data, [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_apng_nth_frame() {
+        let data = apng();
+        let mut frames = PNGDecoder::new(&data[..]).into_raw_frames().unwrap();
+        let third = frames.nth(2).unwrap().unwrap();
+        assert_eq!(third.image().get_pixel(0, 0).data, [255, 0, 0, 255]);
+        assert!(frames.next().is_none());
+
+        let mut frames = PNGDecoder::new(&data[..]).into_frames().unwrap();
+        let third = frames.nth(2).unwrap().unwrap();
+        assert_eq!(third.image().get_pixel(1, 1).data, [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_apng_invalid_frames() {
+        let (ihdr, white) = encode(2, 2, [255, 255, 255, 255]);
+        let (_, black) = encode(1, 1, [0, 0, 0, 255]);
+        // The default image is followed by a frame with the given control
+        let apng = |control: &[u8], sequence: u32| {
+            let mut fdat = vec![0; 4];
+            BigEndian::write_u32(&mut fdat, sequence);
+            fdat.extend_from_slice(&black);
+            let mut png = SIGNATURE.to_vec();
+            write_chunk(&mut png, b"IHDR", &ihdr);
+            write_chunk(&mut png, b"acTL", &[0, 0, 0, 2, 0, 0, 0, 0]);
+            write_chunk(&mut png, b"fcTL", &fctl(0, (2, 2), (0, 0), 0));
+            write_chunk(&mut png, b"IDAT", &white);
+            write_chunk(&mut png, b"fcTL", control);
+            write_chunk(&mut png, b"fdAT", &fdat);
+            write_chunk(&mut png, b"IEND", &[]);
+            png
+        };
+        let malformed = |png: Vec<u8>| {
+            let frames = PNGDecoder::new(&png[..]).into_raw_frames().unwrap();
+            match frames.collect_frames() {
+                Err(ImageError::Decoding(ref err)) => match *err.kind() {
+                    DecodingErrorKind::Malformed(_) => (),
+                    ref kind => panic!("unexpected kind {:?}", kind),
+                },
+                Err(err) => panic!("unexpected error {:?}", err),
+                Ok(_) => panic!("expected an error"),
+            }
+        };
+
+        assert_eq!(PNGDecoder::new(&apng(&fctl(1, (1, 1), (1, 1), 0), 2)[..]).into_raw_frames().unwrap()
+                   .count(), 2);
+        malformed(apng(&fctl(2, (1, 1), (1, 1), 0), 3));
+        malformed(apng(&fctl(1, (1, 1), (1, 1), 0), 1));
+        malformed(apng(&fctl(1, (1, 1), (2, 1), 0), 2));
+        malformed(apng(&fctl(1, (1, 1), (0, u32::max_value()), 0), 2));
+    }
+
+    #[test]
+    fn test_apng_truncated() {
+        let data = apng();
+        let frames = PNGDecoder::new(&data[..data.len() - 20]).into_frames().unwrap();
+        assert!(frames.collect_frames().is_err());
     }
 }
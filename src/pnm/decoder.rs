@@ -3,14 +3,17 @@ use std::io::{Read, BufRead, BufReader};
 use std::ascii::AsciiExt;
 
 use color::{ColorType};
-use image::{DecodingResult, ImageDecoder, ImageResult, ImageError};
+use utils;
+use image::{DecodingError, DecodingResult, ImageDecoder, ImageResult, ImageError, ImageFormat};
 extern crate byteorder;
 use self::byteorder::{BigEndian, ByteOrder};
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 enum TupleType {
     RGB,
+    RGBAlpha,
     Grayscale,
+    GrayscaleAlpha,
     Bit,
 }
 
@@ -64,7 +67,7 @@ impl<R: Read> PNMDecoder<R> {
         let mut buf = BufReader::new(read);
         let magic = try!(buf.read_magic_constant());
         if magic[0] != b'P' {
-            return Err(ImageError::FormatError("Expected magic constant for pnm, P1 through P7".to_string()));
+            return Err(ImageError::malformed(ImageFormat::PNM, "Expected magic constant for pnm, P1 through P7"));
         }
 
         let subtype = match magic[1] {
@@ -75,7 +78,7 @@ impl<R: Read> PNMDecoder<R> {
             b'5' => PNMSubtype::Graymap(SampleEncoding::Binary),
             b'6' => PNMSubtype::Pixmap(SampleEncoding::Binary),
             b'7' => PNMSubtype::ArbitraryMap,
-            _ => return Err(ImageError::FormatError("Expected magic constant for ppm, P1 through P7".to_string())),
+            _ => return Err(ImageError::malformed(ImageFormat::PNM, "Expected magic constant for ppm, P1 through P7")),
         };
 
         let (width, height, maxwhite, tuple) = match subtype {
@@ -86,7 +89,7 @@ impl<R: Read> PNMDecoder<R> {
         };
 
         if !(maxwhite <= u16::max_value() as u32) {
-            return Err(ImageError::FormatError("Image maxval is not less or equal to 65535".to_string()))
+            return Err(ImageError::malformed(ImageFormat::PNM, "Image maxval is not less or equal to 65535"))
         }
 
         Ok(PNMDecoder {
@@ -124,22 +127,26 @@ impl<R: Read> PNMDecoder<R> {
             (Some("BLACKANDWHITE"), 1) if maxval == 1
                 => Ok((width, height, 1, TupleType::Grayscale)),
             (Some("BLACKANDWHITE"), _)
-                => Err(ImageError::FormatError("Unexpected depth value for tuple type BLACKANDWHITE".to_string())),
+                => Err(ImageError::malformed(ImageFormat::PNM, "Unexpected depth value for tuple type BLACKANDWHITE")),
             (Some("GRAYSCALE"), 1) if maxval >= 1 && maxval <= 0xFFFF
                 => Ok((width, height, maxval, TupleType::Grayscale)),
             (Some("GRAYSCALE"), _)
-                => Err(ImageError::FormatError("Invalid depth for tuple type GRAYSCALE".to_string())),
+                => Err(ImageError::malformed(ImageFormat::PNM, "Invalid depth for tuple type GRAYSCALE")),
             (Some("RGB"), 3) if maxval >= 1 && maxval <= 0xFFFF
                 => Ok((width, height, maxval, TupleType::RGB)),
             (Some("RGB"), _)
-                => Err(ImageError::FormatError("Invalid depth for tuple type RGB".to_string())),
+                => Err(ImageError::malformed(ImageFormat::PNM, "Invalid depth for tuple type RGB")),
             (Some("BLACKANDWHITE_ALPHA"), _)
                 => Err(ImageError::UnsupportedColor(ColorType::GrayA(1))),
+            (Some("GRAYSCALE_ALPHA"), 2) if maxval >= 1 && maxval <= 0xFFFF
+                => Ok((width, height, maxval, TupleType::GrayscaleAlpha)),
             (Some("GRAYSCALE_ALPHA"), _)
-                => Err(ImageError::UnsupportedColor(ColorType::GrayA(8))),
+                => Err(ImageError::malformed(ImageFormat::PNM, "Invalid depth for tuple type GRAYSCALE_ALPHA")),
+            (Some("RGB_ALPHA"), 4) if maxval >= 1 && maxval <= 0xFFFF
+                => Ok((width, height, maxval, TupleType::RGBAlpha)),
             (Some("RGB_ALPHA"), _)
-                => Err(ImageError::UnsupportedColor(ColorType::RGBA(8))),
-            _ => Err(ImageError::FormatError("Tuple type not recognized".to_string())),
+                => Err(ImageError::malformed(ImageFormat::PNM, "Invalid depth for tuple type RGB_ALPHA")),
+            _ => Err(ImageError::malformed(ImageFormat::PNM, "Tuple type not recognized")),
         }
     }
 }
@@ -194,26 +201,32 @@ trait HeaderReader: BufRead {
         }
 
         if bytes.is_empty() {
-            return Err(ImageError::FormatError("Unexpected eof".to_string()))
+            return Err(ImageError::malformed(ImageFormat::PNM, "Unexpected eof"))
         }
 
         if !bytes.as_slice().is_ascii() {
-            return Err(ImageError::FormatError("Non ascii character in preamble".to_string()))
+            return Err(ImageError::malformed(ImageFormat::PNM, "Non ascii character in preamble"))
         }
 
-        String::from_utf8(bytes).map_err(|_| ImageError::FormatError("Couldn't read preamble".to_string()))
+        String::from_utf8(bytes).map_err(|err| {
+            DecodingError::malformed(ImageFormat::PNM, "Couldn't read preamble").with_source(err).into()
+        })
     }
 
     /// Read the next line
     fn read_next_line(&mut self) -> ImageResult<String> {
         let mut buffer = String::new();
-        self.read_line(&mut buffer).map_err(|_| ImageError::FormatError("Line not properly formatted".to_string()))?;
+        self.read_line(&mut buffer).map_err(|err| {
+            DecodingError::malformed(ImageFormat::PNM, "Line not properly formatted").with_source(err)
+        })?;
         Ok(buffer)
     }
 
     fn read_next_u32(&mut self) -> ImageResult<u32> {
         let s = try!(self.read_next_string());
-        s.parse::<u32>().map_err(|_| ImageError::FormatError("Invalid number in preamble".to_string()))
+        s.parse::<u32>().map_err(|err| {
+            DecodingError::malformed(ImageFormat::PNM, "Invalid number in preamble").with_source(err).into()
+        })
     }
 
     fn read_bitmap_header(&mut self) -> ImageResult<(u32, u32)> {
@@ -235,10 +248,10 @@ trait HeaderReader: BufRead {
 
     fn read_arbitrary_header(&mut self) -> ImageResult<ArbitraryHeader> {
         match self.bytes().next() {
-            None => return Err(ImageError::FormatError("Input too short".to_string())),
+            None => return Err(ImageError::malformed(ImageFormat::PNM, "Input too short")),
             Some(Err(io)) => return Err(ImageError::IoError(io)),
             Some(Ok(b'\n')) => (),
-            _ => return Err(ImageError::FormatError("Expected newline after P7".to_string())),
+            _ => return Err(ImageError::malformed(ImageFormat::PNM, "Expected newline after P7")),
         }
 
         let mut line = String::new();
@@ -249,38 +262,48 @@ trait HeaderReader: BufRead {
         let mut tupltype: Option<String> = None;
         loop {
             line.truncate(0);
-            self.read_line(&mut line).map_err(|io| ImageError::IoError(io))?;
-            if line.as_bytes()[0] == b'#' {
+            if self.read_line(&mut line).map_err(|io| ImageError::IoError(io))? == 0 {
+                return Err(ImageError::malformed(ImageFormat::PNM, "Unexpected end of pam header"));
+            }
+            if line.starts_with('#') {
                 continue;
             }
             if !line.is_ascii() {
-                return Err(ImageError::FormatError("Only ascii characters allowed in pam header".to_string()));
+                return Err(ImageError::malformed(ImageFormat::PNM, "Only ascii characters allowed in pam header"));
             }
             let (identifier, rest) = line.trim_left().split_at(line.find(char::is_whitespace).unwrap_or(line.len()));
             match identifier {
                 "ENDHDR" => break,
                 "HEIGHT" => if height.is_some() {
-                        return Err(ImageError::FormatError("Duplicate HEIGHT line".to_string()))
+                        return Err(ImageError::malformed(ImageFormat::PNM, "Duplicate HEIGHT line"))
                     } else {
-                        let h = rest.trim().parse::<u32>().map_err(|_| ImageError::FormatError("Invalid height".to_string()))?;
+                        let h = rest.trim().parse::<u32>().map_err(|err| {
+                            DecodingError::malformed(ImageFormat::PNM, "Invalid height").with_source(err)
+                        })?;
                         height = Some(h);
                     },
                 "WIDTH" => if width.is_some() {
-                        return Err(ImageError::FormatError("Duplicate WIDTH line".to_string()))
+                        return Err(ImageError::malformed(ImageFormat::PNM, "Duplicate WIDTH line"))
                     } else {
-                        let w = rest.trim().parse::<u32>().map_err(|_| ImageError::FormatError("Invalid width".to_string()))?;
+                        let w = rest.trim().parse::<u32>().map_err(|err| {
+                            DecodingError::malformed(ImageFormat::PNM, "Invalid width").with_source(err)
+                        })?;
                         width = Some(w);
                     },
                 "DEPTH" => if depth.is_some() {
-                        return Err(ImageError::FormatError("Duplicate DEPTH line".to_string()))
+                        return Err(ImageError::malformed(ImageFormat::PNM, "Duplicate DEPTH line"))
                     } else {
-                        let d = rest.trim().parse::<u32>().map_err(|_| ImageError::FormatError("Invalid depth".to_string()))?;
+                        let d = rest.trim().parse::<u32>().map_err(|err| {
+                            DecodingError::malformed(ImageFormat::PNM, "Invalid depth").with_source(err)
+                        })?;
                         depth = Some(d);
                     },
                 "MAXVAL" => if maxval.is_some() {
-                        return Err(ImageError::FormatError("Duplicate MAXVAL line".to_string()))
+                        return Err(ImageError::malformed(ImageFormat::PNM, "Duplicate MAXVAL line"))
                     } else {
-                        let m = rest.trim().parse::<u32>().map_err(|_| ImageError::FormatError("Invalid maxval".to_string()))?;
+                        let m = rest.trim().parse::<u32>().map_err(|err| {
+                            DecodingError::malformed(ImageFormat::PNM, "Invalid maxval").with_source(err)
+                        })?;
                         maxval = Some(m);
                     },
                 "TUPLTYPE" => {
@@ -292,14 +315,14 @@ trait HeaderReader: BufRead {
                             tupltype = Some(identifier.to_string());
                         }
                     },
-                _ => return Err(ImageError::FormatError("Unknown header line".to_string())),
+                _ => return Err(ImageError::malformed(ImageFormat::PNM, "Unknown header line")),
             }
         }
         let (h, w, d, m) = match (height, width, depth, maxval) {
-            (None, _, _, _) => return Err(ImageError::FormatError("Expected one HEIGHT line".to_string())),
-            (_, None, _, _) => return Err(ImageError::FormatError("Expected one WIDTH line".to_string())),
-            (_, _, None, _) => return Err(ImageError::FormatError("Expected one DEPTH line".to_string())),
-            (_, _, _, None) => return Err(ImageError::FormatError("Expected one MAXVAL line".to_string())),
+            (None, _, _, _) => return Err(ImageError::malformed(ImageFormat::PNM, "Expected one HEIGHT line")),
+            (_, None, _, _) => return Err(ImageError::malformed(ImageFormat::PNM, "Expected one WIDTH line")),
+            (_, _, None, _) => return Err(ImageError::malformed(ImageFormat::PNM, "Expected one DEPTH line")),
+            (_, _, _, None) => return Err(ImageError::malformed(ImageFormat::PNM, "Expected one MAXVAL line")),
             (Some(h), Some(w), Some(d), Some(m)) => (h, w, d, m),
         };
         Ok(ArbitraryHeader{
@@ -326,8 +349,12 @@ impl<R: Read> ImageDecoder for PNMDecoder<R> {
             TupleType::Grayscale if self.maxwhite <= 0xFFFF => Ok(ColorType::Gray(16)),
             TupleType::RGB if self.maxwhite <= 0xFF => Ok(ColorType::RGB(8)),
             TupleType::RGB if self.maxwhite <= 0xFFFF => Ok(ColorType::RGB(16)),
+            TupleType::GrayscaleAlpha if self.maxwhite <= 0xFF => Ok(ColorType::GrayA(8)),
+            TupleType::GrayscaleAlpha if self.maxwhite <= 0xFFFF => Ok(ColorType::GrayA(16)),
+            TupleType::RGBAlpha if self.maxwhite <= 0xFF => Ok(ColorType::RGBA(8)),
+            TupleType::RGBAlpha if self.maxwhite <= 0xFFFF => Ok(ColorType::RGBA(16)),
             TupleType::Bit => Ok(ColorType::Gray(1)),
-            _ => Err(ImageError::FormatError("Can't determine color type".to_string()))
+            _ => Err(ImageError::malformed(ImageFormat::PNM, "Can't determine color type"))
         }
     }
 
@@ -352,7 +379,11 @@ impl<R: Read> PNMDecoder<R> {
             TupleType::RGB if self.maxwhite <= 0xFFFF => U16::bytelen(self.width, 1, 3),
             TupleType::Grayscale if self.maxwhite <= 0xFF => U8::bytelen(self.width, 1, 1),
             TupleType::Grayscale if self.maxwhite <= 0xFFFF => U16::bytelen(self.width, 1, 1),
-            _ => return Err(ImageError::FormatError("Unhandled tuple type".to_string()))
+            TupleType::RGBAlpha if self.maxwhite <= 0xFF => U8::bytelen(self.width, 1, 4),
+            TupleType::RGBAlpha if self.maxwhite <= 0xFFFF => U16::bytelen(self.width, 1, 4),
+            TupleType::GrayscaleAlpha if self.maxwhite <= 0xFF => U8::bytelen(self.width, 1, 2),
+            TupleType::GrayscaleAlpha if self.maxwhite <= 0xFFFF => U16::bytelen(self.width, 1, 2),
+            _ => return Err(ImageError::malformed(ImageFormat::PNM, "Unhandled tuple type"))
         }
     }
 
@@ -363,7 +394,11 @@ impl<R: Read> PNMDecoder<R> {
             TupleType::RGB if self.maxwhite <= 0xFFFF => self.read_samples::<U16>(3),
             TupleType::Grayscale if self.maxwhite <= 0xFF => self.read_samples::<U8>(1),
             TupleType::Grayscale if self.maxwhite <= 0xFFFF => self.read_samples::<U16>(1),
-            _ => return Err(ImageError::FormatError("Unhandled tuple type".to_string()))
+            TupleType::RGBAlpha if self.maxwhite <= 0xFF => self.read_samples::<U8>(4),
+            TupleType::RGBAlpha if self.maxwhite <= 0xFFFF => self.read_samples::<U16>(4),
+            TupleType::GrayscaleAlpha if self.maxwhite <= 0xFF => self.read_samples::<U8>(2),
+            TupleType::GrayscaleAlpha if self.maxwhite <= 0xFFFF => self.read_samples::<U16>(2),
+            _ => return Err(ImageError::malformed(ImageFormat::PNM, "Unhandled tuple type"))
         }
     }
 
@@ -372,8 +407,8 @@ impl<R: Read> PNMDecoder<R> {
         match self.encoding() {
             SampleEncoding::Binary => {
                     let bytecount = S::bytelen(self.width, self.height, components)?;
-                    let mut bytes = vec![0 as u8; bytecount];
-                    (&mut self.reader).read_exact(&mut bytes).map_err(|_| ImageError::NotEnoughData)?;
+                    let bytes = utils::read_exact_vec(&mut self.reader, bytecount)
+                        .map_err(|_| ImageError::NotEnoughData)?;
                     let samples = S::from_bytes(&bytes, self.width, self.height, components)?;
                     Ok(samples.into())
                 },
@@ -386,7 +421,7 @@ impl<R: Read> PNMDecoder<R> {
 
     fn read_ascii<Basic: SampleType>(&mut self, components: u32) -> ImageResult<Vec<Basic::T>> {
         let mut buffer = Vec::new();
-        for _ in 0 .. (self.width * self.height * components) {
+        for _ in 0 .. sample_count(self.width, self.height, components)? {
             let value = self.read_ascii_sample()?;
             let sample = Basic::from_unsigned(value)?;
             buffer.push(sample);
@@ -405,10 +440,14 @@ impl<R: Read> PNMDecoder<R> {
             .take_while(&istoken)
             .collect::<Result<Vec<u8>, _>>()?;
         if !token.is_ascii() {
-            return Err(ImageError::FormatError("Non ascii character where sample value was expected".to_string()))
+            return Err(ImageError::malformed(ImageFormat::PNM, "Non ascii character where sample value was expected"))
         }
-        let string = String::from_utf8(token).map_err(|_| ImageError::FormatError("Error parsing sample".to_string()))?;
-        string.parse::<u32>().map_err(|_| ImageError::FormatError("Error parsing sample value".to_string()))
+        let string = String::from_utf8(token).map_err(|err| {
+            DecodingError::malformed(ImageFormat::PNM, "Error parsing sample").with_source(err)
+        })?;
+        string.parse::<u32>().map_err(|err| {
+            DecodingError::malformed(ImageFormat::PNM, "Error parsing sample value").with_source(err).into()
+        })
     }
 
     /// Get the pnm subtype, depending on the magic constant contained in the header
@@ -427,11 +466,19 @@ impl<R: Read> PNMDecoder<R> {
     }
 }
 
+/// The number of samples in an image, checked for overflows as the dimensions come from the header
+fn sample_count(width: u32, height: u32, samples: u32) -> ImageResult<usize> {
+    (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|count| count.checked_mul(samples as usize))
+        .ok_or(ImageError::DimensionError)
+}
+
 impl SampleType for U8 {
     type T = u8;
 
     fn bytelen(width: u32, height: u32, samples: u32) -> ImageResult<usize> {
-        Ok((width * height * samples) as usize)
+        sample_count(width, height, samples)
     }
 
     fn from_bytes(bytes: &[u8], _width: u32, _height: u32, _samples: u32) -> ImageResult<Vec<Self::T>> {
@@ -443,7 +490,7 @@ impl SampleType for U8 {
 
     fn from_unsigned(val: u32) -> ImageResult<Self::T> {
         if val > u8::max_value() as u32 {
-            Err(ImageError::FormatError("Sample value outside of bounds".to_string()))
+            Err(ImageError::malformed(ImageFormat::PNM, "Sample value outside of bounds"))
         } else {
             Ok(val as u8)
         }
@@ -454,19 +501,19 @@ impl SampleType for U16 {
     type T = u16;
 
     fn bytelen(width: u32, height: u32, samples: u32) -> ImageResult<usize> {
-        Ok((width * height * samples * 2) as usize)
+        sample_count(width, height, samples)?.checked_mul(2).ok_or(ImageError::DimensionError)
     }
 
     fn from_bytes(bytes: &[u8], width: u32, height: u32, samples: u32) -> ImageResult<Vec<Self::T>> {
         let mut buffer = Vec::new();
-        buffer.resize((width * height * samples) as usize, 0 as u16);
+        buffer.resize(sample_count(width, height, samples)?, 0 as u16);
         BigEndian::read_u16_into(bytes, &mut buffer);
         Ok(buffer)
     }
 
     fn from_unsigned(val: u32) -> ImageResult<Self::T> {
         if val > u16::max_value() as u32 {
-            Err(ImageError::FormatError("Sample value outside of bounds".to_string()))
+            Err(ImageError::malformed(ImageFormat::PNM, "Sample value outside of bounds"))
         } else {
             Ok(val as u16)
         }
@@ -480,23 +527,26 @@ impl SampleType for PbmBit {
     type T = u8;
 
     fn bytelen(width: u32, height: u32, samples: u32) -> ImageResult<usize> {
-        let count = width * samples;
-        let linelen = (count/8) + ((count % 8) != 0) as u32;
-        Ok((linelen * height) as usize)
+        let count = sample_count(width, 1, samples)?;
+        let linelen = (count/8) + ((count % 8) != 0) as usize;
+        linelen.checked_mul(height as usize).ok_or(ImageError::DimensionError)
     }
 
     fn from_bytes(bytes: &[u8], width: u32, height: u32, samples: u32) -> ImageResult<Vec<Self::T>> {
         let mut buffer = Vec::new();
-        let linecount = width * samples;
-        let linebytelen = (linecount/8) + ((linecount % 8) != 0) as u32;
-        buffer.resize((width * height * samples) as usize, 0 as u8);
-        for (line, linebuffer) in bytes.chunks(linebytelen as usize).enumerate() {
-            let outbase = line*linecount as usize;
+        let linecount = sample_count(width, 1, samples)?;
+        let linebytelen = (linecount/8) + ((linecount % 8) != 0) as usize;
+        buffer.resize(sample_count(width, height, samples)?, 0 as u8);
+        if linebytelen == 0 {
+            return Ok(buffer)
+        }
+        for (line, linebuffer) in bytes.chunks(linebytelen).enumerate() {
+            let outbase = line*linecount;
             for samplei in 0..linecount {
-                let byteindex = (samplei/8) as usize;
+                let byteindex = samplei/8;
                 let inindex = 7 - samplei % 8;
                 let indicator = (linebuffer[byteindex] >> inindex) & 0x01;
-                buffer[outbase + samplei as usize] = if indicator == 0 { 1 } else { 0 };
+                buffer[outbase + samplei] = if indicator == 0 { 1 } else { 0 };
             }
         }
         Ok(buffer)
@@ -504,7 +554,7 @@ impl SampleType for PbmBit {
 
     fn from_unsigned(val: u32) -> ImageResult<Self::T> {
         if val > 1 {
-            Err(ImageError::FormatError("Sample value outside of bounds".to_string()))
+            Err(ImageError::malformed(ImageFormat::PNM, "Sample value outside of bounds"))
         } else if val == 1 {
             Ok(0 as u8)
         } else {
@@ -606,6 +656,30 @@ ENDHDR
         }
     }
 
+    /// Tests reading of a valid rgb pam with an alpha channel
+    #[test]
+    fn pam_rgb_alpha() {
+        let pamdata =
+b"P7
+WIDTH 2
+HEIGHT 1
+DEPTH 4
+MAXVAL 255
+TUPLTYPE RGB_ALPHA
+ENDHDR
+\xde\xad\xbe\xef\x01\x02\x03\x04";
+        let mut decoder = PNMDecoder::new(&pamdata[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::RGBA(8));
+        assert_eq!(decoder.row_len().unwrap(), 8);
+        match decoder.read_image().unwrap() {
+            DecodingResult::U16(_) => panic!("Decoded wrong image format"),
+            DecodingResult::U8(data) => assert_eq!(data, [0xde, 0xad, 0xbe, 0xef, 1, 2, 3, 4]),
+        }
+
+        let wrong_depth = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 3\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n\0\0\0";
+        assert!(PNMDecoder::new(&wrong_depth[..]).is_err());
+    }
+
     #[test]
     fn pbm_binary() {
         // The data contains two rows of the image (each line is padded to the full byte). For
@@ -675,4 +749,17 @@ ENDHDR
                 (0..16).collect::<Vec<_>>()),
         }
     }
+
+    #[test]
+    fn truncated_pam_header() {
+        assert!(PNMDecoder::new(&b"P7\nWIDTH 4\nHEIGHT 4\n"[..]).is_err());
+    }
+
+    #[test]
+    fn huge_dimensions() {
+        // The declared size overflows 32 bits and is far larger than the data
+        let pgm = b"P5 4294967295 4294967295 255\n\x01\x02\x03";
+        let mut decoder = PNMDecoder::new(&pgm[..]).unwrap();
+        assert!(decoder.read_image().is_err());
+    }
 }
@@ -0,0 +1,80 @@
+//! Encoding of PAM images
+
+use std::io::{self, Write};
+
+use color::ColorType;
+use image::ImageResult;
+use pipeline::ScanlineEncoder;
+
+/// An encoder for the arbitrary map format PAM (magic number P7).
+///
+/// Unlike PPM it can store grayscale images and an alpha channel, which makes it a
+/// simple lossless interchange format for the netpbm tools.
+pub struct PAMEncoder<'a, W: 'a> {
+    w: &'a mut W,
+}
+
+impl<'a, W: Write> PAMEncoder<'a, W> {
+    /// Create a new PAMEncoder from the Writer ```w```.
+    pub fn new(w: &'a mut W) -> PAMEncoder<'a, W> {
+        PAMEncoder { w }
+    }
+
+    /// Encode the buffer ```im``` as a PAM image.
+    /// ```width``` and ```height``` are the dimensions of the buffer.
+    /// ```color``` is the buffers ColorType, gray and RGB images with and without alpha
+    /// and 8 or 16 bits per sample are supported. 16 bit samples are big endian.
+    pub fn encode(&mut self, im: &[u8], width: u32, height: u32, color: ColorType) -> io::Result<()> {
+        let len = row_len(width, color)? * u64::from(height);
+        if (im.len() as u64) < len {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "The buffer is smaller than the image"))
+        }
+
+        write_header(self.w, width, height, color)?;
+        self.w.write_all(&im[..len as usize])
+    }
+}
+
+/// Writes the rows as they are converted, see ```convert_scanlines```
+impl<'a, W: Write> ScanlineEncoder for PAMEncoder<'a, W> {
+    fn start_image(&mut self, width: u32, height: u32, color: ColorType) -> ImageResult<()> {
+        Ok(write_header(self.w, width, height, color)?)
+    }
+
+    fn write_scanline(&mut self, row: &[u8]) -> ImageResult<()> {
+        Ok(self.w.write_all(row)?)
+    }
+}
+
+// Returns the number of bytes of a row, or an error if the color type is not supported
+fn row_len(width: u32, color: ColorType) -> io::Result<u64> {
+    let (depth, _, maxval) = header_values(color)?;
+    let bytes_per_sample = if maxval > 0xFF { 2 } else { 1 };
+    Ok(u64::from(width) * depth * bytes_per_sample)
+}
+
+// Returns the depth, tuple type and maximum value of a color type
+fn header_values(color: ColorType) -> io::Result<(u64, &'static str, u32)> {
+    Ok(match color {
+        ColorType::Gray(n) => (1, "GRAYSCALE", max_value(n)?),
+        ColorType::GrayA(n) => (2, "GRAYSCALE_ALPHA", max_value(n)?),
+        ColorType::RGB(n) => (3, "RGB", max_value(n)?),
+        ColorType::RGBA(n) => (4, "RGB_ALPHA", max_value(n)?),
+        ColorType::Palette(_) => return Err(io::Error::new(
+            io::ErrorKind::InvalidInput, format!("Unsupported color type {:?}", color))),
+    })
+}
+
+fn write_header<W: Write>(w: &mut W, width: u32, height: u32, color: ColorType) -> io::Result<()> {
+    let (depth, tupltype, maxval) = header_values(color)?;
+    write!(w, "P7\nWIDTH {}\nHEIGHT {}\nDEPTH {}\nMAXVAL {}\nTUPLTYPE {}\nENDHDR\n",
+           width, height, depth, maxval, tupltype)
+}
+
+fn max_value(bit_depth: u8) -> io::Result<u32> {
+    match bit_depth {
+        8 => Ok(0xFF),
+        16 => Ok(0xFFFF),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "Only 8 and 16 bit samples are supported")),
+    }
+}
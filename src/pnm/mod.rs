@@ -1,9 +1,69 @@
-//! Decoding of netpbm image formats (pbm, pgm, ppm and pam).
+//! Decoding and encoding of netpbm image formats (pbm, pgm, ppm and pam).
 //!
 //! The formats pbm, pgm and ppm are fully supported. The pam decoder recognizes the tuple types
-//! `BLACKANDWHITE`, `GRAYSCALE` and `RGB` and explicitely recognizes but rejects their `_ALPHA`
-//! variants for now as alpha color types are unsupported.
+//! `BLACKANDWHITE`, `GRAYSCALE`, `RGB`, `GRAYSCALE_ALPHA` and `RGB_ALPHA`, it explicitely
+//! recognizes but rejects `BLACKANDWHITE_ALPHA` for now. The encoder writes pam images.
 
 pub use self::decoder::{PNMDecoder, PNMSubtype};
+pub use self::encoder::PAMEncoder;
 
 mod decoder;
+mod encoder;
+
+#[cfg(test)]
+mod test {
+    use color::ColorType;
+    use image::{DecodingResult, ImageDecoder};
+    use super::{PAMEncoder, PNMDecoder};
+
+    #[test]
+    fn test_roundtrip_pam() {
+        let rgba = [0u8, 10, 20, 255, 30, 40, 50, 0, 60, 70, 80, 128];
+        let gray_alpha_16 = [0u8, 1, 0xFF, 0xFF, 0x12, 0x34, 0, 0, 0xAB, 0xCD, 0x80, 0];
+        let images: [(&[u8], ColorType); 4] = [
+            (&rgba, ColorType::RGBA(8)),
+            (&rgba, ColorType::Gray(8)),
+            (&rgba[..9], ColorType::RGB(8)),
+            (&gray_alpha_16, ColorType::GrayA(16)),
+        ];
+        for &(data, color) in &images {
+            let width = data.len() as u32 / (::color::bits_per_pixel(color) as u32 / 8);
+            let mut stream = Vec::new();
+            PAMEncoder::new(&mut stream).encode(data, width, 1, color).unwrap();
+
+            let mut decoder = PNMDecoder::new(&stream[..]).unwrap();
+            assert_eq!(decoder.colortype().unwrap(), color);
+            assert_eq!(decoder.dimensions().unwrap(), (width, 1));
+            let decoded = match decoder.read_image().unwrap() {
+                DecodingResult::U8(data) => data,
+                DecodingResult::U16(data) => data.iter().flat_map(|v| vec![(v >> 8) as u8, *v as u8]).collect(),
+            };
+            assert_eq!(&decoded[..], data);
+        }
+    }
+
+    #[test]
+    fn test_convert_scanlines() {
+        // A 16 bit grayscale image with two rows
+        let pgm = b"P2 2 2 65535 0 65535 4112 32896";
+        let mut stream = Vec::new();
+        let decoder = PNMDecoder::new(&pgm[..]).unwrap();
+        ::convert_scanlines(decoder, &mut PAMEncoder::new(&mut stream), ColorType::RGB(8)).unwrap();
+
+        let mut decoder = PNMDecoder::new(&stream[..]).unwrap();
+        assert_eq!(decoder.colortype().unwrap(), ColorType::RGB(8));
+        assert_eq!(decoder.dimensions().unwrap(), (2, 2));
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data, vec![0, 0, 0, 255, 255, 255, 16, 16, 16, 128, 128, 128]),
+            DecodingResult::U16(_) => panic!("expected 8 bit data"),
+        }
+    }
+
+    #[test]
+    fn test_encode_unsupported() {
+        let mut stream = Vec::new();
+        assert!(PAMEncoder::new(&mut stream).encode(&[0; 3], 1, 1, ColorType::Palette(8)).is_err());
+        assert!(PAMEncoder::new(&mut stream).encode(&[0; 3], 2, 1, ColorType::RGB(8)).is_err());
+        assert!(stream.is_empty());
+    }
+}
@@ -2,7 +2,7 @@
 use std::io::Read;
 
 use color::{ColorType};
-use image::{DecodingResult, ImageDecoder, ImageResult, ImageError};
+use image::{DecodingResult, ImageDecoder, ImageResult, ImageError, ImageFormat};
 use pnm::{PNMDecoder, PNMSubtype};
 
 /// PPM decoder, restriction pnm type to ppm
@@ -14,7 +14,7 @@ impl<R: Read> PPMDecoder<R> {
         let pnm = PNMDecoder::new(read)?;
         match pnm.subtype() {
             PNMSubtype::Pixmap(_) => {},
-            _ => return Err(ImageError::FormatError("Expected pixmap magic constant (P3 or P6)".to_string())),
+            _ => return Err(ImageError::malformed(ImageFormat::PNM, "Expected pixmap magic constant (P3 or P6)")),
         }
         Ok(PPMDecoder(pnm))
     }
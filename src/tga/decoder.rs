@@ -7,6 +7,7 @@ use image::ImageResult;
 use image::ImageDecoder;
 use image::DecodingResult;
 use color::ColorType;
+use utils;
 
 enum ImageType {
     NoImageData = 0,
@@ -308,9 +309,7 @@ impl<R: Read + Seek> TGADecoder<R> {
             try!(self.read_encoded_data())
         } else {
             let num_raw_bytes = self.width * self.height * self.bytes_per_pixel;
-            let mut buf = vec![0; num_raw_bytes];
-            try!(self.r.by_ref().read_exact(&mut buf));
-            buf
+            try!(utils::read_exact_vec(&mut self.r, num_raw_bytes))
         };
 
         // expand the indices using the color map if necessary
@@ -328,7 +327,7 @@ impl<R: Read + Seek> TGADecoder<R> {
     /// Reads a run length encoded packet
     fn read_encoded_data(&mut self) -> ImageResult<Vec<u8>> {
         let num_bytes = self.width * self.height * self.bytes_per_pixel;
-        let mut pixel_data = Vec::with_capacity(num_bytes);
+        let mut pixel_data = Vec::with_capacity(utils::initial_capacity(num_bytes));
 
         while pixel_data.len() < num_bytes {
             let run_packet = try!(self.r.read_u8());
@@ -350,6 +349,8 @@ impl<R: Read + Seek> TGADecoder<R> {
                 try!(self.r.by_ref().take(num_raw_bytes as u64).read_to_end(&mut pixel_data));
             }
         }
+        // The last packet may run past the end of the image
+        pixel_data.truncate(num_bytes);
 
         Ok(pixel_data)
     }
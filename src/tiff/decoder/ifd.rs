@@ -4,6 +4,7 @@ use std::io::{self, Read, Seek};
 use std::collections::{HashMap};
 
 use super::stream::{ByteOrder, SmartReader, EndianReader};
+use utils;
 
 use self::Value::{Unsigned, List};
 
@@ -56,7 +57,7 @@ tags!{
     MaxSampleValue 281; // TODO add support
     MinSampleValue 280; // TODO add support
     Model 272; // TODO add support
-    NewSubfileType 254; // TODO add support
+    NewSubfileType 254;
     Orientation 274; // TODO add support
     PhotometricInterpretation 262;
     PlanarConfiguration 284;
@@ -98,7 +99,7 @@ impl Value {
     pub fn into_u32(self) -> ::image::ImageResult<u32> {
         match self {
             Unsigned(val) => Ok(val),
-            val => Err(::image::ImageError::FormatError(format!(
+            val => Err(::ImageError::malformed(::ImageFormat::TIFF, format!(
                 "Expected unsigned integer, {:?} found.", val
             )))
         }
@@ -167,7 +168,7 @@ impl Entry {
                 ]))
             },
             (Type::SHORT, n) => {
-                let mut v = Vec::with_capacity(n as usize);
+                let mut v = Vec::with_capacity(utils::initial_capacity(n as usize));
                 try!(decoder.goto_offset(try!(self.r(bo).read_u32())));
                 for _ in 0 .. n {
                     v.push(Unsigned(u32::from(decoder.read_short()?)))
@@ -176,7 +177,7 @@ impl Entry {
             },
             (Type::LONG, 1) => Ok(Unsigned(try!(self.r(bo).read_u32()))),
             (Type::LONG, n) => {
-                let mut v = Vec::with_capacity(n as usize);
+                let mut v = Vec::with_capacity(utils::initial_capacity(n as usize));
                 try!(decoder.goto_offset(try!(self.r(bo).read_u32())));
                 for _ in 0 .. n {
                     v.push(Unsigned(try!(decoder.read_long())))
@@ -1,18 +1,24 @@
+use std::cmp;
 use std::io::{self, Read, Seek};
 use std::mem;
 use num_traits::{FromPrimitive, Num};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use image;
 use image::{
+    DecodingError,
+    DecodingErrorKind,
     ImageError,
+    ImageFormat,
     ImageResult,
     ImageDecoder,
-    DecodingResult,
-    DecodingBuffer
+    MultiImageDecoder,
+    DecodingResult
 };
 
 use color::{ColorType};
+use metadata::{Metadata, read_tiff_metadata};
+use utils;
 
 use self::ifd::Directory;
 
@@ -72,13 +78,17 @@ enum Predictor {
 
 /// The representation of a TIFF decoder
 ///
-/// Currently does not support decoding of interlaced images
+/// Currently does not support decoding of interlaced images. The pages of a file
+/// can be selected with ```MultiImageDecoder```.
 #[derive(Debug)]
 pub struct TIFFDecoder<R> where R: Read + Seek {
     reader: SmartReader<R>,
     byte_order: ByteOrder,
+    first_ifd: Option<u32>,
     next_ifd: Option<u32>,
     ifd: Option<Directory>,
+    // The index of the current image in the IFD chain
+    image_index: usize,
     width: u32,
     height: u32,
     bits_per_sample: Vec<u8>,
@@ -109,12 +119,10 @@ fn rev_hpredict_nsamp<T>(mut image: Vec<T>,
                          -> Vec<T>
                          where T: Num + Copy + Wrapping {
     let width = size.0 as usize;
-    let height = size.1 as usize;
-    for row in 0..height {
-        for col in samples..width * samples {
-            let prev_pixel = image[(row * width * samples + col - samples)];
-            let pixel = &mut image[(row * width * samples + col)];
-            *pixel = pixel.wrapping_add(prev_pixel);
+    // The last row may be incomplete if the image data was truncated
+    for row in image.chunks_mut(width * samples) {
+        for col in samples..row.len() {
+            row[col] = row[col].wrapping_add(row[col - samples]);
         }
     }
     image
@@ -145,8 +153,10 @@ impl<R: Read + Seek> TIFFDecoder<R> {
         TIFFDecoder {
             reader: SmartReader::wrap(r, ByteOrder::LittleEndian),
             byte_order: ByteOrder::LittleEndian,
+            first_ifd: None,
             next_ifd: None,
             ifd: None,
+            image_index: 0,
             width: 0,
             height: 0,
             bits_per_sample: vec![1],
@@ -166,31 +176,46 @@ impl<R: Read + Seek> TIFFDecoder<R> {
             b"MM" => {
                 self.byte_order = ByteOrder::BigEndian;
                 self.reader.byte_order = ByteOrder::BigEndian;  },
-            _ => return Err(image::ImageError::FormatError(
-                "TIFF signature not found.".to_string()
-            ))
+            _ => return Err(DecodingError::new(ImageFormat::TIFF, DecodingErrorKind::InvalidSignature)
+                .with_offset(0)
+                .into())
         }
-        if try!(self.read_short()) != 42 {
-            return Err(image::ImageError::FormatError("TIFF signature invalid.".to_string()))
+        let magic = try!(self.read_short());
+        if magic != 42 {
+            return Err(DecodingError::invalid_value(ImageFormat::TIFF, "magic number", 42, magic)
+                .with_offset(2)
+                .into())
         }
-        self.next_ifd = match try!(self.read_long()) {
+        self.first_ifd = match self.read_long()? {
             0 => None,
             n => Some(n)
         };
+        self.next_ifd = self.first_ifd;
         Ok(())
     }
 
     /// Initializes the decoder.
-    pub fn init(self) -> ImageResult<TIFFDecoder<R>> {
-        self.next_image()
+    pub fn init(mut self) -> ImageResult<TIFFDecoder<R>> {
+        self.read_header()?;
+        self.read_image_header()?;
+        Ok(self)
     }
 
     /// Reads in the next image.
     /// If there is no further image in the TIFF file a format error is returned.
     /// To determine whether there are more images call `TIFFDecoder::more_images` instead.
     pub fn next_image(mut self) -> ImageResult<TIFFDecoder<R>> {
-        try!(self.read_header());
+        self.read_image_header()?;
+        self.image_index += 1;
+        Ok(self)
+    }
+
+    // Reads the IFD at next_ifd and the properties of its image
+    fn read_image_header(&mut self) -> ImageResult<()> {
         self.ifd = Some(try!(self.read_ifd()));
+        self.bits_per_sample = vec![1];
+        self.samples = 1;
+        self.compression_method = CompressionMethod::None;
         self.width = try!(self.get_tag_u32(ifd::Tag::ImageWidth));
         self.height = try!(self.get_tag_u32(ifd::Tag::ImageLength));
         self.photometric_interpretation = match FromPrimitive::from_u32(
@@ -230,7 +255,30 @@ impl<R: Read + Seek> TIFFDecoder<R> {
                 format!("{} samples per pixel is supported.", self.samples)
             ))
         }
-        Ok(self)
+        Ok(())
+    }
+
+    // Returns the offsets of all IFDs, following the chain from the header
+    fn ifd_offsets(&mut self) -> ImageResult<Vec<u32>> {
+        let mut offsets = Vec::new();
+        let mut visited = HashSet::new();
+        let mut next = self.first_ifd;
+        while let Some(offset) = next {
+            if !visited.insert(offset) {
+                return Err(ImageError::malformed(ImageFormat::TIFF,
+                    "The chain of image file directories contains a cycle."
+                ))
+            }
+            offsets.push(offset);
+            self.goto_offset(offset)?;
+            let entries = self.read_short()?;
+            self.reader.seek(io::SeekFrom::Start(u64::from(offset) + 2 + 12 * u64::from(entries)))?;
+            next = match self.read_long()? {
+                0 => None,
+                n => Some(n)
+            };
+        }
+        Ok(offsets)
     }
 
     /// Returns `true` if there is at least one more image available.
@@ -302,8 +350,8 @@ impl<R: Read + Seek> TIFFDecoder<R> {
     fn read_ifd(&mut self) -> ImageResult<Directory> {
         let mut dir: Directory = HashMap::new();
         match self.next_ifd {
-            None => return Err(image::ImageError::FormatError(
-                "Image file directory not found.".to_string())
+            None => return Err(ImageError::malformed(ImageFormat::TIFF,
+                "Image file directory not found.")
             ),
             Some(offset) => try!(self.goto_offset(offset))
         }
@@ -358,7 +406,7 @@ impl<R: Read + Seek> TIFFDecoder<R> {
     fn get_tag(&mut self, tag: ifd::Tag) -> ImageResult<ifd::Value> {
         match try!(self.find_tag(tag)) {
             Some(val) => Ok(val),
-            None => Err(::image::ImageError::FormatError(format!(
+            None => Err(::ImageError::malformed(ImageFormat::TIFF, format!(
                 "Required tag `{:?}` not found.", tag
             )))
         }
@@ -374,9 +422,11 @@ impl<R: Read + Seek> TIFFDecoder<R> {
         self.get_tag(tag)?.into_u32_vec()
     }
 
-    /// Decompresses the strip into the supplied buffer.
-    /// Returns the number of bytes read.
-    fn expand_strip<'a>(&mut self, buffer: DecodingBuffer<'a>, offset: u32, length: u32, max_uncompressed_length: usize) -> ImageResult<usize> {
+    /// Decompresses the strip and appends it to the supplied buffer, which grows with
+    /// the data that is actually present. At most ```units_left``` samples are read.
+    /// Returns the number of samples read.
+    fn expand_strip(&mut self, buffer: &mut DecodingResult, units_left: usize, offset: u32, length: u32,
+                    max_uncompressed_length: usize) -> ImageResult<usize> {
         let color_type = try!(self.colortype());
         try!(self.goto_offset(offset));
         let (bytes, mut reader): (usize, Box<EndianReader>) = match self.compression_method {
@@ -397,31 +447,41 @@ impl<R: Read + Seek> TIFFDecoder<R> {
                 "Compression method {:?} is unsupported", method
             )))
         };
+        // The strip can not contain more data than is left of the image
+        let bytes = cmp::min(bytes, cmp::min(max_uncompressed_length, match *buffer {
+            DecodingResult::U8(_) => units_left,
+            DecodingResult::U16(_) => units_left.saturating_mul(2),
+        }));
         Ok(match (color_type, buffer) {
-            (ColorType:: RGB(8), DecodingBuffer::U8(ref mut buffer)) |
-            (ColorType::RGBA(8), DecodingBuffer::U8(ref mut buffer)) => {
-                try!(reader.read(&mut buffer[..bytes]))
+            (ColorType:: RGB(8), &mut DecodingResult::U8(ref mut buffer)) |
+            (ColorType::RGBA(8), &mut DecodingResult::U8(ref mut buffer)) => {
+                try!(reader.take(bytes as u64).read_to_end(buffer))
             }
-            (ColorType::RGBA(16), DecodingBuffer::U16(ref mut buffer)) |
-            (ColorType:: RGB(16), DecodingBuffer::U16(ref mut buffer)) => {
-                for datum in buffer[..bytes/2].iter_mut() {
-                    *datum = try!(reader.read_u16())
+            (ColorType::RGBA(16), &mut DecodingResult::U16(ref mut buffer)) |
+            (ColorType:: RGB(16), &mut DecodingResult::U16(ref mut buffer)) => {
+                for _ in 0..bytes/2 {
+                    buffer.push(try!(reader.read_u16()));
                 }
                 bytes/2
             }
-            (ColorType::Gray(16), DecodingBuffer::U16(ref mut buffer)) => {
-                for datum in buffer[..bytes/2].iter_mut() {
-                    *datum = try!(reader.read_u16());
+            (ColorType::Gray(16), &mut DecodingResult::U16(ref mut buffer)) => {
+                for _ in 0..bytes/2 {
+                    let datum = try!(reader.read_u16());
                     if self.photometric_interpretation == PhotometricInterpretation::WhiteIsZero {
-                        *datum = 0xffff - *datum
+                        buffer.push(0xffff - datum)
+                    } else {
+                        buffer.push(datum)
                     }
                 }
                 bytes/2
             }
-            (ColorType::Gray(n), DecodingBuffer::U8(ref mut buffer)) if n <= 8 => {
-                try!(reader.read_exact(&mut buffer[..bytes]));
+            (ColorType::Gray(n), &mut DecodingResult::U8(ref mut buffer)) if n <= 8 => {
+                let start = buffer.len();
+                if try!(reader.take(bytes as u64).read_to_end(buffer)) < bytes {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer").into())
+                }
                 if self.photometric_interpretation == PhotometricInterpretation::WhiteIsZero {
-                    for byte in buffer[..bytes].iter_mut() {
+                    for byte in buffer[start..].iter_mut() {
                         *byte = 0xff - *byte
                     }
                 }
@@ -470,13 +530,14 @@ impl<R: Read + Seek> ImageDecoder for TIFFDecoder<R> {
         let scanline_size = (scanline_size_bits + 7) / 8;
         let rows_per_strip = self.get_tag_u32(ifd::Tag::RowsPerStrip)
             .unwrap_or(self.height) as usize;
-        let buffer_size =
-            self.width  as usize
-            * self.height as usize
-            * self.bits_per_sample.iter().count();
+        let buffer_size = (self.width as usize)
+            .checked_mul(self.height as usize)
+            .and_then(|size| size.checked_mul(self.bits_per_sample.len()))
+            .ok_or(ImageError::DimensionError)?;
         let mut result = match self.bits_per_sample.iter().cloned().max().unwrap_or(8) {
-            n if n <= 8 => DecodingResult::U8(Vec::with_capacity(buffer_size)),
-            n if n <= 16 => DecodingResult::U16(Vec::with_capacity(buffer_size)),
+            // The buffer grows with the strips, the dimensions come from the header
+            n if n <= 8 => DecodingResult::U8(Vec::with_capacity(utils::initial_capacity(buffer_size))),
+            n if n <= 16 => DecodingResult::U16(Vec::with_capacity(utils::initial_capacity(buffer_size))),
             n => return Err(
                 ImageError::UnsupportedError(
                     format!("{} bits per channel not supported", n)
@@ -491,46 +552,20 @@ impl<R: Read + Seek> ImageDecoder for TIFFDecoder<R> {
                 ))
             }
         }
-        // Safe since the uninitialized values are never read.
-        match result {
-            DecodingResult::U8(ref mut buffer) =>
-                unsafe { buffer.set_len(buffer_size) },
-            DecodingResult::U16(ref mut buffer) =>
-                unsafe { buffer.set_len(buffer_size) },
-        }
         let mut units_read = 0;
         for (i, (&offset, &byte_count)) in try!(self.get_tag_u32_vec(ifd::Tag::StripOffsets))
         .iter().zip(try!(self.get_tag_u32_vec(ifd::Tag::StripByteCounts)).iter()).enumerate() {
             let uncompressed_strip_size = scanline_size
-                * (self.height as usize - i * rows_per_strip);
+                * (self.height as usize).saturating_sub(i * rows_per_strip);
 
-            units_read += match result {
-                DecodingResult::U8(ref mut buffer) => {
-                    try!(self.expand_strip(
-                        DecodingBuffer::U8(&mut buffer[units_read..]),
-                        offset, byte_count, uncompressed_strip_size
-                    ))
-                },
-                DecodingResult::U16(ref mut buffer) => {
-                    try!(self.expand_strip(
-                        DecodingBuffer::U16(&mut buffer[units_read..]),
-                        offset, byte_count, uncompressed_strip_size
-                    ))
-                },
-            };
+            units_read += try!(self.expand_strip(
+                &mut result, buffer_size - units_read,
+                offset, byte_count, uncompressed_strip_size
+            ));
             if units_read == buffer_size {
                 break
             }
         }
-        // Shrink length such that the uninitialized memory is not exposed.
-        if units_read < buffer_size {
-            match result {
-                DecodingResult::U8(ref mut buffer) =>
-                    unsafe { buffer.set_len(units_read) },
-                DecodingResult::U16(ref mut buffer) =>
-                    unsafe { buffer.set_len(units_read) },
-            }
-        }
         if let Ok(predictor) = self.get_tag_u32(ifd::Tag::Predictor) {
             result = match FromPrimitive::from_u32(predictor) {
                 Some(Predictor::None) => result,
@@ -541,11 +576,195 @@ impl<R: Read + Seek> ImageDecoder for TIFFDecoder<R> {
                         try!(self.colortype())
                     ))
                 },
-                None => return Err(ImageError::FormatError(
+                None => return Err(ImageError::malformed(ImageFormat::TIFF,
                     format!("Unknown predictor “{}” encountered", predictor)
                 ))
             }
         }
         Ok(result)
     }
+
+    /// Selects the smallest reduced resolution version of the selected image which is at
+    /// least ```width``` x ```height``` pixels large in one of the axes, if the file has one.
+    ///
+    /// Reduced resolution versions follow the full resolution image in the chain of
+    /// directories and are marked by their ```NewSubfileType```. The index of the version
+    /// is returned by ```selected_image``` afterwards.
+    fn set_target_dimensions(&mut self, width: u32, height: u32) -> ImageResult<(u32, u32)> {
+        let area = |(w, h): (u32, u32)| u64::from(w) * u64::from(h);
+        let mut best = (self.image_index, (self.width, self.height));
+        let mut index = self.image_index + 1;
+        while let Some(offset) = self.ifd_offsets()?.get(index).cloned() {
+            self.next_ifd = Some(offset);
+            if self.read_image_header().is_err() {
+                break
+            }
+            match self.find_tag_u32(ifd::Tag::NewSubfileType) {
+                Ok(Some(subfile_type)) if subfile_type & 1 == 1 => (),
+                _ => break,
+            }
+            let dimensions = (self.width, self.height);
+            if (dimensions.0 >= width || dimensions.1 >= height) && area(dimensions) < area(best.1) {
+                best = (index, dimensions);
+            }
+            index += 1;
+        }
+        self.select_image(best.0)?;
+        Ok(best.1)
+    }
+
+    /// Returns the metadata stored in the first image of the file
+    fn metadata(&mut self) -> ImageResult<Metadata> {
+        let position = self.reader.seek(io::SeekFrom::Current(0))?;
+        self.reader.seek(io::SeekFrom::Start(0))?;
+        let metadata = read_tiff_metadata(&mut self.reader);
+        self.reader.seek(io::SeekFrom::Start(position))?;
+        metadata
+    }
+}
+
+impl<R: Read + Seek> MultiImageDecoder for TIFFDecoder<R> {
+    fn image_count(&mut self) -> ImageResult<usize> {
+        Ok(self.ifd_offsets()?.len())
+    }
+
+    fn selected_image(&self) -> usize {
+        self.image_index
+    }
+
+    fn select_image(&mut self, index: usize) -> ImageResult<()> {
+        let offset = match self.ifd_offsets()?.get(index) {
+            Some(&offset) => offset,
+            None => return Err(ImageError::ImageEnd),
+        };
+        self.next_ifd = Some(offset);
+        self.read_image_header()?;
+        self.image_index = index;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use byteorder::{ByteOrder, LittleEndian};
+
+    use super::TIFFDecoder;
+    use color::ColorType;
+    use image::{DecodingResult, ImageDecoder, ImageError, MultiImageDecoder};
+
+    // A little endian TIFF with one uncompressed 8 bit grayscale image per entry of ```pages```
+    fn multi_page_tiff(pages: &[(u32, u32, &[u8])]) -> Vec<u8> {
+        build_tiff(pages, false)
+    }
+
+    // Like ```multi_page_tiff```, the pages after the first are marked as its reduced
+    // resolution versions if ```reduced``` is true
+    fn build_tiff(pages: &[(u32, u32, &[u8])], reduced: bool) -> Vec<u8> {
+        let mut data = b"II\x2A\0\x08\0\0\0".to_vec();
+        for (i, &(width, height, samples)) in pages.iter().enumerate() {
+            let subfile_type = if reduced && i > 0 { 1 } else { 0 };
+            let entries = [(254, subfile_type), (256, width), (257, height), (258, 8), (262, 1),
+                           (273, 0), (278, height), (279, samples.len() as u32)];
+            let ifd_end = data.len() as u32 + 2 + 12 * entries.len() as u32 + 4;
+            let next = if i + 1 < pages.len() { ifd_end + samples.len() as u32 } else { 0 };
+
+            let mut ifd = vec![0; ifd_end as usize - data.len()];
+            LittleEndian::write_u16(&mut ifd, entries.len() as u16);
+            for (entry, &(tag, value)) in ifd[2..].chunks_mut(12).zip(entries.iter()) {
+                // The strip directly follows the directory
+                let value = if tag == 273 { ifd_end } else { value };
+                LittleEndian::write_u16(&mut entry[0..], tag);
+                LittleEndian::write_u16(&mut entry[2..], 4);
+                LittleEndian::write_u32(&mut entry[4..], 1);
+                LittleEndian::write_u32(&mut entry[8..], value);
+            }
+            let len = ifd.len();
+            LittleEndian::write_u32(&mut ifd[len - 4..], next);
+            data.extend_from_slice(&ifd);
+            data.extend_from_slice(samples);
+        }
+        data
+    }
+
+    #[test]
+    fn test_select_page() {
+        let tiff = multi_page_tiff(&[(2, 1, &[10, 20]), (1, 3, &[1, 2, 3]), (2, 2, &[4, 5, 6, 7])]);
+        let mut decoder = TIFFDecoder::new(Cursor::new(tiff)).unwrap();
+        assert_eq!(decoder.image_count().unwrap(), 3);
+        assert_eq!(decoder.selected_image(), 0);
+        assert_eq!(decoder.image_dimensions(1).unwrap(), (1, 3));
+        assert_eq!(decoder.image_colortype(2).unwrap(), ColorType::Gray(8));
+        assert_eq!(decoder.dimensions().unwrap(), (2, 1));
+
+        decoder.select_image(2).unwrap();
+        assert_eq!(decoder.selected_image(), 2);
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data, vec![4, 5, 6, 7]),
+            DecodingResult::U16(_) => panic!("expected 8 bit data"),
+        }
+        decoder.select_image(1).unwrap();
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data, vec![1, 2, 3]),
+            DecodingResult::U16(_) => panic!("expected 8 bit data"),
+        }
+        match decoder.select_image(3) {
+            Err(ImageError::ImageEnd) => (),
+            other => panic!("expected the end of the images, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_next_image() {
+        let tiff = multi_page_tiff(&[(2, 1, &[10, 20]), (1, 3, &[1, 2, 3])]);
+        let decoder = TIFFDecoder::new(Cursor::new(tiff)).unwrap();
+        assert!(decoder.more_images());
+        let mut decoder = decoder.next_image().unwrap();
+        assert!(!decoder.more_images());
+        assert_eq!(decoder.selected_image(), 1);
+        assert_eq!(decoder.dimensions().unwrap(), (1, 3));
+    }
+
+    #[test]
+    fn test_target_dimensions() {
+        let pages: [(u32, u32, &[u8]); 4] = [(8, 4, &[0; 32]), (4, 2, &[1; 8]), (2, 1, &[2; 2]), (1, 1, &[3])];
+        let tiff = build_tiff(&pages, true);
+        let mut decoder = TIFFDecoder::new(Cursor::new(&tiff)).unwrap();
+        assert_eq!(decoder.set_target_dimensions(3, 3).unwrap(), (4, 2));
+        assert_eq!(decoder.selected_image(), 1);
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data, vec![1; 8]),
+            DecodingResult::U16(_) => panic!("expected 8 bit data"),
+        }
+        let mut decoder = TIFFDecoder::new(Cursor::new(&tiff)).unwrap();
+        assert_eq!(decoder.set_target_dimensions(100, 100).unwrap(), (8, 4));
+        assert_eq!(decoder.selected_image(), 0);
+
+        // The pages of a multi-page file are independent images
+        let tiff = multi_page_tiff(&pages);
+        let mut decoder = TIFFDecoder::new(Cursor::new(&tiff)).unwrap();
+        assert_eq!(decoder.set_target_dimensions(1, 1).unwrap(), (8, 4));
+        assert_eq!(decoder.selected_image(), 0);
+    }
+
+    #[test]
+    fn test_large_dimensions_without_data() {
+        // The buffer only holds the samples which are present in the file
+        let tiff = multi_page_tiff(&[(65535, 65535, &[1, 2, 3])]);
+        match TIFFDecoder::new(Cursor::new(tiff)).unwrap().read_image().unwrap() {
+            DecodingResult::U8(data) => assert_eq!(data, vec![1, 2, 3]),
+            DecodingResult::U16(_) => panic!("expected 8 bit data"),
+        }
+    }
+
+    #[test]
+    fn test_ifd_cycle() {
+        let mut tiff = multi_page_tiff(&[(2, 1, &[10, 20]), (1, 3, &[1, 2, 3])]);
+        // Let the second directory point back to the first one
+        let len = tiff.len();
+        tiff[len - 7..len - 3].copy_from_slice(&[8, 0, 0, 0]);
+        let mut decoder = TIFFDecoder::new(Cursor::new(tiff)).unwrap();
+        assert!(decoder.image_count().is_err());
+    }
 }
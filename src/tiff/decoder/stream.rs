@@ -5,6 +5,8 @@ use std::io::{Read, Seek};
 use byteorder::{ReadBytesExt, BigEndian, LittleEndian};
 use lzw;
 
+use utils;
+
 /// Byte order of the TIFF file.
 #[derive(Clone, Copy, Debug)]
 pub enum ByteOrder {
@@ -49,9 +51,8 @@ impl LZWReader {
     /// Wraps a reader
     pub fn new<R>(reader: &mut SmartReader<R>, compressed_length: usize, max_uncompressed_length: usize) -> io::Result<(usize, LZWReader)> where R: Read + Seek {
         let order = reader.byte_order;
-        let mut compressed = vec![0; compressed_length as usize];
-        try!(reader.read_exact(&mut compressed[..]));
-        let mut uncompressed = Vec::with_capacity(max_uncompressed_length);
+        let compressed = try!(utils::read_exact_vec(reader, compressed_length));
+        let mut uncompressed = Vec::with_capacity(utils::initial_capacity(max_uncompressed_length));
         let mut decoder = lzw::DecoderEarlyChange::new(lzw::MsbReader::new(), 8);
         let mut bytes_read = 0;
         while bytes_read < compressed_length && uncompressed.len() < max_uncompressed_length {
@@ -94,7 +95,10 @@ impl PackBitsReader {
         let mut buffer = Vec::new();
         let mut read: usize = 0;
         while read < length {
-            read += try!(read_packbits_run(&mut reader, &mut buffer));
+            match try!(read_packbits_run(&mut reader, &mut buffer)) {
+                0 => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "PackBits data ended early")),
+                n => read += n,
+            }
         }
         Ok((buffer.len(), PackBitsReader {
             buffer: io::Cursor::new(buffer),
@@ -202,4 +206,10 @@ mod test {
                             0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA];
         assert_eq!(decoded, expected);
     }
+
+    #[test]
+    fn test_packbits_truncated() {
+        let buff = io::Cursor::new(vec![0xFE, 0xAA, 0x02, 0x80]);
+        assert!(PackBitsReader::new(buff, ByteOrder::LittleEndian, 15).is_err());
+    }
 }
@@ -0,0 +1,287 @@
+//! Processing images in tiles which are too large to be held in memory
+
+use std::collections::VecDeque;
+use std::io::Read;
+
+use buffer::ImageBuffer;
+use color::{self, ColorType};
+use dynimage::DynamicImage;
+use image::{GenericImage, ImageDecoder, ImageError, ImageResult};
+use pipeline::{check_color, convert_row, ScanlineEncoder};
+
+/// A rectangular part of an image which is processed by ```TiledImage::process```
+#[derive(Debug)]
+pub struct Tile {
+    /// The horizontal position of the tile in the image
+    pub x: u32,
+    /// The vertical position of the tile in the image
+    pub y: u32,
+    /// The width of the tile, without the overlap
+    pub width: u32,
+    /// The height of the tile, without the overlap
+    pub height: u32,
+    /// The pixels of the tile and of the overlap around it, which is cut off at the
+    /// borders of the image
+    pub image: DynamicImage,
+    /// The horizontal position of the tile in ```image```
+    pub margin_left: u32,
+    /// The vertical position of the tile in ```image```
+    pub margin_top: u32,
+    /// The width of the result of processing the tile
+    pub output_width: u32,
+    /// The height of the result of processing the tile
+    pub output_height: u32,
+}
+
+/// An image which is decoded, processed and encoded in tiles
+///
+/// The rows of the image are read from the decoder as they are needed, see
+/// ```ImageDecoder::into_reader```, and the results are passed to the encoder as soon as a
+/// row of tiles is complete. The tiling itself only holds one row of tiles of the input
+/// and of the output in memory.
+///
+/// Whether the whole image fits into memory still depends on the decoder: only decoders
+/// which decode row by row, currently the PNG decoder for non-interlaced images, allow
+/// processing scans of several gigapixels. The other decoders decode the entire image
+/// when the first row is read.
+///
+/// The tiles are 8 bit images, other color types are converted to the 8 bit color
+/// type with the same channels.
+pub struct TiledImage<D> {
+    decoder: D,
+    tile_width: u32,
+    tile_height: u32,
+    overlap: u32,
+    output_dimensions: Option<(u32, u32)>,
+}
+
+impl<D: ImageDecoder> TiledImage<D> {
+    /// Creates a ```TiledImage``` which splits the image of ```decoder``` into tiles of
+    /// ```tile_width``` x ```tile_height``` pixels. The tiles at the right and bottom
+    /// border may be smaller.
+    pub fn new(decoder: D, tile_width: u32, tile_height: u32) -> TiledImage<D> {
+        TiledImage {
+            decoder,
+            tile_width,
+            tile_height,
+            overlap: 0,
+            output_dimensions: None,
+        }
+    }
+
+    /// Adds ```overlap``` pixels of the neighbouring tiles on every side of a tile.
+    ///
+    /// Filters which read the neighbours of a pixel, e.g. a blur, need an overlap of at
+    /// least their radius to produce the same result as for the whole image.
+    pub fn set_overlap(&mut self, overlap: u32) {
+        self.overlap = overlap;
+    }
+
+    /// Sets the dimensions of the output image, to resize the image tile by tile.
+    ///
+    /// Each tile is then mapped to the proportional part of the output, whose size is
+    /// given by ```Tile::output_width``` and ```Tile::output_height```. The output has
+    /// the dimensions of the input by default.
+    pub fn set_output_dimensions(&mut self, width: u32, height: u32) {
+        self.output_dimensions = Some((width, height));
+    }
+
+    /// Applies ```operation``` to every tile, from left to right and top to bottom, and
+    /// encodes the results with ```encoder```.
+    ///
+    /// The operation returns the processed tile without the overlap, it must have the
+    /// output dimensions of the tile. Returns a ```DimensionError``` if a result has
+    /// other dimensions or if the tiles are empty.
+    pub fn process<E, F>(self, encoder: &mut E, mut operation: F) -> ImageResult<()>
+        where E: ScanlineEncoder,
+              F: FnMut(&Tile) -> ImageResult<DynamicImage> {
+        let mut decoder = self.decoder;
+        let (width, height) = decoder.dimensions()?;
+        let from = decoder.colortype()?;
+        check_color(from, true)?;
+        let color = match from {
+            ColorType::Gray(_) => ColorType::Gray(8),
+            ColorType::GrayA(_) => ColorType::GrayA(8),
+            ColorType::RGB(_) => ColorType::RGB(8),
+            _ => ColorType::RGBA(8),
+        };
+        if self.tile_width == 0 || self.tile_height == 0 {
+            return Err(ImageError::DimensionError)
+        }
+        let (out_width, out_height) = self.output_dimensions.unwrap_or((width, height));
+        // Maps a position in the input to the output
+        let scale = |v: u32, size: u32, out_size: u32| (u64::from(v) * u64::from(out_size) / u64::from(size)) as u32;
+
+        let bpp = color::num_components(color);
+        let mut raw = vec![0; (width as usize * color::bits_per_pixel(from) + 7) / 8];
+        let mut reader = decoder.into_reader()?;
+        // The rows of the input which are held in memory, starting at row ```first```
+        let mut rows: VecDeque<Vec<u8>> = VecDeque::new();
+        let mut first = 0;
+
+        encoder.start_image(out_width, out_height, color)?;
+        let mut y = 0;
+        while y < height {
+            let tile_height = self.tile_height.min(height - y);
+            let top = y.saturating_sub(self.overlap);
+            let bottom = (y + tile_height).saturating_add(self.overlap).min(height);
+            while first < top {
+                rows.pop_front();
+                first += 1;
+            }
+            while first + (rows.len() as u32) < bottom {
+                reader.read_exact(&mut raw)?;
+                let mut row = vec![0; width as usize * bpp];
+                convert_row(&raw, from, &mut row, color);
+                rows.push_back(row);
+            }
+
+            let (out_top, out_bottom) = (scale(y, height, out_height), scale(y + tile_height, height, out_height));
+            let mut output = vec![0; (out_bottom - out_top) as usize * out_width as usize * bpp];
+            let mut x = 0;
+            while x < width {
+                let tile_width = self.tile_width.min(width - x);
+                let left = x.saturating_sub(self.overlap);
+                let right = (x + tile_width).saturating_add(self.overlap).min(width);
+                let (out_left, out_right) = (scale(x, width, out_width), scale(x + tile_width, width, out_width));
+
+                let mut data = Vec::with_capacity((right - left) as usize * (bottom - top) as usize * bpp);
+                for row in rows.range((top - first) as usize..(bottom - first) as usize) {
+                    data.extend_from_slice(&row[left as usize * bpp..right as usize * bpp]);
+                }
+                let tile = Tile {
+                    x,
+                    y,
+                    width: tile_width,
+                    height: tile_height,
+                    image: to_image(data, right - left, bottom - top, color),
+                    margin_left: x - left,
+                    margin_top: y - top,
+                    output_width: out_right - out_left,
+                    output_height: out_bottom - out_top,
+                };
+                let result = operation(&tile)?;
+                if result.dimensions() != (tile.output_width, tile.output_height) {
+                    return Err(ImageError::DimensionError)
+                }
+
+                let result = into_raw(result, color);
+                let tile_row_len = tile.output_width as usize * bpp;
+                for i in 0..tile.output_height as usize {
+                    let start = (i * out_width as usize + out_left as usize) * bpp;
+                    output[start..start + tile_row_len]
+                        .copy_from_slice(&result[i * tile_row_len..(i + 1) * tile_row_len]);
+                }
+                x += tile_width;
+            }
+
+            let out_row_len = out_width as usize * bpp;
+            for i in 0..(out_bottom - out_top) as usize {
+                encoder.write_scanline(&output[i * out_row_len..(i + 1) * out_row_len])?;
+            }
+            y += tile_height;
+        }
+        encoder.finish_image()
+    }
+}
+
+fn to_image(data: Vec<u8>, width: u32, height: u32, color: ColorType) -> DynamicImage {
+    match color {
+        ColorType::Gray(_) => DynamicImage::ImageLuma8(ImageBuffer::from_raw(width, height, data).unwrap()),
+        ColorType::GrayA(_) => DynamicImage::ImageLumaA8(ImageBuffer::from_raw(width, height, data).unwrap()),
+        ColorType::RGB(_) => DynamicImage::ImageRgb8(ImageBuffer::from_raw(width, height, data).unwrap()),
+        _ => DynamicImage::ImageRgba8(ImageBuffer::from_raw(width, height, data).unwrap()),
+    }
+}
+
+fn into_raw(image: DynamicImage, color: ColorType) -> Vec<u8> {
+    match color {
+        ColorType::Gray(_) => image.into_luma().into_raw(),
+        ColorType::GrayA(_) => image.into_luma_alpha().into_raw(),
+        ColorType::RGB(_) => image.into_rgb().into_raw(),
+        _ => image.into_rgba().into_raw(),
+    }
+}
+
+#[cfg(all(test, feature = "pnm"))]
+mod tests {
+    use super::TiledImage;
+    use buffer::{ImageBuffer, RgbImage};
+    use color::{ColorType, Rgb};
+    use dynimage::{decoder_to_image, DynamicImage};
+    use image::{GenericImage, ImageError};
+    use imageops::FilterType;
+    use pnm::{PAMEncoder, PNMDecoder};
+
+    fn image() -> RgbImage {
+        ImageBuffer::from_fn(23, 17, |x, y| Rgb([(x * 11) as u8, (y * 15) as u8, ((x * y) % 256) as u8]))
+    }
+
+    fn pam(image: &RgbImage) -> Vec<u8> {
+        let mut data = Vec::new();
+        PAMEncoder::new(&mut data).encode(image, image.width(), image.height(), ColorType::RGB(8)).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_tiles() {
+        let input = pam(&image());
+        let mut tiled = TiledImage::new(PNMDecoder::new(&input[..]).unwrap(), 8, 5);
+        tiled.set_overlap(2);
+        let mut output = Vec::new();
+        let mut tiles = Vec::new();
+        tiled.process(&mut PAMEncoder::new(&mut output), |tile| {
+            tiles.push((tile.x, tile.y, tile.width, tile.height, tile.margin_left, tile.margin_top));
+            let mut image = tile.image.clone();
+            assert_eq!(image.get_pixel(tile.margin_left, tile.margin_top),
+                       DynamicImage::ImageRgb8(self::image()).get_pixel(tile.x, tile.y));
+            Ok(image.crop(tile.margin_left, tile.margin_top, tile.width, tile.height))
+        }).unwrap();
+
+        assert_eq!(tiles.len(), 12);
+        assert_eq!(tiles[0], (0, 0, 8, 5, 0, 0));
+        assert_eq!(tiles[4], (8, 5, 8, 5, 2, 2));
+        assert_eq!(tiles[11], (16, 15, 7, 2, 2, 2));
+        let decoded = decoder_to_image(PNMDecoder::new(&output[..]).unwrap()).unwrap();
+        assert_eq!(decoded.to_rgb().into_raw(), image().into_raw());
+    }
+
+    #[test]
+    fn test_blur_with_overlap() {
+        let input = pam(&image());
+        let mut tiled = TiledImage::new(PNMDecoder::new(&input[..]).unwrap(), 6, 6);
+        tiled.set_overlap(4);
+        let mut output = Vec::new();
+        tiled.process(&mut PAMEncoder::new(&mut output), |tile| {
+            let mut blurred = tile.image.blur(1.0);
+            Ok(blurred.crop(tile.margin_left, tile.margin_top, tile.width, tile.height))
+        }).unwrap();
+
+        let decoded = decoder_to_image(PNMDecoder::new(&output[..]).unwrap()).unwrap();
+        let expected = DynamicImage::ImageRgb8(image()).blur(1.0);
+        assert_eq!(decoded.to_rgb().into_raw(), expected.to_rgb().into_raw());
+    }
+
+    #[test]
+    fn test_resize() {
+        let input = pam(&image());
+        let mut tiled = TiledImage::new(PNMDecoder::new(&input[..]).unwrap(), 10, 10);
+        tiled.set_output_dimensions(46, 8);
+        let mut output = Vec::new();
+        tiled.process(&mut PAMEncoder::new(&mut output), |tile| {
+            let mut image = tile.image.clone();
+            let core = image.crop(tile.margin_left, tile.margin_top, tile.width, tile.height);
+            Ok(core.resize_exact(tile.output_width, tile.output_height, FilterType::Nearest))
+        }).unwrap();
+        let decoded = decoder_to_image(PNMDecoder::new(&output[..]).unwrap()).unwrap();
+        assert_eq!(decoded.dimensions(), (46, 8));
+
+        // The results must have the output dimensions of the tiles, without the overlap
+        let mut tiled = TiledImage::new(PNMDecoder::new(&input[..]).unwrap(), 10, 10);
+        tiled.set_overlap(1);
+        match tiled.process(&mut PAMEncoder::new(&mut Vec::new()), |tile| Ok(tile.image.clone())) {
+            Err(ImageError::DimensionError) => (),
+            other => panic!("expected a dimension error, got {:?}", other),
+        }
+    }
+}
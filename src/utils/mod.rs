@@ -1,8 +1,49 @@
 //!  Utilities
 
+use std::cmp;
+use std::io::{self, Read};
 use std::iter::repeat;
 use num_iter::range_step;
+use num_traits::cast;
 
+use traits::Primitive;
+
+/// The largest buffer which is allocated up front for data of a length declared by an image,
+/// larger buffers grow while the data is read.
+#[allow(dead_code)] // Only some of the codecs use these helpers.
+const MAX_INITIAL_CAPACITY: usize = 1 << 16;
+
+/// Returns the capacity to reserve for ```length``` elements declared by an image
+#[allow(dead_code)]
+pub fn initial_capacity(length: usize) -> usize {
+    cmp::min(length, MAX_INITIAL_CAPACITY)
+}
+
+/// Reads ```length``` bytes whose length was declared by an image.
+///
+/// The buffer only grows with the data that is actually read, a bogus length in a short
+/// file results in an ```UnexpectedEof``` error instead of a huge allocation.
+#[allow(dead_code)]
+pub fn read_exact_vec<R: Read>(r: &mut R, length: usize) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(initial_capacity(length));
+    r.take(length as u64).read_to_end(&mut buf)?;
+    if buf.len() < length {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer"))
+    }
+    Ok(buf)
+}
+
+
+/// Returns true for float subpixels, they have no meaningful maximum and are by
+/// convention normalized to 1
+pub fn is_float<S: Primitive>() -> bool {
+    cast(0.5).map(|half: S| cast::<S, f64>(half) == Some(0.5)).unwrap_or(false)
+}
+
+/// The value of a fully saturated channel
+pub fn normalized_max<S: Primitive>() -> f64 {
+    if is_float::<S>() { 1.0 } else { cast(S::max_value()).unwrap() }
+}
 
 #[inline(always)]
 pub fn expand_packed<F>(buf: &mut [u8], channels: usize, bit_depth: u8, mut func: F)
@@ -31,3 +72,87 @@ where F: FnMut(u8, &mut[u8]) {
         func(pixel, &mut buf[j as usize..(j + channels) as usize])
     }
 }
+
+// Conversions between the 8 bit color types of `DynamicImage`.
+//
+// They work on the raw samples in fixed size chunks instead of going through the
+// generic `FromColor` implementations pixel by pixel, which allows the compiler to
+// vectorize the loops. The results are identical to those of `FromColor`.
+
+/// Converts pixels with 1 to 4 channels (gray, gray alpha, RGB, RGBA) to RGBA.
+pub fn to_rgba8(src: &[u8], channels: usize) -> Vec<u8> {
+    let mut dst = vec![0; src.len() / channels * 4];
+    rgba8_into(src, channels, &mut dst);
+    dst
+}
+
+/// Like ```to_rgba8```, but also reverses the order of the rows of an image
+/// which is ```width``` pixels wide.
+pub fn to_rgba8_flipped(src: &[u8], channels: usize, width: usize) -> Vec<u8> {
+    let mut dst = vec![0; src.len() / channels * 4];
+    if width > 0 {
+        for (s, d) in src.chunks(width * channels).rev().zip(dst.chunks_mut(width * 4)) {
+            rgba8_into(s, channels, d);
+        }
+    }
+    dst
+}
+
+fn rgba8_into(src: &[u8], channels: usize, dst: &mut [u8]) {
+    match channels {
+        1 => for (s, d) in src.iter().zip(dst.chunks_exact_mut(4)) {
+            d.copy_from_slice(&[*s, *s, *s, 0xFF]);
+        },
+        2 => for (s, d) in src.chunks_exact(2).zip(dst.chunks_exact_mut(4)) {
+            d.copy_from_slice(&[s[0], s[0], s[0], s[1]]);
+        },
+        3 => for (s, d) in src.chunks_exact(3).zip(dst.chunks_exact_mut(4)) {
+            d.copy_from_slice(&[s[0], s[1], s[2], 0xFF]);
+        },
+        _ => dst.copy_from_slice(src),
+    }
+}
+
+/// Converts pixels with 1 to 4 channels (gray, gray alpha, RGB, RGBA) to RGB.
+pub fn to_rgb8(src: &[u8], channels: usize) -> Vec<u8> {
+    let mut dst = vec![0; src.len() / channels * 3];
+    match channels {
+        1 | 2 => for (s, d) in src.chunks_exact(channels).zip(dst.chunks_exact_mut(3)) {
+            d.copy_from_slice(&[s[0], s[0], s[0]]);
+        },
+        4 => for (s, d) in src.chunks_exact(4).zip(dst.chunks_exact_mut(3)) {
+            d.copy_from_slice(&s[..3]);
+        },
+        _ => dst.copy_from_slice(src),
+    }
+    dst
+}
+
+/// Converts pixels with 1 to 4 channels (gray, gray alpha, RGB, RGBA) to gray.
+pub fn to_luma8(src: &[u8], channels: usize) -> Vec<u8> {
+    match channels {
+        3 | 4 => src.chunks_exact(channels).map(|s| luma(s[0], s[1], s[2])).collect(),
+        _ => src.chunks_exact(channels).map(|s| s[0]).collect(),
+    }
+}
+
+/// Converts pixels with 1 to 4 channels (gray, gray alpha, RGB, RGBA) to gray alpha.
+pub fn to_luma_alpha8(src: &[u8], channels: usize) -> Vec<u8> {
+    let mut dst = vec![0; src.len() / channels * 2];
+    for (s, d) in src.chunks_exact(channels).zip(dst.chunks_exact_mut(2)) {
+        let (l, a) = match channels {
+            1 => (s[0], 0xFF),
+            2 => (s[0], s[1]),
+            3 => (luma(s[0], s[1], s[2]), 0xFF),
+            _ => (luma(s[0], s[1], s[2]), s[3]),
+        };
+        d.copy_from_slice(&[l, a]);
+    }
+    dst
+}
+
+// Same weights and truncation as the `FromColor` implementations for `Luma`
+#[inline(always)]
+fn luma(r: u8, g: u8, b: u8) -> u8 {
+    (0.2126f32 * f32::from(r) + 0.7152f32 * f32::from(g) + 0.0722f32 * f32::from(b)) as u8
+}
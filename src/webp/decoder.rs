@@ -4,7 +4,7 @@ use std::default::Default;
 use byteorder::{ReadBytesExt, LittleEndian};
 
 use image;
-use image::ImageResult;
+use image::{DecodingError, DecodingErrorKind, ImageFormat, ImageResult};
 use image::ImageDecoder;
 
 use color;
@@ -44,11 +44,15 @@ impl<R: Read> WebpDecoder<R> {
         try!(self.r.by_ref().take(4).read_to_end(&mut webp));
 
         if &*riff != b"RIFF" {
-            return Err(image::ImageError::FormatError("Invalid RIFF signature.".to_string()))
+            return Err(DecodingError::new(ImageFormat::WEBP, DecodingErrorKind::InvalidSignature)
+                .with_offset(0)
+                .into())
         }
 
         if &*webp != b"WEBP" {
-            return Err(image::ImageError::FormatError("Invalid WEBP signature.".to_string()))
+            return Err(DecodingError::new(ImageFormat::WEBP, DecodingErrorKind::InvalidSignature)
+                .with_offset(8)
+                .into())
         }
 
         Ok(size)
@@ -59,7 +63,9 @@ impl<R: Read> WebpDecoder<R> {
         try!(self.r.by_ref().take(4).read_to_end(&mut vp8));
 
         if &*vp8 != b"VP8 " {
-            return Err(image::ImageError::FormatError("Invalid VP8 signature.".to_string()))
+            return Err(DecodingError::new(ImageFormat::WEBP, DecodingErrorKind::InvalidSignature)
+                .with_offset(12)
+                .into())
         }
 
         let _len = try!(self.r.read_u32::<LittleEndian>());
@@ -134,4 +140,44 @@ impl<R: Read> ImageDecoder for WebpDecoder<R> {
 
         Ok(image::DecodingResult::U8(self.frame.ybuf.clone()))
     }
+
+    // The VP8 decoder writes the frame into its own buffer, which is copied into ```buf```.
+    // This only saves the allocation of ```read_image```.
+    fn read_image_into(&mut self, buf: &mut [u8]) -> ImageResult<()> {
+        self.read_metadata()?;
+
+        if buf.len() != self.frame.ybuf.len() {
+            return Err(image::ImageError::DimensionError)
+        }
+        buf.copy_from_slice(&self.frame.ybuf);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WebpDecoder;
+    use image::ImageDecoder;
+
+    // A key frame of the given size whose partitions contain only a few zero bytes
+    fn webp(width: u16, height: u16) -> Vec<u8> {
+        let partition_size = 16u32;
+        let tag = partition_size << 5 | 1 << 4;
+        let mut vp8 = vec![tag as u8, (tag >> 8) as u8, (tag >> 16) as u8, 0x9d, 0x01, 0x2a];
+        vp8.extend_from_slice(&[width as u8, (width >> 8) as u8, height as u8, (height >> 8) as u8]);
+        vp8.extend_from_slice(&[0; 32]);
+
+        let mut data = b"RIFF\0\0\0\0WEBPVP8 ".to_vec();
+        data.extend_from_slice(&[vp8.len() as u8, 0, 0, 0]);
+        data.extend_from_slice(&vp8);
+        data
+    }
+
+    #[test]
+    fn test_truncated_frame() {
+        // One row of macroblocks can be decoded from the few bytes
+        assert_eq!(WebpDecoder::new(&webp(20, 10)[..]).read_image().map(|_| ()).ok(), Some(()));
+        // The buffer of the large frame is not allocated before the data runs out
+        assert!(WebpDecoder::new(&webp(16383, 16383)[..]).read_image().is_err());
+    }
 }
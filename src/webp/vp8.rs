@@ -12,6 +12,7 @@
 //! of the VP8 format
 //!
 
+use std::cmp;
 use std::io;
 use std::io::Read;
 use std::default::Default;
@@ -20,6 +21,7 @@ use byteorder::{ReadBytesExt, LittleEndian};
 use super::transform;
 
 use math::utils::clamp;
+use utils;
 
 const MAX_SEGMENTS: usize = 4;
 const NUM_DCT_TOKENS: usize = 12;
@@ -647,8 +649,7 @@ impl BoolReader {
         self.value = 0;
 
         for _ in 0usize..2 {
-            self.value = (self.value << 8) | self.buf[self.index] as u32;
-            self.index += 1;
+            self.value = (self.value << 8) | self.next_byte() as u32;
         }
 
         self.range = 255;
@@ -675,14 +676,25 @@ impl BoolReader {
 
             if self.bit_count == 8 {
                 self.bit_count = 0;
-                self.value |= self.buf[self.index] as u32;
-                self.index += 1;
+                self.value |= self.next_byte() as u32;
             }
         }
 
         retval
     }
 
+    // True if data past the end was decoded, not only read ahead into ```value```
+    fn is_past_end(&self) -> bool {
+        self.index > self.buf.len() + 2
+    }
+
+    // Like libvpx, data past the end of a truncated partition is read as zeros
+    fn next_byte(&mut self) -> u8 {
+        let byte = self.buf.get(self.index).cloned().unwrap_or(0);
+        self.index += 1;
+        byte
+    }
+
     pub fn read_literal(&mut self, n: u8) -> u8 {
         let mut v = 0u8;
         let mut n = n;
@@ -894,12 +906,12 @@ impl<R: Read> VP8Decoder<R> {
 
     fn init_partitions(&mut self, n: usize) -> io::Result<()> {
         if n > 1 {
-            let mut sizes = Vec::with_capacity(3 * n - 3);
-            try!(self.r.by_ref().take(3 * n as u64 - 3).read_to_end(&mut sizes));
+            let mut sizes = vec![0; 3 * n - 3];
+            try!(self.r.read_exact(&mut sizes));
 
             for (i, s) in sizes.chunks(3).enumerate() {
-                let size = s[0] as u32 + ((s[1] as u32) << 8) + ((s[2] as u32) << 8);
-                let mut buf = Vec::with_capacity(size as usize);
+                let size = s[0] as u32 + ((s[1] as u32) << 8) + ((s[2] as u32) << 16);
+                let mut buf = Vec::with_capacity(utils::initial_capacity(size as usize));
                 try!(self.r.by_ref().take(size as u64).read_to_end(&mut buf));
 
                 self.partitions[i].init(buf);
@@ -1037,13 +1049,18 @@ impl<R: Read> VP8Decoder<R> {
 
         if self.frame.keyframe {
             try!(self.r.read_exact(&mut tag));
-            assert_eq!(tag, [0x9d, 0x01, 0x2a]);
+            if tag != [0x9d, 0x01, 0x2a] {
+                return Err(invalid_data("Invalid start code of the key frame"))
+            }
 
             let w = try!(self.r.read_u16::<LittleEndian>());
             let h = try!(self.r.read_u16::<LittleEndian>());
 
             self.frame.width = w & 0x3FFF;
             self.frame.height = h & 0x3FFF;
+            if self.frame.width == 0 || self.frame.height == 0 {
+                return Err(invalid_data("Frame has no pixels"))
+            }
 
             self.top = init_top_macroblocks(self.frame.width as usize);
             self.left = MacroBlock{..self.top[0]};
@@ -1051,8 +1068,8 @@ impl<R: Read> VP8Decoder<R> {
             self.mbwidth  = (self.frame.width + 15) / 16;
             self.mbheight = (self.frame.height + 15) / 16;
 
-            self.frame.ybuf = vec![0u8; self.frame.width as usize * 
-                                        self.frame.height as usize];
+            // The buffer grows with the rows of macroblocks as they are decoded
+            self.frame.ybuf = Vec::new();
 
             self.top_border = vec![127u8; self.frame.width as usize + 4 + 16];
             self.left_border = vec![129u8; 1 + 16];
@@ -1066,7 +1083,9 @@ impl<R: Read> VP8Decoder<R> {
         if self.frame.keyframe {
             let color_space = self.b.read_literal(1);
             self.frame.pixel_type = self.b.read_literal(1);
-            assert_eq!(color_space, 0);
+            if color_space != 0 {
+                return Err(invalid_data("Reserved color space"))
+            }
         }
 
         self.segments_enabled = self.b.read_flag();
@@ -1090,8 +1109,8 @@ impl<R: Read> VP8Decoder<R> {
         self.read_quantization_indices();
 
         if !self.frame.keyframe {
-            // 9.7 refresh golden frame and altref frame
-            panic!("unimplemented")
+            // 9.7 refresh golden frame and altref frame, WebP only uses key frames
+            return Err(invalid_data("Inter frames are not supported"))
         } else {
             // Refresh entropy probs ?????
             let _ = self.b.read_literal(1);
@@ -1110,7 +1129,7 @@ impl<R: Read> VP8Decoder<R> {
             // 9.10 remaining frame data
             self.prob_intra = 0;
 
-            panic!("unimplemented")
+            return Err(invalid_data("Inter frames are not supported"))
         } else {
             // Reset motion vectors
         }
@@ -1225,11 +1244,11 @@ impl<R: Read> VP8Decoder<R> {
 
         let ylength = if mby < self.mbheight as usize - 1 { 16usize }
                       else if self.frame.height % 16 == 0 { 16usize }
-                      else { (16 - (self.frame.height as usize & 15)) % 16 };
+                      else { self.frame.height as usize & 15 };
 
         let xlength = if mbx < self.mbwidth as usize - 1 { 16usize }
                       else if self.frame.width % 16 == 0 { 16usize }
-                      else { (16 - (self.frame.width as usize & 15)) % 16 };
+                      else { self.frame.width as usize & 15 };
 
         for y in 0usize..ylength {
             for x in 0usize..xlength {
@@ -1395,6 +1414,14 @@ impl<R: Read> VP8Decoder<R> {
         try!(self.read_frame_header());
 
         for mby in 0..self.mbheight as usize {
+            // The headers of all macroblocks are stored in the first partition, a
+            // truncated frame ends before its buffer is allocated completely
+            if self.b.is_past_end() {
+                return Err(invalid_data("Truncated first partition"))
+            }
+            let rows = cmp::min(mby * 16 + 16, self.frame.height as usize);
+            self.frame.ybuf.resize(rows * self.frame.width as usize, 0);
+
             let p = mby % self.num_partitions as usize;
             self.left = MacroBlock::new();
 
@@ -1426,6 +1453,10 @@ impl<R: Read> VP8Decoder<R> {
     }
 }
 
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
 fn init_top_macroblocks(width: usize) -> Vec<MacroBlock> {
     let mb_width = (width + 15) / 16;
 
@@ -34,6 +34,7 @@ where F: Fn(&PathBuf, PathBuf, &str) {
 }
 
 #[test]
+#[cfg(feature = "png_codec")]
 fn render_images() {
 	process_images(IMAGE_DIR, None, |base, path, decoder| {
 		let img = match image::open(&path) {
@@ -67,6 +68,7 @@ fn render_images() {
 }
 
 #[test]
+#[cfg(feature = "png_codec")]
 fn check_references() {
 	process_images(REFERENCE_DIR, Some("png"), |base, path, decoder| {
         let ref_img = match image::open(&path) {
@@ -115,6 +117,7 @@ fn check_references() {
 }
 
 #[test]
+#[cfg(feature = "hdr")]
 fn check_hdr_references() {
     let mut ref_path: PathBuf = BASE_PATH.iter().collect();
     ref_path.push(REFERENCE_DIR);
@@ -149,6 +152,7 @@ fn check_hdr_references() {
 ///
 /// The images are postfixed with `bad_bmp` to not be loaded by the other test.
 #[test]
+#[cfg(feature = "bmp")]
 fn bad_bmps() {
     let base_path: PathBuf = BASE_PATH.iter().collect::<PathBuf>().join(IMAGE_DIR).join("bmp/images");
 